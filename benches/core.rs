@@ -0,0 +1,307 @@
+//! Benchmarks for the core operations of a run, over blocks of 10, 100 and 1000 transactions.
+//! Before timing them, it prints how many allocations each makes with the integer pool and
+//! without it. The `rsa_backend` and `pow_mod` groups time the `rsa` group's arithmetic on the
+//! backend the build selects; see `simulation::arith`.
+use accumulator::group::{Rsa2048, TypeRep, UnknownOrderGroup};
+use accumulator_demo::simulation::arith;
+use accumulator_demo::simulation::bridge::{self, Bridge, WitnessRequest};
+use accumulator_demo::simulation::clock::ManualClock;
+use accumulator_demo::simulation::config::ModulusSource;
+use accumulator_demo::simulation::genesis::Genesis;
+use accumulator_demo::simulation::int_pool;
+use accumulator_demo::simulation::metrics::Metrics;
+use accumulator_demo::simulation::miner::Miner;
+use accumulator_demo::simulation::modulus::{self, CustomRsa};
+use accumulator_demo::simulation::state::{Block, Transaction, UpdateProof, Utxo};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rug::integer::Order;
+use rug::Integer;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+const SIZES: [usize; 3] = [10, 100, 1000];
+
+/// The system allocator, counting allocations.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// How many allocations and reallocations `f` makes.
+fn allocations<R, F: FnOnce() -> R>(f: F) -> u64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let result = f();
+    let after = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(result);
+    after - before
+}
+
+/// A chain at genesis, where every one of `n` users (all behind one bridge) spends its UTXO in the
+/// next block.
+struct Fixture<G: UnknownOrderGroup> {
+    genesis: Genesis<G>,
+    bridge: Bridge<G>,
+    transactions: Vec<Arc<Transaction<G, Utxo>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<G: UnknownOrderGroup> Fixture<G> {
+    fn new(n: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(0);
+        let genesis = Genesis::<G>::new(&[n], &mut rng);
+        let metrics = Arc::new(Metrics::new(Arc::new(ManualClock::new())));
+        let bridge = Bridge::new(
+            0,
+            genesis.acc.clone(),
+            genesis.bridge_witnesses[0].clone(),
+            genesis.bridge_utxos(0),
+            genesis.bridge_users[0].clone().collect(),
+            metrics.clone(),
+        );
+        let response = bridge.handle_witness_request(&WitnessRequest {
+            user_id: 0,
+            request_id: Uuid::from_bytes(rng.gen()),
+            utxos: genesis.user_utxos.clone(),
+        });
+        let transactions = response
+            .utxos_with_witnesses
+            .into_iter()
+            .map(|(utxo, witness)| {
+                Arc::new(Transaction {
+                    utxos_created: vec![Utxo {
+                        id: Uuid::from_bytes(rng.gen()),
+                        user_id: utxo.user_id,
+                        value: utxo.value,
+                    }],
+                    utxos_spent_with_witnesses: vec![(utxo, witness)],
+                })
+            })
+            .collect();
+        Self {
+            genesis,
+            bridge,
+            transactions,
+            metrics,
+        }
+    }
+
+    /// A miner at genesis with every transaction pending.
+    fn miner(&self) -> Miner<G, Utxo> {
+        let miner = Miner::new(0, self.genesis.acc.clone(), self.metrics.clone());
+        for transaction in &self.transactions {
+            miner.add_transaction(transaction.clone());
+        }
+        miner
+    }
+
+    fn block(&self) -> Block<G, Utxo> {
+        self.miner().forge_block().unwrap()
+    }
+}
+
+/// Prints the allocations of forging, checking and applying a block of each size, without the
+/// integer pool and then with it. Each is run once beforehand, so the pool holds the integers of a
+/// block before, as it would in a long run.
+fn report_allocations(fixtures: &[(usize, Fixture<Rsa2048>)]) {
+    for (n, fixture) in fixtures {
+        let block = Arc::new(fixture.block());
+        let mut counts = Vec::new();
+        for pooled in &[false, true] {
+            if *pooled {
+                int_pool::enable();
+            } else {
+                int_pool::disable();
+            }
+            let miner = fixture.miner();
+            miner.forge_block().unwrap();
+            let forge = allocations(|| miner.forge_block().unwrap());
+            fixture.miner().validate_block(block.clone());
+            let validator = fixture.miner();
+            let validate = allocations(|| validator.validate_block(block.clone()));
+            fixture.bridge.clone().update(&block);
+            let mut bridge = fixture.bridge.clone();
+            let update = allocations(|| bridge.update(&block));
+            counts.push((forge, validate, update));
+        }
+        for (name, without, with) in &[
+            ("forge_block", counts[0].0, counts[1].0),
+            ("validate_block", counts[0].1, counts[1].1),
+            ("bridge_update", counts[0].2, counts[1].2),
+        ] {
+            println!(
+                "{}/{}: {} allocations without the integer pool, {} with it",
+                name, n, without, with
+            );
+        }
+    }
+    let stats = int_pool::stats();
+    println!(
+        "Integer pool: {} integers reused, {} fresh",
+        stats.reused, stats.fresh
+    );
+    int_pool::disable();
+}
+
+fn bench_core(c: &mut Criterion) {
+    let fixtures: Vec<(usize, Fixture<Rsa2048>)> =
+        SIZES.iter().map(|n| (*n, Fixture::new(*n))).collect();
+    report_allocations(&fixtures);
+
+    let mut group = c.benchmark_group("forge_block");
+    group.sample_size(10);
+    for (n, fixture) in &fixtures {
+        let miner = fixture.miner();
+        group.bench_with_input(BenchmarkId::from_parameter(n), &miner, |b, miner| {
+            b.iter(|| miner.forge_block().unwrap())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("validate_block");
+    group.sample_size(10);
+    for (n, fixture) in &fixtures {
+        let block = Arc::new(fixture.block());
+        group.bench_with_input(BenchmarkId::from_parameter(n), &block, |b, block| {
+            b.iter_batched(
+                || (fixture.miner(), block.clone()),
+                |(miner, block)| miner.validate_block(block),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("bridge_update");
+    group.sample_size(10);
+    for (n, fixture) in &fixtures {
+        let block = fixture.block();
+        group.bench_with_input(BenchmarkId::from_parameter(n), &block, |b, block| {
+            b.iter_batched(
+                || fixture.bridge.clone(),
+                |mut bridge| bridge.update(block),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("verify_membership_batch");
+    group.sample_size(10);
+    for (n, fixture) in &fixtures {
+        let block = fixture.block();
+        let elems_added: Vec<Utxo> = block
+            .transactions
+            .iter()
+            .flat_map(|transaction| transaction.utxos_created.iter().cloned())
+            .collect();
+        let proof_added = match &block.proof_update {
+            UpdateProof::Sequential { added, .. } => added,
+            UpdateProof::Swap(_) => unreachable!("Miners only swap when asked to"),
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(n), &block, |b, block| {
+            b.iter(|| {
+                assert!(block
+                    .acc_new
+                    .verify_membership_batch(&elems_added, proof_added))
+            })
+        });
+    }
+    group.finish();
+
+    let pool = bridge::witness_pool(0);
+    let mut group = c.benchmark_group("witness_request");
+    group.sample_size(10);
+    for (n, fixture) in &fixtures {
+        let request = WitnessRequest {
+            user_id: 0,
+            request_id: Uuid::nil(),
+            utxos: fixture.genesis.user_utxos.clone(),
+        };
+        let mut parallel = fixture.bridge.clone();
+        parallel.generate_witnesses_on(pool.clone());
+        group.bench_with_input(BenchmarkId::new("serial", n), &request, |b, request| {
+            b.iter(|| fixture.bridge.handle_witness_request(request))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", n), &request, |b, request| {
+            b.iter(|| parallel.handle_witness_request(request))
+        });
+    }
+    group.finish();
+}
+
+/// Times forging and checking blocks in the `rsa` group, whose arithmetic goes through the backend
+/// the build selects. Run it without the `gmp` feature and then with it: the ids are the same, so
+/// criterion reports the second backend's change from the first. `pow_mod` then times both
+/// backends side by side on an exponentiation by as many bits as a block's primes multiply to.
+fn bench_backends(c: &mut Criterion) {
+    modulus::install(&ModulusSource::Generate(2048)).unwrap();
+    println!("Modular arithmetic backend: {}", arith::BACKEND);
+    let fixtures: Vec<(usize, Fixture<CustomRsa>)> =
+        SIZES.iter().map(|n| (*n, Fixture::new(*n))).collect();
+
+    let mut group = c.benchmark_group("rsa_backend");
+    group.sample_size(10);
+    for (n, fixture) in &fixtures {
+        let miner = fixture.miner();
+        group.bench_with_input(BenchmarkId::new("forge_block", n), &miner, |b, miner| {
+            b.iter(|| miner.forge_block().unwrap())
+        });
+        let block = Arc::new(fixture.block());
+        group.bench_with_input(BenchmarkId::new("validate_block", n), &block, |b, block| {
+            b.iter_batched(
+                || (fixture.miner(), block.clone()),
+                |(miner, block)| miner.validate_block(block),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+
+    let n = &CustomRsa::rep().n;
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut random = |bits: usize| {
+        let mut bytes = vec![0; bits / 8];
+        rng.fill_bytes(&mut bytes);
+        Integer::from_digits(&bytes, Order::Msf)
+    };
+    let base = random(2048) % n;
+    let mut group = c.benchmark_group("pow_mod");
+    group.sample_size(10);
+    for size in &SIZES {
+        // Primes of UTXOs are 256 bits.
+        let exp = random(256 * size);
+        group.bench_with_input(BenchmarkId::new("rug", size), &exp, |b, exp| {
+            b.iter(|| arith::rug_backend::pow_mod(&base, exp, n).unwrap())
+        });
+        #[cfg(feature = "gmp")]
+        group.bench_with_input(BenchmarkId::new("gmp", size), &exp, |b, exp| {
+            b.iter(|| arith::gmp_backend::pow_mod(&base, exp, n).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_core, bench_backends);
+criterion_main!(benches);
@@ -0,0 +1,5 @@
+fn main() {
+    // The gRPC API is generated from its protobuf definition, and only with the grpc feature.
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/miner.proto").expect("Failed to compile proto/miner.proto");
+}
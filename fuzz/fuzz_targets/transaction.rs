@@ -0,0 +1,16 @@
+//! Feeds arbitrary bytes to transaction decoding, as a miner reading a transaction off a link or
+//! an API would: as a wire envelope, signed or not, and as the bare bincode of
+//! `simulation::codec`.
+#![no_main]
+use accumulator::group::Rsa100;
+use accumulator_demo::simulation::auth::Keyring;
+use accumulator_demo::simulation::codec::Decode;
+use accumulator_demo::simulation::state::{Transaction, Utxo};
+use accumulator_demo::simulation::wire;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = wire::decode::<Transaction<Rsa100, Utxo>>(data);
+    let _ = wire::decode_verified::<Transaction<Rsa100, Utxo>>(data, &Keyring::new(0));
+    let _ = Transaction::<Rsa100, Utxo>::from_bytes(data);
+});
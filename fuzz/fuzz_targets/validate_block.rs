@@ -0,0 +1,41 @@
+//! Feeds whatever block decodes from arbitrary bytes to the validation every miner and bridge runs
+//! on the blocks it receives, against the genesis of a small run, so a block that decodes but is
+//! malformed (wrong height, spends of unknown UTXOs, proofs of the wrong elements) is rejected
+//! rather than panicking the node.
+#![no_main]
+use accumulator::group::Rsa100;
+use accumulator_demo::simulation::clock::ManualClock;
+use accumulator_demo::simulation::genesis::Genesis;
+use accumulator_demo::simulation::metrics::Metrics;
+use accumulator_demo::simulation::state::{Block, Utxo};
+use accumulator_demo::simulation::validation;
+use accumulator_demo::simulation::wire;
+use libfuzzer_sys::fuzz_target;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::{Arc, OnceLock};
+
+/// The chain state blocks are validated against: the genesis of two bridges of two users each.
+fn genesis() -> &'static Genesis<Rsa100> {
+    static GENESIS: OnceLock<Genesis<Rsa100>> = OnceLock::new();
+    GENESIS.get_or_init(|| Genesis::new(&[2, 2], &mut StdRng::seed_from_u64(0)))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let block = match wire::decode::<Block<Rsa100, Utxo>>(data) {
+        Ok(block) => block,
+        Err(_) => return,
+    };
+    let metrics = Metrics::new(Arc::new(ManualClock::new()));
+    for require_fresh in [false, true].iter() {
+        let _ = validation::validate_block(
+            &genesis().acc,
+            0,
+            &block,
+            *require_fresh,
+            None,
+            &metrics,
+            None,
+        );
+    }
+});
@@ -0,0 +1,44 @@
+//! Runs one miner of a simulation deployed as a cluster, e.g.
+//! `miner --deployment cluster.yaml --id 0`.
+use accumulator::group::{ClassGroup, Rsa100, Rsa2048, UnknownOrderGroup};
+use accumulator_demo::simulation::bridge::{UserUpdate, WitnessResponse};
+use accumulator_demo::simulation::cluster::{ClusterNode, NodeArgs};
+use accumulator_demo::simulation::logging;
+use accumulator_demo::simulation::modulus::{self, CustomRsa};
+use accumulator_demo::simulation::state::{Block, Transaction, Utxo};
+use accumulator_demo::simulation::trapdoor::{self, TrapdoorRsa};
+use accumulator_demo::simulation::wire::WireMessage;
+use accumulator_demo::simulation::GroupKind;
+use tracing::info;
+
+pub fn main() {
+    let args = NodeArgs::from_env("id");
+    logging::init(&args.log_filter);
+    match args.deployment.config.group {
+        GroupKind::Rsa100 => run::<Rsa100>(args),
+        GroupKind::Rsa2048 => run::<Rsa2048>(args),
+        GroupKind::Rsa => {
+            modulus::install(&args.deployment.config.rsa_modulus)
+                .expect("Failed to set up the RSA modulus");
+            run::<CustomRsa>(args)
+        }
+        GroupKind::ClassGroup => run::<ClassGroup>(args),
+        GroupKind::Trapdoor => {
+            trapdoor::install(args.deployment.config.seed);
+            run::<TrapdoorRsa>(args)
+        }
+    }
+}
+
+fn run<G: UnknownOrderGroup>(args: NodeArgs)
+where
+    Block<G, Utxo>: WireMessage + Send + 'static,
+    Transaction<G, Utxo>: WireMessage + Send + 'static,
+    WitnessResponse<G, Utxo>: WireMessage + Send + 'static,
+    UserUpdate<G>: WireMessage + Send + 'static,
+{
+    let node = ClusterNode::<G>::new(args.deployment);
+    let state = node.run_miner(args.id).expect("Failed to start the miner");
+    info!("Miner {} exiting: {:?}", args.id, state);
+    node.write_metrics(args.output_dir.as_deref());
+}
@@ -0,0 +1,3 @@
+//! A simulation of stateless Bitcoin-style nodes built on the accumulator crate, usable from the
+//! `accumulator-demo` executable, benchmarks and other crates.
+pub mod simulation;
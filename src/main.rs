@@ -1,184 +1,608 @@
 //! Simulation runner.
-mod simulation;
-use accumulator::group::{Rsa100, UnknownOrderGroup};
-use accumulator::{Accumulator, Witness};
-use multiqueue::{broadcast_queue, BroadcastReceiver, BroadcastSender};
-use simulation::state::Utxo;
-use simulation::{Bridge, Miner, User};
-use std::collections::HashMap;
+use accumulator::group::{ClassGroup, Rsa100, Rsa2048, UnknownOrderGroup};
+use accumulator::{Accumulator, NonmembershipProof, Witness};
+use accumulator_demo::simulation;
+use serde::Serialize;
+use simulation::baseline;
+use simulation::control::{self, Command, RunControl};
+#[cfg(feature = "tui")]
+use simulation::dashboard::Dashboard;
+use simulation::diagram::{self, Selection};
+use simulation::embed::Builder;
+use simulation::events::{EventLog, EventSink};
+use simulation::experiment::Experiment;
+#[cfg(feature = "grpc")]
+use simulation::grpc;
+use simulation::jsonrpc;
+use simulation::launcher::{self, LocalCluster};
+use simulation::logging;
+use simulation::metrics::Metrics;
+use simulation::modulus::{self, CustomRsa};
+#[cfg(feature = "profiling")]
+use simulation::profile;
+use simulation::prometheus;
+use simulation::report::RunResult;
+use simulation::rest;
+use simulation::scenario::{Scenario, ScenarioRunner};
+use simulation::state::{Block, Transaction, Utxo};
+use simulation::sweep::Sweep;
+use simulation::trapdoor::{self, TrapdoorRsa};
+use simulation::vector::VectorDemo;
+use simulation::wire::WireMessage;
+use simulation::{
+    ClusterTransport, Config, GroupKind, Miner, Mode, ModulusSource, PayloadKind, Simulation,
+};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
 use std::thread;
-use std::thread::sleep;
-use std::time::Duration;
-use uuid::Uuid;
-
-const NUM_MINERS: usize = 5;
-const NUM_BRIDGES: usize = 5;
-const NUM_USERS: usize = 15;
-
-// NOTE: Ensure that sum of USERS_ASSIGNED_TO_BRIDGE is NUM_USERS.
-const USERS_ASSIGNED_TO_BRIDGE: [usize; NUM_BRIDGES] = [3; 5];
-const BLOCK_TIME_MS: u64 = 5000;
-
-fn new_queue<T: Clone>() -> (BroadcastSender<T>, BroadcastReceiver<T>) {
-    broadcast_queue(256)
-}
-
-/// Runs our simulation over some group `G`.
-pub fn run_simulation<G: UnknownOrderGroup>() {
-    println!("Simulation starting.");
-    let mut simulation_threads = Vec::new();
-    let (block_sender, block_receiver) = new_queue();
-    let (tx_sender, tx_receiver) = new_queue();
-
-    // Initialize genesis user data (each user has a single UTXO).
-    let mut user_utxos = Vec::new();
-    for user_id in 0..NUM_USERS {
-        let user_utxo = Utxo {
-            id: Uuid::new_v4(),
-            user_id,
+use tracing::{error, info, warn};
+
+/// Runs our simulation over some group `G` until it is stopped or reaches the bounds in `config`.
+/// `builder` carries any hooks to run it with.
+pub fn run_simulation<G: UnknownOrderGroup>(
+    mut config: Config,
+    builder: Builder<G>,
+) -> RunResult<G> {
+    info!("Simulation starting.");
+    // The dashboard owns the terminal while it is up: it takes the operator's keys in place of the
+    // console, and the summary waits until it has closed.
+    let print_summary = config.dashboard && !config.quiet;
+    config.quiet |= config.dashboard;
+    let (command_sender, command_receiver) = mpsc::channel();
+    if !config.dashboard {
+        spawn_operator_console(command_sender);
+    }
+    let simulation = builder.config(config.clone()).build();
+    control::listen(simulation.control(), command_receiver);
+    if let Some(addr) = &config.prometheus_addr {
+        serve_prometheus(addr, simulation.metrics());
+    }
+    let result = {
+        let _dashboard = start_dashboard(&config, simulation.metrics(), simulation.control());
+        simulation.wait()
+    };
+    if print_summary {
+        println!("{}", result.summary);
+    }
+    info!("Simulation exiting at block {}.", result.block_height);
+    result
+}
+
+fn serve_prometheus(addr: &str, metrics: Arc<Metrics>) {
+    if let Err(err) = prometheus::serve(addr, metrics) {
+        error!("Failed to serve Prometheus metrics on {}: {}", addr, err);
+    }
+}
+
+/// Runs a simulation of `config` in the group it names, serving the APIs it asks for, or a
+/// vector-commitment run if that is its payload. The Merkle baseline, if asked for, runs after.
+fn run_in_group(config: Config) {
+    match config.group {
+        GroupKind::Rsa100 => run_with_apis::<Rsa100>(config),
+        GroupKind::Rsa2048 => run_with_apis::<Rsa2048>(config),
+        GroupKind::Rsa => {
+            modulus::install(&config.rsa_modulus).expect("Failed to set up the RSA modulus");
+            run_with_apis::<CustomRsa>(config)
+        }
+        GroupKind::ClassGroup => run_with_apis::<ClassGroup>(config),
+        GroupKind::Trapdoor => {
+            trapdoor::install(config.seed);
+            run_with_apis::<TrapdoorRsa>(config)
+        }
+    }
+}
+
+fn run_with_apis<G: UnknownOrderGroup>(config: Config)
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+    Witness<G, Utxo>: Serialize,
+    NonmembershipProof<G, Utxo>: Serialize,
+{
+    if config.payload == PayloadKind::VectorCommitment {
+        let quiet = config.quiet;
+        let summary = VectorDemo::<G>::new(config).run();
+        if !quiet {
+            println!("{}", summary);
+        }
+        return;
+    }
+    let baseline = if config.merkle_baseline {
+        Some(config.clone())
+    } else {
+        None
+    };
+    let builder = serve_miner_apis(Simulation::builder(), &config);
+    let builder = serve_bridge_apis(builder, &config);
+    run_simulation::<G>(config, builder);
+    if let Some(config) = baseline {
+        for summary in baseline::compare_with_merkle::<G>(&config) {
+            if !config.quiet {
+                println!("{}", summary);
+            }
+        }
+    }
+}
+
+/// Runs `scenario` in the group its config names.
+fn run_scenario(scenario: Scenario) {
+    match scenario.config.group {
+        GroupKind::Rsa100 => {
+            ScenarioRunner::<Rsa100>::new(scenario).run();
+        }
+        GroupKind::Rsa2048 => {
+            ScenarioRunner::<Rsa2048>::new(scenario).run();
+        }
+        GroupKind::Rsa => {
+            modulus::install(&scenario.config.rsa_modulus)
+                .expect("Failed to set up the RSA modulus");
+            ScenarioRunner::<CustomRsa>::new(scenario).run();
+        }
+        GroupKind::ClassGroup => {
+            ScenarioRunner::<ClassGroup>::new(scenario).run();
+        }
+        GroupKind::Trapdoor => {
+            trapdoor::install(scenario.config.seed);
+            ScenarioRunner::<TrapdoorRsa>::new(scenario).run();
+        }
+    }
+}
+
+/// Serves the gRPC and JSON-RPC APIs on each miner as it starts, each at consecutive ports from
+/// its address in `config`.
+fn serve_miner_apis<G: UnknownOrderGroup>(builder: Builder<G>, config: &Config) -> Builder<G>
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+{
+    let grpc_addr = parse_addr("gRPC", &config.grpc_addr);
+    let jsonrpc_addr = parse_addr("JSON-RPC", &config.jsonrpc_addr);
+    if grpc_addr.is_none() && jsonrpc_addr.is_none() {
+        return builder;
+    }
+    if config.mode != Mode::RealTime {
+        warn!("Miners only serve their APIs in real-time mode.");
+        return builder;
+    }
+    builder.on_miner_started(move |miner_id, miner| {
+        let port_for = |addr: SocketAddr| {
+            let mut miner_addr = addr;
+            miner_addr.set_port(addr.port() + miner_id as u16);
+            miner_addr
         };
-        user_utxos.push(user_utxo);
-    }
-
-    let mut init_acc = Accumulator::<G, Utxo>::empty();
-    println!("initial empty accumulator: {:#?}\n", init_acc);
-    init_acc = init_acc.add(&user_utxos);
-    println!("initial accumulator with {} utxo sets: {:#?}\n", NUM_USERS, init_acc);
-
-    // Compute initial user witnesses.
-    let mut user_witnesses = Vec::new();
-    let witness_all = Witness(Accumulator::<G, Utxo>::empty());
-    println!("initial empty witness_all: {:#?}\n", witness_all);
-    for user_utxo in &user_utxos {
-        let user_witness = witness_all
-            .clone()
-            .compute_subset_witness(&user_utxos, &[user_utxo.clone()])
-            .unwrap();
-        user_witnesses.push(user_witness.clone());
-
-        // check if 'user_witness' add 'user_utxo' equal 'init_acc'
-        assert_eq!(user_witness.0.add(&[user_utxo.clone()]), init_acc);
-    }
-    println!("initial user witness: {:#?}\n", user_witnesses);
-
-    // Initialize bridge threads, each of which manages witnesses for a number of users.
-    let mut user_idx = 0;
-    #[allow(clippy::needless_range_loop)]
-    for bridge_idx in 0..NUM_BRIDGES {
-        let (witness_request_sender, witness_request_receiver) = new_queue();
-        let mut witness_response_senders = HashMap::new();
-        let mut utxo_update_senders = HashMap::new();
-
-        let num_users_for_bridge = USERS_ASSIGNED_TO_BRIDGE[bridge_idx];
-        let user_elem_witnesses: Vec<(Utxo, Witness<G, Utxo>)> = user_utxos
-            [user_idx..user_idx + num_users_for_bridge]
-            .iter()
-            .zip(user_witnesses[user_idx..user_idx + num_users_for_bridge].iter())
-            .map(|(elem, witness)| (elem.clone(), witness.clone()))
-            .collect();
-        println!("bridge {} - user_elem_witnesses: {:#?}\n", bridge_idx, user_elem_witnesses);
-
-        // Bridge initial witness is the accumulator without bridge users's utxo sets
-        let bridge_init_witness = Witness(init_acc.clone().delete(&user_elem_witnesses).unwrap());
-        {   // Verify
-            let mut utxos = Vec::new();
-            for user_id in 0..NUM_USERS {
-                match user_id {
-                    d if d >= user_idx && d < (user_idx + num_users_for_bridge) => (),
-                    _ => utxos.push(user_utxos[user_id].clone()),
+        if let Some(addr) = grpc_addr {
+            serve_grpc(port_for(addr), miner);
+        }
+        if let Some(addr) = jsonrpc_addr {
+            let addr = port_for(addr);
+            if let Err(err) = jsonrpc::serve(addr, Arc::clone(miner)) {
+                error!("Failed to serve JSON-RPC on {}: {}", addr, err);
+            }
+        }
+    })
+}
+
+/// Serves the REST API on each bridge as it starts, at consecutive ports from its address in
+/// `config`.
+fn serve_bridge_apis<G: UnknownOrderGroup>(builder: Builder<G>, config: &Config) -> Builder<G>
+where
+    Accumulator<G, Utxo>: Serialize,
+    Witness<G, Utxo>: Serialize,
+    NonmembershipProof<G, Utxo>: Serialize,
+{
+    let rest_addr = match parse_addr("REST", &config.rest_addr) {
+        Some(addr) => addr,
+        None => return builder,
+    };
+    if config.mode != Mode::RealTime {
+        warn!("Bridges only serve their APIs in real-time mode.");
+        return builder;
+    }
+    builder.on_bridge_started(move |bridge_id, bridge| {
+        let mut addr = rest_addr;
+        addr.set_port(rest_addr.port() + bridge_id as u16);
+        if let Err(err) = rest::serve(addr, Arc::clone(bridge)) {
+            error!("Failed to serve the REST API on {}: {}", addr, err);
+        }
+    })
+}
+
+fn parse_addr(api: &str, addr: &Option<String>) -> Option<SocketAddr> {
+    let addr = addr.as_ref()?;
+    match addr.parse() {
+        Ok(addr) => Some(addr),
+        Err(err) => {
+            error!("Failed to parse {} address {}: {}", api, addr, err);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn serve_grpc<G: UnknownOrderGroup>(addr: SocketAddr, miner: &Arc<Miner<G, Utxo>>)
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+{
+    grpc::serve(addr, Arc::clone(miner));
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc<G: UnknownOrderGroup>(_: SocketAddr, _: &Arc<Miner<G, Utxo>>) {
+    warn!("Built without the grpc feature, so miners serve no gRPC API.");
+}
+
+#[cfg(feature = "tui")]
+fn start_dashboard(
+    config: &Config,
+    metrics: Arc<Metrics>,
+    control: Arc<RunControl>,
+) -> Option<Dashboard> {
+    if !config.dashboard {
+        return None;
+    }
+    match Dashboard::start(metrics, control) {
+        Ok(dashboard) => Some(dashboard),
+        Err(err) => {
+            error!("Failed to start the dashboard: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn start_dashboard(config: &Config, _: Arc<Metrics>, _: Arc<RunControl>) -> Option<()> {
+    if config.dashboard {
+        warn!("Built without the tui feature, so there is no dashboard.");
+    }
+    None
+}
+
+/// Forwards `pause`, `resume`, `step` and `stop` commands typed on stdin to the simulation.
+fn spawn_operator_console(commands: Sender<Command>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            match Command::parse(&line) {
+                Some(command) => {
+                    if commands.send(command).is_err() {
+                        break;
+                    }
                 }
+                None => warn!(
+                    "Unknown command {:?}; try pause, resume, step or stop.",
+                    line
+                ),
             }
-            let mut acc = Accumulator::<G, Utxo>::empty();
-            acc = acc.add(&utxos);
-            assert_eq!(acc, bridge_init_witness.0);
-        }
-        println!("bridge {} - init_witness: {:#?}\n", bridge_idx, bridge_init_witness);
-
-        let bridge_utxo_set: Vec<Utxo> =
-            user_utxos[user_idx..user_idx + num_users_for_bridge].to_vec();
-
-        // Initialize configurable user threads per bridge.
-        for _ in 0..num_users_for_bridge {
-            let user_utxo = user_utxos[user_idx].clone();
-
-            // Associate user IDs with RPC response channels.
-            let (witness_response_sender, witness_response_receiver) = new_queue();
-            let (utxo_update_sender, utxo_update_receiver) = new_queue();
-            witness_response_senders.insert(user_idx, witness_response_sender);
-            utxo_update_senders.insert(user_idx, utxo_update_sender);
-
-            let witness_request_sender = witness_request_sender.clone();
-            let tx_sender = tx_sender.clone();
-            simulation_threads.push(thread::spawn(move || {
-                User::start(
-                    user_idx,
-                    bridge_idx,
-                    user_utxo,
-                    &witness_request_sender,
-                    &witness_response_receiver,
-                    &utxo_update_receiver,
-                    &tx_sender,
-                );
-            }));
-            user_idx += 1;
-        }
-
-        let block_receiver = block_receiver.add_stream();
-        simulation_threads.push(thread::spawn(move || {
-            Bridge::<G>::start(
-                bridge_idx,
-                bridge_init_witness,
-                bridge_utxo_set,
-                block_receiver,
-                witness_request_receiver,
-                witness_response_senders,
-                utxo_update_senders,
+        }
+    });
+}
+
+/// Runs a cluster of node processes on this machine, from the arguments after `cluster`:
+/// `--miners <n> --bridges <n> --users <n> [--base-port <port>] [--output <dir>] [--blocks <n>]
+/// [--duration-ms <ms>] [--seed <seed>] [--log <filter>] [--quic] [-q]`.
+fn run_cluster<I: Iterator<Item = String>>(mut args: I) {
+    let mut config = Config::default();
+    let mut num_bridges = config.num_bridges();
+    let mut num_users = config.num_users();
+    let mut base_port = 7000;
+    let mut output_dir = PathBuf::from("cluster");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--miners" => config.num_miners = next_number(&mut args, &arg, "a miner count"),
+            "--bridges" => num_bridges = next_number(&mut args, &arg, "a bridge count"),
+            "--users" => num_users = next_number(&mut args, &arg, "a user count"),
+            "--base-port" => base_port = next_number(&mut args, &arg, "a port"),
+            "--blocks" => config.max_blocks = Some(next_number(&mut args, &arg, "a block count")),
+            "--duration-ms" => {
+                config.max_duration_ms =
+                    Some(next_number(&mut args, &arg, "a number of milliseconds"))
+            }
+            "--seed" => config.seed = next_number(&mut args, &arg, "a number"),
+            "--output" => output_dir = args.next().expect("--output takes a directory").into(),
+            "--log" => config.log_filter = args.next().expect("--log takes a filter"),
+            "--quic" => config.transport = ClusterTransport::Quic,
+            "-q" | "--quiet" => config.quiet = true,
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+    assert!(
+        num_bridges > 0 && num_users >= num_bridges,
+        "A cluster needs at least one bridge and a user for each"
+    );
+    config.users_per_bridge = launcher::users_per_bridge(num_users, num_bridges);
+    init_output(config.quiet, &config.log_filter);
+    let cluster =
+        LocalCluster::new(config, output_dir, base_port).expect("Failed to find the node binaries");
+    match cluster.run() {
+        Ok(statuses) => {
+            let failed = statuses
+                .iter()
+                .filter(|(_, status)| !status.success())
+                .count();
+            info!(
+                "{} processes exited, {} with errors.",
+                statuses.len(),
+                failed
             );
-        }));
-    }
-
-    println!("Sleeping so bridges can start up before miner.");
-    sleep(Duration::from_millis(2000));
-
-    // Initialize miner threads.
-    for miner_idx in 0..NUM_MINERS {
-        // These clones cannot go inside the thread closure, since the variable being cloned would get
-        // swallowed by the move (see below as well).
-        let init_acc = init_acc.clone();
-        let block_sender = block_sender.clone();
-        let block_receiver = block_receiver.add_stream();
-        let tx_receiver = tx_receiver.add_stream();
-        simulation_threads.push(thread::spawn(move || {
-            Miner::<G, Utxo>::start(
-                miner_idx == 0, // Elect first miner as leader.
-                init_acc,
-                BLOCK_TIME_MS,
-                &block_sender,
-                block_receiver,
-                tx_receiver,
-            )
-        }));
-    }
-
-    tx_receiver.unsubscribe();
-    println!("Simulation running.");
-    simulation_threads.push(thread::spawn(move || loop {
-        match block_receiver.try_recv() {
-            Ok(block) => println!(
-                "Block {} has {} transactions.",
-                block.height,
-                block.transactions.len()
-            ),
-            Err(_) => (),
-        }
-        sleep(Duration::from_millis(10));
-    }));
-    for thread in simulation_threads {
-        thread.join().unwrap();
-    }
-    println!("Simulation exiting.");
+        }
+        Err(err) => error!("Cluster failed: {}", err),
+    }
+}
+
+/// Profiles the standard workload, from the arguments after `profile`:
+/// `[--output <dir>] [--blocks <n>] [--frequency <hz>] [--log <filter>] [-q]`.
+#[cfg(feature = "profiling")]
+fn run_profile<I: Iterator<Item = String>>(mut args: I) {
+    let mut output_dir = PathBuf::from("profile");
+    let mut blocks = profile::DEFAULT_BLOCKS;
+    let mut frequency = profile::DEFAULT_FREQUENCY;
+    let mut log_filter = Config::default().log_filter;
+    let mut quiet = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output_dir = args.next().expect("--output takes a directory").into(),
+            "--blocks" => blocks = next_number(&mut args, &arg, "a block count"),
+            "--frequency" => {
+                frequency = next_number(&mut args, &arg, "a number of samples a second")
+            }
+            "--log" => log_filter = args.next().expect("--log takes a filter"),
+            "-q" | "--quiet" => quiet = true,
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+    init_output(quiet, &log_filter);
+    match profile::run(profile::workload(blocks), &output_dir, frequency) {
+        Ok(summary) => {
+            if !quiet {
+                println!("{}", summary);
+            }
+        }
+        Err(err) => error!("Profiling failed: {}", err),
+    }
+}
+
+#[cfg(not(feature = "profiling"))]
+fn run_profile<I: Iterator<Item = String>>(_args: I) {
+    panic!("profile needs the profiling feature: cargo run --features profiling -- profile");
+}
+
+/// Prints a diagram, from the arguments after `diagram`: `[--scenario <file>] [--output <file>]`
+/// for the topology of the default run or a scenario's, as Graphviz DOT, or `--events <file>
+/// (--trace <id> | --block <height>) [--output <file>]` for the messages of a spend or a block in
+/// an event log, as a Mermaid sequence diagram.
+fn run_diagram<I: Iterator<Item = String>>(mut args: I) {
+    let mut config = Config::default();
+    let mut events_path = None;
+    let mut selection = None;
+    let mut output_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scenario" => {
+                let path = args.next().expect("--scenario takes a file path");
+                config = Scenario::from_file(&path)
+                    .expect("Failed to load scenario")
+                    .config
+            }
+            "--events" => events_path = Some(args.next().expect("--events takes a file path")),
+            "--trace" => {
+                selection = Some(Selection::Trace(next_number(&mut args, &arg, "a trace ID")))
+            }
+            "--block" => {
+                selection = Some(Selection::Block(next_number(&mut args, &arg, "a height")))
+            }
+            "--output" => output_path = Some(args.next().expect("--output takes a file path")),
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+    let diagram = match (events_path, selection) {
+        (None, None) => diagram::topology_dot(&config),
+        (Some(path), Some(selection)) => {
+            let log = EventLog::from_file(&path).expect("Failed to load event log");
+            diagram::sequence_mermaid(&log, selection)
+                .unwrap_or_else(|| panic!("No events of {:?} in {}", selection, path))
+        }
+        (Some(_), None) => panic!("--events takes --trace or --block to pick what to draw"),
+        (None, Some(_)) => panic!("--trace and --block take --events to draw from"),
+    };
+    match output_path {
+        Some(path) => fs::write(&path, diagram).expect("Failed to write the diagram"),
+        None => print!("{}", diagram),
+    }
+}
+
+fn next_number<T: FromStr, I: Iterator<Item = String>>(args: &mut I, flag: &str, what: &str) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("{} takes {}", flag, what))
 }
 
 pub fn main() {
-    run_simulation::<Rsa100>();
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("cluster") {
+        args.next();
+        run_cluster(args);
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("profile") {
+        args.next();
+        run_profile(args);
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("diagram") {
+        args.next();
+        run_diagram(args);
+        return;
+    }
+    let mut config = Config::default();
+    let mut scenario_path = None;
+    let mut experiment_path = None;
+    let mut sweep_path = None;
+    let mut log_filter = None;
+    let mut quiet = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--discrete" => config.mode = Mode::DiscreteEvent,
+            "--vector" => config.payload = PayloadKind::VectorCommitment,
+            "--trapdoor" => config.group = GroupKind::Trapdoor,
+            "--modulus-bits" => {
+                let bits = args
+                    .next()
+                    .and_then(|bits| bits.parse().ok())
+                    .expect("--modulus-bits takes a number of bits");
+                config.group = GroupKind::Rsa;
+                config.rsa_modulus = ModulusSource::Generate(bits);
+            }
+            "--merkle-baseline" => config.merkle_baseline = true,
+            "--log" => log_filter = Some(args.next().expect("--log takes a filter")),
+            "-v" | "--verbose" => log_filter = Some("debug".to_string()),
+            "-vv" => log_filter = Some("trace".to_string()),
+            "-q" | "--quiet" => quiet = true,
+            "--tui" if cfg!(feature = "tui") => config.dashboard = true,
+            "--tui" => panic!("--tui needs the tui feature: cargo run --features tui -- --tui"),
+            "--scenario" => {
+                scenario_path = Some(args.next().expect("--scenario takes a file path"))
+            }
+            "--sweep" => sweep_path = Some(args.next().expect("--sweep takes a file path")),
+            "--experiment" => {
+                experiment_path = Some(args.next().expect("--experiment takes a file path"))
+            }
+            "--output" => {
+                config.output_dir = Some(args.next().expect("--output takes a directory").into())
+            }
+            "--checkpoint" => {
+                config.checkpoint_output =
+                    Some(args.next().expect("--checkpoint takes a file path").into())
+            }
+            "--restore" => {
+                config.restore_from = Some(args.next().expect("--restore takes a file path").into())
+            }
+            "--record" => {
+                config.trace_output = Some(args.next().expect("--record takes a file path").into())
+            }
+            "--replay" => {
+                config.replay_trace = Some(args.next().expect("--replay takes a file path").into())
+            }
+            "--viz" => {
+                config.viz_output = Some(args.next().expect("--viz takes a file path").into())
+            }
+            "--events" => {
+                config.event_stream = Some(args.next().expect("--events takes a file path").into())
+            }
+            "--prometheus" => {
+                config.prometheus_addr = Some(args.next().expect("--prometheus takes an address"))
+            }
+            "--grpc" if cfg!(feature = "grpc") => {
+                config.grpc_addr = Some(args.next().expect("--grpc takes an address"))
+            }
+            "--grpc" => {
+                panic!("--grpc needs the grpc feature: cargo run --features grpc -- --grpc")
+            }
+            "--jsonrpc" => {
+                config.jsonrpc_addr = Some(args.next().expect("--jsonrpc takes an address"))
+            }
+            "--rest" => config.rest_addr = Some(args.next().expect("--rest takes an address")),
+            "--websocket" => {
+                config.websocket_addr = Some(args.next().expect("--websocket takes an address"))
+            }
+            "--blocks" => {
+                config.max_blocks = args
+                    .next()
+                    .and_then(|blocks| blocks.parse().ok())
+                    .map(Some)
+                    .expect("--blocks takes a block count")
+            }
+            "--duration-ms" => {
+                config.max_duration_ms = args
+                    .next()
+                    .and_then(|ms| ms.parse().ok())
+                    .map(Some)
+                    .expect("--duration-ms takes a number of milliseconds")
+            }
+            "--speedup" => {
+                config.speedup = args
+                    .next()
+                    .and_then(|speedup| speedup.parse().ok())
+                    .expect("--speedup takes a positive integer")
+            }
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+
+    if let Some(path) = sweep_path {
+        let sweep = Sweep::from_file(&path).expect("Failed to load sweep");
+        init_output(quiet, log_filter.as_ref().unwrap_or(&config.log_filter));
+        info!("Running sweep {}.", path);
+        let result = sweep.run();
+        if !quiet {
+            println!("{}", result);
+        }
+        return;
+    }
+
+    if let Some(path) = experiment_path {
+        let experiment = Experiment::from_file(&path).expect("Failed to load experiment");
+        init_output(quiet, log_filter.as_ref().unwrap_or(&config.log_filter));
+        info!("Running experiment {}.", path);
+        let comparison = experiment.run();
+        if !quiet {
+            println!("{}", comparison);
+        }
+        return;
+    }
+
+    match scenario_path {
+        Some(path) => {
+            let mut scenario = Scenario::from_file(&path).expect("Failed to load scenario");
+            scenario.config.quiet |= quiet;
+            let config = &scenario.config;
+            init_run_output(
+                config,
+                config.quiet,
+                log_filter.as_ref().unwrap_or(&config.log_filter),
+            );
+            info!("Running scenario {}.", path);
+            run_scenario(scenario);
+        }
+        None => {
+            config.quiet |= quiet;
+            // Log output would garble the dashboard.
+            init_run_output(
+                &config,
+                config.quiet || config.dashboard,
+                log_filter.as_ref().unwrap_or(&config.log_filter),
+            );
+            run_in_group(config);
+        }
+    }
+}
+
+/// Installs log output filtered by `log_filter`, unless the run is to be silent.
+fn init_output(quiet: bool, log_filter: &str) {
+    if !quiet {
+        logging::init(log_filter);
+    }
+}
+
+/// Installs log output for a run of `config` as `init_output` does, on standard error if the run
+/// streams its events to standard output, so the stream is not interleaved with log lines.
+fn init_run_output(config: &Config, quiet: bool, log_filter: &str) {
+    let events_on_stdout = config.event_stream.as_deref() == Some(Path::new(EventSink::STDOUT));
+    if !quiet && events_on_stdout {
+        logging::init_on_stderr(log_filter);
+    } else {
+        init_output(quiet, log_filter);
+    }
 }
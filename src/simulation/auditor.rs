@@ -0,0 +1,134 @@
+use super::state::{Block, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// An accumulator that does not hold what the chain says it should.
+pub enum AuditError {
+    /// Miner `miner_id`'s accumulator as of `height` is not that of the UTXO set at that height.
+    Mismatch { miner_id: usize, height: u64 },
+    /// The block at `height` spends a UTXO that was not in the set.
+    UnknownSpend { height: u64, utxo: Utxo },
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::Mismatch { miner_id, height } => write!(
+                f,
+                "Miner {}'s accumulator at block {} does not match its UTXO set",
+                miner_id, height
+            ),
+            AuditError::UnknownSpend { height, utxo } => write!(
+                f,
+                "Block {} spends {:?}, which is not in the UTXO set",
+                height, utxo
+            ),
+        }
+    }
+}
+
+impl Error for AuditError {}
+
+/// Follows the chain as a plain UTXO set, with no accumulator of its own, and every `interval`
+/// blocks recomputes the accumulator from scratch by adding the whole set to an empty one, to
+/// check that the miners' accumulator, carried along by updates and their proofs, still holds
+/// exactly the UTXOs it should. Recomputing takes a hash to a prime and an exponentiation per
+/// UTXO, so this is a check for long runs and refactors rather than something a node would do.
+pub struct Auditor<G: UnknownOrderGroup> {
+    interval: u64,
+    height: u64,
+    last_audit: u64,
+    utxos: HashSet<Utxo>,
+    _group: PhantomData<G>,
+}
+
+impl<G: UnknownOrderGroup> Auditor<G> {
+    /// An auditor following the chain from `base_height`, where the UTXO set is `base_utxos`.
+    pub fn new(interval: u64, base_height: u64, base_utxos: Vec<Utxo>) -> Self {
+        Self {
+            interval: interval.max(1),
+            height: base_height,
+            last_audit: base_height,
+            utxos: base_utxos.into_iter().collect(),
+            _group: PhantomData,
+        }
+    }
+
+    /// The height of the last block the auditor has followed.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn utxos(&self) -> &HashSet<Utxo> {
+        &self.utxos
+    }
+
+    /// Applies each of `blocks` that extends the chain the auditor has followed, in order, to its
+    /// UTXO set. Blocks at or below its height are skipped, and so is everything after a gap.
+    pub fn follow(&mut self, blocks: &[Block<G, Utxo>]) -> Result<(), AuditError> {
+        for block in blocks {
+            if block.height <= self.height {
+                continue;
+            }
+            if block.height != self.height + 1 {
+                break;
+            }
+            for transaction in &block.transactions {
+                for (utxo, _) in &transaction.utxos_spent_with_witnesses {
+                    if !self.utxos.remove(utxo) {
+                        return Err(AuditError::UnknownSpend {
+                            height: block.height,
+                            utxo: utxo.clone(),
+                        });
+                    }
+                }
+                self.utxos.extend(transaction.utxos_created.iter().cloned());
+            }
+            self.height = block.height;
+        }
+        Ok(())
+    }
+
+    /// Whether the auditor has followed `interval` blocks since its last audit.
+    pub fn is_due(&self) -> bool {
+        self.height >= self.last_audit + self.interval
+    }
+
+    /// Recomputes the accumulator of the UTXO set at the auditor's height, and checks it against
+    /// `accs`, the accumulators as of that height of the miners that have one.
+    pub fn audit<'a, I>(&mut self, accs: I) -> Result<(), AuditError>
+    where
+        I: IntoIterator<Item = (usize, &'a Accumulator<G, Utxo>)>,
+        G: 'a,
+    {
+        let start = Instant::now();
+        let utxos: Vec<Utxo> = self.utxos.iter().cloned().collect();
+        let expected = Accumulator::<G, Utxo>::empty().add(&utxos);
+        let mut miners = 0;
+        for (miner_id, acc) in accs {
+            if *acc != expected {
+                return Err(AuditError::Mismatch {
+                    miner_id,
+                    height: self.height,
+                });
+            }
+            miners += 1;
+        }
+        self.last_audit = self.height;
+        info!(
+            "Audited block {}: {} miners agree with the {} UTXOs, recomputed in {:?}.",
+            self.height,
+            miners,
+            utxos.len(),
+            start.elapsed()
+        );
+        Ok(())
+    }
+}
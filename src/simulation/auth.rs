@@ -0,0 +1,186 @@
+use super::injection::FaultInjector;
+use super::noise::NoiseKeys;
+use super::state::NodeId;
+use super::util;
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier as _};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The environment variable holding a cluster deployment's Noise pre-shared key.
+pub const NOISE_PSK_VAR: &str = "ACCUMULATOR_NOISE_PSK";
+
+/// The prefix of the environment variables holding components' Noise static keys, followed by
+/// the component, e.g. `ACCUMULATOR_NOISE_KEY_MINER_0`.
+pub const NOISE_KEY_VAR_PREFIX: &str = "ACCUMULATOR_NOISE_KEY_";
+
+/// The signing keys of every component of a run, derived from the run's seed, so that each
+/// process of a deployment can check any other's signatures without exchanging keys first.
+///
+/// The seed is in the config and is no secret, so neither are the keys: anyone can sign as any
+/// component. The keyring only keeps apart deployments that were started with different seeds and
+/// reach each other by accident, and lets the simulation model components that lie about who they
+/// are. It is not a defense against an attacker, which would take per-node secret keys and
+/// distributed public keys.
+pub struct Keyring {
+    secret: [u8; 32],
+}
+
+impl Keyring {
+    /// The keyring of a run with the given seed.
+    pub fn new(seed: u64) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"accumulator-demo keyring");
+        hasher.update(seed.to_be_bytes());
+        Self {
+            secret: hasher.finalize().into(),
+        }
+    }
+
+    /// A key derived from the secret for `node`'s use as `purpose`.
+    fn derive(&self, purpose: &[u8], node: Option<NodeId>) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.secret);
+        hasher.update(purpose);
+        if let Some(node) = node {
+            let (kind, id) = match node {
+                NodeId::Miner(id) => (0u8, id),
+                NodeId::Bridge(id) => (1, id),
+                NodeId::User(id) => (2, id),
+            };
+            hasher.update([kind]);
+            hasher.update((id as u64).to_be_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    fn signing_key(&self, node: NodeId) -> SigningKey {
+        SigningKey::from_bytes(&self.derive(b"", Some(node)))
+    }
+
+    /// `node`'s keys for encrypted connections, derived from the seed like the signing keys: a
+    /// static key of its own, and the pre-shared key of the whole run. Only for runs meant to be
+    /// reproducible; deployments load keys with `load_noise_keys`.
+    pub fn noise_keys(&self, node: NodeId) -> NoiseKeys {
+        NoiseKeys {
+            private_key: self.derive(b"noise static", Some(node)),
+            psk: self.derive(b"noise psk", None),
+        }
+    }
+
+    /// `message`, signed with `node`'s key.
+    pub fn sign(&self, node: NodeId, message: &[u8]) -> Signature {
+        self.signing_key(node).sign(message)
+    }
+
+    /// Whether `signature` is `sender`'s on `message`.
+    pub fn verify(&self, sender: NodeId, message: &[u8], signature: &Signature) -> bool {
+        self.signing_key(sender)
+            .verifying_key()
+            .verify(message, signature)
+            .is_ok()
+    }
+}
+
+/// `node`'s keys for encrypted connections, each 64 hex digits in the environment or in a file of
+/// `dir`. The pre-shared key is in `ACCUMULATOR_NOISE_PSK` or `dir/psk`, and every process of a
+/// deployment needs the same one. `node`'s static key is in `ACCUMULATOR_NOISE_KEY_MINER_0` or
+/// `dir/miner-0.key` for miner 0, and likewise for bridges and users. Peers do not pin each
+/// other's static keys, so a component without one gets a fresh random key.
+pub fn load_noise_keys(node: NodeId, dir: Option<&Path>) -> io::Result<NoiseKeys> {
+    let psk = read_key(NOISE_PSK_VAR, dir.map(|dir| dir.join("psk")))?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no pre-shared key in ${} or a key directory", NOISE_PSK_VAR),
+        )
+    })?;
+    let name = key_name(node);
+    let var = format!(
+        "{}{}",
+        NOISE_KEY_VAR_PREFIX,
+        name.to_uppercase().replace('-', "_")
+    );
+    let private_key = read_key(&var, dir.map(|dir| dir.join(format!("{}.key", name))))?
+        .unwrap_or_else(random_key);
+    Ok(NoiseKeys { private_key, psk })
+}
+
+/// A key in environment variable `var`, or else in the file at `path`, if either has one.
+fn read_key(var: &str, path: Option<PathBuf>) -> io::Result<Option<[u8; 32]>> {
+    let (hex, source) = match (env::var(var), path) {
+        (Ok(hex), _) => (hex, format!("${}", var)),
+        (Err(_), Some(path)) if path.exists() => {
+            (fs::read_to_string(&path)?, path.display().to_string())
+        }
+        _ => return Ok(None),
+    };
+    match util::from_hex(hex.trim()) {
+        Some(bytes) if bytes.len() == 32 => {
+            let mut key = [0; 32];
+            key.copy_from_slice(&bytes);
+            Ok(Some(key))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not hold a key of 64 hex digits", source),
+        )),
+    }
+}
+
+/// Writes `key` to `path` as `load_noise_keys` reads it, readable only by its owner on Unix.
+pub fn write_key(path: &Path, key: &[u8; 32]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    writeln!(options.open(path)?, "{}", util::to_hex(key))
+}
+
+/// A fresh random key, from a generator seeded by the operating system.
+pub fn random_key() -> [u8; 32] {
+    let mut key = [0; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
+
+/// What a component's key files and variables are named after, e.g. `miner-0`.
+fn key_name(node: NodeId) -> String {
+    match node {
+        NodeId::Miner(id) => format!("miner-{}", id),
+        NodeId::Bridge(id) => format!("bridge-{}", id),
+        NodeId::User(id) => format!("user-{}", id),
+    }
+}
+
+/// Signs what one component sends with its own key, naming it as the sender unless a
+/// `forge_sender` fault has it claim to be another component.
+pub struct Signer {
+    node: NodeId,
+    keyring: Arc<Keyring>,
+    faults: Arc<FaultInjector>,
+}
+
+impl Signer {
+    pub fn new(node: NodeId, keyring: Arc<Keyring>, faults: Arc<FaultInjector>) -> Self {
+        Self {
+            node,
+            keyring,
+            faults,
+        }
+    }
+
+    /// The sender to name on `message`, and the signature on it.
+    pub fn sign(&self, message: &[u8]) -> (NodeId, Signature) {
+        (
+            self.faults.claimed_sender(self.node),
+            self.keyring.sign(self.node, message),
+        )
+    }
+}
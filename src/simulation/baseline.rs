@@ -0,0 +1,255 @@
+use super::commitment::{SetCommitment, WitnessStore};
+use super::config::Config;
+use super::genesis::GENESIS_VALUE;
+use super::merkle::MerkleTree;
+use super::network::UTXO_BYTES;
+use super::state::Utxo;
+use super::util;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Chance that a user spends a UTXO in any one block interval.
+const SPEND_PROBABILITY: f64 = 0.5;
+
+#[derive(Clone, Debug, Default)]
+/// Totals for a finished baseline run with one commitment backend.
+pub struct BaselineSummary {
+    pub backend: &'static str,
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Estimated bytes of every block: the commitment, the update proof and the transactions with
+    /// their witnesses.
+    pub block_bytes: u64,
+    /// The part of `block_bytes` that is update proofs.
+    pub proof_bytes: u64,
+    /// Estimated bytes of the witnesses bridges handed out to users.
+    pub witness_bytes: u64,
+    /// The most any miner or bridge kept to update the commitment or serve witnesses, in bytes.
+    pub max_miner_state_bytes: u64,
+    pub max_bridge_state_bytes: u64,
+    pub forge_time: Duration,
+    pub verification_time: Duration,
+    pub witness_time: Duration,
+    /// Witnesses and blocks that did not verify, and bridges that could not apply a block.
+    pub failures: u64,
+}
+
+impl fmt::Display for BaselineSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Baseline summary ({})", self.backend)?;
+        writeln!(f, "  blocks:                  {}", self.blocks)?;
+        writeln!(f, "  transactions:            {}", self.transactions)?;
+        writeln!(
+            f,
+            "  block bytes:             {}, {} of them proofs",
+            self.block_bytes, self.proof_bytes
+        )?;
+        writeln!(f, "  witness bytes:           {}", self.witness_bytes)?;
+        writeln!(
+            f,
+            "  state bytes:             {} per miner, {} per bridge at most",
+            self.max_miner_state_bytes, self.max_bridge_state_bytes
+        )?;
+        writeln!(
+            f,
+            "  block time:              {:.1?} mean, {:.1?} to verify",
+            mean(self.forge_time, self.blocks),
+            mean(self.verification_time, self.blocks)
+        )?;
+        writeln!(
+            f,
+            "  witness time:            {:.1?} mean",
+            mean(self.witness_time, self.transactions)
+        )?;
+        write!(f, "  failures:                {}", self.failures)
+    }
+}
+
+fn mean(total: Duration, count: u64) -> Duration {
+    if count == 0 {
+        return Duration::from_secs(0);
+    }
+    total / count as u32
+}
+
+/// A UTXO chain run against any `SetCommitment`, to compare backends on the same workload. A
+/// miner updates the commitment block by block, a bridge per entry of `users_per_bridge` keeps
+/// what its users need for witnesses, and every block interval each user spends a UTXO at random
+/// with a witness from its bridge, sending its value to a random user. Runs are stepped on a single
+/// thread, and the workload depends only on the config's seed, so runs with different backends
+/// make the same transactions.
+pub struct BaselineDemo<C: SetCommitment<Utxo>> {
+    config: Config,
+    rng: StdRng,
+    height: u64,
+    commitment: C,
+    /// The users each bridge serves, and what it keeps for their witnesses.
+    bridges: Vec<(Range<usize>, C::Store)>,
+    /// Each user's UTXOs.
+    utxos: Vec<Vec<Utxo>>,
+    summary: BaselineSummary,
+}
+
+impl<C: SetCommitment<Utxo>> BaselineDemo<C> {
+    /// Starts from a genesis where every user owns a single UTXO, as in the simulation.
+    pub fn new(config: Config) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let genesis: Vec<Utxo> = (0..config.num_users())
+            .map(|user_id| Utxo {
+                id: util::new_uuid(&mut rng),
+                user_id,
+                value: GENESIS_VALUE,
+            })
+            .collect();
+        let mut bridges = Vec::new();
+        let mut first_user = 0;
+        for num_users in &config.users_per_bridge {
+            let users = first_user..first_user + num_users;
+            let tracked = genesis[users.clone()].to_vec();
+            bridges.push((users, C::Store::new(&genesis, &tracked)));
+            first_user += num_users;
+        }
+        let summary = BaselineSummary {
+            backend: C::NAME,
+            ..BaselineSummary::default()
+        };
+        Self {
+            commitment: C::commit(&genesis),
+            utxos: genesis.iter().map(|utxo| vec![utxo.clone()]).collect(),
+            config,
+            rng,
+            height: 0,
+            bridges,
+            summary,
+        }
+    }
+
+    /// Runs until the chain reaches the bounds in the config.
+    pub fn run(mut self) -> BaselineSummary {
+        info!(
+            "Baseline run with a {} starting with {} users.",
+            C::NAME,
+            self.config.num_users()
+        );
+        if self.config.max_blocks.is_none() && self.config.max_duration_ms.is_none() {
+            warn!("The run has no bounds, so it never ends.");
+        }
+        let block_interval = Duration::from_millis(self.config.block_interval_ms);
+        let mut now = Duration::from_secs(0);
+        while !self.config.is_finished(self.height, now) {
+            self.step();
+            now += block_interval;
+        }
+        info!("Baseline run exiting at block {}.", self.height);
+        self.summary
+    }
+
+    /// Has users spend, forges a block of their transactions, and has every node apply it.
+    fn step(&mut self) {
+        let digest = self.commitment.digest();
+        let num_users = self.config.num_users();
+        let mut added = Vec::new();
+        let mut deleted = Vec::new();
+        for user_id in 0..num_users {
+            if !self.rng.gen_bool(SPEND_PROBABILITY) || self.utxos[user_id].is_empty() {
+                continue;
+            }
+            let spent = self.utxos[user_id].remove(0);
+            let recipient = self.rng.gen_range(0, num_users);
+            let created = Utxo {
+                id: util::new_uuid(&mut self.rng),
+                user_id: recipient,
+                value: spent.value,
+            };
+            let (_, store) = self
+                .bridges
+                .iter()
+                .find(|(users, _)| users.contains(&user_id))
+                .unwrap();
+            let start = Instant::now();
+            let witness = match store.witnesses(&[spent.clone()]) {
+                Ok(mut witnesses) => witnesses.remove(0).1,
+                Err(err) => {
+                    warn!("No witness to a UTXO of user {}: {:?}", user_id, err);
+                    self.summary.failures += 1;
+                    continue;
+                }
+            };
+            self.summary.witness_time += start.elapsed();
+            let witness_bytes = C::witness_bytes(&witness);
+            self.summary.witness_bytes += witness_bytes as u64;
+            if !C::verify_membership(&digest, &spent, &witness) {
+                warn!("The witness to a UTXO of user {} does not verify.", user_id);
+                self.summary.failures += 1;
+            }
+            self.summary.transactions += 1;
+            self.summary.block_bytes += (2 * UTXO_BYTES + witness_bytes) as u64;
+            added.push(created);
+            deleted.push((spent, witness));
+        }
+
+        let start = Instant::now();
+        let proof = match self.commitment.update(&added, &deleted) {
+            Ok(proof) => proof,
+            Err(err) => {
+                warn!("Failed to forge block {}: {:?}", self.height + 1, err);
+                self.summary.failures += 1;
+                return;
+            }
+        };
+        self.summary.forge_time += start.elapsed();
+        self.height += 1;
+        self.summary.blocks += 1;
+        let proof_bytes = C::proof_bytes(&proof) as u64;
+        self.summary.proof_bytes += proof_bytes;
+        self.summary.block_bytes += C::digest_bytes() as u64 + proof_bytes;
+        self.summary.max_miner_state_bytes = self
+            .summary
+            .max_miner_state_bytes
+            .max(self.commitment.state_bytes() as u64);
+
+        let new_digest = self.commitment.digest();
+        let deleted: Vec<Utxo> = deleted.into_iter().map(|(utxo, _)| utxo).collect();
+        let start = Instant::now();
+        let is_valid = C::verify_update(&digest, &new_digest, &added, &deleted, &proof);
+        self.summary.verification_time += start.elapsed();
+        if !is_valid {
+            warn!("Block {} does not prove its update.", self.height);
+            self.summary.failures += 1;
+        }
+        for (users, store) in &mut self.bridges {
+            let is_tracked = |utxo: &Utxo| users.contains(&utxo.user_id);
+            if let Err(err) = store.update(&new_digest, &added, &deleted, &is_tracked) {
+                warn!("A bridge failed to apply block {}: {:?}", self.height, err);
+                self.summary.failures += 1;
+            }
+            self.summary.max_bridge_state_bytes = self
+                .summary
+                .max_bridge_state_bytes
+                .max(store.state_bytes() as u64);
+        }
+        for utxo in added {
+            self.utxos[utxo.user_id].push(utxo);
+        }
+        debug!(
+            "Forged block {} with {} transactions.",
+            self.height,
+            deleted.len()
+        );
+    }
+}
+
+/// Runs the workload of `config` against the accumulator in group `G` and against a sparse Merkle
+/// tree, returning a summary for each.
+pub fn compare_with_merkle<G: UnknownOrderGroup>(config: &Config) -> Vec<BaselineSummary> {
+    vec![
+        BaselineDemo::<Accumulator<G, Utxo>>::new(config.clone()).run(),
+        BaselineDemo::<MerkleTree<Utxo>>::new(config.clone()).run(),
+    ]
+}
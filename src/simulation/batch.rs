@@ -0,0 +1,214 @@
+use super::deletion::DeletionProof;
+use super::state::Block;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, MembershipProof, NonmembershipProof};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "DeletionProof<G, T>: Serialize, MembershipProof<G, T>: Serialize, \
+                 NonmembershipProof<G, T>: Serialize",
+    deserialize = "DeletionProof<G, T>: Deserialize<'de>, MembershipProof<G, T>: Deserialize<'de>, \
+                   NonmembershipProof<G, T>: Deserialize<'de>"
+))]
+/// One proof for the accumulator updates of consecutive blocks, taking the place of theirs for a
+/// node catching up on them: that deleting the UTXOs the blocks spend from before them, then adding
+/// those they create and leave unspent, takes the accumulator before the first block to the one
+/// after the last. UTXOs created and spent within the blocks cancel out, so a node checks a single
+/// PoKE2, a single PoE and a single non-membership proof however many blocks it missed.
+///
+/// Only the accumulator after the last block is proven: those of the blocks before it are taken
+/// on trust, so a node applying a batch must not vouch for them.
+pub struct BatchProof<G: UnknownOrderGroup, T: Hash + Debug> {
+    pub proof_deleted: DeletionProof<G, T>,
+    pub proof_added: MembershipProof<G, T>,
+    /// A proof that none of the UTXOs the blocks create, including those they spend again, were
+    /// in the accumulator before them. It takes the place of the blocks' own proofs of that, which
+    /// are against accumulators the batch does not prove.
+    pub proof_fresh: Option<NonmembershipProof<G, T>>,
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug> BatchProof<G, T> {
+    /// Proves the updates of `blocks`, which follow `acc`, given `utxos`, every UTXO in the
+    /// accumulator after them. Transactions only carry witnesses against the accumulator of their
+    /// own block, so the accumulator without the UTXOs spent is worked out from the UTXO set.
+    pub fn prove(acc: &Accumulator<G, T>, blocks: &[Block<G, T>], utxos: &HashSet<T>) -> Self {
+        let (added, deleted) = net_changes(blocks);
+        let added_set: HashSet<&T> = added.iter().collect();
+        let remaining: Vec<T> = utxos
+            .iter()
+            .filter(|utxo| !added_set.contains(utxo))
+            .cloned()
+            .collect();
+        let acc_deleted = Accumulator::<G, T>::empty().add(&remaining);
+        let proof_deleted = DeletionProof::prove_from(acc, acc_deleted.clone(), &deleted);
+        let (_acc_new, proof_added) = acc_deleted.add_with_proof(&added);
+        let utxos_before: Vec<T> = remaining.into_iter().chain(deleted).collect();
+        let proof_fresh = acc
+            .prove_nonmembership(&utxos_before, &created(blocks))
+            .ok();
+        Self {
+            proof_deleted,
+            proof_added,
+            proof_fresh,
+        }
+    }
+
+    /// Whether this proves that `blocks` take `acc` to the accumulator of the last of them.
+    pub fn verify(&self, acc: &Accumulator<G, T>, blocks: &[Block<G, T>]) -> bool {
+        let acc_new = match blocks.last() {
+            Some(block) => &block.acc_new,
+            None => return false,
+        };
+        let (added, deleted) = net_changes(blocks);
+        self.proof_deleted.witness == self.proof_added.witness
+            && self.proof_deleted.verify(acc, &deleted)
+            && acc_new.verify_membership_batch(&added, &self.proof_added)
+    }
+}
+
+/// The UTXOs `blocks` create and leave unspent, and those they spend from before them, in order.
+/// Blocks that pass validation create and spend each UTXO at most once.
+pub fn net_changes<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+    blocks: &[Block<G, T>],
+) -> (Vec<T>, Vec<T>) {
+    let created = created(blocks);
+    let spent = spent(blocks);
+    let created_set: HashSet<&T> = created.iter().collect();
+    let spent_set: HashSet<&T> = spent.iter().collect();
+    let added = created
+        .iter()
+        .filter(|utxo| !spent_set.contains(utxo))
+        .cloned()
+        .collect();
+    let deleted = spent
+        .iter()
+        .filter(|utxo| !created_set.contains(utxo))
+        .cloned()
+        .collect();
+    (added, deleted)
+}
+
+/// Every UTXO `blocks` create, in order.
+pub fn created<G: UnknownOrderGroup, T: Clone + Hash + Debug>(blocks: &[Block<G, T>]) -> Vec<T> {
+    blocks
+        .iter()
+        .flat_map(|block| &block.transactions)
+        .flat_map(|transaction| transaction.utxos_created.iter().cloned())
+        .collect()
+}
+
+/// Every UTXO `blocks` spend, in order.
+fn spent<G: UnknownOrderGroup, T: Clone + Hash + Debug>(blocks: &[Block<G, T>]) -> Vec<T> {
+    blocks
+        .iter()
+        .flat_map(|block| &block.transactions)
+        .flat_map(|transaction| {
+            transaction
+                .utxos_spent_with_witnesses
+                .iter()
+                .map(|(utxo, _witness)| utxo.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::state::{self, Transaction, UpdateProof};
+    use crate::simulation::swap::SwapProof;
+    use accumulator::group::Rsa2048;
+    use accumulator::Witness;
+    use std::sync::Arc;
+
+    type Acc = Accumulator<Rsa2048, &'static str>;
+
+    /// A block at `height` after `acc` spending `spent` and creating `created`, which leaves the
+    /// accumulator holding `elems`.
+    fn block(
+        height: u64,
+        acc: &Acc,
+        spent: &[&'static str],
+        created: &[&'static str],
+        elems: &[&'static str],
+    ) -> Block<Rsa2048, &'static str> {
+        let acc_new = Acc::empty().add(elems);
+        let transaction = Transaction {
+            utxos_created: created.to_vec(),
+            utxos_spent_with_witnesses: spent
+                .iter()
+                .map(|elem| (*elem, Witness(Acc::empty())))
+                .collect(),
+        };
+        Block {
+            height,
+            transactions: vec![Arc::new(transaction)],
+            proof_update: UpdateProof::Swap(SwapProof::prove_from(acc, &acc_new, spent, created)),
+            proof_fresh: None,
+            acc_commitment: state::acc_commitment(&acc_new),
+            prev_acc_commitment: state::acc_commitment(acc),
+            acc_new,
+        }
+    }
+
+    /// An accumulator of "a" and "b", two blocks after it, one swapping "a" for "c" and the next
+    /// "c" for "d", and a batch proof of them.
+    fn batch() -> (
+        Acc,
+        Vec<Block<Rsa2048, &'static str>>,
+        BatchProof<Rsa2048, &'static str>,
+    ) {
+        let acc = Acc::empty().add(&["a", "b"]);
+        let first = block(1, &acc, &["a"], &["c"], &["b", "c"]);
+        let second = block(2, &first.acc_new, &["c"], &["d"], &["b", "d"]);
+        let blocks = vec![first, second];
+        let utxos: HashSet<&'static str> = ["b", "d"].iter().cloned().collect();
+        let proof = BatchProof::prove(&acc, &blocks, &utxos);
+        (acc, blocks, proof)
+    }
+
+    #[test]
+    fn test_net_changes_cancel_out() {
+        let (_, blocks, _) = batch();
+        assert_eq!(net_changes(&blocks), (vec!["d"], vec!["a"]));
+    }
+
+    #[test]
+    fn test_honest_proof_verifies() {
+        let (acc, blocks, proof) = batch();
+        assert!(proof.verify(&acc, &blocks));
+        let proof_fresh = proof
+            .proof_fresh
+            .expect("No created UTXO was in the accumulator");
+        assert!(acc.verify_nonmembership(&created(&blocks), &proof_fresh));
+    }
+
+    #[test]
+    fn test_tampered_proof_fails() {
+        let (acc, blocks, proof) = batch();
+        let mut tampered = proof.clone();
+        tampered.proof_deleted.r += 1;
+        assert!(!tampered.verify(&acc, &blocks));
+        let mut tampered = proof;
+        tampered.proof_deleted.witness = Witness(acc.clone());
+        assert!(!tampered.verify(&acc, &blocks));
+    }
+
+    #[test]
+    fn test_wrong_elements_fail() {
+        let (acc, mut blocks, proof) = batch();
+        blocks[1] = block(2, &blocks[0].acc_new, &["c"], &["e"], &["b", "d"]);
+        assert!(!proof.verify(&acc, &blocks));
+    }
+
+    #[test]
+    fn test_swapped_accumulator_fails() {
+        let (acc, mut blocks, proof) = batch();
+        assert!(!proof.verify(&blocks[0].acc_new, &blocks));
+        blocks[1].acc_new = acc.clone();
+        assert!(!proof.verify(&acc, &blocks));
+    }
+}
@@ -1,17 +1,49 @@
-use super::state::{Block, Utxo};
+use super::batch::BatchProof;
+use super::clock::Clock;
+use super::control::RunControl;
+use super::genesis::Mints;
+use super::injection::FaultInjector;
+use super::instrument::Instrumented;
+use super::logging;
+use super::metrics::{Metrics, ResourceUsage};
+use super::proof_cache::ProofCache;
+use super::proofs::ProofOp;
+use super::rpc::Responder;
+use super::shutdown::FinalState;
+use super::state::{Block, BlockHeader, NodeId, Utxo};
+use super::transport::{BlockTopic, Receiver, Sender, WitnessChannel, RECEIVE_TIMEOUT};
+use super::validation::{self, BlockError};
+use super::witnesses::WitnessSet;
 use accumulator::group::UnknownOrderGroup;
-use accumulator::Witness;
-use multiqueue::{BroadcastReceiver, BroadcastSender};
+use accumulator::{Accumulator, NonmembershipProof, Witness};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
 use std::clone::Clone;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-#[derive(Clone, Debug)]
+/// Requests for fewer UTXOs per thread of the witness pool than this are split across fewer
+/// threads, as each part costs a subset witness of its own.
+const MIN_WITNESSES_PER_THREAD: usize = 8;
+
+/// At most how many queued witness requests the bridge takes at once to serve together.
+const MAX_WITNESS_BURST: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// How a bridge gathers witness requests to answer together: after one arrives, it waits up to
+/// `window` of real time for more, stopping early once it has `max_requests`.
+pub struct WitnessBatching {
+    pub window: Duration,
+    pub max_requests: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 /// A request from a user for a witness stored on some bridge.
 pub struct WitnessRequest {
     pub user_id: usize,
@@ -19,100 +51,327 @@ pub struct WitnessRequest {
     pub utxos: Vec<Utxo>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "Witness<G, T>: Serialize",
+    deserialize = "Witness<G, T>: Deserialize<'de>"
+))]
 /// A response for a particular witness request.
 pub struct WitnessResponse<G: UnknownOrderGroup, T: Clone + Hash> {
     pub request_id: Uuid,
     pub utxos_with_witnesses: Vec<(Utxo, Witness<G, T>)>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "SpentReceipt<G>: Serialize",
+    deserialize = "SpentReceipt<G>: Deserialize<'de>"
+))]
 /// An update to the set of UTXOs tracked by a user (e.g. when a block is received by a bridge).
-pub struct UserUpdate {
+pub struct UserUpdate<G: UnknownOrderGroup> {
     pub utxos_added: Vec<Utxo>,
     pub utxos_deleted: Vec<Utxo>,
+    /// A proof that `utxos_deleted` are spent, if the bridge sends receipts.
+    pub spent_receipt: Option<SpentReceipt<G>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "Accumulator<G, Utxo>: Serialize, NonmembershipProof<G, Utxo>: Serialize",
+    deserialize = "Accumulator<G, Utxo>: Deserialize<'de>, \
+                   NonmembershipProof<G, Utxo>: Deserialize<'de>"
+))]
+/// A user's proof that its UTXOs were spent: that they are not in the accumulator of the block
+/// that spent them. Anyone holding the chain can check it, by comparing `acc` with that block's,
+/// so the owner can settle a dispute over whether a UTXO is still unspent long after the fact.
+pub struct SpentReceipt<G: UnknownOrderGroup> {
+    pub utxos: Vec<Utxo>,
+    pub height: u64,
+    pub acc: Accumulator<G, Utxo>,
+    pub proof: NonmembershipProof<G, Utxo>,
+}
+
+impl<G: UnknownOrderGroup> SpentReceipt<G> {
+    /// Whether the proof shows that none of `utxos` are in `acc`.
+    pub fn verify(&self) -> bool {
+        self.acc.verify_nonmembership(&self.utxos, &self.proof)
+    }
+
+    /// Whether this proves the UTXOs spent as of the block `header` heads, for a verifier that
+    /// only holds headers.
+    pub fn verify_against(&self, header: &BlockHeader) -> bool {
+        header.height == self.height && header.commits_to(&self.acc) && self.verify()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+/// What a bridge knows of a UTXO.
+pub enum UtxoStatus {
+    /// In the accumulator as of `height`, the bridge's latest block.
+    Unspent { utxo: Utxo, height: u64 },
+    /// Spent in the block at `height`.
+    Spent { utxo: Utxo, height: u64 },
 }
 
 #[derive(Clone, Debug)]
 /// A bridge node in our system, managing UTXO witnesses for a set of users.
 pub struct Bridge<G: UnknownOrderGroup> {
     bridge_id: usize,
-    utxo_set: Vec<Utxo>,
-    utxo_set_witness: Witness<G, Utxo>,
+    /// The accumulator as of the latest block, against which new blocks are validated.
+    acc: Accumulator<G, Utxo>,
+    /// The users' UTXOs, and one witness to all of them.
+    utxo_set: WitnessSet<G, Utxo>,
     block_height: u64,
     user_ids: HashSet<usize>,
+    /// The users' UTXOs that have been spent, and the height of the block that spent each.
+    spent: HashMap<Uuid, (Utxo, u64)>,
+    /// Every UTXO in the accumulator, if this bridge keeps them all to prove non-membership.
+    all_utxos: Option<HashSet<Utxo>>,
+    /// Whether users are sent a receipt for the UTXOs they spend. Needs `all_utxos`.
+    send_spent_receipts: bool,
+    /// Whether blocks creating UTXOs must prove they are not already in the accumulator.
+    require_fresh_utxos: bool,
+    /// Every how many blocks the witness is re-derived from `all_utxos` rather than updated.
+    refresh_interval: Option<u64>,
+    /// Results of checking blocks' proofs, shared with the other components of the process.
+    proof_cache: Option<Arc<ProofCache<G, Utxo>>>,
+    /// The mints the run has authorized, if it mints any. Without them, no transaction may
+    /// spend nothing.
+    mints: Option<Arc<Mints<Utxo>>>,
+    /// Threads to compute witnesses on, if not only the bridge's own.
+    witness_pool: Option<Arc<ThreadPool>>,
+    /// How witness requests are gathered to be answered from one computation, if they are.
+    witness_batching: Option<WitnessBatching>,
+    metrics: Arc<Metrics>,
 }
 
 impl<G: UnknownOrderGroup> Bridge<G> {
-    /// Runs a bridge node's simulation loop.
-    // Assumes all bridges are online from genesis. We may want to implement syncing later.
-    // Also assumes that bridge/user relationships are fixed in `main`.
-    pub fn start(
+    pub fn new(
         bridge_id: usize,
+        acc: Accumulator<G, Utxo>,
         utxo_set_witness: Witness<G, Utxo>,
         utxo_set: Vec<Utxo>,
-        block_receiver: BroadcastReceiver<Block<G, Utxo>>,
-        witness_request_receiver: BroadcastReceiver<WitnessRequest>,
-        witness_response_senders: HashMap<usize, BroadcastSender<WitnessResponse<G, Utxo>>>,
-        user_update_senders: HashMap<usize, BroadcastSender<UserUpdate>>,
-    ) {
-        let bridge_ref = Arc::new(Mutex::new(Self {
+        user_ids: HashSet<usize>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
             bridge_id,
-            utxo_set,
-            utxo_set_witness,
+            acc,
+            utxo_set: WitnessSet::new(utxo_set, utxo_set_witness),
             block_height: 0,
-            user_ids: user_update_senders.keys().cloned().collect(),
-        }));
+            user_ids,
+            spent: HashMap::new(),
+            all_utxos: None,
+            send_spent_receipts: false,
+            require_fresh_utxos: false,
+            refresh_interval: None,
+            proof_cache: None,
+            mints: None,
+            witness_pool: None,
+            witness_batching: None,
+            metrics,
+        }
+    }
+
+    /// Has this bridge keep track of every UTXO in the accumulator, starting from `utxos`, so it
+    /// can prove that a UTXO is not in it.
+    pub fn keep_all_utxos(&mut self, utxos: Vec<Utxo>) {
+        self.all_utxos = Some(utxos.into_iter().collect());
+    }
+
+    /// Has this bridge send each user, along with the update for a block spending its UTXOs, a
+    /// receipt proving they are spent. Only takes effect if it keeps every UTXO.
+    pub fn send_spent_receipts(&mut self) {
+        self.send_spent_receipts = true;
+    }
+
+    /// Has this bridge reject blocks that create UTXOs without proving they are not already in
+    /// the accumulator.
+    pub fn require_fresh_utxos(&mut self) {
+        self.require_fresh_utxos = true;
+    }
+
+    /// Has this bridge re-derive its witness from every UTXO in the accumulator at each height
+    /// that is a multiple of `interval`, rather than from the chain of updates since genesis, and
+    /// whenever a block's update fails. Only takes effect if it keeps every UTXO.
+    pub fn refresh_witnesses_every(&mut self, interval: u64) {
+        self.refresh_interval = Some(interval.max(1));
+    }
+
+    /// Has this bridge look up blocks' proofs in `cache` before checking them, and add what it
+    /// checks.
+    pub fn share_proof_cache(&mut self, cache: Arc<ProofCache<G, Utxo>>) {
+        self.proof_cache = Some(cache);
+    }
+
+    /// Has this bridge accept the mints of `mints`, which the run adds to as it authorizes them.
+    pub fn accept_mints(&mut self, mints: Arc<Mints<Utxo>>) {
+        self.mints = Some(mints);
+    }
+
+    /// Has this bridge compute witnesses on `pool`, splitting a request for many UTXOs, and a
+    /// burst of requests, across its threads rather than serving them one by one.
+    pub fn generate_witnesses_on(&mut self, pool: Arc<ThreadPool>) {
+        self.witness_pool = Some(pool);
+    }
+
+    /// Has this bridge gather witness requests as `batching` says and answer each batch from a
+    /// single subset witness to every UTXO asked for, rather than one per request.
+    pub fn coalesce_witness_requests(&mut self, batching: WitnessBatching) {
+        self.witness_batching = Some(batching);
+    }
+
+    pub fn witness_batching(&self) -> Option<WitnessBatching> {
+        self.witness_batching
+    }
+
+    /// Runs a bridge node's simulation loop until the run is stopped or the bridge crashes, then
+    /// returns where it got to.
+    // A bridge that misses blocks here does not catch up on them: only the discrete-event
+    // scheduler has restarted nodes sync (see `apply_batch`).
+    // Also assumes that bridge/user relationships are fixed in `main`.
+    pub fn start<N: BlockTopic<G, Utxo> + WitnessChannel<G>>(
+        bridge_ref: Arc<Mutex<Self>>,
+        block_receiver: N::BlockReceiver,
+        witness_request_receiver: N::RequestReceiver,
+        witness_response_senders: HashMap<usize, N::ResponseSender>,
+        user_update_senders: HashMap<usize, N::UpdateSender>,
+        clock: Arc<dyn Clock>,
+        control: Arc<RunControl>,
+        faults: Arc<FaultInjector>,
+    ) -> FinalState {
+        let node = NodeId::Bridge(bridge_ref.lock().unwrap().bridge_id);
 
         {
-            println!("Initial state: {:#?}", bridge_ref.lock().unwrap());
+            let _span = logging::node_span(node).entered();
+            debug!("Initial state: {:#?}", bridge_ref.lock().unwrap());
         }
 
         // Block updater thread.
         let bridge = bridge_ref.clone();
-        let update_thread = thread::spawn(move || loop {
-            match block_receiver.try_recv() {
-                Ok(block) => bridge.lock().unwrap().update(block, &user_update_senders),
-                Err(_) => (),
+        let update_clock = clock.clone();
+        let update_control = control.clone();
+        let update_faults = faults.clone();
+        let update_thread = thread::spawn(move || {
+            let _span = logging::node_span(node).entered();
+            while update_control.wait_while_paused()
+                && update_faults.interrupt(node, update_clock.as_ref())
+            {
+                if let Some(block) = block_receiver.receive_timeout(RECEIVE_TIMEOUT) {
+                    for (user_id, update) in bridge.lock().unwrap().update(&block) {
+                        user_update_senders[&user_id].send(update_faults.tamper(node, update));
+                    }
+                }
             }
-            sleep(Duration::from_millis(10));
         });
 
-        // Witness request handler.
+        // Witness request handler, answering each request to the user that sent it. Requests
+        // queued behind the first, or arriving within the batching window, are taken along with
+        // it, to be served together.
         let bridge = bridge_ref.clone();
-        let witness_thread = thread::spawn(move || loop {
-            match witness_request_receiver.try_recv() {
-                Ok(request) => {
-                    let bridge = bridge.lock().unwrap();
-                    let utxos_with_witnesses = bridge.create_membership_witnesses(&request.utxos);
-                    witness_response_senders[&request.user_id]
-                        .try_send(WitnessResponse {
-                            request_id: request.request_id,
-                            utxos_with_witnesses,
-                        })
-                        .unwrap();
+        let batching = bridge_ref.lock().unwrap().witness_batching;
+        let responder = Responder::new(witness_response_senders);
+        let witness_thread = thread::spawn(move || {
+            let _span = logging::node_span(node).entered();
+            while control.wait_while_paused() && faults.interrupt(node, clock.as_ref()) {
+                if let Some(request) = witness_request_receiver.receive_timeout(RECEIVE_TIMEOUT) {
+                    let requests =
+                        gather_witness_requests(&witness_request_receiver, request, batching);
+                    let responses = bridge.lock().unwrap().handle_witness_requests(&requests);
+                    for (request, response) in requests.iter().zip(responses) {
+                        responder.reply(request, faults.tamper(node, response));
+                    }
                 }
-                Err(_) => (),
             }
-            sleep(Duration::from_millis(10));
         });
 
         update_thread.join().unwrap();
         witness_thread.join().unwrap();
+        let final_state = bridge_ref.lock().unwrap().final_state();
+        final_state
+    }
+
+    /// Given a new block, updates the witnesses stored on this bridge and returns the UTXO changes
+    /// to propagate to individual users, ordered by user ID. Blocks that do not validly extend the
+    /// chain produce no updates.
+    pub fn update(&mut self, block: &Block<G, Utxo>) -> Vec<(usize, UserUpdate<G>)> {
+        let start = Instant::now();
+        let node = NodeId::Bridge(self.bridge_id);
+        match validation::validate_block(
+            &self.acc,
+            self.block_height,
+            block,
+            self.require_fresh_utxos,
+            self.mints.as_deref(),
+            &self.metrics,
+            self.proof_cache.as_deref(),
+        ) {
+            Ok(()) => (),
+            Err(BlockError::Stale) => return Vec::new(),
+            Err(err) => {
+                warn!("Rejected block {}: {:?}.", block.height, err);
+                self.metrics.block_rejected(node);
+                self.metrics.cpu_used(node, start.elapsed());
+                return Vec::new();
+            }
+        }
+        self.apply(block, start, true)
     }
 
-    /// Given a new block, updates the witnesses stored on this bridge and propagates UTXO changes to
-    /// individual users.
-    fn update(
+    /// Applies `blocks`, fetched to catch up, if `proof` shows they validly extend this bridge's
+    /// chain, checking it in place of each block's own proofs, and returns the UTXO changes for
+    /// users block by block. Returns `None` if it did not apply them; they can still be validated
+    /// one by one. The proof only covers the accumulator after the last block, so spent receipts
+    /// are all against that one.
+    pub fn apply_batch(
         &mut self,
-        block: Block<G, Utxo>,
-        user_update_senders: &HashMap<usize, BroadcastSender<UserUpdate>>,
-    ) {
-        // Preserves idempotency if multiple miners are leaders.
-        if block.height != self.block_height + 1 {
-            return;
+        blocks: &[Block<G, Utxo>],
+        proof: &BatchProof<G, Utxo>,
+    ) -> Option<Vec<(usize, UserUpdate<G>)>> {
+        let start = Instant::now();
+        if let Err(err) = validation::validate_batch(
+            &self.acc,
+            self.block_height,
+            blocks,
+            proof,
+            self.require_fresh_utxos,
+            self.mints.as_deref(),
+            &self.metrics,
+            self.proof_cache.as_deref(),
+        ) {
+            debug!(
+                "Not applying a batch of {} blocks: {:?}.",
+                blocks.len(),
+                err
+            );
+            self.metrics
+                .cpu_used(NodeId::Bridge(self.bridge_id), start.elapsed());
+            return None;
+        }
+        info!("Caught up on {} blocks with one proof.", blocks.len());
+        let mut user_updates = Vec::new();
+        for block in blocks {
+            user_updates.extend(self.apply(block, start, false));
         }
+        if self.send_spent_receipts {
+            for (_, update) in &mut user_updates {
+                update.spent_receipt = self.spent_receipt(&update.utxos_deleted);
+            }
+        }
+        Some(user_updates)
+    }
 
+    /// Applies a block that has been validated, returning the UTXO changes for users, with spent
+    /// receipts against its accumulator if `receipts` and this bridge sends them. `start` is when
+    /// its validation started.
+    fn apply(
+        &mut self,
+        block: &Block<G, Utxo>,
+        start: Instant,
+        receipts: bool,
+    ) -> Vec<(usize, UserUpdate<G>)> {
+        let node = NodeId::Bridge(self.bridge_id);
         let mut user_updates = HashMap::new();
         for user_id in &self.user_ids {
             user_updates.insert(
@@ -120,73 +379,409 @@ impl<G: UnknownOrderGroup> Bridge<G> {
                 UserUpdate {
                     utxos_added: Vec::new(),
                     utxos_deleted: Vec::new(),
+                    spent_receipt: None,
                 },
             );
         }
 
+        let mut tracked_additions = Vec::new();
+        let mut tracked_deletions = Vec::new();
         let mut untracked_additions = Vec::new();
         let mut untracked_deletions = Vec::new();
-        for transaction in block.transactions {
-            for (utxo, _witness) in transaction.utxos_spent_with_witnesses {
+        for transaction in &block.transactions {
+            for (utxo, _witness) in &transaction.utxos_spent_with_witnesses {
+                let utxo = utxo.clone();
+                if let Some(all_utxos) = &mut self.all_utxos {
+                    all_utxos.remove(&utxo);
+                }
                 if self.user_ids.contains(&utxo.user_id) {
+                    self.spent.insert(utxo.id, (utxo.clone(), block.height));
                     user_updates
                         .get_mut(&utxo.user_id)
                         .unwrap()
                         .utxos_deleted
                         .push(utxo.clone());
-                    self.utxo_set.retain(|x| *x != utxo);
+                    tracked_deletions.push(utxo);
                 } else {
                     untracked_deletions.push(utxo);
                 }
             }
-            for utxo in transaction.utxos_created {
+            for utxo in transaction.utxos_created.iter().cloned() {
+                if let Some(all_utxos) = &mut self.all_utxos {
+                    all_utxos.insert(utxo.clone());
+                }
                 if self.user_ids.contains(&utxo.user_id) {
                     user_updates
                         .get_mut(&utxo.user_id)
                         .unwrap()
                         .utxos_added
                         .push(utxo.clone());
-                    self.utxo_set.push(utxo);
+                    tracked_additions.push(utxo);
                 } else {
                     untracked_additions.push(utxo);
                 }
             }
         }
 
-        self.utxo_set_witness = block
-            .acc_new
-            .update_membership_witness(
-                self.utxo_set_witness.clone(),
-                &self.utxo_set,
-                &untracked_additions[..],
-                &untracked_deletions[..],
-            )
-            .unwrap();
+        let result = self.utxo_set.update(
+            &block.acc_new,
+            &tracked_additions,
+            &tracked_deletions,
+            &untracked_additions,
+            &untracked_deletions,
+        );
+        self.acc = block.acc_new.clone();
         self.block_height = block.height;
+        let is_due = self
+            .refresh_interval
+            .map_or(false, |interval| self.block_height % interval == 0);
+        match result {
+            Ok(()) if is_due => {
+                self.refresh_witnesses();
+            }
+            Ok(()) => (),
+            Err(err) => {
+                warn!("Failed to update the witness: {:?}; re-deriving it.", err);
+                if !self.refresh_witnesses() {
+                    panic!("Bridge {} lost its witness: {:?}", self.bridge_id, err);
+                }
+            }
+        }
+        self.metrics
+            .block_applied(node, self.block_height, start.elapsed());
+        // A bridge holds its users' UTXOs and a single witness to all of them.
+        self.metrics.resources_changed(
+            node,
+            ResourceUsage::estimate(self.utxo_set.len(), 1, 0, 0, 0, 0),
+        );
 
-        println!(
-            "Bridge {} received block {}. {:#?}",
-            self.bridge_id, self.block_height, self,
+        info!("Received block {}.", self.block_height);
+        debug!("{:#?}", self);
+
+        if receipts && self.send_spent_receipts {
+            for update in user_updates.values_mut() {
+                update.spent_receipt = self.spent_receipt(&update.utxos_deleted);
+            }
+        }
+        let mut user_updates: Vec<(usize, UserUpdate<G>)> = user_updates
+            .into_iter()
+            .map(|(user_id, update)| (*user_id, update))
+            .collect();
+        user_updates.sort_by_key(|(user_id, _)| *user_id);
+        self.metrics.cpu_used(node, start.elapsed());
+        user_updates
+    }
+
+    /// Re-derives the witness to the users' UTXOs from every other UTXO in the accumulator.
+    /// Returns whether it could, which needs this bridge to keep every UTXO and to know of a
+    /// refresh interval.
+    fn refresh_witnesses(&mut self) -> bool {
+        let all_utxos = match (&self.all_utxos, self.refresh_interval) {
+            (Some(all_utxos), Some(_)) => all_utxos,
+            _ => return false,
+        };
+        let tracked: HashSet<&Utxo> = self.utxo_set.elems().iter().collect();
+        let others: Vec<Utxo> = all_utxos
+            .iter()
+            .filter(|utxo| !tracked.contains(utxo))
+            .cloned()
+            .collect();
+        match self.utxo_set.refresh(&self.acc, &others) {
+            Ok(true) => debug!("Refreshed the witness at block {}.", self.block_height),
+            Ok(false) => warn!(
+                "Refreshed the witness at block {}, which had drifted from the accumulator.",
+                self.block_height
+            ),
+            Err(err) => {
+                warn!("Failed to re-derive the witness: {:?}", err);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Picks up the chain at `block_height`, e.g. when restoring a checkpoint.
+    pub fn resume_at(&mut self, block_height: u64) {
+        self.block_height = block_height;
+    }
+
+    pub fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    pub fn final_state(&self) -> FinalState {
+        FinalState::Bridge {
+            bridge_id: self.bridge_id,
+            block_height: self.block_height,
+            utxos: self.utxo_set.len(),
+        }
+    }
+
+    /// The users this bridge serves, in order.
+    pub fn user_ids(&self) -> Vec<usize> {
+        let mut user_ids: Vec<usize> = self.user_ids.iter().cloned().collect();
+        user_ids.sort();
+        user_ids
+    }
+
+    /// The UTXOs this bridge knows to be owned by `user_id`.
+    pub fn utxos_of(&self, user_id: usize) -> Vec<Utxo> {
+        self.utxo_set
+            .elems()
+            .iter()
+            .filter(|utxo| utxo.user_id == user_id)
+            .cloned()
+            .collect()
+    }
+
+    /// The accumulator as of the latest block this bridge applied.
+    pub fn acc(&self) -> &Accumulator<G, Utxo> {
+        &self.acc
+    }
+
+    /// What this bridge knows of the UTXO with the given ID: whether it is unspent, or when it was
+    /// spent. Bridges only follow their own users' UTXOs, unless they keep all of them.
+    pub fn utxo_status(&self, id: Uuid) -> Option<UtxoStatus> {
+        let unspent = self
+            .utxo_set
+            .elems()
+            .iter()
+            .chain(self.all_utxos.iter().flatten())
+            .find(|utxo| utxo.id == id);
+        if let Some(utxo) = unspent {
+            return Some(UtxoStatus::Unspent {
+                utxo: utxo.clone(),
+                height: self.block_height,
+            });
+        }
+        self.spent.get(&id).map(|(utxo, height)| UtxoStatus::Spent {
+            utxo: utxo.clone(),
+            height: *height,
+        })
+    }
+
+    /// Witnesses to each of `utxos` against the current accumulator, or `None` if this bridge
+    /// does not hold all of them.
+    pub fn membership_witnesses(&self, utxos: &[Utxo]) -> Option<Vec<(Utxo, Witness<G, Utxo>)>> {
+        if !utxos.iter().all(|utxo| self.utxo_set.contains(utxo)) {
+            return None;
+        }
+        Some(self.create_membership_witnesses(utxos))
+    }
+
+    /// A proof that `utxo` is not in the current accumulator, or `None` if this bridge does not
+    /// keep every UTXO or `utxo` is in it.
+    pub fn prove_nonmembership(&self, utxo: &Utxo) -> Option<NonmembershipProof<G, Utxo>> {
+        self.prove_absent(&[utxo.clone()])
+    }
+
+    /// A receipt that `utxos` are spent as of the current block, or `None` if there are none or
+    /// they cannot be proven absent.
+    fn spent_receipt(&self, utxos: &[Utxo]) -> Option<SpentReceipt<G>> {
+        if utxos.is_empty() {
+            return None;
+        }
+        self.prove_absent(utxos).map(|proof| SpentReceipt {
+            utxos: utxos.to_vec(),
+            height: self.block_height,
+            acc: self.acc.clone(),
+            proof,
+        })
+    }
+
+    /// A proof that none of `utxos` are in the current accumulator, or `None` if this bridge does
+    /// not keep every UTXO or any of `utxos` is in it.
+    fn prove_absent(&self, utxos: &[Utxo]) -> Option<NonmembershipProof<G, Utxo>> {
+        let all_utxos = self.all_utxos.as_ref()?;
+        if utxos.iter().any(|utxo| all_utxos.contains(utxo)) {
+            return None;
+        }
+        let all_utxos: Vec<Utxo> = all_utxos.iter().cloned().collect();
+        Instrumented::new(self.metrics.as_ref())
+            .prove_nonmembership(ProofOp::SpentUtxo, &self.acc, &all_utxos, utxos)
+            .ok()
+    }
+
+    /// Whether this bridge keeps every UTXO in the accumulator.
+    pub fn keeps_all_utxos(&self) -> bool {
+        self.all_utxos.is_some()
+    }
+
+    /// Starts tracking UTXOs owned by `user_id`, e.g. for a user joining mid-run.
+    pub fn add_user(&mut self, user_id: usize) {
+        self.user_ids.insert(user_id);
+    }
+
+    /// Answers a user's request for witnesses to the UTXOs it wants to spend. UTXOs this bridge
+    /// does not hold, e.g. ones already spent, are left out of the response, as are repeats.
+    pub fn handle_witness_request(&self, request: &WitnessRequest) -> WitnessResponse<G, Utxo> {
+        let _span = logging::trace_span(request.request_id).entered();
+        let start = Instant::now();
+        let mut asked = HashSet::new();
+        let held: Vec<Utxo> = request
+            .utxos
+            .iter()
+            .filter(|utxo| self.utxo_set.contains(utxo) && asked.insert(*utxo))
+            .cloned()
+            .collect();
+        if held.len() < request.utxos.len() {
+            debug!(
+                "Leaving {} UTXOs not held or asked for twice out of a witness response.",
+                request.utxos.len() - held.len()
+            );
+        }
+        let utxos_with_witnesses = self.create_membership_witnesses(&held);
+        self.metrics.witness_served(
+            self.bridge_id,
+            request.user_id,
+            request.request_id,
+            self.block_height,
+            start.elapsed(),
         );
+        self.metrics
+            .cpu_used(NodeId::Bridge(self.bridge_id), start.elapsed());
+        WitnessResponse {
+            request_id: request.request_id,
+            utxos_with_witnesses,
+        }
+    }
 
-        for (user_id, update) in user_updates {
-            user_update_senders[user_id].try_send(update).unwrap();
+    /// Answers `requests`, which arrived together, in order. A bridge coalescing requests answers
+    /// them from one computation; otherwise, with a witness pool they are served in parallel on
+    /// it.
+    pub fn handle_witness_requests(
+        &self,
+        requests: &[WitnessRequest],
+    ) -> Vec<WitnessResponse<G, Utxo>> {
+        if self.witness_batching.is_some() && requests.len() > 1 {
+            return self.handle_witness_batch(requests);
+        }
+        match &self.witness_pool {
+            Some(pool) if requests.len() > 1 => pool.install(|| {
+                requests
+                    .par_iter()
+                    .map(|request| self.handle_witness_request(request))
+                    .collect()
+            }),
+            _ => requests
+                .iter()
+                .map(|request| self.handle_witness_request(request))
+                .collect(),
+        }
+    }
+
+    /// Answers `requests` from a single computation of the witnesses to every UTXO they ask for,
+    /// all against the current accumulator, so the subset witness and the exponentiations that
+    /// split it are shared between them. UTXOs this bridge does not hold are left out of the
+    /// responses. Each request counts as served in its share of the time the batch took.
+    fn handle_witness_batch(&self, requests: &[WitnessRequest]) -> Vec<WitnessResponse<G, Utxo>> {
+        let start = Instant::now();
+        let mut asked = HashSet::new();
+        let utxos: Vec<Utxo> = requests
+            .iter()
+            .flat_map(|request| &request.utxos)
+            .filter(|utxo| self.utxo_set.contains(utxo) && asked.insert(*utxo))
+            .cloned()
+            .collect();
+        let witnesses: HashMap<Utxo, Witness<G, Utxo>> = self
+            .create_membership_witnesses(&utxos)
+            .into_iter()
+            .collect();
+        let responses = requests
+            .iter()
+            .map(|request| WitnessResponse {
+                request_id: request.request_id,
+                utxos_with_witnesses: request
+                    .utxos
+                    .iter()
+                    .filter_map(|utxo| {
+                        let witness = witnesses.get(utxo)?;
+                        Some((utxo.clone(), witness.clone()))
+                    })
+                    .collect(),
+            })
+            .collect();
+        let elapsed = start.elapsed();
+        for request in requests {
+            self.metrics.witness_served(
+                self.bridge_id,
+                request.user_id,
+                request.request_id,
+                self.block_height,
+                elapsed / requests.len() as u32,
+            );
         }
+        self.metrics
+            .cpu_used(NodeId::Bridge(self.bridge_id), elapsed);
+        responses
     }
 
-    /// Generates individual membership witnesses for each given UTXO. See `Witness::root_factor`
-    /// and BBF V3 Section 4.1.
+    /// Generates individual membership witnesses for each given UTXO, which callers have checked
+    /// this bridge holds, split across the witness pool if there are enough of them. See
+    /// `WitnessSet`.
     fn create_membership_witnesses(&self, utxos: &[Utxo]) -> Vec<(Utxo, Witness<G, Utxo>)> {
-        let agg_mem_wit = self
-            .utxo_set_witness
-            .clone()
-            .compute_subset_witness(&self.utxo_set, utxos)
-            .unwrap();
-        agg_mem_wit.compute_individual_witnesses(utxos)
+        let witnesses = match &self.witness_pool {
+            Some(pool) => {
+                let parts = pool
+                    .current_num_threads()
+                    .min(utxos.len() / MIN_WITNESSES_PER_THREAD);
+                if parts < 2 {
+                    self.utxo_set.witnesses(utxos)
+                } else {
+                    pool.install(|| self.utxo_set.witnesses_in_parallel(utxos, parts))
+                }
+            }
+            None => self.utxo_set.witnesses(utxos),
+        };
+        witnesses.expect("The bridge holds every UTXO it makes a witness to")
     }
 }
 
-impl UserUpdate {
+/// The witness requests to serve along with `first`: with `batching`, those arriving within its
+/// window, and otherwise those already queued behind it.
+fn gather_witness_requests<R: Receiver<WitnessRequest>>(
+    receiver: &R,
+    first: WitnessRequest,
+    batching: Option<WitnessBatching>,
+) -> Vec<WitnessRequest> {
+    let mut requests = vec![first];
+    match batching {
+        Some(batching) => {
+            let deadline = Instant::now() + batching.window;
+            while requests.len() < batching.max_requests {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                match receiver.receive_timeout(deadline - now) {
+                    Some(request) => requests.push(request),
+                    None => break,
+                }
+            }
+        }
+        None => {
+            while requests.len() < MAX_WITNESS_BURST {
+                match receiver.try_receive() {
+                    Some(request) => requests.push(request),
+                    None => break,
+                }
+            }
+        }
+    }
+    requests
+}
+
+/// A pool of `threads` threads for bridges to compute witnesses on, or of one per CPU if 0.
+pub fn witness_pool(threads: usize) -> Arc<ThreadPool> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("witness-{}", i))
+        .build()
+        .expect("Failed to start the witness pool");
+    Arc::new(pool)
+}
+
+impl<G: UnknownOrderGroup> UserUpdate<G> {
     pub fn is_empty(&self) -> bool {
         self.utxos_added.len() == 0 && self.utxos_deleted.len() == 0
     }
@@ -0,0 +1,628 @@
+use super::bridge::{self, Bridge, UserUpdate, WitnessBatching, WitnessRequest, WitnessResponse};
+use super::byzantine::Behavior;
+use super::clock::Clock;
+use super::config::Config;
+use super::control::RunControl;
+use super::genesis::Genesis;
+use super::hooks::Hooks;
+use super::injection::FaultInjector;
+use super::int_pool;
+#[cfg(feature = "async-runtime")]
+use super::logging;
+use super::metrics::Metrics;
+use super::miner::Miner;
+use super::network::{LossySender, MessageKind, Payload};
+use super::prime_cache;
+use super::primes::PrimeHasher;
+use super::proof_cache::ProofCache;
+use super::shutdown::{FinalState, Running};
+use super::state::{Block, NodeId, Transaction, Utxo};
+#[cfg(feature = "async-runtime")]
+use super::tasks;
+use super::transport::{self, Channel, Multiqueue, QueueReceiver, QueueSender};
+use super::user::User;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, Witness};
+use rayon::ThreadPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+#[cfg(feature = "async-runtime")]
+use tracing::Instrument;
+use tracing::{debug, info};
+
+/// How long bridges get to start up before miners begin forging.
+const BRIDGE_STARTUP: Duration = Duration::from_millis(2000);
+
+/// A queue for `channel`, whose depth `metrics` follow.
+fn new_queue<T: Clone>(
+    config: &Config,
+    channel: Channel,
+    metrics: &Metrics,
+) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (sender, receiver) = transport::queue(config.channel_capacity(channel));
+    metrics.watch_queue(channel, sender.depth());
+    (sender, receiver)
+}
+
+/// Who reads what `from` broadcasts: every other miner, and for blocks every bridge as well.
+fn broadcast_recipients(config: &Config, from: NodeId, kind: MessageKind) -> Vec<NodeId> {
+    let mut recipients: Vec<NodeId> = (0..config.num_miners).map(NodeId::Miner).collect();
+    if kind == MessageKind::Block {
+        recipients.extend((0..config.num_bridges()).map(NodeId::Bridge));
+    }
+    recipients.retain(|&node| node != from);
+    recipients
+}
+
+/// `sender`, losing and duplicating messages like the link from `from` to `to` in `config`, or
+/// from `from` to every component reading its broadcasts without `to`, and counting the traffic.
+/// Components rewired to read other queues are not counted as reading them.
+fn link<T: Clone + Payload>(
+    sender: QueueSender<T>,
+    config: &Config,
+    from: NodeId,
+    to: Option<NodeId>,
+    channel: Channel,
+    metrics: &Arc<Metrics>,
+) -> LossySender<T> {
+    let kind = channel.kind();
+    let recipients = match to {
+        Some(to) => vec![to],
+        None => broadcast_recipients(config, from, kind),
+    };
+    LossySender::new(
+        sender,
+        channel,
+        config.network.faults(from, to, kind),
+        config.queue_wait(),
+        metrics.clone(),
+    )
+    .metered(from, recipients, kind)
+}
+
+/// A miner ready to run on its own threads, with the channels it talks over.
+pub struct MinerHandle<G: UnknownOrderGroup> {
+    pub miner_id: usize,
+    pub is_leader: bool,
+    pub byzantine: Option<Behavior>,
+    pub acc: Accumulator<G, Utxo>,
+    pub block_interval_ms: u64,
+    pub block_sender: LossySender<Arc<Block<G, Utxo>>>,
+    pub block_receiver: QueueReceiver<Arc<Block<G, Utxo>>>,
+    pub tx_receiver: QueueReceiver<Arc<Transaction<G, Utxo>>>,
+    /// Every UTXO at genesis, if the miner is to keep them all and prove its UTXOs fresh.
+    pub utxo_set: Option<Vec<Utxo>>,
+    /// Results of checking blocks' proofs, if shared with the other components.
+    pub proof_cache: Option<Arc<ProofCache<G, Utxo>>>,
+    /// A hash-to-prime strategy to measure, shared with the other miners.
+    pub prime_hasher: Option<Arc<PrimeHasher>>,
+    /// Whether the miner proves its blocks' updates with a single swap proof.
+    pub multiswap: bool,
+    /// Whether the miner validates blocks in a pipeline.
+    pub pipeline_validation: bool,
+}
+
+impl<G: UnknownOrderGroup> MinerHandle<G> {
+    /// The miner to run, set up as the handle says. What it takes is left out of the handle.
+    pub fn miner(&mut self, metrics: Arc<Metrics>) -> Miner<G, Utxo> {
+        let mut miner = Miner::new(self.miner_id, self.acc.clone(), metrics);
+        if let Some(utxo_set) = self.utxo_set.take() {
+            miner.keep_utxo_set(utxo_set);
+        }
+        if let Some(cache) = self.proof_cache.take() {
+            miner.share_proof_cache(cache);
+        }
+        if let Some(hasher) = self.prime_hasher.take() {
+            miner.hash_to_primes(hasher);
+        }
+        if self.multiswap {
+            miner.prove_multiswaps();
+        }
+        if self.pipeline_validation {
+            miner.pipeline_validation();
+        }
+        miner
+    }
+
+    pub fn spawn(
+        mut self,
+        clock: Arc<dyn Clock>,
+        control: Arc<RunControl>,
+        metrics: Arc<Metrics>,
+        faults: Arc<FaultInjector>,
+        hooks: &Hooks<G>,
+    ) -> JoinHandle<FinalState> {
+        let miner = Arc::new(self.miner(metrics));
+        hooks.miner_started(self.miner_id, &miner);
+        thread::spawn(move || {
+            Miner::<G, Utxo>::start::<Multiqueue>(
+                miner,
+                self.is_leader,
+                self.byzantine,
+                self.block_interval_ms,
+                clock,
+                control,
+                faults,
+                &self.block_sender,
+                self.block_receiver,
+                self.tx_receiver,
+            )
+        })
+    }
+}
+
+/// A bridge ready to run on its own threads, with a channel to each of its users.
+pub struct BridgeHandle<G: UnknownOrderGroup> {
+    pub bridge_id: usize,
+    pub acc: Accumulator<G, Utxo>,
+    pub utxo_set_witness: Witness<G, Utxo>,
+    pub utxo_set: Vec<Utxo>,
+    pub block_receiver: QueueReceiver<Arc<Block<G, Utxo>>>,
+    pub witness_request_receiver: QueueReceiver<WitnessRequest>,
+    pub witness_response_senders: HashMap<usize, LossySender<WitnessResponse<G, Utxo>>>,
+    pub user_update_senders: HashMap<usize, LossySender<UserUpdate<G>>>,
+    /// Every UTXO at genesis, if the bridge is to keep them all.
+    pub all_utxos: Option<Vec<Utxo>>,
+    /// Whether the bridge sends users receipts for the UTXOs they spend, which needs `all_utxos`.
+    pub send_spent_receipts: bool,
+    /// Whether the bridge rejects blocks that do not prove the UTXOs they create are fresh.
+    pub require_fresh_utxos: bool,
+    /// Every how many blocks the bridge re-derives its witness, which needs `all_utxos`.
+    pub witness_refresh_interval: Option<u64>,
+    /// Results of checking blocks' proofs, if shared with the other components.
+    pub proof_cache: Option<Arc<ProofCache<G, Utxo>>>,
+    /// Threads to compute witnesses on, if shared with the other bridges.
+    pub witness_pool: Option<Arc<ThreadPool>>,
+    /// How the bridge gathers witness requests to answer together, if it waits for them.
+    pub witness_batching: Option<WitnessBatching>,
+}
+
+impl<G: UnknownOrderGroup> BridgeHandle<G> {
+    /// The bridge to run, set up as the handle says. What it takes is left out of the handle.
+    pub fn bridge(&mut self, metrics: Arc<Metrics>) -> Bridge<G> {
+        let mut bridge = Bridge::new(
+            self.bridge_id,
+            self.acc.clone(),
+            self.utxo_set_witness.clone(),
+            std::mem::take(&mut self.utxo_set),
+            self.user_update_senders.keys().cloned().collect(),
+            metrics,
+        );
+        if let Some(all_utxos) = self.all_utxos.take() {
+            bridge.keep_all_utxos(all_utxos);
+        }
+        if self.send_spent_receipts {
+            bridge.send_spent_receipts();
+        }
+        if self.require_fresh_utxos {
+            bridge.require_fresh_utxos();
+        }
+        if let Some(interval) = self.witness_refresh_interval {
+            bridge.refresh_witnesses_every(interval);
+        }
+        if let Some(cache) = self.proof_cache.take() {
+            bridge.share_proof_cache(cache);
+        }
+        if let Some(pool) = self.witness_pool.take() {
+            bridge.generate_witnesses_on(pool);
+        }
+        if let Some(batching) = self.witness_batching {
+            bridge.coalesce_witness_requests(batching);
+        }
+        bridge
+    }
+
+    pub fn spawn(
+        mut self,
+        clock: Arc<dyn Clock>,
+        control: Arc<RunControl>,
+        metrics: Arc<Metrics>,
+        faults: Arc<FaultInjector>,
+        hooks: &Hooks<G>,
+    ) -> JoinHandle<FinalState> {
+        let bridge = Arc::new(Mutex::new(self.bridge(metrics)));
+        hooks.bridge_started(self.bridge_id, &bridge);
+        thread::spawn(move || {
+            Bridge::<G>::start::<Multiqueue>(
+                bridge,
+                self.block_receiver,
+                self.witness_request_receiver,
+                self.witness_response_senders,
+                self.user_update_senders,
+                clock,
+                control,
+                faults,
+            )
+        })
+    }
+}
+
+/// A user ready to run on its own thread, with the channels to its bridge and the miners.
+pub struct UserHandle<G: UnknownOrderGroup> {
+    pub user_id: usize,
+    pub bridge_id: usize,
+    pub utxo: Utxo,
+    pub witness_request_sender: LossySender<WitnessRequest>,
+    pub witness_response_receiver: QueueReceiver<WitnessResponse<G, Utxo>>,
+    pub user_update_receiver: QueueReceiver<UserUpdate<G>>,
+    pub tx_sender: LossySender<Arc<Transaction<G, Utxo>>>,
+    pub retry_timeout: Option<Duration>,
+}
+
+impl<G: UnknownOrderGroup> UserHandle<G> {
+    pub fn spawn(
+        self,
+        clock: Arc<dyn Clock>,
+        control: Arc<RunControl>,
+        metrics: Arc<Metrics>,
+        faults: Arc<FaultInjector>,
+    ) -> JoinHandle<FinalState> {
+        thread::spawn(move || {
+            User::<G>::start::<Multiqueue>(
+                self.user_id,
+                self.bridge_id,
+                self.utxo,
+                &self.witness_request_sender,
+                &self.witness_response_receiver,
+                &self.user_update_receiver,
+                &self.tx_sender,
+                self.retry_timeout,
+                clock.as_ref(),
+                &control,
+                metrics,
+                &faults,
+            )
+        })
+    }
+}
+
+/// Every component of a real-time run, wired up but not yet running. Handles can be rewired or
+/// left out before they are spawned, e.g. to give some users a transaction queue only some miners
+/// read.
+pub struct Components<G: UnknownOrderGroup> {
+    pub genesis: Genesis<G>,
+    pub miners: Vec<MinerHandle<G>>,
+    pub bridges: Vec<BridgeHandle<G>>,
+    pub users: Vec<UserHandle<G>>,
+}
+
+impl<G: UnknownOrderGroup> Components<G> {
+    /// Starts bridges and users, then miners once the bridges have had time to start up.
+    pub fn spawn(
+        self,
+        clock: &Arc<dyn Clock>,
+        control: &Arc<RunControl>,
+        metrics: &Arc<Metrics>,
+        faults: &Arc<FaultInjector>,
+        hooks: &Hooks<G>,
+    ) -> Running {
+        let mut threads = Vec::new();
+        for user in self.users {
+            let node = NodeId::User(user.user_id);
+            let handle = user.spawn(
+                clock.clone(),
+                control.clone(),
+                metrics.clone(),
+                faults.clone(),
+            );
+            threads.push((node, handle));
+        }
+        for bridge in self.bridges {
+            let node = NodeId::Bridge(bridge.bridge_id);
+            let handle = bridge.spawn(
+                clock.clone(),
+                control.clone(),
+                metrics.clone(),
+                faults.clone(),
+                hooks,
+            );
+            threads.push((node, handle));
+        }
+        info!("Sleeping so bridges can start up before miner.");
+        clock.sleep(BRIDGE_STARTUP);
+        for miner in self.miners {
+            let node = NodeId::Miner(miner.miner_id);
+            let handle = miner.spawn(
+                clock.clone(),
+                control.clone(),
+                metrics.clone(),
+                faults.clone(),
+                hooks,
+            );
+            threads.push((node, handle));
+        }
+        Running::new(threads)
+    }
+
+    /// Starts every component as tasks on a new tokio runtime rather than on threads of its own,
+    /// bridges and users first, then miners once the bridges have had time to start up. See
+    /// `tasks`.
+    #[cfg(feature = "async-runtime")]
+    pub fn spawn_tasks(
+        self,
+        clock: &Arc<dyn Clock>,
+        control: &Arc<RunControl>,
+        metrics: &Arc<Metrics>,
+        faults: &Arc<FaultInjector>,
+        hooks: &Hooks<G>,
+    ) -> Running {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .thread_name("component")
+            .build()
+            .expect("Failed to start the component runtime");
+        let mut components = Vec::new();
+        for user in self.users {
+            let node = NodeId::User(user.user_id);
+            let task = tasks::user(
+                user,
+                clock.clone(),
+                control.clone(),
+                metrics.clone(),
+                faults.clone(),
+            );
+            components.push((
+                node,
+                runtime.spawn(task.instrument(logging::node_span(node))),
+            ));
+        }
+        for mut bridge in self.bridges {
+            let node = NodeId::Bridge(bridge.bridge_id);
+            let bridge_ref = Arc::new(Mutex::new(bridge.bridge(metrics.clone())));
+            hooks.bridge_started(bridge.bridge_id, &bridge_ref);
+            let task = tasks::bridge(
+                bridge_ref,
+                bridge,
+                clock.clone(),
+                control.clone(),
+                faults.clone(),
+            );
+            components.push((
+                node,
+                runtime.spawn(task.instrument(logging::node_span(node))),
+            ));
+        }
+        info!("Sleeping so bridges can start up before miner.");
+        clock.sleep(BRIDGE_STARTUP);
+        for mut miner in self.miners {
+            let node = NodeId::Miner(miner.miner_id);
+            let miner_ref = Arc::new(miner.miner(metrics.clone()));
+            hooks.miner_started(miner.miner_id, &miner_ref);
+            let task = tasks::miner(
+                miner_ref,
+                miner,
+                clock.clone(),
+                control.clone(),
+                faults.clone(),
+            );
+            components.push((
+                node,
+                runtime.spawn(task.instrument(logging::node_span(node))),
+            ));
+        }
+        Running::from_tasks(components, runtime)
+    }
+}
+
+/// Builds the miners, bridges and users of a real-time run from its config, along with every
+/// queue between them. Messages on each link are lost and duplicated according to the config's
+/// network faults. Other code can subscribe to the block, transaction and witness request queues
+/// before the components are built.
+pub struct SimulationBuilder<G: UnknownOrderGroup> {
+    config: Config,
+    metrics: Arc<Metrics>,
+    genesis: Genesis<G>,
+    blocks: (
+        QueueSender<Arc<Block<G, Utxo>>>,
+        QueueReceiver<Arc<Block<G, Utxo>>>,
+    ),
+    transactions: (
+        QueueSender<Arc<Transaction<G, Utxo>>>,
+        QueueReceiver<Arc<Transaction<G, Utxo>>>,
+    ),
+    witness_requests: Vec<(QueueSender<WitnessRequest>, QueueReceiver<WitnessRequest>)>,
+}
+
+impl<G: UnknownOrderGroup> SimulationBuilder<G> {
+    /// Sets up genesis, where each user has a single UTXO, and the queues between components.
+    pub fn new(config: &Config, metrics: Arc<Metrics>) -> Self {
+        let genesis = Genesis::<G>::new(&config.users_per_bridge, &mut rand::thread_rng());
+        debug!(
+            "initial accumulator with {} utxo sets: {:#?}\n",
+            config.num_users(),
+            genesis.acc
+        );
+        Self {
+            config: config.clone(),
+            genesis,
+            blocks: new_queue(config, Channel::Blocks, &metrics),
+            transactions: new_queue(config, Channel::Transactions, &metrics),
+            witness_requests: (0..config.num_bridges())
+                .map(|_| new_queue(config, Channel::WitnessRequests, &metrics))
+                .collect(),
+            metrics,
+        }
+    }
+
+    /// A stream of every block broadcast by a miner. It must be read, or the queue fills up.
+    pub fn subscribe_blocks(&self) -> QueueReceiver<Arc<Block<G, Utxo>>> {
+        self.blocks.1.add_stream()
+    }
+
+    /// A stream of every transaction that reaches the miners. It must be read, or the queue fills
+    /// up.
+    pub fn subscribe_transactions(&self) -> QueueReceiver<Arc<Transaction<G, Utxo>>> {
+        self.transactions.1.add_stream()
+    }
+
+    /// A stream of every witness request that reaches bridge `bridge_id`. It must be read, or the
+    /// queue fills up.
+    pub fn subscribe_witness_requests(&self, bridge_id: usize) -> QueueReceiver<WitnessRequest> {
+        self.witness_requests[bridge_id].1.add_stream()
+    }
+
+    /// Creates every component, with a channel for each link between them.
+    pub fn build(self) -> Components<G> {
+        let Self {
+            config,
+            metrics,
+            genesis,
+            blocks: (block_sender, block_receiver),
+            transactions: (tx_sender, tx_receiver),
+            witness_requests,
+        } = self;
+
+        let proof_cache = if config.cache_proof_verification {
+            Some(Arc::new(ProofCache::new()))
+        } else {
+            None
+        };
+        if config.cache_primes {
+            prime_cache::enable();
+        }
+        if config.pool_integers {
+            int_pool::enable();
+        }
+        let prime_hasher = config
+            .hash_to_prime
+            .map(|hash_to_prime| Arc::new(PrimeHasher::new(hash_to_prime, metrics.clone())));
+        let witness_pool = config.witness_threads.map(bridge::witness_pool);
+        let mut bridges = Vec::new();
+        let mut users = Vec::new();
+        for (bridge_id, (witness_request_sender, witness_request_receiver)) in
+            witness_requests.into_iter().enumerate()
+        {
+            let bridge = NodeId::Bridge(bridge_id);
+            let mut witness_response_senders = HashMap::new();
+            let mut user_update_senders = HashMap::new();
+            for user_id in genesis.bridge_users[bridge_id].clone() {
+                let user = NodeId::User(user_id);
+                let (witness_response_sender, witness_response_receiver) =
+                    new_queue(&config, Channel::WitnessResponses, &metrics);
+                let (user_update_sender, user_update_receiver) =
+                    new_queue(&config, Channel::UserUpdates, &metrics);
+                let to_user = Some(user);
+                witness_response_senders.insert(
+                    user_id,
+                    link(
+                        witness_response_sender,
+                        &config,
+                        bridge,
+                        to_user,
+                        Channel::WitnessResponses,
+                        &metrics,
+                    ),
+                );
+                user_update_senders.insert(
+                    user_id,
+                    link(
+                        user_update_sender,
+                        &config,
+                        bridge,
+                        to_user,
+                        Channel::UserUpdates,
+                        &metrics,
+                    ),
+                );
+                users.push(UserHandle {
+                    user_id,
+                    bridge_id,
+                    utxo: genesis.user_utxos[user_id].clone(),
+                    witness_request_sender: link(
+                        witness_request_sender.clone(),
+                        &config,
+                        user,
+                        Some(bridge),
+                        Channel::WitnessRequests,
+                        &metrics,
+                    ),
+                    witness_response_receiver,
+                    user_update_receiver,
+                    tx_sender: link(
+                        tx_sender.clone(),
+                        &config,
+                        user,
+                        None,
+                        Channel::Transactions,
+                        &metrics,
+                    ),
+                    retry_timeout: config.retry_timeout(),
+                });
+            }
+
+            // A bridge's initial witness is the accumulator without its users' UTXOs.
+            debug!(
+                "bridge {} - init_witness: {:#?}\n",
+                bridge_id, genesis.bridge_witnesses[bridge_id]
+            );
+            bridges.push(BridgeHandle {
+                bridge_id,
+                acc: genesis.acc.clone(),
+                utxo_set_witness: genesis.bridge_witnesses[bridge_id].clone(),
+                utxo_set: genesis.bridge_utxos(bridge_id),
+                block_receiver: block_receiver.add_stream(),
+                witness_request_receiver,
+                witness_response_senders,
+                user_update_senders,
+                // Bridges serving the REST API or sending receipts keep every UTXO, to prove
+                // non-membership, and bridges refreshing their witness keep them to re-derive it.
+                all_utxos: if config.rest_addr.is_some()
+                    || config.spent_receipts
+                    || config.witness_refresh_interval.is_some()
+                {
+                    Some(genesis.user_utxos.clone())
+                } else {
+                    None
+                },
+                send_spent_receipts: config.spent_receipts,
+                require_fresh_utxos: config.prove_fresh_utxos,
+                witness_refresh_interval: config.witness_refresh_interval,
+                proof_cache: proof_cache.clone(),
+                witness_pool: witness_pool.clone(),
+                witness_batching: config.witness_batching(),
+            });
+        }
+
+        let leader = config.initial_leader();
+        let miners = (0..config.num_miners)
+            .map(|miner_id| MinerHandle {
+                miner_id,
+                is_leader: leader == Some(miner_id),
+                byzantine: config.byzantine_behavior(miner_id),
+                acc: genesis.acc.clone(),
+                block_interval_ms: config.block_interval_ms,
+                block_sender: link(
+                    block_sender.clone(),
+                    &config,
+                    NodeId::Miner(miner_id),
+                    None,
+                    Channel::Blocks,
+                    &metrics,
+                ),
+                block_receiver: block_receiver.add_stream(),
+                tx_receiver: tx_receiver.add_stream(),
+                utxo_set: if config.prove_fresh_utxos {
+                    Some(genesis.user_utxos.clone())
+                } else {
+                    None
+                },
+                proof_cache: proof_cache.clone(),
+                prime_hasher: prime_hasher.clone(),
+                multiswap: config.multiswap,
+                pipeline_validation: config.pipeline_validation,
+            })
+            .collect();
+        // Only subscribers read these; an unread stream would fill up.
+        block_receiver.unsubscribe();
+        tx_receiver.unsubscribe();
+
+        Components {
+            genesis,
+            miners,
+            bridges,
+            users,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use super::miner::Miner;
+use super::state::{self, Block, Transaction, UpdateProof, Valued};
+use super::swap::SwapProof;
+use super::util;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::AccError;
+use serde::Deserialize;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How a Byzantine miner corrupts the blocks it forges.
+pub enum Behavior {
+    /// Claims the block leaves the accumulator unchanged, so the proofs no longer match it.
+    InvalidProof,
+    /// Numbers the block one past the next height.
+    SkipHeight,
+    /// Includes the block's first transaction a second time.
+    DoubleInclusion,
+    /// Re-creates a UTXO the block spends, so it can be spent again: the transaction spending it
+    /// creates it anew in place of its own outputs, so creates no more value than it spends. Only
+    /// caught when blocks must prove the UTXOs they create are fresh, or prove their update with a
+    /// single swap, which cannot both delete and add a UTXO.
+    Reinsertion,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+/// A miner that, instead of following the leader, forges corrupt blocks of its own every block
+/// interval and broadcasts them alongside the leader's.
+pub struct ByzantineMiner {
+    pub miner: usize,
+    pub behavior: Behavior,
+}
+
+impl<
+        G: UnknownOrderGroup,
+        T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+    > Miner<G, T>
+{
+    /// Builds the block an honest miner would, then corrupts it according to `behavior`. Unlike
+    /// honest blocks, corrupt ones are not recorded in the metrics.
+    pub fn forge_byzantine_block(&self, behavior: Behavior) -> Result<Block<G, T>, AccError> {
+        let mut block = self.build_block()?;
+        info!("Forging a corrupt block ({:?}).", behavior);
+        match behavior {
+            Behavior::InvalidProof => {
+                block.acc_new = self.acc();
+                block.acc_commitment = state::acc_commitment(&block.acc_new);
+            }
+            Behavior::SkipHeight => block.height += 1,
+            Behavior::DoubleInclusion => {
+                if let Some(transaction) = block.transactions.first().cloned() {
+                    block.transactions.push(transaction);
+                }
+            }
+            Behavior::Reinsertion => {
+                let (_, elems_deleted) = util::elems_from_transactions(&block.transactions);
+                if let Some((utxo, _witness)) = elems_deleted.into_iter().next() {
+                    let spender = block
+                        .transactions
+                        .iter()
+                        .position(|transaction| {
+                            transaction
+                                .utxos_spent_with_witnesses
+                                .iter()
+                                .any(|(spent, _witness)| *spent == utxo)
+                        })
+                        .expect("A UTXO the block spends has a transaction spending it");
+                    let spent = block.transactions[spender]
+                        .utxos_spent_with_witnesses
+                        .clone();
+                    block.transactions[spender] = Arc::new(Transaction {
+                        utxos_created: vec![utxo],
+                        utxos_spent_with_witnesses: spent,
+                    });
+                    let (elems_added, elems_deleted) =
+                        util::elems_from_transactions(&block.transactions);
+                    match &mut block.proof_update {
+                        UpdateProof::Sequential { deleted, added } => {
+                            let (acc_new, proof_added) =
+                                deleted.witness.0.clone().add_with_proof(&elems_added);
+                            block.acc_new = acc_new;
+                            *added = proof_added;
+                        }
+                        UpdateProof::Swap(proof) => {
+                            block.acc_new = self.acc().delete(&elems_deleted)?.add(&elems_added);
+                            let elems_deleted: Vec<T> = elems_deleted
+                                .into_iter()
+                                .map(|(elem, _witness)| elem)
+                                .collect();
+                            *proof = SwapProof::prove_from(
+                                &self.acc(),
+                                &block.acc_new,
+                                &elems_deleted,
+                                &elems_added,
+                            );
+                        }
+                    }
+                    block.acc_commitment = state::acc_commitment(&block.acc_new);
+                }
+            }
+        }
+        Ok(block)
+    }
+}
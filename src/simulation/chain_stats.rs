@@ -0,0 +1,164 @@
+use super::proofs::{
+    DELETION_PROOF_BYTES, MEMBERSHIP_PROOF_BYTES, NONMEMBERSHIP_PROOF_BYTES, SWAP_PROOF_BYTES,
+};
+use super::state::{Block, UpdateProof};
+use accumulator::group::UnknownOrderGroup;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Blocks the rolling averages cover unless the config says otherwise.
+pub const DEFAULT_WINDOW: usize = 20;
+
+#[derive(Clone, Copy, Debug)]
+/// What a block observed on the block stream contributed to the averages.
+struct BlockSample {
+    /// Simulated time at which the block was seen.
+    at: Duration,
+    transactions: usize,
+    elems_added: usize,
+    elems_deleted: usize,
+    proof_bytes: usize,
+}
+
+#[derive(Default)]
+struct ChainStatsState {
+    /// The last blocks seen, oldest first, at most a window's worth.
+    recent: VecDeque<BlockSample>,
+    height: u64,
+    blocks: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize)]
+/// The chain's rolling averages at one moment, over the last `window_blocks` blocks.
+pub struct ChainStatsSnapshot {
+    /// Blocks seen over the whole run.
+    pub blocks: u64,
+    pub height: u64,
+    /// Blocks the averages cover: the window, or fewer early in the run.
+    pub window_blocks: usize,
+    /// Mean simulated time between consecutive blocks.
+    pub block_interval: Duration,
+    pub transactions_per_block: f64,
+    pub elems_added_per_block: f64,
+    pub elems_deleted_per_block: f64,
+    /// Mean bytes of a block's deletion, membership, swap and freshness proofs.
+    pub proof_bytes_per_block: f64,
+}
+
+/// Rolling averages of the blocks on the block stream: the interval between them, and their
+/// transactions, additions, deletions and proof bytes. Only the first block seen at each height
+/// counts, so forks, duplicates and corrupt blocks racing honest ones do not skew them. Kept by
+/// the run's metrics, so they can be read while the run goes on, and given in its summary.
+pub struct ChainStats {
+    window: Mutex<usize>,
+    state: Mutex<ChainStatsState>,
+}
+
+impl Default for ChainStats {
+    fn default() -> Self {
+        Self {
+            window: Mutex::new(DEFAULT_WINDOW),
+            state: Mutex::new(ChainStatsState::default()),
+        }
+    }
+}
+
+impl ChainStats {
+    /// Has the averages cover the last `blocks` blocks (at least one).
+    pub fn set_window(&self, blocks: usize) {
+        *self.window.lock().unwrap() = blocks.max(1);
+    }
+
+    /// Takes in `block`, seen on the block stream at simulated time `at`.
+    pub fn observe<G: UnknownOrderGroup, T: Hash + Debug>(
+        &self,
+        at: Duration,
+        block: &Block<G, T>,
+    ) {
+        let window = *self.window.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        if block.height <= state.height {
+            return;
+        }
+        state.height = block.height;
+        state.blocks += 1;
+        state.recent.push_back(BlockSample {
+            at,
+            transactions: block.transactions.len(),
+            elems_added: block
+                .transactions
+                .iter()
+                .map(|transaction| transaction.utxos_created.len())
+                .sum(),
+            elems_deleted: block
+                .transactions
+                .iter()
+                .map(|transaction| transaction.utxos_spent_with_witnesses.len())
+                .sum(),
+            proof_bytes: proof_bytes(block),
+        });
+        while state.recent.len() > window {
+            state.recent.pop_front();
+        }
+    }
+
+    pub fn snapshot(&self) -> ChainStatsSnapshot {
+        let state = self.state.lock().unwrap();
+        let recent = &state.recent;
+        let mean = |value: fn(&BlockSample) -> usize| {
+            if recent.is_empty() {
+                return 0.0;
+            }
+            recent.iter().map(value).sum::<usize>() as f64 / recent.len() as f64
+        };
+        let block_interval = match (recent.front(), recent.back()) {
+            (Some(first), Some(last)) if recent.len() > 1 => {
+                (last.at - first.at) / (recent.len() - 1) as u32
+            }
+            _ => Duration::default(),
+        };
+        ChainStatsSnapshot {
+            blocks: state.blocks,
+            height: state.height,
+            window_blocks: recent.len(),
+            block_interval,
+            transactions_per_block: mean(|sample| sample.transactions),
+            elems_added_per_block: mean(|sample| sample.elems_added),
+            elems_deleted_per_block: mean(|sample| sample.elems_deleted),
+            proof_bytes_per_block: mean(|sample| sample.proof_bytes),
+        }
+    }
+}
+
+/// Bytes of `block`'s proofs, as they would go over the network.
+fn proof_bytes<G: UnknownOrderGroup, T: Hash + Debug>(block: &Block<G, T>) -> usize {
+    let update = match block.proof_update {
+        UpdateProof::Sequential { .. } => DELETION_PROOF_BYTES + MEMBERSHIP_PROOF_BYTES,
+        UpdateProof::Swap(_) => SWAP_PROOF_BYTES,
+    };
+    update
+        + block
+            .proof_fresh
+            .as_ref()
+            .map_or(0, |_| NONMEMBERSHIP_PROOF_BYTES)
+}
+
+impl Display for ChainStatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1?} apart, {:.1} transactions, {:.1} added, {:.1} deleted, {:.0} proof bytes \
+             (last {} blocks)",
+            self.block_interval,
+            self.transactions_per_block,
+            self.elems_added_per_block,
+            self.elems_deleted_per_block,
+            self.proof_bytes_per_block,
+            self.window_blocks
+        )
+    }
+}
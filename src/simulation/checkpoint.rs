@@ -0,0 +1,143 @@
+use super::state::{Block, NodeId, Transaction, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, Witness};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+/// A transaction waiting to be mined, without the witnesses to what it spends.
+pub struct PendingTransaction {
+    pub spent: Vec<Utxo>,
+    pub created: Vec<Utxo>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+/// The state of a discrete-event run at a block boundary, from which it can be resumed. Only the
+/// UTXO set is stored: the accumulator and every witness are determined by it, so they are
+/// recomputed on restore. Messages in flight are not stored; users whose transactions were lost
+/// that way start over.
+pub struct Checkpoint {
+    pub block_height: u64,
+    /// Every unspent output as of `block_height`.
+    pub utxos: Vec<Utxo>,
+    /// Transactions waiting in the tip miner's mempool.
+    pub mempool: Vec<PendingTransaction>,
+    /// The users served by each bridge, including any added during the run.
+    pub bridge_users: Vec<Vec<usize>>,
+    pub leader: Option<usize>,
+    /// Nodes stopped at the time of the checkpoint, which stay stopped until started again.
+    pub offline: Vec<NodeId>,
+    /// Seeds the restored run's randomness, so it never reuses the IDs of earlier UTXOs.
+    pub seed: u64,
+}
+
+impl Checkpoint {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    /// The UTXO set after applying `blocks` to `utxos`.
+    pub fn apply_blocks<G: UnknownOrderGroup>(
+        mut utxos: Vec<Utxo>,
+        blocks: &[Block<G, Utxo>],
+    ) -> Vec<Utxo> {
+        for transaction in blocks.iter().flat_map(|block| &block.transactions) {
+            let spent: HashSet<&Utxo> = transaction
+                .utxos_spent_with_witnesses
+                .iter()
+                .map(|(utxo, _)| utxo)
+                .collect();
+            utxos.retain(|utxo| !spent.contains(utxo));
+            utxos.extend(transaction.utxos_created.iter().cloned());
+        }
+        utxos
+    }
+
+    /// The accumulator over the checkpoint's UTXO set.
+    pub fn acc<G: UnknownOrderGroup>(&self) -> Accumulator<G, Utxo> {
+        Accumulator::empty().add(&self.utxos)
+    }
+
+    /// The UTXOs owned by `users`, and a witness for them, i.e. the accumulator of all the others.
+    pub fn utxos_of<G: UnknownOrderGroup>(
+        &self,
+        users: &HashSet<usize>,
+    ) -> (Vec<Utxo>, Witness<G, Utxo>) {
+        let (owned, others): (Vec<Utxo>, Vec<Utxo>) = self
+            .utxos
+            .iter()
+            .cloned()
+            .partition(|utxo| users.contains(&utxo.user_id));
+        (owned, Witness(Accumulator::empty().add(&others)))
+    }
+
+    /// The pending transactions, with witnesses recomputed against the checkpoint's accumulator.
+    /// Transactions spending outputs that are not in the UTXO set, or that an earlier transaction
+    /// spends, could never be mined, and are dropped.
+    pub fn mempool<G: UnknownOrderGroup>(&self) -> Vec<Transaction<G, Utxo>> {
+        let unspent: HashSet<&Utxo> = self.utxos.iter().collect();
+        let mut spent = HashSet::new();
+        let mut pending = Vec::new();
+        for transaction in &self.mempool {
+            let is_minable = transaction
+                .spent
+                .iter()
+                .all(|utxo| unspent.contains(utxo) && !spent.contains(utxo));
+            if is_minable {
+                spent.extend(transaction.spent.iter());
+                pending.push(transaction);
+            }
+        }
+        if spent.is_empty() {
+            return pending
+                .into_iter()
+                .map(|transaction| Transaction {
+                    utxos_created: transaction.created.clone(),
+                    utxos_spent_with_witnesses: Vec::new(),
+                })
+                .collect();
+        }
+
+        let others: Vec<Utxo> = self
+            .utxos
+            .iter()
+            .filter(|utxo| !spent.contains(utxo))
+            .cloned()
+            .collect();
+        let spent: Vec<Utxo> = spent.into_iter().cloned().collect();
+        let witnesses = Witness(Accumulator::<G, Utxo>::empty().add(&others))
+            .compute_individual_witnesses(&spent);
+        pending
+            .into_iter()
+            .map(|transaction| Transaction {
+                utxos_created: transaction.created.clone(),
+                utxos_spent_with_witnesses: witnesses
+                    .iter()
+                    .filter(|(utxo, _)| transaction.spent.contains(utxo))
+                    .cloned()
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl<G: UnknownOrderGroup> From<&Transaction<G, Utxo>> for PendingTransaction {
+    fn from(transaction: &Transaction<G, Utxo>) -> Self {
+        Self {
+            spent: transaction
+                .utxos_spent_with_witnesses
+                .iter()
+                .map(|(utxo, _)| utxo.clone())
+                .collect(),
+            created: transaction.utxos_created.clone(),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A source of simulated time, shared by every component of a run. Components sleep through their
+/// clock instead of the OS, so the same code can run in wall-clock time, faster than it, or under
+/// manual control.
+pub trait Clock: Send + Sync {
+    /// Simulated time elapsed since the clock was created.
+    fn now(&self) -> Duration;
+
+    /// Blocks the calling thread until `duration` of simulated time has passed.
+    fn sleep(&self, duration: Duration);
+
+    /// How long `duration` of simulated time takes in real time, for components that wait on a
+    /// timer of their own rather than sleep. `None` if the clock does not move with real time.
+    fn real_time(&self, _duration: Duration) -> Option<Duration> {
+        None
+    }
+}
+
+/// Wall-clock time, accelerated by a constant factor (e.g. at a speedup of 10, a 5 second block
+/// interval passes in half a real second).
+pub struct SystemClock {
+    start: Instant,
+    speedup: u32,
+}
+
+impl SystemClock {
+    pub fn new(speedup: u32) -> Self {
+        assert!(speedup > 0, "Clock speedup must be positive.");
+        Self {
+            start: Instant::now(),
+            speedup,
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed() * self.speedup
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration / self.speedup);
+    }
+
+    fn real_time(&self, duration: Duration) -> Option<Duration> {
+        Some(duration / self.speedup)
+    }
+}
+
+#[derive(Default)]
+/// A clock that only moves when `advance` is called, e.g. by a test stepping through a run.
+/// Sleeping threads wake once the clock has been advanced past their wake-up time.
+pub struct ManualClock {
+    now: Mutex<Duration>,
+    advanced: Condvar,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward by `duration`, waking any threads whose sleep has elapsed.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+        self.advanced.notify_all();
+    }
+
+    /// Moves the clock forward to `now`. Does nothing if the clock is already past it.
+    pub fn advance_to(&self, now: Duration) {
+        let mut current = self.now.lock().unwrap();
+        if *current < now {
+            *current = now;
+            self.advanced.notify_all();
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        let wake_at = *now + duration;
+        while *now < wake_at {
+            now = self.advanced.wait(now).unwrap();
+        }
+    }
+}
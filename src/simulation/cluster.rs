@@ -0,0 +1,619 @@
+use super::auth::{self, Keyring, Signer};
+use super::bridge::{self, Bridge, UserUpdate, WitnessRequest, WitnessResponse};
+use super::clock::{Clock, SystemClock};
+use super::config::{ClusterTransport, Config, GroupKind, Mode, ModulusSource};
+use super::control::RunControl;
+use super::discovery::PeerList;
+use super::events::EventSink;
+use super::genesis::Genesis;
+use super::injection::FaultInjector;
+use super::int_pool;
+use super::metrics::Metrics;
+use super::miner::Miner;
+use super::noise::NoiseKeys;
+use super::prime_cache;
+use super::primes::PrimeHasher;
+#[cfg(feature = "quic")]
+use super::quic::{QuicNode, QuicReceiver, QuicSender};
+use super::shutdown::FinalState;
+use super::state::{Block, NodeId, Transaction, Utxo};
+use super::tcp::{Meter, TcpReceiver, TcpSender, Verifier};
+use super::transport::{BlockTopic, Receiver, SendError, Sender, TxTopic, WitnessChannel};
+use super::user::User;
+use super::wire::WireMessage;
+use accumulator::group::UnknownOrderGroup;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, Deserialize)]
+/// A simulation deployed as a small cluster of processes talking over TCP or QUIC: one per miner,
+/// one per bridge, and a user swarm per bridge running all of its users. Every process loads the
+/// same file, with the config they all run under and the peer list they find each other by:
+///
+/// ```yaml
+/// config:
+///   num_miners: 2
+///   users_per_bridge: [3]
+///   seed: 7
+/// peers:
+///   - node: { miner: 0 }
+///     addr: 10.0.0.2:7000
+///   - node: { miner: 1 }
+///     addr: 10.0.0.3:7000
+///   - node: { bridge: 0 }
+///     addr: 10.0.0.4:7000
+///   - node: { user: 0 }
+///     addr: 10.0.0.5:7000
+/// ```
+///
+/// A miner or bridge gets blocks at its listed address, and transactions or witness requests one
+/// port up. A user swarm is listed under the first of its bridge's users; user `first + k` gets
+/// witness responses at the swarm's port + 2k, and UTXO updates one port above that.
+pub struct Deployment {
+    #[serde(default)]
+    pub config: Config,
+    #[serde(flatten)]
+    pub peers: PeerList,
+}
+
+#[derive(Debug)]
+pub enum DeploymentError {
+    Io(io::Error),
+    Parse(serde_yaml::Error),
+}
+
+impl From<io::Error> for DeploymentError {
+    fn from(err: io::Error) -> Self {
+        DeploymentError::Io(err)
+    }
+}
+
+impl From<serde_yaml::Error> for DeploymentError {
+    fn from(err: serde_yaml::Error) -> Self {
+        DeploymentError::Parse(err)
+    }
+}
+
+impl Deployment {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, DeploymentError> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str(&contents)?)
+    }
+}
+
+/// What a node binary is told to run:
+/// `--deployment <file> --<id_flag> <id> [--log <filter>] [--output <dir>]`.
+pub struct NodeArgs {
+    pub deployment: Deployment,
+    pub id: usize,
+    /// The log filter to use, from `--log` or `-v`, or else the deployment config's.
+    pub log_filter: String,
+    /// Directory to which the process exports its metrics as CSV when it exits, if any.
+    pub output_dir: Option<PathBuf>,
+}
+
+impl NodeArgs {
+    /// Parses the arguments the process was started with, panicking on bad ones as the simulation
+    /// runner does.
+    pub fn from_env(id_flag: &str) -> Self {
+        let mut deployment_path = None;
+        let mut id = None;
+        let mut log_filter = None;
+        let mut output_dir = None;
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--deployment" => {
+                    deployment_path = Some(args.next().expect("--deployment takes a file path"))
+                }
+                "--log" => log_filter = Some(args.next().expect("--log takes a filter")),
+                "-v" | "--verbose" => log_filter = Some("debug".to_string()),
+                "--output" => {
+                    output_dir = Some(args.next().expect("--output takes a directory").into())
+                }
+                flag if flag.trim_start_matches("--") == id_flag => {
+                    id = args.next().and_then(|id| id.parse().ok());
+                    assert!(id.is_some(), "{} takes a number", flag);
+                }
+                _ => panic!("Unknown argument: {}", arg),
+            }
+        }
+        let path = deployment_path.expect("--deployment is required");
+        let deployment = Deployment::from_file(&path).expect("Failed to load deployment");
+        let log_filter = log_filter.unwrap_or_else(|| deployment.config.log_filter.clone());
+        Self {
+            deployment,
+            id: id.unwrap_or_else(|| panic!("--{} is required", id_flag)),
+            log_filter,
+            output_dir,
+        }
+    }
+}
+
+/// The sending end of a link between cluster processes, over the deployment's transport.
+pub enum ClusterSender<T> {
+    Tcp(TcpSender<T>),
+    #[cfg(feature = "quic")]
+    Quic(QuicSender<T>),
+}
+
+/// The receiving end of a link between cluster processes, over the deployment's transport.
+pub enum ClusterReceiver<T> {
+    Tcp(TcpReceiver<T>),
+    #[cfg(feature = "quic")]
+    Quic(QuicReceiver<T>),
+}
+
+impl<T: WireMessage + 'static> Sender<T> for ClusterSender<T> {
+    fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        match self {
+            ClusterSender::Tcp(sender) => Sender::try_send(sender, message),
+            #[cfg(feature = "quic")]
+            ClusterSender::Quic(sender) => Sender::try_send(sender, message),
+        }
+    }
+
+    fn send(&self, message: T) {
+        match self {
+            ClusterSender::Tcp(sender) => Sender::send(sender, message),
+            #[cfg(feature = "quic")]
+            ClusterSender::Quic(sender) => Sender::send(sender, message),
+        }
+    }
+}
+
+impl<T: WireMessage + Send + 'static> Receiver<T> for ClusterReceiver<T> {
+    fn try_receive(&self) -> Option<T> {
+        match self {
+            ClusterReceiver::Tcp(receiver) => receiver.try_receive(),
+            #[cfg(feature = "quic")]
+            ClusterReceiver::Quic(receiver) => receiver.try_receive(),
+        }
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        match self {
+            ClusterReceiver::Tcp(receiver) => receiver.receive_timeout(timeout),
+            #[cfg(feature = "quic")]
+            ClusterReceiver::Quic(receiver) => receiver.receive_timeout(timeout),
+        }
+    }
+}
+
+/// Links between cluster processes, over TCP or QUIC as the deployment says.
+pub struct ClusterLinks;
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> BlockTopic<G, T> for ClusterLinks
+where
+    Block<G, T>: WireMessage + Send + Sync + 'static,
+{
+    type BlockSender = ClusterSender<Arc<Block<G, T>>>;
+    type BlockReceiver = ClusterReceiver<Arc<Block<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> TxTopic<G, T> for ClusterLinks
+where
+    Transaction<G, T>: WireMessage + Send + Sync + 'static,
+{
+    type TxSender = ClusterSender<Arc<Transaction<G, T>>>;
+    type TxReceiver = ClusterReceiver<Arc<Transaction<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup> WitnessChannel<G> for ClusterLinks
+where
+    WitnessResponse<G, Utxo>: WireMessage + Send + 'static,
+    UserUpdate<G>: WireMessage + Send + 'static,
+{
+    type RequestSender = ClusterSender<WitnessRequest>;
+    type RequestReceiver = ClusterReceiver<WitnessRequest>;
+    type ResponseSender = ClusterSender<WitnessResponse<G, Utxo>>;
+    type ResponseReceiver = ClusterReceiver<WitnessResponse<G, Utxo>>;
+    type UpdateSender = ClusterSender<UserUpdate<G>>;
+    type UpdateReceiver = ClusterReceiver<UserUpdate<G>>;
+}
+
+fn port_above(addr: SocketAddr, offset: u16) -> SocketAddr {
+    let mut addr = addr;
+    addr.set_port(addr.port() + offset);
+    addr
+}
+
+/// One process of a deployment, which runs a single miner, a single bridge, or a bridge's users.
+/// The run ends once the chain this process sees reaches the bounds in the config, or when it is
+/// stopped through its run control. Since a message sent to a process that is not up yet is lost,
+/// bridges and user swarms should be started before the miners.
+pub struct ClusterNode<G: UnknownOrderGroup> {
+    config: Config,
+    peers: PeerList,
+    genesis: Genesis<G>,
+    clock: Arc<dyn Clock>,
+    control: Arc<RunControl>,
+    metrics: Arc<Metrics>,
+    faults: Arc<FaultInjector>,
+    /// The keys messages are signed and connections encrypted with, as the config says.
+    keyring: Arc<Keyring>,
+    /// Whether to encrypt connections: the config says to, and this build can.
+    encrypt: bool,
+    /// The QUIC endpoint links are made from, if the config says to talk over QUIC.
+    #[cfg(feature = "quic")]
+    quic: Option<QuicNode>,
+}
+
+impl<G: UnknownOrderGroup> ClusterNode<G>
+where
+    Block<G, Utxo>: WireMessage + Send + 'static,
+    Transaction<G, Utxo>: WireMessage + Send + 'static,
+    WitnessResponse<G, Utxo>: WireMessage + Send + 'static,
+    UserUpdate<G>: WireMessage + Send + 'static,
+{
+    pub fn new(deployment: Deployment) -> Self {
+        let Deployment { config, peers } = deployment;
+        // Every process derives the same genesis from the shared seed.
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let genesis = Genesis::new(&config.users_per_bridge, &mut rng);
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock::new(config.speedup));
+        let metrics = Arc::new(Metrics::new(clock.clone()));
+        if let Some(sink) = EventSink::from_config(&config) {
+            metrics.stream_events(sink);
+        }
+        let faults = Arc::new(FaultInjector::new(
+            config.injected_faults.clone(),
+            metrics.clone(),
+        ));
+        if config.compress_blocks && !cfg!(feature = "compression") {
+            warn!("Blocks are sent uncompressed: this build lacks the compression feature.");
+        }
+        let encrypt = config.encrypt_links && cfg!(feature = "encryption");
+        if config.encrypt_links && !encrypt {
+            warn!("Connections are not encrypted: this build lacks the encryption feature.");
+        }
+        #[cfg(feature = "quic")]
+        let quic = match config.transport {
+            ClusterTransport::Quic => {
+                Some(QuicNode::new(metrics.clone()).expect("Failed to start QUIC"))
+            }
+            ClusterTransport::Tcp => None,
+        };
+        #[cfg(not(feature = "quic"))]
+        {
+            if config.transport == ClusterTransport::Quic {
+                warn!("Processes talk over TCP: this build lacks the quic feature.");
+            }
+        }
+        if config.cache_proof_verification {
+            warn!("Each process runs a single node, so there are no checked proofs to share.");
+        }
+        if config.cache_primes {
+            prime_cache::enable();
+        }
+        if config.pool_integers {
+            int_pool::enable();
+        }
+        if let (GroupKind::Rsa, ModulusSource::Generate(_)) = (config.group, &config.rsa_modulus) {
+            warn!("Each process generates an RSA modulus of its own: give them one in a file.");
+        }
+        let keyring = Arc::new(Keyring::new(config.seed));
+        Self {
+            config,
+            peers,
+            genesis,
+            clock,
+            control: Arc::new(RunControl::new()),
+            metrics,
+            faults,
+            keyring,
+            encrypt,
+            #[cfg(feature = "quic")]
+            quic,
+        }
+    }
+
+    /// The run state an operator can use to pause, resume and stop this process.
+    pub fn control(&self) -> Arc<RunControl> {
+        self.control.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Exports this process's metrics as CSV to `dir`, if given, logging any failure.
+    pub fn write_metrics(&self, dir: Option<&Path>) {
+        if let Some(dir) = dir {
+            if let Err(err) = self.metrics.write_csv(dir) {
+                warn!("Failed to export metrics to {}: {}", dir.display(), err);
+            }
+        }
+    }
+
+    fn addr(&self, node: NodeId) -> io::Result<SocketAddr> {
+        self.peers.addr(node).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} has no address in the peer list", node),
+            )
+        })
+    }
+
+    fn signer(&self, node: NodeId) -> Option<Signer> {
+        if self.config.authenticate_messages {
+            Some(Signer::new(node, self.keyring.clone(), self.faults.clone()))
+        } else {
+            None
+        }
+    }
+
+    fn verifier(&self) -> Option<Verifier> {
+        if self.config.authenticate_messages {
+            Some(Verifier::new(self.keyring.clone(), self.metrics.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// `node`'s Noise keys, from the environment or the config's key directory. Only a
+    /// discrete-event config, which is meant to be reproducible, falls back to keys derived from
+    /// the seed, which anyone who knows the seed can derive too.
+    fn noise_keys(&self, node: NodeId) -> NoiseKeys {
+        match auth::load_noise_keys(node, self.config.noise_keys.as_deref()) {
+            Ok(keys) => keys,
+            Err(err) if self.config.mode == Mode::DiscreteEvent => {
+                warn!(
+                    "Deriving {:?}'s Noise keys from the seed, which is no secret: {}.",
+                    node, err
+                );
+                self.keyring.noise_keys(node)
+            }
+            Err(err) => panic!("Failed to load {:?}'s Noise keys: {}", node, err),
+        }
+    }
+
+    /// A sender from `node` to `peers`, signing what it sends if messages are authenticated. Over
+    /// TCP, it also compresses blocks and encrypts connections if the config says to.
+    fn sender<T: WireMessage>(&self, node: NodeId, peers: Vec<SocketAddr>) -> ClusterSender<T> {
+        #[cfg(feature = "quic")]
+        {
+            if let Some(quic) = &self.quic {
+                let mut sender = quic.sender(node, peers);
+                if let Some(signer) = self.signer(node) {
+                    sender = sender.signed(signer);
+                }
+                return ClusterSender::Quic(sender);
+            }
+        }
+        let mut sender = TcpSender::new(node, peers, self.metrics.clone());
+        if self.config.compress_blocks {
+            sender = sender.compressing_blocks();
+        }
+        if self.encrypt {
+            sender = sender.encrypted(self.noise_keys(node));
+        }
+        if let Some(signer) = self.signer(node) {
+            sender = sender.signed(signer);
+        }
+        ClusterSender::Tcp(sender)
+    }
+
+    /// A receiver for `node` on `addr`, checking signatures on what it receives if messages are
+    /// authenticated. Over TCP, it only accepts encrypted connections if the config says to.
+    fn receiver<T: WireMessage + Send + 'static>(
+        &self,
+        node: NodeId,
+        addr: SocketAddr,
+    ) -> io::Result<ClusterReceiver<T>> {
+        #[cfg(feature = "quic")]
+        {
+            if let Some(quic) = &self.quic {
+                return Ok(ClusterReceiver::Quic(quic.receiver(
+                    node,
+                    addr,
+                    self.verifier(),
+                )?));
+            }
+        }
+        let keys = if self.encrypt {
+            Some(self.noise_keys(node))
+        } else {
+            None
+        };
+        let meter = Meter::new(node, self.metrics.clone());
+        let receiver = TcpReceiver::bind_with(addr, self.verifier(), keys, Some(meter))?;
+        Ok(ClusterReceiver::Tcp(receiver))
+    }
+
+    /// Stops the run once the chain reaches the bounds in the config, as seen from this process.
+    fn watch_bounds(&self) {
+        let config = self.config.clone();
+        let clock = self.clock.clone();
+        let control = self.control.clone();
+        let metrics = self.metrics.clone();
+        thread::spawn(move || {
+            while !control.is_stopped() {
+                let block_height = metrics.chain_height();
+                if config.is_finished(block_height, clock.now()) {
+                    info!("Run bounds reached at block {}.", block_height);
+                    control.stop();
+                }
+                clock.sleep(Duration::from_millis(100));
+            }
+        });
+    }
+
+    /// Runs miner `miner_id` until the run ends.
+    pub fn run_miner(&self, miner_id: usize) -> io::Result<FinalState> {
+        let addr = self.addr(NodeId::Miner(miner_id))?;
+        let node = NodeId::Miner(miner_id);
+        let block_receiver = self.receiver(node, addr)?;
+        let tx_receiver = self.receiver(node, port_above(addr, 1))?;
+        // A miner applies its own blocks as it receives them, so it sends them to itself too.
+        let mut block_peers = self.peers.miners(None);
+        block_peers.extend(self.peers.bridges::<SocketAddr>(None));
+        let block_sender = self.sender(node, block_peers);
+        let mut miner = Miner::new(miner_id, self.genesis.acc.clone(), self.metrics.clone());
+        if self.config.prove_fresh_utxos {
+            miner.keep_utxo_set(self.genesis.user_utxos.clone());
+        }
+        if let Some(hash_to_prime) = self.config.hash_to_prime {
+            miner.hash_to_primes(Arc::new(PrimeHasher::new(
+                hash_to_prime,
+                self.metrics.clone(),
+            )));
+        }
+        if self.config.multiswap {
+            miner.prove_multiswaps();
+        }
+        if self.config.pipeline_validation {
+            miner.pipeline_validation();
+        }
+        self.watch_bounds();
+        info!("Miner {} listening on {}.", miner_id, addr);
+        let state = Miner::<G, Utxo>::start::<ClusterLinks>(
+            Arc::new(miner),
+            self.config.initial_leader() == Some(miner_id),
+            self.config.byzantine_behavior(miner_id),
+            self.config.block_interval_ms,
+            self.clock.clone(),
+            self.control.clone(),
+            self.faults.clone(),
+            &block_sender,
+            block_receiver,
+            tx_receiver,
+        );
+        let compression = self.metrics.compression();
+        if compression.raw_bytes > 0 {
+            info!(
+                "Compressing blocks saved {} of {} bytes.",
+                compression.bytes_saved(),
+                compression.raw_bytes
+            );
+        }
+        Ok(state)
+    }
+
+    /// Runs bridge `bridge_id` until the run ends.
+    pub fn run_bridge(&self, bridge_id: usize) -> io::Result<FinalState> {
+        let addr = self.addr(NodeId::Bridge(bridge_id))?;
+        let node = NodeId::Bridge(bridge_id);
+        let block_receiver = self.receiver(node, addr)?;
+        let witness_request_receiver = self.receiver(node, port_above(addr, 1))?;
+        let users = self.genesis.bridge_users[bridge_id].clone();
+        let swarm = self.addr(NodeId::User(users.start))?;
+        let mut witness_response_senders = HashMap::new();
+        let mut user_update_senders = HashMap::new();
+        for (k, user_id) in users.clone().enumerate() {
+            let responses = port_above(swarm, 2 * k as u16);
+            witness_response_senders.insert(user_id, self.sender(node, vec![responses]));
+            user_update_senders.insert(user_id, self.sender(node, vec![port_above(responses, 1)]));
+        }
+        self.watch_bounds();
+        info!("Bridge {} listening on {}.", bridge_id, addr);
+        let mut bridge = Bridge::new(
+            bridge_id,
+            self.genesis.acc.clone(),
+            self.genesis.bridge_witnesses[bridge_id].clone(),
+            self.genesis.bridge_utxos(bridge_id),
+            users.collect(),
+            self.metrics.clone(),
+        );
+        if self.config.spent_receipts || self.config.witness_refresh_interval.is_some() {
+            bridge.keep_all_utxos(self.genesis.user_utxos.clone());
+        }
+        if self.config.spent_receipts {
+            bridge.send_spent_receipts();
+        }
+        if let Some(interval) = self.config.witness_refresh_interval {
+            bridge.refresh_witnesses_every(interval);
+        }
+        if self.config.prove_fresh_utxos {
+            bridge.require_fresh_utxos();
+        }
+        if let Some(threads) = self.config.witness_threads {
+            bridge.generate_witnesses_on(bridge::witness_pool(threads));
+        }
+        if let Some(batching) = self.config.witness_batching() {
+            bridge.coalesce_witness_requests(batching);
+        }
+        Ok(Bridge::<G>::start::<ClusterLinks>(
+            Arc::new(Mutex::new(bridge)),
+            block_receiver,
+            witness_request_receiver,
+            witness_response_senders,
+            user_update_senders,
+            self.clock.clone(),
+            self.control.clone(),
+            self.faults.clone(),
+        ))
+    }
+
+    /// Runs every user of bridge `bridge_id`, each on its own thread, until the run ends.
+    pub fn run_users(&self, bridge_id: usize) -> io::Result<Vec<FinalState>> {
+        let users = self.genesis.bridge_users[bridge_id].clone();
+        let swarm = self.addr(NodeId::User(users.start))?;
+        let bridge = port_above(self.addr(NodeId::Bridge(bridge_id))?, 1);
+        let miners: Vec<SocketAddr> = self
+            .peers
+            .miners::<SocketAddr>(None)
+            .into_iter()
+            .map(|miner| port_above(miner, 1))
+            .collect();
+        let mut threads = Vec::new();
+        for (k, user_id) in users.enumerate() {
+            let responses = port_above(swarm, 2 * k as u16);
+            let node = NodeId::User(user_id);
+            let witness_response_receiver = self.receiver(node, responses)?;
+            let user_update_receiver = self.receiver(node, port_above(responses, 1))?;
+            let witness_request_sender = self.sender(node, vec![bridge]);
+            let tx_sender = self.sender(node, miners.clone());
+            let utxo = self.genesis.user_utxos[user_id].clone();
+            let retry_timeout = self.config.retry_timeout();
+            let clock = self.clock.clone();
+            let control = self.control.clone();
+            let metrics = self.metrics.clone();
+            let faults = self.faults.clone();
+            threads.push(thread::spawn(move || {
+                User::<G>::start::<ClusterLinks>(
+                    user_id,
+                    bridge_id,
+                    utxo,
+                    &witness_request_sender,
+                    &witness_response_receiver,
+                    &user_update_receiver,
+                    &tx_sender,
+                    retry_timeout,
+                    clock.as_ref(),
+                    &control,
+                    metrics,
+                    &faults,
+                )
+            }));
+        }
+        self.watch_bounds();
+        info!(
+            "{} users of bridge {} listening from {}.",
+            threads.len(),
+            bridge_id,
+            swarm
+        );
+        let mut states = Vec::new();
+        for thread in threads {
+            match thread.join() {
+                Ok(state) => states.push(state),
+                Err(_) => warn!("A user panicked."),
+            }
+        }
+        Ok(states)
+    }
+}
@@ -0,0 +1,74 @@
+use super::util;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+#[derive(Debug)]
+/// Why a value could not be encoded or decoded.
+pub enum CodecError {
+    Bincode(bincode::Error),
+    /// The text to decode is not valid hex.
+    Hex,
+}
+
+impl From<bincode::Error> for CodecError {
+    fn from(err: bincode::Error) -> Self {
+        CodecError::Bincode(err)
+    }
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Bincode(err) => write!(f, "{}", err),
+            CodecError::Hex => write!(f, "Not valid hex"),
+        }
+    }
+}
+
+impl Error for CodecError {}
+
+/// Bytes and hex for the chain state the simulation passes around: accumulators, witnesses and
+/// proofs. Chain exports, checkpoints, transports and the APIs all carry them this way, as bincode,
+/// so that one can be decoded from what another wrote.
+pub trait Encode {
+    fn to_bytes(&self) -> Result<Vec<u8>, CodecError>;
+
+    /// The value's bytes as lowercase hex.
+    fn to_hex(&self) -> Result<String, CodecError> {
+        Ok(util::to_hex(&self.to_bytes()?))
+    }
+}
+
+/// The inverse of `Encode`.
+pub trait Decode: Sized {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, CodecError>;
+
+    fn from_hex(hex: &str) -> Result<Self, CodecError> {
+        Self::from_bytes(&util::from_hex(hex).ok_or(CodecError::Hex)?)
+    }
+}
+
+impl<V: Serialize> Encode for V {
+    fn to_bytes(&self) -> Result<Vec<u8>, CodecError> {
+        Ok(bincode::serialize(self)?)
+    }
+}
+
+impl<V: DeserializeOwned> Decode for V {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, CodecError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Displays a value as the hex of its bytes, e.g. `format!("{}", Hex(&acc))` to log an
+/// accumulator.
+pub struct Hex<'a, V>(pub &'a V);
+
+impl<'a, V: Encode> Display for Hex<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = self.0.to_hex().map_err(|_| fmt::Error)?;
+        f.write_str(&hex)
+    }
+}
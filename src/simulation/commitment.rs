@@ -0,0 +1,214 @@
+use super::deletion::DeletionProof;
+use super::network::ELEM_BYTES;
+use super::proofs::{DELETION_PROOF_BYTES, MEMBERSHIP_PROOF_BYTES};
+use super::witnesses::WitnessSet;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{AccError, Accumulator, MembershipProof, Witness};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+#[derive(Debug)]
+/// Why a commitment or a store of witnesses could not be updated.
+pub enum CommitmentError {
+    Accumulator(AccError),
+    /// An element to delete or to serve a witness to is not in the set.
+    Missing,
+    /// An element to add is already in the set.
+    Present,
+    /// Applying a block's changes did not give the commitment the block names.
+    Mismatch,
+}
+
+impl From<AccError> for CommitmentError {
+    fn from(err: AccError) -> Self {
+        CommitmentError::Accumulator(err)
+    }
+}
+
+/// A commitment to a set of elements, as a chain keeps it: the miner updates it block by block
+/// with a proof that nodes check against the commitment they hold, users spend elements with a
+/// witness to each, and bridges keep what they need to hand out those witnesses.
+///
+/// Implemented by the accumulator and by `merkle::MerkleTree`, so a workload can be run against
+/// each and their proof sizes and bandwidth compared. See `baseline::BaselineDemo`. Miners and
+/// bridges themselves work on the accumulator directly rather than through this trait.
+pub trait SetCommitment<T>: Sized {
+    /// What blocks carry and nodes check against: the accumulator itself, or a tree's root.
+    type Digest: Clone + PartialEq;
+    type Witness: Clone;
+    /// A block's proof that its commitment follows from the one before.
+    type Proof;
+    /// What a bridge keeps to hand out witnesses to its users' elements.
+    type Store: WitnessStore<T, Self>;
+
+    /// A name for the backend in reports.
+    const NAME: &'static str;
+
+    /// A commitment to `elems`, e.g. at genesis.
+    fn commit(elems: &[T]) -> Self;
+
+    fn digest(&self) -> Self::Digest;
+
+    /// Deletes `deleted` and adds `added`, returning a proof of the update.
+    fn update(
+        &mut self,
+        added: &[T],
+        deleted: &[(T, Self::Witness)],
+    ) -> Result<Self::Proof, CommitmentError>;
+
+    /// Whether `proof` shows that `new_digest` is `digest` with `deleted` deleted and `added`
+    /// added.
+    fn verify_update(
+        digest: &Self::Digest,
+        new_digest: &Self::Digest,
+        added: &[T],
+        deleted: &[T],
+        proof: &Self::Proof,
+    ) -> bool;
+
+    /// Whether `witness` shows that `elem` is in the set `digest` commits to.
+    fn verify_membership(digest: &Self::Digest, elem: &T, witness: &Self::Witness) -> bool;
+
+    /// Estimated bytes of a digest, a witness and a proof on the wire.
+    fn digest_bytes() -> usize;
+    fn witness_bytes(witness: &Self::Witness) -> usize;
+    fn proof_bytes(proof: &Self::Proof) -> usize;
+
+    /// Estimated bytes a miner keeps to update the commitment.
+    fn state_bytes(&self) -> usize;
+}
+
+/// What a bridge keeps to hand out witnesses to the elements it tracks, brought up to date with
+/// each block.
+pub trait WitnessStore<T, C: SetCommitment<T>>: Sized {
+    /// A store tracking `tracked`, out of the set of `all` elements.
+    fn new(all: &[T], tracked: &[T]) -> Self;
+
+    /// Applies a block that deleted `deleted` and added `added`, giving `digest`. Elements for
+    /// which `is_tracked` holds are tracked from then on.
+    fn update(
+        &mut self,
+        digest: &C::Digest,
+        added: &[T],
+        deleted: &[T],
+        is_tracked: &dyn Fn(&T) -> bool,
+    ) -> Result<(), CommitmentError>;
+
+    /// A witness to each of `elems`, which must all be tracked.
+    fn witnesses(&self, elems: &[T]) -> Result<Vec<(T, C::Witness)>, CommitmentError>;
+
+    /// Estimated bytes kept to serve witnesses, besides the elements themselves.
+    fn state_bytes(&self) -> usize;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug> SetCommitment<T> for Accumulator<G, T> {
+    type Digest = Self;
+    type Witness = Witness<G, T>;
+    type Proof = (DeletionProof<G, T>, MembershipProof<G, T>);
+    type Store = WitnessSet<G, T>;
+
+    const NAME: &'static str = "accumulator";
+
+    fn commit(elems: &[T]) -> Self {
+        Accumulator::empty().add(elems)
+    }
+
+    fn digest(&self) -> Self {
+        self.clone()
+    }
+
+    fn update(
+        &mut self,
+        added: &[T],
+        deleted: &[(T, Witness<G, T>)],
+    ) -> Result<Self::Proof, CommitmentError> {
+        let (acc_deleted, proof_deleted) = DeletionProof::prove(self, deleted)?;
+        let (acc_new, proof_added) = acc_deleted.add_with_proof(added);
+        *self = acc_new;
+        Ok((proof_deleted, proof_added))
+    }
+
+    fn verify_update(
+        acc: &Self,
+        acc_new: &Self,
+        added: &[T],
+        deleted: &[T],
+        (proof_deleted, proof_added): &Self::Proof,
+    ) -> bool {
+        proof_deleted.verify(acc, deleted)
+            && acc_new.verify_membership_batch(added, proof_added)
+            && proof_deleted.witness == proof_added.witness
+    }
+
+    fn verify_membership(acc: &Self, elem: &T, witness: &Witness<G, T>) -> bool {
+        witness.0.clone().add(&[elem.clone()]) == *acc
+    }
+
+    fn digest_bytes() -> usize {
+        ELEM_BYTES
+    }
+
+    fn witness_bytes(_witness: &Witness<G, T>) -> usize {
+        ELEM_BYTES
+    }
+
+    fn proof_bytes(_proof: &Self::Proof) -> usize {
+        DELETION_PROOF_BYTES + MEMBERSHIP_PROOF_BYTES
+    }
+
+    fn state_bytes(&self) -> usize {
+        ELEM_BYTES
+    }
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug> WitnessStore<T, Accumulator<G, T>>
+    for WitnessSet<G, T>
+{
+    fn new(all: &[T], tracked: &[T]) -> Self {
+        let untracked: Vec<T> = all
+            .iter()
+            .filter(|elem| !tracked.contains(elem))
+            .cloned()
+            .collect();
+        WitnessSet::new(
+            tracked.to_vec(),
+            Witness(Accumulator::empty().add(&untracked)),
+        )
+    }
+
+    fn update(
+        &mut self,
+        acc_new: &Accumulator<G, T>,
+        added: &[T],
+        deleted: &[T],
+        is_tracked: &dyn Fn(&T) -> bool,
+    ) -> Result<(), CommitmentError> {
+        let (tracked_added, untracked_added): (Vec<T>, Vec<T>) =
+            added.iter().cloned().partition(is_tracked);
+        let (tracked_deleted, untracked_deleted): (Vec<T>, Vec<T>) = deleted
+            .iter()
+            .cloned()
+            .partition(|elem| self.contains(elem));
+        WitnessSet::update(
+            self,
+            acc_new,
+            &tracked_added,
+            &tracked_deleted,
+            &untracked_added,
+            &untracked_deleted,
+        )?;
+        Ok(())
+    }
+
+    fn witnesses(&self, elems: &[T]) -> Result<Vec<(T, Witness<G, T>)>, CommitmentError> {
+        if !elems.iter().all(|elem| self.contains(elem)) {
+            return Err(CommitmentError::Missing);
+        }
+        Ok(WitnessSet::witnesses(self, elems)?)
+    }
+
+    /// A single witness to every tracked element.
+    fn state_bytes(&self) -> usize {
+        ELEM_BYTES
+    }
+}
@@ -0,0 +1,487 @@
+use super::bridge::WitnessBatching;
+use super::byzantine::{Behavior, ByzantineMiner};
+use super::chain_stats;
+use super::injection::InjectedFault;
+use super::network::NetworkConfig;
+use super::transport::Channel;
+use super::watchdog::WatchdogConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How simulated components are driven.
+pub enum Mode {
+    /// Every component runs on its own threads, polling its channels in wall-clock time.
+    RealTime,
+    /// Components are stepped on a single thread by a discrete-event scheduler with a virtual
+    /// clock, so runs are as fast as the CPU allows and fully deterministic for a given seed.
+    DiscreteEvent,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What runs the components of a real-time run.
+pub enum ComponentRuntime {
+    /// A thread for each of a component's loops, blocking on its links.
+    Threads,
+    /// A task for each component on a tokio runtime with a thread per CPU, waiting on all of its
+    /// links at once without holding a thread. Needs the `async-runtime` feature.
+    Tokio,
+}
+
+impl Default for ComponentRuntime {
+    fn default() -> Self {
+        ComponentRuntime::Threads
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// The group the accumulator works in.
+pub enum GroupKind {
+    /// A toy RSA group with a 100-bit modulus: fast, but offers no security.
+    Rsa100,
+    /// The RSA-2048 group, whose modulus came from a trusted setup.
+    Rsa2048,
+    /// An RSA group whose modulus is set up as the run starts, as `rsa_modulus` says.
+    Rsa,
+    /// A class group of an imaginary quadratic field, which needs no trusted setup but is slower
+    /// to compute in.
+    ClassGroup,
+    /// A 2048-bit RSA group whose factors are drawn from the seed and kept, so powers are taken
+    /// modulo the group's order: far faster, for testing logic at large populations, and
+    /// completely insecure. See `trapdoor::TrapdoorRsa`.
+    Trapdoor,
+}
+
+impl Default for GroupKind {
+    fn default() -> Self {
+        GroupKind::Rsa100
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Where the modulus of the `rsa` group comes from.
+pub enum ModulusSource {
+    /// Generated from two fresh random primes of half this many bits each, which are discarded
+    /// once multiplied, as a setup ceremony would.
+    Generate(u32),
+    /// Read from a file holding it in decimal or, prefixed with `0x`, in hex, e.g. one published
+    /// by a past ceremony.
+    File(PathBuf),
+}
+
+impl Default for ModulusSource {
+    fn default() -> Self {
+        ModulusSource::Generate(2048)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// How an element's hash is turned into a prime.
+pub enum PrimeStrategy {
+    /// Hashes the element and a counter with Blake2b, counting up until the hash is prime, as the
+    /// accumulator crate does.
+    Blake2bRehash,
+    /// The same, with SHA-256.
+    Sha256Rehash,
+    /// Hashes the element once with SHA-256, then steps to the next prime above the hash. Fewer
+    /// hashes, but primes after long gaps are likelier to be picked.
+    Sha256NextPrime,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+/// A hash-to-prime strategy to measure over a run's elements.
+pub struct HashToPrime {
+    pub strategy: PrimeStrategy,
+    /// Bits of each prime, from 2 to 256. Fewer bits make primes cheaper to find and to
+    /// exponentiate by, but likelier to be shared by two elements.
+    #[serde(default = "default_prime_bits")]
+    pub bits: u32,
+}
+
+fn default_prime_bits() -> u32 {
+    256
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// What the accumulator holds over a run.
+pub enum PayloadKind {
+    /// The UTXO set, spent and added to by users' transactions through miners and bridges.
+    Utxos,
+    /// A sparse vector commitment to a bit per user, which users flip and open. See
+    /// `vector::VectorDemo`.
+    VectorCommitment,
+}
+
+impl Default for PayloadKind {
+    fn default() -> Self {
+        PayloadKind::Utxos
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// What the processes of a cluster deployment talk over.
+pub enum ClusterTransport {
+    /// A TCP connection per link, with frames sent one after another.
+    Tcp,
+    /// A QUIC connection per link, with a stream per message. Needs the `quic` feature.
+    Quic,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+/// Parameters for a simulation run. When loaded from a file, omitted fields take their defaults.
+pub struct Config {
+    pub mode: Mode,
+    /// The group the accumulator works in.
+    pub group: GroupKind,
+    /// Where the modulus of the `rsa` group comes from. Every process of a cluster deployment
+    /// needs the same one, so a launched cluster generates it once and hands it to them in a file.
+    pub rsa_modulus: ModulusSource,
+    /// What the accumulator holds. Vector-commitment runs only use the group, users, block
+    /// interval, seed and bounds of the config.
+    pub payload: PayloadKind,
+    pub num_miners: usize,
+    /// Miners that forge corrupt blocks instead of following the leader.
+    pub byzantine_miners: Vec<ByzantineMiner>,
+    /// Number of users assigned to each bridge. Its length is the number of bridges.
+    pub users_per_bridge: Vec<usize>,
+    pub block_interval_ms: u64,
+    /// In real-time mode, simulated time runs this many times faster than wall-clock time.
+    pub speedup: u32,
+    /// In real-time mode, how long components get to shut down once the run ends, in wall-clock
+    /// milliseconds. Those that take longer are left behind and reported without a final state.
+    pub shutdown_timeout_ms: u64,
+    /// In real-time mode, whether components run on threads of their own or as tasks on a tokio
+    /// runtime, which fits thousands of users in a thread per CPU.
+    pub runtime: ComponentRuntime,
+    /// Message latency, loss and duplication.
+    pub network: NetworkConfig,
+    /// In real-time mode, how many messages each queue between components holds, unless
+    /// `queue_capacities` sets its channel's.
+    pub queue_capacity: usize,
+    /// In real-time mode, how many messages the queues of particular channels hold, e.g.
+    /// `{blocks: 16, witness_requests: 1024}`. The summary gives each channel's drop rate, to tune
+    /// them by.
+    pub queue_capacities: BTreeMap<Channel, usize>,
+    /// In real-time mode, how long a sender waits for room in a full queue before giving up on a
+    /// message: users back off and try again later, while others drop it.
+    pub queue_wait_ms: u64,
+    /// Crashes, slowdowns, corrupt messages, freezes and forged senders to inject into components
+    /// as the chain grows.
+    pub injected_faults: Vec<InjectedFault>,
+    /// Sign blocks, transactions and witness responses with the sender's key and have recipients
+    /// check the signatures, rejecting forged messages. Applies to discrete-event runs and
+    /// cluster deployments, where keys are derived from the seed.
+    pub authenticate_messages: bool,
+    /// Have miners keep every UTXO and prove in each block, with a non-membership proof, that the
+    /// UTXOs it creates were not already in the accumulator. Every node then rejects blocks that
+    /// do not, so a miner cannot create a UTXO a second time.
+    pub prove_fresh_utxos: bool,
+    /// Have the miners and bridges of a process share the results of checking blocks' proofs, and
+    /// the batch proofs of catching up, so each is checked once rather than by every component
+    /// that receives it. See `proof_cache::ProofCache`.
+    pub cache_proof_verification: bool,
+    /// Cache the prime representing each UTXO the first time a deletion or swap proof derives it,
+    /// for every component of the process to reuse in those proofs rather than search for it
+    /// again. Additions, witnesses and membership checks go through the accumulator crate, which
+    /// derives primes itself. The summary gives the proofs' hit rate. See `prime_cache`.
+    pub cache_primes: bool,
+    /// Have the products of primes that deletion and swap proofs work with reuse the integers of
+    /// blocks before, kept by each thread, rather than allocate them afresh. See `int_pool`.
+    pub pool_integers: bool,
+    /// Have miners validate the blocks they receive in a pipeline of threads, one per stage, so
+    /// the next block's cheap checks overlap the proof checks of the one before. Real-time runs
+    /// only. See `pipeline::ValidationPipeline`.
+    pub pipeline_validation: bool,
+    /// Have miners prove each block's accumulator update with a single swap of the UTXOs spent
+    /// for those created, rather than a deletion proof and a membership proof. Every node checks
+    /// whichever proof a block comes with. See `swap::SwapProof`.
+    pub multiswap: bool,
+    /// A hash-to-prime strategy for the leader to run over every UTXO it creates, recording the
+    /// primality tests it takes, its time and any collisions, if any. See `primes::PrimeHasher`.
+    pub hash_to_prime: Option<HashToPrime>,
+    /// Whether to repeat the run's workload against the accumulator and against a sparse Merkle
+    /// tree once it ends, to compare their proof sizes and bandwidth. See `baseline::BaselineDemo`.
+    pub merkle_baseline: bool,
+    /// In discrete-event runs, have the leader answer a node catching up with one proof for all
+    /// the blocks it missed, which the node checks in place of each block's proofs. The proof is
+    /// worked out from the UTXO set, so only a leader keeping it, with `prove_fresh_utxos`, makes
+    /// one; otherwise the blocks are sent to be checked one by one as usual.
+    pub batch_sync: bool,
+    /// Have bridges send each user, with the update for a block spending its UTXOs, a receipt: a
+    /// non-membership proof that they are no longer in the accumulator, which the user checks and
+    /// keeps to settle any later dispute over them. Bridges keep every UTXO to prove it.
+    pub spent_receipts: bool,
+    /// In discrete-event runs, have the leader publish with each block what a holder of a witness
+    /// needs to bring it up to date, and have users keep their own witnesses from it: they spend
+    /// without asking their bridge, and learn of their UTXOs from the updates rather than from it.
+    /// A user that misses an update asks its bridge for its UTXOs, and for witnesses to any it
+    /// could not keep current.
+    pub publish_witness_updates: bool,
+    /// Every this many blocks, have bridges re-derive their witness from every UTXO in the
+    /// accumulator rather than update it, so errors cannot build up along the chain of updates,
+    /// and have users that keep their own witnesses fetch fresh ones from their bridge. Bridges
+    /// then keep every UTXO, and also re-derive their witness when a block's update fails, and
+    /// users that miss a witness update fetch fresh ones at once.
+    pub witness_refresh_interval: Option<u64>,
+    /// Have bridges compute witnesses on a pool of this many threads, or one per CPU if 0,
+    /// splitting requests for many UTXOs and bursts of requests across them, rather than serving
+    /// each request in turn on a single thread. In-process runs share one pool between bridges.
+    pub witness_threads: Option<usize>,
+    /// Have bridges, once a witness request arrives, wait up to this many milliseconds of real
+    /// time for more, up to `witness_batch_size`, and answer them all from one witness computation
+    /// against the same accumulator. Real-time runs only.
+    pub witness_batch_window_ms: Option<u64>,
+    /// At most how many witness requests a bridge answers together.
+    pub witness_batch_size: usize,
+    /// Every this many blocks, recompute the accumulator from scratch from the UTXO set an
+    /// auditor keeps alongside the chain, and stop the run if any miner's differs. Only the
+    /// discrete-event scheduler runs an auditor. See `auditor::Auditor`.
+    pub audit_interval: Option<u64>,
+    /// Each time a bridge applies a block, check its users' UTXOs against a plain UTXO set kept
+    /// alongside the chain: every unspent one must have a witness that verifies, and no spent one
+    /// may, or the run stops with the difference. Meant for tests, and only the discrete-event
+    /// scheduler checks. See `invariants::InvariantChecker`.
+    pub check_invariants: bool,
+    /// In cluster deployments, compress blocks with zstd when sending them to peers that say they
+    /// can decompress them. Needs the `compression` feature.
+    pub compress_blocks: bool,
+    /// In cluster deployments, encrypt connections between processes with Noise, using the keys
+    /// `noise_keys` points to. Needs the `encryption` feature.
+    pub encrypt_links: bool,
+    /// The directory of the Noise keys of a cluster deployment that encrypts its links, unless
+    /// they are in the environment. A launched cluster generates them. See
+    /// `auth::load_noise_keys`.
+    pub noise_keys: Option<PathBuf>,
+    /// In cluster deployments, what processes talk over.
+    pub transport: ClusterTransport,
+    /// Users whose transaction is not confirmed within this many block intervals assume a message
+    /// was lost and try again (or never do, if zero).
+    pub retry_after_blocks: u64,
+    /// Have the watchdog alert when the chain goes this many block intervals without a new block.
+    pub watchdog_block_intervals: Option<u64>,
+    /// Have the watchdog alert when a user's transaction goes this many blocks unconfirmed.
+    pub watchdog_unconfirmed_blocks: Option<u64>,
+    /// In real-time mode, have the watchdog alert when a channel's queue stays full this many
+    /// milliseconds.
+    pub watchdog_full_channel_ms: Option<u64>,
+    /// Have the watchdog stop the run at the first stall it alerts on, logging where every
+    /// component and queue stood. See `watchdog::Watchdog`.
+    pub watchdog_abort: bool,
+    /// Seed for all randomness in discrete-event mode (UTXO ids, request ids).
+    pub seed: u64,
+    /// Directory to which metrics are exported as CSV, if any.
+    pub output_dir: Option<PathBuf>,
+    /// Metrics are re-exported every this many blocks (or never, if zero), as well as at the end of
+    /// the run.
+    pub metrics_interval_blocks: u64,
+    /// How many of the latest blocks the chain's rolling averages (block interval, transactions,
+    /// additions, deletions and proof bytes per block) cover.
+    pub chain_stats_window: usize,
+    /// Address on which to serve live metrics to Prometheus, e.g. `127.0.0.1:9898`.
+    pub prometheus_addr: Option<String>,
+    /// In real-time mode, address at which miner 0 serves its gRPC API, e.g. `127.0.0.1:50051`.
+    /// Miner `i` serves on the port `i` above it. Needs the `grpc` feature.
+    pub grpc_addr: Option<String>,
+    /// In real-time mode, address at which miner 0 serves Bitcoin-style JSON-RPC, e.g.
+    /// `127.0.0.1:8332`. Miner `i` serves on the port `i` above it.
+    pub jsonrpc_addr: Option<String>,
+    /// In real-time mode, address at which bridge 0 serves its REST API, e.g. `127.0.0.1:8080`.
+    /// Bridge `i` serves on the port `i` above it. Bridges then keep every UTXO, to prove
+    /// non-membership.
+    pub rest_addr: Option<String>,
+    /// In real-time mode, address at which to serve a WebSocket feed of blocks and user updates as
+    /// JSON events, e.g. `127.0.0.1:9001`.
+    pub websocket_addr: Option<String>,
+    /// Show a live terminal dashboard instead of log output. Needs the `tui` feature.
+    pub dashboard: bool,
+    /// File to which a JSON-lines feed of the topology, blocks and messages is streamed for a web
+    /// front end, if any. Messages are only recorded in discrete-event mode.
+    pub viz_output: Option<PathBuf>,
+    /// File to which the event log is streamed as JSON lines while the run goes on, or `-` for
+    /// standard output, if any.
+    pub event_stream: Option<PathBuf>,
+    /// File to which every message of a discrete-event run is recorded, if any.
+    pub trace_output: Option<PathBuf>,
+    /// A recorded trace to replay: messages arrive when they did in the recording. Replaying a
+    /// trace under the config and seed it was recorded with reproduces that run exactly.
+    pub replay_trace: Option<PathBuf>,
+    /// File to which the state of a discrete-event run is saved when it ends, if any.
+    pub checkpoint_output: Option<PathBuf>,
+    /// A checkpoint to resume from instead of genesis, overriding `users_per_bridge` and `seed`.
+    /// Block heights carry on from the checkpoint, while virtual time starts again at zero.
+    pub restore_from: Option<PathBuf>,
+    /// Which log output to show, in `tracing_subscriber::EnvFilter` syntax. `RUST_LOG` overrides it.
+    pub log_filter: String,
+    /// Print nothing at all, neither logs nor the final summary, e.g. for benchmarks and CI.
+    /// Metrics are still exported.
+    pub quiet: bool,
+    /// The run ends once the chain reaches this height, if set.
+    pub max_blocks: Option<u64>,
+    /// The run ends once this much simulated time has passed, if set.
+    pub max_duration_ms: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: Mode::RealTime,
+            group: GroupKind::Rsa100,
+            rsa_modulus: ModulusSource::default(),
+            payload: PayloadKind::Utxos,
+            num_miners: 5,
+            byzantine_miners: Vec::new(),
+            users_per_bridge: vec![3; 5],
+            block_interval_ms: 5000,
+            speedup: 1,
+            shutdown_timeout_ms: 5000,
+            runtime: ComponentRuntime::Threads,
+            network: NetworkConfig::default(),
+            queue_capacity: 256,
+            queue_capacities: BTreeMap::new(),
+            queue_wait_ms: 100,
+            injected_faults: Vec::new(),
+            authenticate_messages: false,
+            prove_fresh_utxos: false,
+            cache_proof_verification: false,
+            cache_primes: false,
+            pool_integers: false,
+            pipeline_validation: false,
+            multiswap: false,
+            hash_to_prime: None,
+            merkle_baseline: false,
+            batch_sync: false,
+            spent_receipts: false,
+            publish_witness_updates: false,
+            witness_refresh_interval: None,
+            witness_threads: None,
+            witness_batch_window_ms: None,
+            witness_batch_size: 64,
+            audit_interval: None,
+            check_invariants: false,
+            compress_blocks: false,
+            encrypt_links: false,
+            noise_keys: None,
+            transport: ClusterTransport::Tcp,
+            retry_after_blocks: 3,
+            watchdog_block_intervals: None,
+            watchdog_unconfirmed_blocks: None,
+            watchdog_full_channel_ms: None,
+            watchdog_abort: false,
+            seed: 0,
+            output_dir: None,
+            metrics_interval_blocks: 10,
+            chain_stats_window: chain_stats::DEFAULT_WINDOW,
+            prometheus_addr: None,
+            grpc_addr: None,
+            jsonrpc_addr: None,
+            rest_addr: None,
+            websocket_addr: None,
+            dashboard: false,
+            viz_output: None,
+            event_stream: None,
+            trace_output: None,
+            replay_trace: None,
+            checkpoint_output: None,
+            restore_from: None,
+            log_filter: "info".to_string(),
+            quiet: false,
+            max_blocks: None,
+            max_duration_ms: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn queue_wait(&self) -> Duration {
+        Duration::from_millis(self.queue_wait_ms)
+    }
+
+    /// How many messages each queue of `channel` holds.
+    pub fn channel_capacity(&self, channel: Channel) -> usize {
+        self.queue_capacities
+            .get(&channel)
+            .copied()
+            .unwrap_or(self.queue_capacity)
+    }
+
+    /// How miner `miner_id` misbehaves, if it is Byzantine.
+    pub fn byzantine_behavior(&self, miner_id: usize) -> Option<Behavior> {
+        self.byzantine_miners
+            .iter()
+            .find(|byzantine| byzantine.miner == miner_id)
+            .map(|byzantine| byzantine.behavior)
+    }
+
+    /// The lowest-numbered honest miner, which leads the run.
+    pub fn initial_leader(&self) -> Option<usize> {
+        (0..self.num_miners).find(|miner_id| self.byzantine_behavior(*miner_id).is_none())
+    }
+
+    pub fn num_bridges(&self) -> usize {
+        self.users_per_bridge.len()
+    }
+
+    pub fn num_users(&self) -> usize {
+        self.users_per_bridge.iter().sum()
+    }
+
+    /// How long a user waits for its transaction to be confirmed before trying again, if at all.
+    pub fn retry_timeout(&self) -> Option<Duration> {
+        if self.retry_after_blocks == 0 {
+            return None;
+        }
+        Some(Duration::from_millis(
+            self.retry_after_blocks * self.block_interval_ms,
+        ))
+    }
+
+    /// How bridges gather witness requests to answer together, if they wait for them.
+    pub fn witness_batching(&self) -> Option<WitnessBatching> {
+        self.witness_batch_window_ms.map(|ms| WitnessBatching {
+            window: Duration::from_millis(ms),
+            max_requests: self.witness_batch_size.max(1),
+        })
+    }
+
+    /// What the watchdog takes for a stall, if any of its checks is on.
+    pub fn watchdog(&self) -> Option<WatchdogConfig> {
+        let config = WatchdogConfig {
+            block_timeout: self
+                .watchdog_block_intervals
+                .map(|intervals| Duration::from_millis(intervals * self.block_interval_ms)),
+            unconfirmed_blocks: self.watchdog_unconfirmed_blocks,
+            full_channel_timeout: self.watchdog_full_channel_ms.map(Duration::from_millis),
+            abort: self.watchdog_abort,
+        };
+        if config.block_timeout.is_none()
+            && config.unconfirmed_blocks.is_none()
+            && config.full_channel_timeout.is_none()
+        {
+            return None;
+        }
+        Some(config)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_millis(self.shutdown_timeout_ms)
+    }
+
+    /// Whether a run at `block_height` and simulated time `now` has reached either of its bounds.
+    /// Unbounded runs never finish.
+    pub fn is_finished(&self, block_height: u64, now: Duration) -> bool {
+        self.max_blocks.map_or(false, |max| block_height >= max)
+            || self
+                .max_duration_ms
+                .map_or(false, |max| now >= Duration::from_millis(max))
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use tracing::info;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// An operator command for a running simulation.
+pub enum Command {
+    Pause,
+    Resume,
+    /// Lets a paused simulation produce exactly one more block, then pauses it again.
+    Step,
+    /// Ends the run.
+    Stop,
+}
+
+impl Command {
+    pub fn parse(command: &str) -> Option<Self> {
+        match command.trim() {
+            "pause" => Some(Command::Pause),
+            "resume" => Some(Command::Resume),
+            "step" => Some(Command::Step),
+            "stop" => Some(Command::Stop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RunState {
+    paused: bool,
+    /// Blocks that may still be forged while paused.
+    step_permits: u64,
+    /// Whether a step's block is in flight, i.e. forged but the next block interval not yet over.
+    stepping: bool,
+    stopped: bool,
+}
+
+impl RunState {
+    fn is_blocked(&self) -> bool {
+        self.paused && self.step_permits == 0 && !self.stepping && !self.stopped
+    }
+}
+
+#[derive(Default)]
+/// Run state shared by every component. Components check in at the top of each loop iteration and
+/// block while the simulation is paused, instead of free-running.
+pub struct RunControl {
+    state: Mutex<RunState>,
+    changed: Condvar,
+}
+
+impl RunControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&self, command: Command) {
+        let mut state = self.state.lock().unwrap();
+        match command {
+            Command::Pause => state.paused = true,
+            Command::Resume => {
+                state.paused = false;
+                state.step_permits = 0;
+            }
+            Command::Step => {
+                state.paused = true;
+                state.step_permits += 1;
+            }
+            Command::Stop => state.stopped = true,
+        }
+        self.changed.notify_all();
+    }
+
+    /// Ends the run. Components exit their loops the next time they check in.
+    pub fn stop(&self) {
+        self.apply(Command::Stop);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.lock().unwrap().stopped
+    }
+
+    /// Whether components are held up: paused, with no step in progress. For components that
+    /// cannot block in `wait_while_paused`, such as tasks, to poll instead.
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().is_blocked()
+    }
+
+    /// Blocks while the simulation is paused. While a step is in progress, components keep running
+    /// so the stepped block propagates everywhere. Returns false once the run has been stopped.
+    pub fn wait_while_paused(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.is_blocked() {
+            state = self.changed.wait(state).unwrap();
+        }
+        !state.stopped
+    }
+
+    /// Called by the leader before forging. Ends any step in progress, then blocks while paused
+    /// unless a step permits one more block. Returns false once the run has been stopped.
+    pub fn wait_for_block_permit(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.stepping = false;
+        while state.is_blocked() {
+            state = self.changed.wait(state).unwrap();
+        }
+        if state.stopped {
+            return false;
+        }
+        if state.paused {
+            state.step_permits -= 1;
+            state.stepping = true;
+        }
+        self.changed.notify_all();
+        true
+    }
+}
+
+/// Applies operator commands from `commands` to `control` until the channel closes.
+pub fn listen(control: Arc<RunControl>, commands: Receiver<Command>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for command in commands {
+            info!("Simulation control: {:?}.", command);
+            control.apply(command);
+        }
+    })
+}
@@ -0,0 +1,192 @@
+use super::control::{Command, RunControl};
+use super::metrics::Metrics;
+use super::state::NodeId;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Sparkline, Table};
+use ratatui::{Frame, Terminal};
+use std::io::{self, Stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::error;
+
+/// How often the dashboard redraws, in wall-clock time.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many of the latest blocks the dashboard lists.
+const RECENT_BLOCKS: usize = 10;
+
+type DashboardTerminal = Terminal<CrosstermBackend<Stdout>>;
+
+/// A live terminal view of a run: chain height, miners' mempools, bridges' queues and recent
+/// blocks, read from the run's metrics. Keys `p`, `r`, `s` and `q` pause, resume, step and stop the
+/// run. The dashboard takes over the terminal until it is dropped.
+pub struct Dashboard {
+    closed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Dashboard {
+    pub fn start(metrics: Arc<Metrics>, control: Arc<RunControl>) -> io::Result<Self> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let thread_closed = closed.clone();
+        let thread = thread::spawn(move || {
+            if let Err(err) = refresh(&mut terminal, &metrics, &control, &thread_closed) {
+                error!("Dashboard failed: {}", err);
+            }
+            let _ = disable_raw_mode();
+            let _ = execute!(terminal.backend_mut(), LeaveAlternateScreen);
+            let _ = terminal.show_cursor();
+        });
+        Ok(Self {
+            closed,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Dashboard {
+    /// Closes the dashboard and gives the terminal back.
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Redraws until the dashboard is closed, forwarding operator keys to `control` in between.
+fn refresh(
+    terminal: &mut DashboardTerminal,
+    metrics: &Metrics,
+    control: &RunControl,
+    closed: &AtomicBool,
+) -> io::Result<()> {
+    while !closed.load(Ordering::SeqCst) {
+        terminal.draw(|frame| draw(frame, metrics))?;
+        if !event::poll(REFRESH_INTERVAL)? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let command = match key.code {
+                KeyCode::Char('p') => Command::Pause,
+                KeyCode::Char('r') => Command::Resume,
+                KeyCode::Char('s') => Command::Step,
+                KeyCode::Char('q') | KeyCode::Esc => Command::Stop,
+                _ => continue,
+            };
+            control.apply(command);
+        }
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, metrics: &Metrics) {
+    let live = metrics.live();
+    let blocks = metrics.blocks();
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(6),
+            Constraint::Length(5),
+            Constraint::Length(RECENT_BLOCKS as u16 + 3),
+        ])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(rows[1]);
+
+    let height = blocks.last().map_or(0, |block| block.height);
+    let status = Paragraph::new(format!(
+        "height {}   simulated time {:.1?}   dropped messages {}   \
+         [p]ause [r]esume [s]tep [q]uit",
+        height,
+        metrics.now(),
+        metrics.dropped_messages()
+    ))
+    .block(titled("Chain"));
+    frame.render_widget(status, rows[0]);
+
+    let miners = live.mempool_depths.iter().map(|(miner_id, depth)| {
+        let height = live.block_heights.get(&NodeId::Miner(*miner_id));
+        Row::new(vec![
+            miner_id.to_string(),
+            height.map_or("-".to_string(), u64::to_string),
+            depth.to_string(),
+        ])
+    });
+    let miners = Table::new(miners, [Constraint::Length(8); 3])
+        .header(header(&["miner", "height", "mempool"]))
+        .block(titled("Miners"));
+    frame.render_widget(miners, columns[0]);
+
+    let bridges = live.bridge_queue_lengths.iter().map(|(bridge_id, length)| {
+        let height = live.block_heights.get(&NodeId::Bridge(*bridge_id));
+        Row::new(vec![
+            bridge_id.to_string(),
+            height.map_or("-".to_string(), u64::to_string),
+            length.to_string(),
+        ])
+    });
+    let bridges = Table::new(bridges, [Constraint::Length(8); 3])
+        .header(header(&["bridge", "height", "queue"]))
+        .block(titled("Bridges"));
+    frame.render_widget(bridges, columns[1]);
+
+    let transactions: Vec<u64> = blocks
+        .iter()
+        .map(|block| block.transactions as u64)
+        .collect();
+    let width = rows[2].width.saturating_sub(2) as usize;
+    let sparkline = Sparkline::default()
+        .data(&transactions[transactions.len().saturating_sub(width)..])
+        .block(titled("Transactions per block"));
+    frame.render_widget(sparkline, rows[2]);
+
+    let recent = blocks.iter().rev().take(RECENT_BLOCKS).map(|block| {
+        Row::new(vec![
+            block.height.to_string(),
+            format!("{:.1?}", block.forged_at),
+            block.transactions.to_string(),
+            block.elems_added.to_string(),
+            block.elems_deleted.to_string(),
+            format!("{:.1?}", block.forge_time),
+        ])
+    });
+    let recent = Table::new(recent, [Constraint::Length(12); 6])
+        .header(header(&[
+            "height",
+            "forged at",
+            "txs",
+            "added",
+            "deleted",
+            "forge time",
+        ]))
+        .block(titled("Recent blocks"));
+    frame.render_widget(recent, rows[3]);
+}
+
+fn titled(title: &str) -> Block {
+    Block::default().borders(Borders::ALL).title(title)
+}
+
+fn header(titles: &[&'static str]) -> Row<'static> {
+    Row::new(titles.to_vec()).style(Style::default().add_modifier(Modifier::BOLD))
+}
@@ -0,0 +1,190 @@
+use super::int_pool;
+use super::multiexp::multi_exp;
+use super::prime_cache;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::hash::{blake2b, hash_to_prime};
+use accumulator::{AccError, Accumulator, Witness};
+use rug::{Assign, Integer};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "G::Elem: Serialize, Witness<G, T>: Serialize",
+    deserialize = "G::Elem: Deserialize<'de>, Witness<G, T>: Deserialize<'de>"
+))]
+/// A block's proof that deleting the UTXOs it spends takes the accumulator from `acc` to
+/// `witness`, i.e. that `acc = witness^x` for x the product of the spent UTXOs' primes.
+///
+/// It is a non-interactive proof of knowledge of exponent (NI-PoKE2, BBF V3 Section 3.3). With g
+/// the group's unknown-order element, ℓ a prime and α an integer hashed from the whole statement,
+/// the deleted elements included, the prover sends z = g^x, Q = (witness · g^α)^⌊x/ℓ⌋ and
+/// r = x mod ℓ, and the verifier checks that Q^ℓ · (witness · g^α)^r = acc · z^α. That shows the
+/// prover knows some x taking `witness` to `acc`; the verifier then checks that r is the product
+/// of the spent UTXOs' primes modulo ℓ, so x is that product, without computing it in full.
+pub struct DeletionProof<G: UnknownOrderGroup, T: Hash> {
+    /// The accumulator without the spent UTXOs, to which the block adds the UTXOs it creates.
+    pub witness: Witness<G, T>,
+    pub z: G::Elem,
+    pub q: G::Elem,
+    #[serde(serialize_with = "serialize_integer")]
+    #[serde(deserialize_with = "deserialize_integer")]
+    pub r: Integer,
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug> DeletionProof<G, T> {
+    /// Deletes `elem_witnesses` from `acc`, returning the accumulator without them and a proof
+    /// that it is one.
+    pub fn prove(
+        acc: &Accumulator<G, T>,
+        elem_witnesses: &[(T, Witness<G, T>)],
+    ) -> Result<(Accumulator<G, T>, Self), AccError> {
+        let acc_deleted = acc.clone().delete(elem_witnesses)?;
+        let elems: Vec<T> = elem_witnesses
+            .iter()
+            .map(|(elem, _witness)| elem.clone())
+            .collect();
+        let proof = Self::prove_from(acc, acc_deleted.clone(), &elems);
+        Ok((acc_deleted, proof))
+    }
+
+    /// A proof that `acc_deleted`, worked out by the caller, is `acc` with `elems` deleted.
+    pub fn prove_from(
+        acc: &Accumulator<G, T>,
+        acc_deleted: Accumulator<G, T>,
+        elems: &[T],
+    ) -> Self {
+        let mut x = int_pool::take();
+        x.assign(1);
+        for elem in elems {
+            prime_cache::multiply_by_prime(&mut x, elem);
+        }
+        let (u, w) = (acc_deleted.value(), acc.value());
+        let z = G::exp(&G::unknown_order_elem(), &x);
+        let (l, alpha) = challenge::<G, T>(u, w, &z, elems);
+        let mut quotient = int_pool::take();
+        let mut r = Integer::new();
+        (&mut *quotient, &mut r).assign(x.div_rem_euc_ref(&l));
+        let q = G::exp(&base::<G>(u, &alpha), &quotient);
+        Self {
+            witness: Witness(acc_deleted),
+            z,
+            q,
+            r,
+        }
+    }
+
+    /// Whether this proves that `acc` is the witness with `elems` added. The check is rearranged
+    /// as Q^ℓ · u^r · g^(α·r) = acc · z^α, u being the witness, so the three powers on the left
+    /// share one multi-exponentiation. Every exponent stays non-negative, as inverting a `z` from
+    /// the network need not be possible.
+    pub fn verify(&self, acc: &Accumulator<G, T>, elems: &[T]) -> bool {
+        let (u, w) = (self.witness.0.value(), acc.value());
+        let (l, alpha) = challenge::<G, T>(u, w, &self.z, elems);
+        if self.r < 0 || self.r >= l {
+            return false;
+        }
+        let mut x_mod_l = int_pool::take();
+        x_mod_l.assign(1);
+        for elem in elems {
+            prime_cache::multiply_by_prime(&mut x_mod_l, elem);
+            *x_mod_l %= &l;
+        }
+        if self.r != *x_mod_l {
+            return false;
+        }
+        let g = G::unknown_order_elem();
+        let alpha_r = Integer::from(&alpha * &self.r);
+        let lhs = multi_exp::<G>(&[(&self.q, &l), (u, &self.r), (&g, &alpha_r)]);
+        lhs == G::op(w, &G::exp(&self.z, &alpha))
+    }
+}
+
+/// The prime ℓ and integer α the verifier would have challenged the prover with, hashed from the
+/// base `u`, the result `w`, the commitment `z` to the exponent and the elements `elems` the
+/// exponent is claimed to be the product of. Without the elements, a proof for one set could be
+/// replayed for another whose product agrees with it modulo ℓ.
+fn challenge<G: UnknownOrderGroup, T: Hash>(
+    u: &G::Elem,
+    w: &G::Elem,
+    z: &G::Elem,
+    elems: &[T],
+) -> (Integer, Integer) {
+    let l = hash_to_prime(&(u, w, z, elems));
+    let alpha = blake2b(&(u, w, z, elems, &l));
+    (l, alpha)
+}
+
+/// u · g^α, the base the quotient and remainder of the exponent are raised to.
+fn base<G: UnknownOrderGroup>(u: &G::Elem, alpha: &Integer) -> G::Elem {
+    G::op(u, &G::exp(&G::unknown_order_elem(), alpha))
+}
+
+fn serialize_integer<S: Serializer>(value: &Integer, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_string_radix(16))
+}
+
+fn deserialize_integer<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Integer, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    Integer::from_str_radix(&hex, 16).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use accumulator::group::{ElemFrom, Group, Rsa2048};
+
+    type Acc = Accumulator<Rsa2048, &'static str>;
+
+    /// An accumulator, the one without "b" and "c", and a proof of deleting them.
+    fn deletion() -> (Acc, Acc, DeletionProof<Rsa2048, &'static str>) {
+        let acc_deleted = Acc::empty().add(&["a"]);
+        let acc = acc_deleted.clone().add(&["b", "c"]);
+        let proof = DeletionProof::prove_from(&acc, acc_deleted.clone(), &["b", "c"]);
+        (acc, acc_deleted, proof)
+    }
+
+    #[test]
+    fn test_honest_proof_verifies() {
+        let (acc, _, proof) = deletion();
+        assert!(proof.verify(&acc, &["b", "c"]));
+    }
+
+    #[test]
+    fn test_tampered_proof_fails() {
+        let (acc, _, proof) = deletion();
+        let mut tampered = proof.clone();
+        tampered.r += 1;
+        assert!(!tampered.verify(&acc, &["b", "c"]));
+        let mut tampered = proof;
+        tampered.z = Rsa2048::op(&tampered.z, &Rsa2048::unknown_order_elem());
+        assert!(!tampered.verify(&acc, &["b", "c"]));
+    }
+
+    #[test]
+    fn test_non_unit_commitment_fails() {
+        let (acc, _, proof) = deletion();
+        let mut tampered = proof;
+        tampered.z = Rsa2048::elem(0);
+        assert!(!tampered.verify(&acc, &["b", "c"]));
+    }
+
+    #[test]
+    fn test_wrong_elements_fail() {
+        let (acc, _, proof) = deletion();
+        assert!(!proof.verify(&acc, &["b"]));
+        assert!(!proof.verify(&acc, &["b", "d"]));
+        assert!(!proof.verify(&acc, &["a", "b", "c"]));
+    }
+
+    #[test]
+    fn test_swapped_accumulator_fails() {
+        let (acc, acc_deleted, proof) = deletion();
+        assert!(!proof.verify(&acc_deleted, &["b", "c"]));
+        let mut tampered = proof;
+        tampered.witness = Witness(acc.clone());
+        assert!(!tampered.verify(&acc, &["b", "c"]));
+    }
+}
@@ -0,0 +1,277 @@
+//! Diagrams of how the protocol is wired, for its documentation and for debugging it: the topology
+//! of a run as a Graphviz digraph, and the messages of one spend or one block, read back from a
+//! run's event log, as a Mermaid sequence diagram.
+use super::config::Config;
+use super::events::{EventLog, EventRecord, RunEvent, TraceId};
+use super::network::MessageKind;
+use super::state::NodeId;
+use super::viz::{self, VizNode};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Hex digits of a transaction ID shown in a diagram: enough to tell a block's transactions apart.
+const TXID_DIGITS: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// What a sequence diagram follows.
+pub enum Selection {
+    /// One spend, from the user's witness request to the update confirming its transaction.
+    Trace(TraceId),
+    /// The block at a height: its forging, the transactions it took in, the nodes that applied it,
+    /// and the confirmations of the transactions it took in.
+    Block(u64),
+}
+
+/// The topology of a run configured by `config` in Graphviz's DOT language, for `dot -Tsvg` and
+/// the like: a node for each miner, bridge and user, each bridge boxed with its users, and an edge
+/// for each pair of nodes that exchange messages, labelled with what they exchange.
+pub fn topology_dot(config: &Config) -> String {
+    let (nodes, links) = viz::topology(config);
+    let mut out = String::new();
+    writeln!(out, "digraph topology {{").unwrap();
+    writeln!(out, "  rankdir=LR;").unwrap();
+    for node in nodes.iter().filter(|node| node.kind == "miner") {
+        writeln!(out, "  {}", dot_node(node)).unwrap();
+    }
+    for (bridge_id, bridge) in nodes
+        .iter()
+        .filter(|node| node.kind == "bridge")
+        .enumerate()
+    {
+        writeln!(out, "  subgraph cluster_bridge_{} {{", bridge_id).unwrap();
+        writeln!(out, "    label=\"bridge {}\";", bridge_id).unwrap();
+        writeln!(out, "    {}", dot_node(bridge)).unwrap();
+        for user in nodes.iter().filter(|node| node.bridge == Some(bridge_id)) {
+            writeln!(out, "    {}", dot_node(user)).unwrap();
+        }
+        writeln!(out, "  }}").unwrap();
+    }
+    for link in &links {
+        // Witnesses go both ways: users ask for them, and bridges answer and push UTXO updates.
+        let both_ways = if link.kind == MessageKind::Witness {
+            ", dir=both"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "  \"{}\" -> \"{}\" [label=\"{}\"{}];",
+            link.source,
+            link.target,
+            link.kind.name(),
+            both_ways
+        )
+        .unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+fn dot_node(node: &VizNode) -> String {
+    let shape = match node.kind {
+        "miner" => "box",
+        "bridge" => "hexagon",
+        _ => "ellipse",
+    };
+    format!("\"{}\" [shape={}];", node.id, shape)
+}
+
+/// The events of `log` that `selection` picks out as a Mermaid sequence diagram, each message or
+/// note stamped with when it happened. None if the log has no such events.
+///
+/// The log records what each component did rather than the messages between them, so a message
+/// is drawn where both of its ends are known, e.g. a transaction from the user who issued it to
+/// a miner that took it in, and a note over the one component otherwise.
+pub fn sequence_mermaid(log: &EventLog, selection: Selection) -> Option<String> {
+    let records: Vec<&EventRecord> = match selection {
+        Selection::Trace(trace) => log.journey(trace),
+        Selection::Block(height) => {
+            let traces: Vec<TraceId> = log
+                .records()
+                .iter()
+                .filter_map(|record| match record.event {
+                    RunEvent::TxIncluded {
+                        height: included,
+                        trace,
+                        ..
+                    } if included == height => trace,
+                    _ => None,
+                })
+                .collect();
+            log.records()
+                .iter()
+                .filter(|record| match record.event {
+                    RunEvent::BlockForged { height: at, .. }
+                    | RunEvent::BlockApplied { height: at, .. }
+                    | RunEvent::TxIncluded { height: at, .. } => at == height,
+                    RunEvent::TxConfirmed { trace, .. } => {
+                        trace.map_or(false, |trace| traces.contains(&trace))
+                    }
+                    _ => false,
+                })
+                .collect()
+        }
+    };
+    let mut sequence = Sequence::default();
+    for record in records {
+        sequence.add(record);
+    }
+    sequence.render()
+}
+
+#[derive(Default)]
+/// A sequence diagram as it is put together from event records, in the order they happened.
+struct Sequence {
+    /// Components in the order they first appear, which is the order Mermaid draws them in.
+    participants: Vec<NodeId>,
+    lines: Vec<String>,
+    /// The user that issued each transaction, by transaction ID.
+    issuers: HashMap<String, usize>,
+    /// The bridge each user asked for witnesses.
+    bridges: HashMap<usize, usize>,
+    /// The miner that forged the block at each height.
+    forgers: HashMap<u64, usize>,
+}
+
+impl Sequence {
+    fn add(&mut self, record: &EventRecord) {
+        let at = record.at();
+        match &record.event {
+            RunEvent::WitnessRequested {
+                user_id, bridge_id, ..
+            } => {
+                self.bridges.insert(*user_id, *bridge_id);
+                self.message(
+                    at,
+                    NodeId::User(*user_id),
+                    NodeId::Bridge(*bridge_id),
+                    "witness request",
+                );
+            }
+            RunEvent::WitnessServed {
+                bridge_id, user_id, ..
+            } => self.message(
+                at,
+                NodeId::Bridge(*bridge_id),
+                NodeId::User(*user_id),
+                "witnesses",
+            ),
+            RunEvent::TxIssued { user_id, txid, .. } => {
+                self.issuers.insert(txid.clone(), *user_id);
+                self.note(
+                    at,
+                    NodeId::User(*user_id),
+                    &format!("issues tx {}", short(txid)),
+                );
+            }
+            RunEvent::TxAccepted { miner_id, txid, .. } => {
+                let text = format!("tx {}", short(txid));
+                match self.issuers.get(txid) {
+                    Some(user_id) => {
+                        self.message(at, NodeId::User(*user_id), NodeId::Miner(*miner_id), &text)
+                    }
+                    None => self.note(at, NodeId::Miner(*miner_id), &format!("takes in {}", text)),
+                }
+            }
+            RunEvent::TxIncluded {
+                miner_id,
+                height,
+                txid,
+                ..
+            } => self.note(
+                at,
+                NodeId::Miner(*miner_id),
+                &format!("includes tx {} in block {}", short(txid), height),
+            ),
+            RunEvent::TxConfirmed { user_id, .. } => {
+                let user = NodeId::User(*user_id);
+                match self.bridges.get(user_id) {
+                    Some(bridge_id) => {
+                        self.message(at, NodeId::Bridge(*bridge_id), user, "update confirming tx")
+                    }
+                    None => self.note(at, user, "tx confirmed"),
+                }
+            }
+            RunEvent::BlockForged {
+                miner_id,
+                height,
+                transactions,
+            } => {
+                self.forgers.insert(*height, *miner_id);
+                self.note(
+                    at,
+                    NodeId::Miner(*miner_id),
+                    &format!("forges block {} of {} txs", height, transactions),
+                );
+            }
+            RunEvent::BlockApplied { node, height } => {
+                let text = format!("block {}", height);
+                match self.forgers.get(height) {
+                    Some(miner_id) if NodeId::Miner(*miner_id) != *node => {
+                        self.message(at, NodeId::Miner(*miner_id), *node, &text)
+                    }
+                    _ => self.note(at, *node, &format!("applies {}", text)),
+                }
+            }
+        }
+    }
+
+    fn message(&mut self, at: Duration, from: NodeId, to: NodeId, text: &str) {
+        let line = format!(
+            "{}->>{}: {} ({:.1?})",
+            self.participant(from),
+            self.participant(to),
+            text,
+            at
+        );
+        self.lines.push(line);
+    }
+
+    fn note(&mut self, at: Duration, over: NodeId, text: &str) {
+        let line = format!(
+            "Note over {}: {} ({:.1?})",
+            self.participant(over),
+            text,
+            at
+        );
+        self.lines.push(line);
+    }
+
+    /// The name `node` goes by in the diagram, which Mermaid does not allow dashes in.
+    fn participant(&mut self, node: NodeId) -> String {
+        if !self.participants.contains(&node) {
+            self.participants.push(node);
+        }
+        mermaid_id(node)
+    }
+
+    fn render(self) -> Option<String> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        let mut out = String::new();
+        writeln!(out, "sequenceDiagram").unwrap();
+        for node in &self.participants {
+            writeln!(
+                out,
+                "  participant {} as {}",
+                mermaid_id(*node),
+                viz::node_id(*node)
+            )
+            .unwrap();
+        }
+        for line in &self.lines {
+            writeln!(out, "  {}", line).unwrap();
+        }
+        Some(out)
+    }
+}
+
+fn mermaid_id(node: NodeId) -> String {
+    viz::node_id(node).replace('-', "_")
+}
+
+fn short(txid: &str) -> &str {
+    &txid[..txid.len().min(TXID_DIGITS)]
+}
@@ -0,0 +1,92 @@
+use super::state::NodeId;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::warn;
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+/// Where a component of a multi-process deployment can be reached.
+pub struct Peer {
+    pub node: NodeId,
+    /// A socket address for the TCP transport, e.g. `10.0.0.2:7000`, or a multiaddr for libp2p,
+    /// e.g. `/ip4/10.0.0.2/tcp/4001`.
+    pub addr: String,
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
+/// How the processes of a deployment find each other: a static list of peers, shared by every
+/// process, and whether libp2p nodes should also look for peers on the local network with mDNS.
+/// Loaded from YAML:
+///
+/// ```yaml
+/// mdns: true
+/// peers:
+///   - node: { miner: 0 }
+///     addr: /ip4/10.0.0.2/tcp/4001
+///   - node: { bridge: 0 }
+///     addr: /ip4/10.0.0.3/tcp/4001
+/// ```
+pub struct PeerList {
+    #[serde(default)]
+    pub peers: Vec<Peer>,
+    #[serde(default)]
+    pub mdns: bool,
+}
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    Io(io::Error),
+    Parse(serde_yaml::Error),
+}
+
+impl From<io::Error> for DiscoveryError {
+    fn from(err: io::Error) -> Self {
+        DiscoveryError::Io(err)
+    }
+}
+
+impl From<serde_yaml::Error> for DiscoveryError {
+    fn from(err: serde_yaml::Error) -> Self {
+        DiscoveryError::Parse(err)
+    }
+}
+
+impl PeerList {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, DiscoveryError> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str(&contents)?)
+    }
+
+    /// `node`'s address, if it is listed and parses as an `A`.
+    pub fn addr<A: FromStr>(&self, node: NodeId) -> Option<A> {
+        self.addrs(|peer| peer == node).into_iter().next()
+    }
+
+    /// The addresses of the listed peers that `select` picks, as `A`s, e.g. every miner's but
+    /// one's. Addresses that do not parse are logged and left out.
+    pub fn addrs<A: FromStr, F: Fn(NodeId) -> bool>(&self, select: F) -> Vec<A> {
+        self.peers
+            .iter()
+            .filter(|peer| select(peer.node))
+            .filter_map(|peer| match peer.addr.parse() {
+                Ok(addr) => Some(addr),
+                Err(_) => {
+                    warn!("Ignoring {:?}'s bad address {}.", peer.node, peer.addr);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Every listed miner's address but `except`'s.
+    pub fn miners<A: FromStr>(&self, except: Option<NodeId>) -> Vec<A> {
+        self.addrs(|node| matches!(node, NodeId::Miner(_)) && Some(node) != except)
+    }
+
+    /// Every listed bridge's address but `except`'s.
+    pub fn bridges<A: FromStr>(&self, except: Option<NodeId>) -> Vec<A> {
+        self.addrs(|node| matches!(node, NodeId::Bridge(_)) && Some(node) != except)
+    }
+}
@@ -0,0 +1,259 @@
+use super::bridge::{Bridge, WitnessRequest};
+use super::config::{Config, Mode};
+use super::control::{Command, RunControl};
+use super::engine::EventSimulation;
+use super::hooks::Hooks;
+use super::metrics::Metrics;
+use super::miner::Miner;
+use super::realtime::RealTimeSimulation;
+use super::report::RunResult;
+use super::state::{Block, Transaction, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use std::panic;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+enum Runner<G: UnknownOrderGroup> {
+    RealTime(RealTimeSimulation<G>),
+    DiscreteEvent(EventSimulation<G>),
+}
+
+impl<G: UnknownOrderGroup> Runner<G> {
+    fn run(self) -> RunResult<G> {
+        match self {
+            Runner::RealTime(simulation) => simulation.run(),
+            Runner::DiscreteEvent(mut simulation) => simulation.run(),
+        }
+    }
+}
+
+/// Sets up a `Simulation`: its config, and the callbacks it calls as it runs.
+pub struct Builder<G: UnknownOrderGroup> {
+    config: Config,
+    hooks: Hooks<G>,
+}
+
+impl<G: UnknownOrderGroup> Builder<G> {
+    /// Replaces the whole config, e.g. with one loaded from a scenario.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    pub fn users_per_bridge(mut self, users_per_bridge: Vec<usize>) -> Self {
+        self.config.users_per_bridge = users_per_bridge;
+        self
+    }
+
+    pub fn max_blocks(mut self, max_blocks: u64) -> Self {
+        self.config.max_blocks = Some(max_blocks);
+        self
+    }
+
+    pub fn quiet(mut self) -> Self {
+        self.config.quiet = true;
+        self
+    }
+
+    /// Has the run check, each time a bridge applies a block, that its users' unspent UTXOs have
+    /// witnesses that verify and their spent ones none, panicking with the difference if not. See
+    /// `Config::check_invariants`.
+    pub fn check_invariants(mut self) -> Self {
+        self.config.check_invariants = true;
+        self
+    }
+
+    /// Sets the run up for tests: components stepped in turn on one thread by the discrete-event
+    /// scheduler, under its virtual clock, with no witness thread pool and nothing printed or
+    /// written out. The same seed and bounds then always make the same run, down to its
+    /// `RunResult::outcome`, and in `Rsa100` a hundred blocks take milliseconds. Call it after
+    /// `config`, which replaces the whole config.
+    pub fn deterministic(mut self) -> Self {
+        let config = &mut self.config;
+        config.mode = Mode::DiscreteEvent;
+        config.quiet = true;
+        config.witness_threads = None;
+        config.output_dir = None;
+        config.viz_output = None;
+        config.event_stream = None;
+        config.trace_output = None;
+        config.checkpoint_output = None;
+        self
+    }
+
+    pub fn on_block<F: FnMut(&Block<G, Utxo>, &RunControl) + Send + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.hooks.on_block(callback);
+        self
+    }
+
+    pub fn on_transaction<F: FnMut(&Transaction<G, Utxo>, &RunControl) + Send + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.hooks.on_transaction(callback);
+        self
+    }
+
+    pub fn on_witness_request<F: FnMut(&WitnessRequest, &RunControl) + Send + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.hooks.on_witness_request(callback);
+        self
+    }
+
+    pub fn on_miner_started<F: FnMut(usize, &Arc<Miner<G, Utxo>>) + Send + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.hooks.on_miner_started(callback);
+        self
+    }
+
+    pub fn on_bridge_started<F: FnMut(usize, &Arc<Mutex<Bridge<G>>>) + Send + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.hooks.on_bridge_started(callback);
+        self
+    }
+
+    pub fn build(self) -> Simulation<G> {
+        let runner = match self.config.mode {
+            Mode::RealTime => {
+                let mut simulation = RealTimeSimulation::new(self.config);
+                simulation.set_hooks(self.hooks);
+                Runner::RealTime(simulation)
+            }
+            Mode::DiscreteEvent => {
+                let mut simulation = EventSimulation::new(self.config);
+                simulation.set_hooks(self.hooks);
+                Runner::DiscreteEvent(simulation)
+            }
+        };
+        let (control, metrics) = match &runner {
+            Runner::RealTime(simulation) => (simulation.control(), simulation.metrics()),
+            Runner::DiscreteEvent(simulation) => (simulation.control(), simulation.metrics()),
+        };
+        Simulation {
+            runner: Some(runner),
+            thread: None,
+            control,
+            metrics,
+        }
+    }
+}
+
+/// A simulation driven from other code, e.g. a test or a crate building on this one:
+///
+/// ```ignore
+/// let simulation = Simulation::<Rsa100>::builder()
+///     .mode(Mode::DiscreteEvent)
+///     .max_blocks(10)
+///     .on_block(|block, control| {
+///         if block.transactions.is_empty() {
+///             control.stop();
+///         }
+///     })
+///     .build();
+/// let result = simulation.wait();
+/// ```
+///
+/// A test wanting exact results builds a deterministic run instead and runs it where it is:
+///
+/// ```ignore
+/// let run = || Simulation::<Rsa100>::builder().deterministic().seed(7).max_blocks(100).build();
+/// assert_eq!(run().run_here().outcome(), run().run_here().outcome());
+/// ```
+///
+/// Once started, the run goes on in the background until it reaches the bounds in its config or
+/// is stopped; in the meantime, it can be paused, stepped and resumed, and its metrics read.
+pub struct Simulation<G: UnknownOrderGroup> {
+    /// The run, until it is started.
+    runner: Option<Runner<G>>,
+    thread: Option<JoinHandle<RunResult<G>>>,
+    control: Arc<RunControl>,
+    metrics: Arc<Metrics>,
+}
+
+impl<G: UnknownOrderGroup> Simulation<G> {
+    pub fn builder() -> Builder<G> {
+        Builder {
+            config: Config::default(),
+            hooks: Hooks::default(),
+        }
+    }
+
+    pub fn new(config: Config) -> Self {
+        Self::builder().config(config).build()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn control(&self) -> Arc<RunControl> {
+        self.control.clone()
+    }
+
+    /// Starts the run on a background thread. Does nothing if it has already started.
+    pub fn start(&mut self) {
+        if let Some(runner) = self.runner.take() {
+            self.thread = Some(thread::spawn(move || runner.run()));
+        }
+    }
+
+    pub fn pause(&self) {
+        self.control.apply(Command::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.control.apply(Command::Resume);
+    }
+
+    /// Lets a paused run produce exactly one more block, then pauses it again.
+    pub fn step(&self) {
+        self.control.apply(Command::Step);
+    }
+
+    /// Runs the simulation to its end on the calling thread rather than in the background, e.g. in
+    /// a test. If it was already started, waits for it instead.
+    pub fn run_here(mut self) -> RunResult<G> {
+        match self.runner.take() {
+            Some(runner) => runner.run(),
+            None => self.wait(),
+        }
+    }
+
+    /// Stops the run and waits for it to wrap up.
+    pub fn stop(self) -> RunResult<G> {
+        self.control.stop();
+        self.wait()
+    }
+
+    /// Waits for the run to finish, starting it first if need be. A panic in the run is passed
+    /// on to the caller.
+    pub fn wait(mut self) -> RunResult<G> {
+        self.start();
+        let thread = self
+            .thread
+            .take()
+            .expect("A started simulation has a thread");
+        thread
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+}
@@ -0,0 +1,1577 @@
+use super::auditor::Auditor;
+use super::auth::Keyring;
+use super::batch::BatchProof;
+use super::bridge::{self, Bridge, UserUpdate, WitnessRequest, WitnessResponse};
+use super::checkpoint::{Checkpoint, PendingTransaction};
+use super::clock::ManualClock;
+use super::config::Config;
+use super::control::RunControl;
+use super::events::EventSink;
+use super::genesis::{Genesis, Mints, GENESIS_VALUE};
+use super::history::ChainHistory;
+use super::hooks::Hooks;
+use super::injection::{Corrupt, FaultInjector};
+use super::int_pool;
+use super::invariants::InvariantChecker;
+use super::logging;
+use super::metrics::Metrics;
+use super::miner::Miner;
+use super::network::{MessageKind, Network, Payload, ELEM_BYTES, MESSAGE_HEADER_BYTES, UTXO_BYTES};
+use super::prime_cache;
+use super::primes::PrimeHasher;
+use super::proof_cache::ProofCache;
+use super::proofs::BATCH_PROOF_BYTES;
+use super::report::{RunResult, Summary};
+use super::scheduler::Scheduler;
+use super::shutdown::ComponentReport;
+use super::state::{Block, NodeId, Transaction, Utxo};
+use super::trace::{TraceRecord, TraceRecorder, TraceReplayer};
+use super::user::User;
+use super::util;
+use super::viz::Feed;
+use super::watchdog::Watchdog;
+use super::wire::WireKind;
+use super::witness_update::WitnessUpdate;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Witness;
+use ed25519_dalek::Signature;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+#[derive(Clone, Debug)]
+/// Something that happens to a component at a point in virtual time.
+pub enum Event<G: UnknownOrderGroup> {
+    /// The leader's block interval has elapsed.
+    ForgeBlock,
+    /// A Byzantine miner's block interval has elapsed.
+    ForgeByzantineBlock {
+        miner_id: usize,
+    },
+    /// A user is ready to spend its next UTXO.
+    IssueTransaction {
+        user_id: usize,
+    },
+    BlockDelivered {
+        to: NodeId,
+        block: Arc<Block<G, Utxo>>,
+    },
+    TransactionDelivered {
+        miner_id: usize,
+        transaction: Arc<Transaction<G, Utxo>>,
+    },
+    WitnessRequestDelivered {
+        bridge_id: usize,
+        request: WitnessRequest,
+    },
+    WitnessResponseDelivered {
+        user_id: usize,
+        response: WitnessResponse<G, Utxo>,
+    },
+    UserUpdateDelivered {
+        user_id: usize,
+        update: UserUpdate<G>,
+    },
+    /// What the leader publishes with a block for users to update their own witnesses.
+    WitnessUpdateDelivered {
+        user_id: usize,
+        update: WitnessUpdate<G>,
+    },
+    /// A user's attempt to spend a UTXO has gone unconfirmed for too long.
+    RetryTimeout {
+        user_id: usize,
+        attempt: u64,
+    },
+    /// A node that has fallen behind asks a miner for the blocks above `height`.
+    SyncRequestDelivered {
+        miner_id: usize,
+        from: NodeId,
+        height: u64,
+    },
+    /// The blocks a node asked for, and a proof of all their updates together if the miner made
+    /// one.
+    SyncResponseDelivered {
+        to: NodeId,
+        blocks: Vec<Block<G, Utxo>>,
+        proof: Option<BatchProof<G, Utxo>>,
+    },
+    /// A restarted user asks its bridge for the UTXOs it currently owns.
+    UtxoSyncRequestDelivered {
+        bridge_id: usize,
+        user_id: usize,
+    },
+    UtxoSyncResponseDelivered {
+        user_id: usize,
+        utxos: Vec<Utxo>,
+    },
+    /// A user that keeps its own witnesses asks its bridge for fresh ones, to re-anchor them.
+    WitnessRefreshRequestDelivered {
+        bridge_id: usize,
+        user_id: usize,
+    },
+    /// Witnesses to a user's UTXOs as of the bridge's block at `height`.
+    WitnessRefreshResponseDelivered {
+        user_id: usize,
+        height: u64,
+        witnesses: Vec<(Utxo, Witness<G, Utxo>)>,
+    },
+    /// A message signed by its sender as it was sent, naming `sender` as the component it comes
+    /// from. The recipient checks the signature before it handles the message.
+    Signed {
+        sender: NodeId,
+        signature: Signature,
+        message: Box<Event<G>>,
+    },
+}
+
+impl<G: UnknownOrderGroup> Event<G> {
+    /// A short description of a message for traces, e.g. `block 12 (40 transactions)`.
+    fn describe(&self) -> String {
+        match self {
+            Event::BlockDelivered { block, .. } => format!(
+                "block {} ({} transactions)",
+                block.height,
+                block.transactions.len()
+            ),
+            Event::TransactionDelivered { transaction, .. } => format!(
+                "transaction ({} spent, {} created)",
+                transaction.utxos_spent_with_witnesses.len(),
+                transaction.utxos_created.len()
+            ),
+            Event::WitnessRequestDelivered { request, .. } => {
+                format!("witness request for {} utxos", request.utxos.len())
+            }
+            Event::WitnessResponseDelivered { response, .. } => format!(
+                "witness response with {} witnesses",
+                response.utxos_with_witnesses.len()
+            ),
+            Event::UserUpdateDelivered { update, .. } => format!(
+                "utxo update ({} added, {} deleted)",
+                update.utxos_added.len(),
+                update.utxos_deleted.len()
+            ),
+            Event::WitnessUpdateDelivered { update, .. } => {
+                format!("witness update for block {}", update.height)
+            }
+            Event::SyncRequestDelivered { height, .. } => {
+                format!("sync request above height {}", height)
+            }
+            Event::SyncResponseDelivered {
+                blocks,
+                proof: Some(_),
+                ..
+            } => format!(
+                "sync response with {} blocks and a batch proof",
+                blocks.len()
+            ),
+            Event::SyncResponseDelivered { blocks, .. } => {
+                format!("sync response with {} blocks", blocks.len())
+            }
+            Event::UtxoSyncRequestDelivered { .. } => "utxo sync request".to_string(),
+            Event::UtxoSyncResponseDelivered { utxos, .. } => {
+                format!("utxo sync response with {} utxos", utxos.len())
+            }
+            Event::WitnessRefreshRequestDelivered { .. } => "witness refresh request".to_string(),
+            Event::WitnessRefreshResponseDelivered {
+                height, witnesses, ..
+            } => format!(
+                "witness refresh for block {} with {} witnesses",
+                height,
+                witnesses.len()
+            ),
+            Event::Signed { message, .. } => message.describe(),
+            Event::ForgeBlock
+            | Event::ForgeByzantineBlock { .. }
+            | Event::IssueTransaction { .. }
+            | Event::RetryTimeout { .. } => unreachable!("Timers are not messages"),
+        }
+    }
+
+    /// Roughly how many bytes the message takes up on the wire, for links with limited bandwidth
+    /// and for counting traffic.
+    fn size_bytes(&self) -> usize {
+        let size = match self {
+            Event::BlockDelivered { block, .. } => block.payload_bytes(),
+            Event::TransactionDelivered { transaction, .. } => transaction.payload_bytes(),
+            Event::WitnessRequestDelivered { request, .. } => request.payload_bytes(),
+            Event::WitnessResponseDelivered { response, .. } => response.payload_bytes(),
+            Event::UserUpdateDelivered { update, .. } => update.payload_bytes(),
+            Event::WitnessUpdateDelivered { update, .. } => update.payload_bytes(),
+            Event::SyncResponseDelivered { blocks, proof, .. } => {
+                blocks.iter().map(Payload::payload_bytes).sum::<usize>()
+                    + proof.as_ref().map_or(0, |_| BATCH_PROOF_BYTES)
+            }
+            Event::UtxoSyncResponseDelivered { utxos, .. } => utxos.len() * UTXO_BYTES,
+            Event::WitnessRefreshResponseDelivered { witnesses, .. } => {
+                8 + witnesses.len() * (UTXO_BYTES + ELEM_BYTES)
+            }
+            _ => 0,
+        };
+        MESSAGE_HEADER_BYTES + size
+    }
+
+    /// The kind of traffic a message is, for the network config and traffic counts.
+    fn kind(&self) -> MessageKind {
+        match self {
+            Event::BlockDelivered { .. } | Event::WitnessUpdateDelivered { .. } => {
+                MessageKind::Block
+            }
+            Event::TransactionDelivered { .. } => MessageKind::Transaction,
+            _ => MessageKind::Witness,
+        }
+    }
+
+    /// The wire type of a message that has to be signed when messages are authenticated.
+    fn signed_kind(&self) -> Option<WireKind> {
+        match self {
+            Event::BlockDelivered { .. }
+            | Event::SyncResponseDelivered { .. }
+            | Event::WitnessUpdateDelivered { .. } => Some(WireKind::Block),
+            Event::TransactionDelivered { .. } => Some(WireKind::Transaction),
+            Event::WitnessResponseDelivered { .. }
+            | Event::WitnessRefreshResponseDelivered { .. } => Some(WireKind::WitnessResponse),
+            _ => None,
+        }
+    }
+
+    /// What a signature on a message covers: a digest of everything the message carries, so that
+    /// a message changed on its way no longer matches it.
+    fn signed_bytes(&self) -> [u8; 32] {
+        match self {
+            Event::BlockDelivered { to, block } => util::sha256(&("block", to, block)),
+            Event::SyncResponseDelivered { to, blocks, proof } => {
+                util::sha256(&("sync response", to, blocks, proof))
+            }
+            Event::WitnessUpdateDelivered { user_id, update } => {
+                util::sha256(&("witness update", user_id, update))
+            }
+            Event::TransactionDelivered {
+                miner_id,
+                transaction,
+            } => util::sha256(&("transaction", miner_id, transaction)),
+            Event::WitnessResponseDelivered { user_id, response } => {
+                util::sha256(&("witness response", user_id, response))
+            }
+            Event::WitnessRefreshResponseDelivered {
+                user_id,
+                height,
+                witnesses,
+            } => util::sha256(&("witness refresh", user_id, height, witnesses)),
+            _ => unreachable!("Only blocks, transactions and witness responses are signed"),
+        }
+    }
+
+    /// Whether this event is a message arriving at a node, as opposed to a timer.
+    fn is_delivery(&self) -> bool {
+        match self {
+            Event::ForgeBlock
+            | Event::ForgeByzantineBlock { .. }
+            | Event::IssueTransaction { .. }
+            | Event::RetryTimeout { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+impl<G: UnknownOrderGroup> Corrupt for Event<G> {
+    /// Corrupts the message's payload. Sync requests carry nothing worth corrupting.
+    fn corrupt(&mut self) {
+        match self {
+            Event::BlockDelivered { block, .. } => block.corrupt(),
+            Event::TransactionDelivered { transaction, .. } => transaction.corrupt(),
+            Event::WitnessRequestDelivered { request, .. } => request.corrupt(),
+            Event::WitnessResponseDelivered { response, .. } => response.corrupt(),
+            Event::UserUpdateDelivered { update, .. } => update.corrupt(),
+            Event::SyncResponseDelivered { blocks, .. } => {
+                blocks.iter_mut().for_each(Corrupt::corrupt)
+            }
+            Event::UtxoSyncResponseDelivered { utxos, .. } => utxos.clear(),
+            Event::WitnessRefreshResponseDelivered { witnesses, .. } => witnesses.clear(),
+            _ => (),
+        }
+    }
+}
+
+/// The nodes a run starts with, and the chain state they start from.
+struct Start<G: UnknownOrderGroup> {
+    rng: StdRng,
+    miners: Vec<Miner<G, Utxo>>,
+    bridges: Vec<Bridge<G>>,
+    users: Vec<User<G>>,
+    leader: Option<usize>,
+    offline: HashSet<NodeId>,
+    base_height: u64,
+    base_utxos: Vec<Utxo>,
+    mints: Arc<Mints<Utxo>>,
+}
+
+impl<G: UnknownOrderGroup> Start<G> {
+    fn genesis(config: &Config, metrics: &Arc<Metrics>) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let genesis = Genesis::<G>::new(&config.users_per_bridge, &mut rng);
+        let proof_cache = new_proof_cache(config);
+        if config.cache_primes {
+            prime_cache::enable();
+        }
+        if config.pool_integers {
+            int_pool::enable();
+        }
+        let prime_hasher = new_prime_hasher(config, metrics);
+        let witness_pool = config.witness_threads.map(bridge::witness_pool);
+        let mints = Arc::new(Mints::default());
+
+        let miners = (0..config.num_miners)
+            .map(|miner_id| {
+                let mut miner = Miner::new(miner_id, genesis.acc.clone(), metrics.clone());
+                if config.prove_fresh_utxos {
+                    miner.keep_utxo_set(genesis.user_utxos.clone());
+                }
+                if let Some(cache) = &proof_cache {
+                    miner.share_proof_cache(cache.clone());
+                }
+                miner.accept_mints(mints.clone());
+                if let Some(hasher) = &prime_hasher {
+                    miner.hash_to_primes(hasher.clone());
+                }
+                if config.multiswap {
+                    miner.prove_multiswaps();
+                }
+                miner
+            })
+            .collect();
+        let mut bridges = Vec::new();
+        let mut users = Vec::new();
+        for (bridge_id, user_ids) in genesis.bridge_users.iter().enumerate() {
+            let mut bridge = Bridge::new(
+                bridge_id,
+                genesis.acc.clone(),
+                genesis.bridge_witnesses[bridge_id].clone(),
+                genesis.bridge_utxos(bridge_id),
+                user_ids.clone().collect(),
+                metrics.clone(),
+            );
+            if config.spent_receipts || config.witness_refresh_interval.is_some() {
+                bridge.keep_all_utxos(genesis.user_utxos.clone());
+            }
+            if config.spent_receipts {
+                bridge.send_spent_receipts();
+            }
+            if let Some(interval) = config.witness_refresh_interval {
+                bridge.refresh_witnesses_every(interval);
+            }
+            if config.prove_fresh_utxos {
+                bridge.require_fresh_utxos();
+            }
+            if let Some(cache) = &proof_cache {
+                bridge.share_proof_cache(cache.clone());
+            }
+            bridge.accept_mints(mints.clone());
+            if let Some(pool) = &witness_pool {
+                bridge.generate_witnesses_on(pool.clone());
+            }
+            bridges.push(bridge);
+            let witnesses = if config.publish_witness_updates {
+                genesis.bridge_witnesses[bridge_id]
+                    .clone()
+                    .compute_individual_witnesses(&genesis.bridge_utxos(bridge_id))
+            } else {
+                Vec::new()
+            };
+            for user_id in user_ids.clone() {
+                let mut user = User::new(
+                    user_id,
+                    bridge_id,
+                    genesis.user_utxos[user_id].clone(),
+                    metrics.clone(),
+                );
+                if config.publish_witness_updates {
+                    user.keep_witnesses(owned_by(user_id, &witnesses), 0);
+                }
+                users.push(user);
+            }
+        }
+
+        Self {
+            rng,
+            miners,
+            bridges,
+            users,
+            leader: config.initial_leader(),
+            offline: HashSet::new(),
+            base_height: 0,
+            base_utxos: genesis.user_utxos,
+            mints,
+        }
+    }
+
+    /// Every node picks up at the checkpoint's height. Users with a transaction in the mempool
+    /// wait for it to be confirmed; the others start spending right away.
+    fn restore(config: &Config, checkpoint: Checkpoint, metrics: &Arc<Metrics>) -> Self {
+        info!(
+            "Restoring a checkpoint at block {}.",
+            checkpoint.block_height
+        );
+        let block_height = checkpoint.block_height;
+        let acc = checkpoint.acc::<G>();
+        let mempool: Vec<_> = checkpoint
+            .mempool::<G>()
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        let proof_cache = new_proof_cache(config);
+        if config.cache_primes {
+            prime_cache::enable();
+        }
+        if config.pool_integers {
+            int_pool::enable();
+        }
+        let prime_hasher = new_prime_hasher(config, metrics);
+        let witness_pool = config.witness_threads.map(bridge::witness_pool);
+        // The mints the run had authorized and not yet confirmed are in its mempool.
+        let mints = Arc::new(Mints::default());
+        for transaction in &mempool {
+            if transaction.utxos_spent_with_witnesses.is_empty() {
+                for utxo in &transaction.utxos_created {
+                    mints.authorize(utxo.clone());
+                }
+            }
+        }
+
+        let miners = (0..config.num_miners)
+            .map(|miner_id| {
+                let mut miner = Miner::new(miner_id, acc.clone(), metrics.clone());
+                miner.resume_at(block_height);
+                if config.prove_fresh_utxos {
+                    miner.keep_utxo_set(checkpoint.utxos.clone());
+                }
+                if let Some(cache) = &proof_cache {
+                    miner.share_proof_cache(cache.clone());
+                }
+                miner.accept_mints(mints.clone());
+                if let Some(hasher) = &prime_hasher {
+                    miner.hash_to_primes(hasher.clone());
+                }
+                if config.multiswap {
+                    miner.prove_multiswaps();
+                }
+                for transaction in &mempool {
+                    miner.add_transaction(transaction.clone());
+                }
+                miner
+            })
+            .collect();
+
+        let mut bridges = Vec::new();
+        let mut user_bridges = Vec::new();
+        let mut witnesses = Vec::new();
+        for (bridge_id, user_ids) in checkpoint.bridge_users.iter().enumerate() {
+            let user_ids: HashSet<usize> = user_ids.iter().cloned().collect();
+            let (utxos, witness) = checkpoint.utxos_of::<G>(&user_ids);
+            if config.publish_witness_updates {
+                witnesses.extend(witness.clone().compute_individual_witnesses(&utxos));
+            }
+            let mut bridge = Bridge::new(
+                bridge_id,
+                acc.clone(),
+                witness,
+                utxos,
+                user_ids.clone(),
+                metrics.clone(),
+            );
+            bridge.resume_at(block_height);
+            if config.spent_receipts || config.witness_refresh_interval.is_some() {
+                bridge.keep_all_utxos(checkpoint.utxos.clone());
+            }
+            if config.spent_receipts {
+                bridge.send_spent_receipts();
+            }
+            if let Some(interval) = config.witness_refresh_interval {
+                bridge.refresh_witnesses_every(interval);
+            }
+            if config.prove_fresh_utxos {
+                bridge.require_fresh_utxos();
+            }
+            if let Some(cache) = &proof_cache {
+                bridge.share_proof_cache(cache.clone());
+            }
+            bridge.accept_mints(mints.clone());
+            if let Some(pool) = &witness_pool {
+                bridge.generate_witnesses_on(pool.clone());
+            }
+            bridges.push(bridge);
+            user_bridges.extend(user_ids.into_iter().map(|user_id| (user_id, bridge_id)));
+        }
+        user_bridges.sort();
+
+        let spenders: HashSet<usize> = mempool
+            .iter()
+            .flat_map(|transaction| &transaction.utxos_created)
+            .map(|utxo| utxo.user_id)
+            .collect();
+        let users = user_bridges
+            .into_iter()
+            .map(|(user_id, bridge_id)| {
+                let utxos = checkpoint
+                    .utxos
+                    .iter()
+                    .filter(|utxo| utxo.user_id == user_id)
+                    .cloned()
+                    .collect();
+                let waiting = spenders.contains(&user_id);
+                let mut user = User::restored(user_id, bridge_id, utxos, waiting, metrics.clone());
+                if config.publish_witness_updates {
+                    user.keep_witnesses(owned_by(user_id, &witnesses), block_height);
+                }
+                user
+            })
+            .collect();
+
+        Self {
+            rng: StdRng::seed_from_u64(checkpoint.seed),
+            miners,
+            bridges,
+            users,
+            leader: checkpoint.leader,
+            offline: checkpoint.offline.into_iter().collect(),
+            base_height: block_height,
+            base_utxos: checkpoint.utxos,
+            mints,
+        }
+    }
+}
+
+/// The witnesses in `witnesses` to UTXOs owned by `user_id`.
+fn owned_by<G: UnknownOrderGroup>(
+    user_id: usize,
+    witnesses: &[(Utxo, Witness<G, Utxo>)],
+) -> Vec<(Utxo, Witness<G, Utxo>)> {
+    witnesses
+        .iter()
+        .filter(|(utxo, _)| utxo.user_id == user_id)
+        .cloned()
+        .collect()
+}
+
+/// A cache of checked proofs for every node to share, if the config asks for one.
+fn new_proof_cache<G: UnknownOrderGroup>(config: &Config) -> Option<Arc<ProofCache<G, Utxo>>> {
+    if config.cache_proof_verification {
+        Some(Arc::new(ProofCache::new()))
+    } else {
+        None
+    }
+}
+
+/// A hasher measuring the config's hash-to-prime strategy for every miner to share, if it names
+/// one.
+fn new_prime_hasher(config: &Config, metrics: &Arc<Metrics>) -> Option<Arc<PrimeHasher>> {
+    config
+        .hash_to_prime
+        .map(|hash_to_prime| Arc::new(PrimeHasher::new(hash_to_prime, metrics.clone())))
+}
+
+/// Drives miners, bridges and users on a single thread from a discrete-event scheduler. Messages
+/// become events delivered after a latency sampled from `Config::network`, so a run proceeds as
+/// fast as the CPU allows and is fully determined by its config and seed.
+pub struct EventSimulation<G: UnknownOrderGroup> {
+    config: Config,
+    rng: StdRng,
+    clock: Arc<ManualClock>,
+    scheduler: Scheduler<Event<G>>,
+    metrics: Arc<Metrics>,
+    network: Network,
+    miners: Vec<Miner<G, Utxo>>,
+    bridges: Vec<Bridge<G>>,
+    users: Vec<User<G>>,
+    leader: Option<usize>,
+    /// Components that have been stopped; messages addressed to them are dropped.
+    offline: HashSet<NodeId>,
+    /// The height and UTXO set the run started from: genesis, or a checkpoint.
+    base_height: u64,
+    base_utxos: Vec<Utxo>,
+    /// The UTXOs the run has minted for users added mid-run, which only it can authorize.
+    mints: Arc<Mints<Utxo>>,
+    faults: FaultInjector,
+    /// The keys messages are signed with, if they are authenticated.
+    keyring: Option<Keyring>,
+    /// Components frozen by an injected fault, and when they thaw.
+    frozen_until: HashMap<NodeId, Duration>,
+    control: Arc<RunControl>,
+    viz: Option<Feed>,
+    recorder: Option<TraceRecorder>,
+    replay: Option<TraceReplayer>,
+    hooks: Hooks<G>,
+    /// Checks the chain's accumulator against its UTXO set, if the config asks for audits.
+    auditor: Option<Auditor<G>>,
+    /// Checks each bridge's witnesses against a plain UTXO set, if the config asks for it.
+    invariants: Option<InvariantChecker<G>>,
+    /// Looks for stalls once a block interval, if the config asks for it.
+    watchdog: Option<Watchdog>,
+}
+
+impl<G: UnknownOrderGroup> EventSimulation<G> {
+    /// Sets up a run from genesis, or from the checkpoint in `config.restore_from` if there is one.
+    pub fn new(config: Config) -> Self {
+        let clock = Arc::new(ManualClock::new());
+        let metrics = Arc::new(Metrics::new(clock.clone()));
+        metrics.chain_stats().set_window(config.chain_stats_window);
+        if let Some(sink) = EventSink::from_config(&config) {
+            metrics.stream_events(sink);
+        }
+        let start = match &config.restore_from {
+            Some(path) => {
+                let checkpoint = Checkpoint::from_file(path).unwrap_or_else(|err| {
+                    panic!("Failed to load checkpoint {}: {}", path.display(), err)
+                });
+                Start::restore(&config, checkpoint, &metrics)
+            }
+            None => Start::genesis(&config, &metrics),
+        };
+        let users = start.users;
+        let auditor = config
+            .audit_interval
+            .map(|interval| Auditor::new(interval, start.base_height, start.base_utxos.clone()));
+        let invariants = if config.check_invariants {
+            Some(InvariantChecker::new(
+                start.base_height,
+                start.base_utxos.clone(),
+            ))
+        } else {
+            None
+        };
+        let watchdog = Watchdog::from_config(&config);
+
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(
+            Duration::from_millis(config.block_interval_ms),
+            Event::ForgeBlock,
+        );
+        // Byzantine miners forge halfway between the leader's blocks, so corrupt blocks race
+        // honest ones.
+        for byzantine in &config.byzantine_miners {
+            scheduler.schedule(
+                Duration::from_millis(config.block_interval_ms / 2),
+                Event::ForgeByzantineBlock {
+                    miner_id: byzantine.miner,
+                },
+            );
+        }
+        for (user_id, user) in users.iter().enumerate() {
+            if !user.is_waiting_on(user.attempt()) {
+                scheduler.schedule(
+                    Duration::from_millis(0),
+                    Event::IssueTransaction { user_id },
+                );
+            } else if user.is_funded() {
+                // A restored user's pending transaction may have been lost with the messages in
+                // flight, so it retries like any other.
+                if let Some(timeout) = config.retry_timeout() {
+                    let attempt = user.attempt();
+                    scheduler.schedule(timeout, Event::RetryTimeout { user_id, attempt });
+                }
+            }
+        }
+
+        let viz = Feed::from_config(&config);
+        let recorder =
+            config
+                .trace_output
+                .as_ref()
+                .and_then(|path| match TraceRecorder::create(path) {
+                    Ok(recorder) => Some(recorder),
+                    Err(err) => {
+                        error!("Failed to create {}: {}", path.display(), err);
+                        None
+                    }
+                });
+        let replay = config.replay_trace.as_ref().map(|path| {
+            TraceReplayer::from_file(path)
+                .unwrap_or_else(|err| panic!("Failed to load trace {}: {}", path.display(), err))
+        });
+
+        Self {
+            network: Network::new(config.network.clone()),
+            faults: FaultInjector::new(config.injected_faults.clone(), metrics.clone()),
+            keyring: if config.authenticate_messages {
+                Some(Keyring::new(config.seed))
+            } else {
+                None
+            },
+            frozen_until: HashMap::new(),
+            leader: start.leader,
+            config,
+            rng: start.rng,
+            clock,
+            scheduler,
+            metrics,
+            miners: start.miners,
+            bridges: start.bridges,
+            users,
+            offline: start.offline,
+            base_height: start.base_height,
+            base_utxos: start.base_utxos,
+            mints: start.mints,
+            control: Arc::new(RunControl::new()),
+            viz,
+            recorder,
+            replay,
+            hooks: Hooks::default(),
+            auditor,
+            invariants,
+            watchdog,
+        }
+    }
+
+    /// Registers callbacks to be called as the run goes on, replacing any registered before.
+    pub fn set_hooks(&mut self, hooks: Hooks<G>) {
+        self.hooks = hooks;
+    }
+
+    /// The run state an operator can use to pause, step and resume this simulation from another
+    /// thread. Pausing takes effect before the next block is forged, so stepping advances the run
+    /// exactly one block at a time.
+    pub fn control(&self) -> Arc<RunControl> {
+        self.control.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// The current virtual time.
+    pub fn now(&self) -> Duration {
+        self.scheduler.now()
+    }
+
+    /// The virtual time at which the next event is due, if any.
+    pub fn next_event_time(&self) -> Option<Duration> {
+        self.scheduler.peek_time()
+    }
+
+    /// The height of the longest chain among running miners.
+    pub fn block_height(&self) -> u64 {
+        self.miners
+            .iter()
+            .enumerate()
+            .filter(|(miner_id, _)| !self.offline.contains(&NodeId::Miner(*miner_id)))
+            .map(|(_, miner)| miner.block_height())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Stops a miner. If it was the leader, leadership passes to the lowest-numbered honest miner
+    /// still running.
+    pub fn stop_miner(&mut self, miner_id: usize) {
+        self.stop_node(NodeId::Miner(miner_id));
+    }
+
+    /// Stops a node: it does nothing, and messages to it are dropped, until it is started again.
+    /// If it was the leader, leadership passes to the lowest-numbered honest miner still running.
+    pub fn stop_node(&mut self, node: NodeId) {
+        info!("Stopping {:?}.", node);
+        self.offline.insert(node);
+        if let Some(viz) = &self.viz {
+            viz.status(self.now(), node, false);
+        }
+        if self.leader.map(NodeId::Miner) == Some(node) {
+            self.leader = (0..self.miners.len()).find(|miner_id| self.can_lead(*miner_id));
+            match self.leader {
+                Some(leader) => info!("Miner {} is now the leader.", leader),
+                None => warn!("No miners left to lead."),
+            }
+        }
+    }
+
+    /// Restarts a stopped node. Miners and bridges catch up on the blocks they missed from the
+    /// leader; users ask their bridge which UTXOs they own. A miner restarting while no miner
+    /// leads becomes the leader.
+    pub fn start_node(&mut self, node: NodeId) {
+        if self.offline.remove(&node) {
+            info!("Starting {:?}.", node);
+            if let Some(viz) = &self.viz {
+                viz.status(self.now(), node, true);
+            }
+        }
+        match node {
+            NodeId::Miner(miner_id) => {
+                if self.leader.is_none() && self.can_lead(miner_id) {
+                    info!("Miner {} is now the leader.", miner_id);
+                    self.leader = Some(miner_id);
+                }
+                self.request_sync(node);
+            }
+            NodeId::Bridge(_) => self.request_sync(node),
+            NodeId::User(user_id) => {
+                let bridge_id = self.users[user_id].bridge_id();
+                self.send(node, Event::UtxoSyncRequestDelivered { bridge_id, user_id });
+            }
+        }
+    }
+
+    /// Cuts the network into `groups` of nodes that can only reach each other, until `heal` is
+    /// called. Nodes not listed in any group form one more group together.
+    pub fn partition(&mut self, groups: Vec<Vec<NodeId>>) {
+        info!("Partitioning the network into {} groups.", groups.len() + 1);
+        self.network.partition(groups);
+    }
+
+    pub fn heal(&mut self) {
+        info!("Healing the network partition.");
+        self.network.heal();
+    }
+
+    /// Adds `count` users to bridge `bridge_id`. Each new user's first UTXO is minted in a
+    /// transaction without inputs, which the run authorizes for its miners and bridges, and the
+    /// user starts transacting once that transaction is confirmed.
+    pub fn add_users(&mut self, bridge_id: usize, count: usize) {
+        info!("Adding {} users to bridge {}.", count, bridge_id);
+        for _ in 0..count {
+            let user_id = self.users.len();
+            let mut user = User::unfunded(user_id, bridge_id, self.metrics.clone());
+            if self.config.publish_witness_updates {
+                user.keep_witnesses(Vec::new(), self.block_height());
+            }
+            self.users.push(user);
+            self.bridges[bridge_id].add_user(user_id);
+            if let Some(viz) = &self.viz {
+                viz.joined(self.now(), user_id, bridge_id);
+            }
+            let utxo = Utxo {
+                id: util::new_uuid(&mut self.rng),
+                user_id,
+                value: GENESIS_VALUE,
+            };
+            self.mints.authorize(utxo.clone());
+            let mint = Transaction {
+                utxos_created: vec![utxo],
+                utxos_spent_with_witnesses: Vec::new(),
+            };
+            self.broadcast_transaction(NodeId::User(user_id), mint);
+        }
+    }
+
+    /// Processes the next event. Returns false if there was nothing left to do.
+    pub fn step(&mut self) -> bool {
+        match self.scheduler.pop() {
+            Some((at, event)) => {
+                self.clock.advance_to(at);
+                self.handle(event);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the run has been stopped or has reached the block or time bound in its config. The
+    /// time bound is checked against the next event, so no event at or past it is processed.
+    pub fn is_finished(&self) -> bool {
+        let now = self.next_event_time().unwrap_or_else(|| self.now());
+        self.control.is_stopped() || self.config.is_finished(self.block_height(), now)
+    }
+
+    /// Runs until the run is finished or no events remain. Without bounds, that means forever.
+    pub fn run(&mut self) -> RunResult<G> {
+        while !self.is_finished() && self.step() {}
+        self.finish()
+    }
+
+    /// Wraps up a run: exports its metrics, prints a summary unless quiet, and returns the final
+    /// state.
+    pub fn finish(&self) -> RunResult<G> {
+        self.export_metrics();
+        if let Some(path) = &self.config.checkpoint_output {
+            let checkpoint = self.checkpoint();
+            match checkpoint.write(path) {
+                Ok(()) => info!("Saved a checkpoint at block {}.", checkpoint.block_height),
+                Err(err) => error!("Failed to save a checkpoint to {}: {}", path.display(), err),
+            }
+        }
+        if let Some(replay) = &self.replay {
+            replay.report();
+        }
+        let summary = Summary::new(&self.metrics);
+        if !self.config.quiet {
+            println!("{}", summary);
+        }
+        let states = self
+            .miners
+            .iter()
+            .map(Miner::final_state)
+            .chain(self.bridges.iter().map(Bridge::final_state))
+            .chain(self.users.iter().map(User::final_state))
+            .collect();
+        let tip = self.tip();
+        RunResult {
+            block_height: tip.block_height(),
+            acc: tip.acc(),
+            metrics: self.metrics.clone(),
+            summary,
+            components: ComponentReport::collect(states, &self.metrics),
+        }
+    }
+
+    /// The state of the run as of its longest chain, from which it can be resumed later.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let tip = self.tip();
+        let blocks = tip.blocks_since(self.base_height);
+        let mut offline: Vec<NodeId> = self.offline.iter().cloned().collect();
+        offline.sort();
+        Checkpoint {
+            block_height: tip.block_height(),
+            utxos: Checkpoint::apply_blocks(self.base_utxos.clone(), &blocks),
+            mempool: tip
+                .pending_transactions()
+                .iter()
+                .map(|transaction| PendingTransaction::from(transaction.as_ref()))
+                .collect(),
+            bridge_users: self.bridges.iter().map(Bridge::user_ids).collect(),
+            leader: self.leader,
+            offline,
+            seed: self.rng.clone().gen(),
+        }
+    }
+
+    /// The history of the run's longest chain since it started, from which the accumulator's
+    /// contents at past heights can be proven.
+    pub fn history(&self) -> ChainHistory<G> {
+        let tip = self.tip();
+        ChainHistory::new(
+            self.base_height,
+            self.base_utxos.clone(),
+            tip.blocks_since(self.base_height),
+            tip.unverified_heights(),
+        )
+    }
+
+    /// The miner with the longest chain among running miners, or the first miner if none are left.
+    fn tip(&self) -> &Miner<G, Utxo> {
+        self.miners
+            .iter()
+            .enumerate()
+            .filter(|(miner_id, _)| self.is_online(NodeId::Miner(*miner_id)))
+            .map(|(_, miner)| miner)
+            .max_by_key(|miner| miner.block_height())
+            .unwrap_or(&self.miners[0])
+    }
+
+    /// Brings the auditor, if any, up to the longest chain, and if an audit is due checks every
+    /// miner's accumulator as of the audited height. Any difference means the accumulator no
+    /// longer holds the UTXO set, a bug the run cannot go on from, so it panics.
+    fn audit(&mut self) {
+        let height = match &self.auditor {
+            Some(auditor) => auditor.height(),
+            None => return,
+        };
+        let blocks = self.tip().blocks_since(height);
+        let auditor = self
+            .auditor
+            .as_mut()
+            .expect("The auditor was just checked for");
+        if let Err(err) = auditor.follow(&blocks) {
+            panic!("Audit failed: {}", err);
+        }
+        if !auditor.is_due() {
+            return;
+        }
+        let height = auditor.height();
+        let accs: Vec<_> = self
+            .miners
+            .iter()
+            .enumerate()
+            .filter_map(|(miner_id, miner)| {
+                miner
+                    .block_at(height)
+                    .map(|block| (miner_id, block.acc_new.clone()))
+            })
+            .collect();
+        if let Err(err) = auditor.audit(accs.iter().map(|(miner_id, acc)| (*miner_id, acc))) {
+            panic!("Audit failed: {}", err);
+        }
+    }
+
+    /// Brings the invariant checker, if any, up to the longest chain, and checks bridge `bridge_id`
+    /// against it. Like a failed audit, a difference is a bug the run cannot go on from, so it
+    /// panics with it.
+    fn check_invariants(&mut self, bridge_id: usize) {
+        let height = match &self.invariants {
+            Some(invariants) => invariants.height(),
+            None => return,
+        };
+        let blocks = self.tip().blocks_since(height);
+        let bridge = &self.bridges[bridge_id];
+        let chain_acc = self
+            .tip()
+            .block_at(bridge.block_height())
+            .map(|block| block.acc_new.clone());
+        let invariants = self
+            .invariants
+            .as_mut()
+            .expect("The invariant checker was just checked for");
+        let result = invariants
+            .follow(&blocks)
+            .and_then(|()| invariants.check(bridge_id, bridge, chain_acc.as_ref()));
+        if let Err(err) = result {
+            panic!("Invariant violated: {}", err);
+        }
+    }
+
+    /// Writes metrics to the configured output directory, if any.
+    pub fn export_metrics(&self) {
+        if let Some(dir) = &self.config.output_dir {
+            if let Err(err) = self.metrics.write_csv(dir) {
+                error!("Failed to export metrics to {}: {}", dir.display(), err);
+            }
+        }
+    }
+
+    /// Sends a message from `from` to the target of `event`, which is delivered once the message
+    /// has crossed the network, if it is not lost on the way. When replaying a trace, the message
+    /// arrives when the trace says instead.
+    fn send(&mut self, from: NodeId, mut event: Event<G>) {
+        let to = self.target(&event).expect("Messages have a recipient");
+        let signed = self.sign(from, &event);
+        if self.faults.corrupts(from) {
+            event.corrupt();
+        }
+        self.metrics
+            .bytes_sent(from, event.kind(), event.size_bytes());
+        let kind = event.kind();
+        let now = self.now();
+        let size_bytes = event.size_bytes();
+        let mut deliveries =
+            self.network
+                .delivery_times(now, from, to, kind, size_bytes, &mut self.rng);
+        if self.recorder.is_some() || self.replay.is_some() {
+            let mut record = TraceRecord::new(now, from, to, kind, event.describe(), &deliveries);
+            if let Some(recorded) = self
+                .replay
+                .as_mut()
+                .and_then(|replay| replay.replay(&record))
+            {
+                record = recorded;
+                deliveries = record.deliveries();
+            }
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(&record);
+            }
+        }
+        if deliveries.is_empty() {
+            self.metrics.message_dropped();
+        }
+        if let Some(viz) = &self.viz {
+            viz.message(now, &deliveries, from, to, kind);
+        }
+        let event = match signed {
+            Some((sender, signature)) => Event::Signed {
+                sender,
+                signature,
+                message: Box::new(event),
+            },
+            None => event,
+        };
+        // A slowed-down recipient takes longer to get to the message.
+        let delay = self.faults.delay(to);
+        for at in deliveries {
+            self.scheduler.schedule_at(at + delay, event.clone());
+        }
+    }
+
+    /// The sender named on a message from `from` and `from`'s signature on it, if messages are
+    /// authenticated and this one has to be signed. A `forge_sender` fault has it name another
+    /// component, whose key it does not sign with.
+    fn sign(&self, from: NodeId, event: &Event<G>) -> Option<(NodeId, Signature)> {
+        let keyring = self.keyring.as_ref()?;
+        event.signed_kind()?;
+        Some((
+            self.faults.claimed_sender(from),
+            keyring.sign(from, &event.signed_bytes()),
+        ))
+    }
+
+    /// Whether `signature` is that of `sender` on `message` as it arrived, and `sender` is the
+    /// kind of component that sends such messages.
+    fn is_authentic(&self, sender: NodeId, signature: &Signature, message: &Event<G>) -> bool {
+        match (&self.keyring, message.signed_kind()) {
+            (Some(keyring), Some(kind)) => {
+                kind.may_come_from(sender)
+                    && keyring.verify(sender, &message.signed_bytes(), signature)
+            }
+            _ => false,
+        }
+    }
+
+    fn is_online(&self, node: NodeId) -> bool {
+        !self.offline.contains(&node)
+    }
+
+    fn can_lead(&self, miner_id: usize) -> bool {
+        self.is_online(NodeId::Miner(miner_id))
+            && self.config.byzantine_behavior(miner_id).is_none()
+    }
+
+    fn node_height(&self, node: NodeId) -> u64 {
+        match node {
+            NodeId::Miner(miner_id) => self.miners[miner_id].block_height(),
+            NodeId::Bridge(bridge_id) => self.bridges[bridge_id].block_height(),
+            NodeId::User(_) => 0,
+        }
+    }
+
+    /// Asks the leader for the blocks `node` is missing.
+    fn request_sync(&mut self, node: NodeId) {
+        let leader = match self.leader {
+            Some(leader) if NodeId::Miner(leader) != node => leader,
+            _ => return,
+        };
+        debug!("Syncing {:?} from miner {}.", node, leader);
+        let height = self.node_height(node);
+        self.send(
+            node,
+            Event::SyncRequestDelivered {
+                miner_id: leader,
+                from: node,
+                height,
+            },
+        );
+    }
+
+    /// Applies a block at a miner or bridge. A block from beyond the next height means the node
+    /// has missed some, so it also asks to sync. Until it has caught up, it asks again with every
+    /// block, so a lost sync message only delays recovery.
+    fn deliver_block(&mut self, to: NodeId, block: Arc<Block<G, Utxo>>) {
+        if block.height > self.node_height(to) + 1 {
+            self.request_sync(to);
+        }
+        match to {
+            NodeId::Miner(miner_id) => self.miners[miner_id].validate_block(block),
+            NodeId::Bridge(bridge_id) => {
+                let updates = self.bridges[bridge_id].update(&block);
+                self.push_user_updates(bridge_id, updates);
+                self.check_invariants(bridge_id);
+            }
+            NodeId::User(_) => unreachable!("Users do not receive blocks"),
+        }
+    }
+
+    /// Applies blocks fetched to catch up at a miner or bridge by their batch proof, or one by one
+    /// if it does not apply, e.g. because the node has received some of them since it asked.
+    fn deliver_batch(
+        &mut self,
+        to: NodeId,
+        blocks: Vec<Block<G, Utxo>>,
+        proof: &BatchProof<G, Utxo>,
+    ) {
+        let is_applied = match to {
+            NodeId::Miner(miner_id) => self.miners[miner_id].apply_batch(&blocks, proof),
+            NodeId::Bridge(bridge_id) => {
+                match self.bridges[bridge_id].apply_batch(&blocks, proof) {
+                    Some(updates) => {
+                        self.push_user_updates(bridge_id, updates);
+                        self.check_invariants(bridge_id);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            NodeId::User(_) => unreachable!("Users do not receive blocks"),
+        };
+        if !is_applied {
+            for block in blocks {
+                self.deliver_block(to, block);
+            }
+        }
+    }
+
+    /// Sends bridge `bridge_id`'s UTXO changes for its users to them, unless users learn of their
+    /// UTXOs from published witness updates instead.
+    fn push_user_updates(&mut self, bridge_id: usize, updates: Vec<(usize, UserUpdate<G>)>) {
+        if self.config.publish_witness_updates {
+            return;
+        }
+        for (user_id, update) in updates {
+            self.send(
+                NodeId::Bridge(bridge_id),
+                Event::UserUpdateDelivered { user_id, update },
+            );
+        }
+    }
+
+    /// Publishes what users need from `block`, which the leader has just forged, to update their
+    /// witnesses, by sending it to every user.
+    fn publish_witness_update(&mut self, leader: usize, block: &Block<G, Utxo>) {
+        let update = match WitnessUpdate::from_block(block, &self.miners[leader].acc()) {
+            Ok(update) => update,
+            Err(err) => {
+                error!(
+                    "Failed to work out block {}'s witness update: {:?}",
+                    block.height, err
+                );
+                return;
+            }
+        };
+        for user_id in 0..self.users.len() {
+            self.send(
+                NodeId::Miner(leader),
+                Event::WitnessUpdateDelivered {
+                    user_id,
+                    update: update.clone(),
+                },
+            );
+        }
+    }
+
+    /// Sends `block` from miner `from`, which has just forged it, to every miner and bridge.
+    /// Byzantine miners keep their corrupt blocks to themselves, so they go on building on the
+    /// honest chain.
+    fn broadcast_block(&mut self, from: usize, block: Block<G, Utxo>) {
+        let block = Arc::new(block);
+        let is_byzantine = self.config.byzantine_behavior(from).is_some();
+        self.hooks.block(&block, &self.control);
+        if !is_byzantine {
+            self.metrics.chain_stats().observe(self.now(), &block);
+        }
+        if let Some(viz) = &self.viz {
+            let transactions = block.transactions.len();
+            viz.block(
+                self.now(),
+                Some(from),
+                block.height,
+                transactions,
+                is_byzantine,
+            );
+        }
+        let recipients: Vec<NodeId> = (0..self.miners.len())
+            .filter(|miner_id| *miner_id != from || !is_byzantine)
+            .map(NodeId::Miner)
+            .chain((0..self.bridges.len()).map(NodeId::Bridge))
+            .collect();
+        for to in recipients {
+            self.send(
+                NodeId::Miner(from),
+                Event::BlockDelivered {
+                    to,
+                    block: block.clone(),
+                },
+            );
+        }
+    }
+
+    fn broadcast_transaction(&mut self, from: NodeId, transaction: Transaction<G, Utxo>) {
+        let transaction = Arc::new(transaction);
+        self.hooks.transaction(&transaction, &self.control);
+        for miner_id in 0..self.miners.len() {
+            self.send(
+                from,
+                Event::TransactionDelivered {
+                    miner_id,
+                    transaction: transaction.clone(),
+                },
+            );
+        }
+    }
+
+    /// Applies the faults that have struck `node` by now, as `event` is about to happen to it.
+    /// Returns whether the event should go ahead: if `node` has crashed, it is stopped and the
+    /// event handled afresh, e.g. by the new leader; if it is frozen, the event waits until it
+    /// thaws.
+    fn inject_faults(&mut self, node: NodeId, event: &Event<G>) -> bool {
+        let interruption = self.faults.check(node);
+        if interruption.crashed && self.is_online(node) {
+            self.stop_node(node);
+            self.handle(event.clone());
+            return false;
+        }
+        let now = self.now();
+        if interruption.freeze > Duration::from_millis(0) {
+            self.frozen_until.insert(node, now + interruption.freeze);
+        }
+        match self.frozen_until.get(&node) {
+            Some(thaw) if *thaw > now => {
+                self.scheduler.schedule_at(*thaw, event.clone());
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// The component an event happens to.
+    fn target(&self, event: &Event<G>) -> Option<NodeId> {
+        match event {
+            Event::ForgeBlock => self.leader.map(NodeId::Miner),
+            Event::ForgeByzantineBlock { miner_id } => Some(NodeId::Miner(*miner_id)),
+            Event::IssueTransaction { user_id }
+            | Event::WitnessResponseDelivered { user_id, .. }
+            | Event::UserUpdateDelivered { user_id, .. }
+            | Event::WitnessUpdateDelivered { user_id, .. }
+            | Event::RetryTimeout { user_id, .. }
+            | Event::UtxoSyncResponseDelivered { user_id, .. }
+            | Event::WitnessRefreshResponseDelivered { user_id, .. } => {
+                Some(NodeId::User(*user_id))
+            }
+            Event::BlockDelivered { to, .. } | Event::SyncResponseDelivered { to, .. } => Some(*to),
+            Event::TransactionDelivered { miner_id, .. }
+            | Event::SyncRequestDelivered { miner_id, .. } => Some(NodeId::Miner(*miner_id)),
+            Event::WitnessRequestDelivered { bridge_id, .. }
+            | Event::UtxoSyncRequestDelivered { bridge_id, .. }
+            | Event::WitnessRefreshRequestDelivered { bridge_id, .. } => {
+                Some(NodeId::Bridge(*bridge_id))
+            }
+            Event::Signed { message, .. } => self.target(message),
+        }
+    }
+
+    fn handle(&mut self, event: Event<G>) {
+        let event = match event {
+            Event::Signed {
+                sender,
+                signature,
+                message,
+            } => {
+                if !self.is_authentic(sender, &signature, &message) {
+                    debug!(
+                        "{:?} rejects a forged {} from {:?}.",
+                        self.target(&message),
+                        message.describe(),
+                        sender
+                    );
+                    self.metrics.message_forged();
+                    return;
+                }
+                *message
+            }
+            event => event,
+        };
+        let target = self.target(&event);
+        let _span = target.map(|node| logging::node_span(node).entered());
+        if let Some(node) = target {
+            if !self.is_online(node) && event.is_delivery() {
+                self.metrics.message_dropped();
+                return;
+            }
+            if !self.inject_faults(node, &event) {
+                return;
+            }
+            if event.is_delivery() {
+                self.metrics
+                    .bytes_received(node, event.kind(), event.size_bytes());
+            }
+        }
+        match event {
+            Event::ForgeBlock => {
+                if !self.control.wait_for_block_permit() {
+                    return;
+                }
+                let forged = self
+                    .leader
+                    .map(|leader| (leader, self.miners[leader].forge_block()));
+                match forged {
+                    Some((leader, Ok(block))) => {
+                        info!(
+                            "Block {} has {} transactions.",
+                            block.height,
+                            block.transactions.len()
+                        );
+                        let height = block.height;
+                        if self.config.publish_witness_updates {
+                            self.publish_witness_update(leader, &block);
+                        }
+                        self.broadcast_block(leader, block);
+                        self.audit();
+                        let interval = self.config.metrics_interval_blocks;
+                        if interval > 0 && height % interval == 0 {
+                            self.export_metrics();
+                        }
+                    }
+                    Some((_, Err(_))) => error!("Fail on forging block"),
+                    None => (),
+                }
+                let now = self.now();
+                if let Some(watchdog) = &mut self.watchdog {
+                    watchdog.check(now, &self.metrics, &self.control);
+                }
+                self.scheduler.schedule(
+                    Duration::from_millis(self.config.block_interval_ms),
+                    Event::ForgeBlock,
+                );
+            }
+            Event::ForgeByzantineBlock { miner_id } => {
+                if self.is_online(NodeId::Miner(miner_id)) && self.control.wait_while_paused() {
+                    let behavior = self
+                        .config
+                        .byzantine_behavior(miner_id)
+                        .expect("Only Byzantine miners forge corrupt blocks");
+                    if let Ok(block) = self.miners[miner_id].forge_byzantine_block(behavior) {
+                        self.broadcast_block(miner_id, block);
+                    }
+                }
+                self.scheduler.schedule(
+                    Duration::from_millis(self.config.block_interval_ms),
+                    Event::ForgeByzantineBlock { miner_id },
+                );
+            }
+            Event::IssueTransaction { user_id } if !self.is_online(NodeId::User(user_id)) => (),
+            Event::IssueTransaction { user_id } => {
+                let user = &mut self.users[user_id];
+                if let Some(transaction) = user.transaction_from_own_witness(&mut self.rng) {
+                    let attempt = user.attempt();
+                    self.broadcast_transaction(NodeId::User(user_id), transaction);
+                    if let Some(timeout) = self.config.retry_timeout() {
+                        self.scheduler
+                            .schedule(timeout, Event::RetryTimeout { user_id, attempt });
+                    }
+                    return;
+                }
+                let bridge_id = user.bridge_id();
+                let request = user.witness_request(&mut self.rng);
+                let attempt = user.attempt();
+                self.send(
+                    NodeId::User(user_id),
+                    Event::WitnessRequestDelivered { bridge_id, request },
+                );
+                if let Some(timeout) = self.config.retry_timeout() {
+                    self.scheduler
+                        .schedule(timeout, Event::RetryTimeout { user_id, attempt });
+                }
+            }
+            Event::BlockDelivered { to, block } => self.deliver_block(to, block),
+            Event::TransactionDelivered {
+                miner_id,
+                transaction,
+            } => self.miners[miner_id].add_transaction(transaction),
+            Event::WitnessRequestDelivered { bridge_id, request } => {
+                self.hooks.witness_request(&request, &self.control);
+                let response = self.bridges[bridge_id].handle_witness_request(&request);
+                self.send(
+                    NodeId::Bridge(bridge_id),
+                    Event::WitnessResponseDelivered {
+                        user_id: request.user_id,
+                        response,
+                    },
+                );
+            }
+            Event::WitnessResponseDelivered { user_id, response } => {
+                let transaction =
+                    self.users[user_id].transaction_from_response(response, &mut self.rng);
+                if let Some(transaction) = transaction {
+                    self.broadcast_transaction(NodeId::User(user_id), transaction);
+                }
+            }
+            Event::UserUpdateDelivered { user_id, update } => {
+                if self.users[user_id].apply_update(update) {
+                    self.scheduler.schedule(
+                        Duration::from_millis(0),
+                        Event::IssueTransaction { user_id },
+                    );
+                }
+            }
+            Event::WitnessUpdateDelivered { user_id, update } => {
+                let user = &mut self.users[user_id];
+                let is_missing_updates = update.height > user.witness_height() + 1;
+                let is_confirmed = user.apply_witness_update(&update);
+                let bridge_id = user.bridge_id();
+                let is_refresh_due = self
+                    .config
+                    .witness_refresh_interval
+                    .map_or(false, |interval| update.height % interval.max(1) == 0);
+                if is_missing_updates || is_refresh_due {
+                    self.send(
+                        NodeId::User(user_id),
+                        Event::WitnessRefreshRequestDelivered { bridge_id, user_id },
+                    );
+                }
+                if is_missing_updates {
+                    // The user may have missed changes to its UTXOs too, so it asks its bridge.
+                    self.send(
+                        NodeId::User(user_id),
+                        Event::UtxoSyncRequestDelivered { bridge_id, user_id },
+                    );
+                } else if is_confirmed {
+                    self.scheduler.schedule(
+                        Duration::from_millis(0),
+                        Event::IssueTransaction { user_id },
+                    );
+                }
+            }
+            Event::RetryTimeout { user_id, attempt } => {
+                let user = &self.users[user_id];
+                if self.is_online(NodeId::User(user_id)) && user.is_waiting_on(attempt) {
+                    debug!("Transaction not confirmed; retrying.");
+                    self.scheduler.schedule(
+                        Duration::from_millis(0),
+                        Event::IssueTransaction { user_id },
+                    );
+                }
+            }
+            Event::SyncRequestDelivered {
+                miner_id,
+                from,
+                height,
+            } => {
+                let miner = &self.miners[miner_id];
+                let (blocks, proof) = if self.config.batch_sync {
+                    miner.batch_since(height)
+                } else {
+                    (miner.blocks_since(height), None)
+                };
+                self.send(
+                    NodeId::Miner(miner_id),
+                    Event::SyncResponseDelivered {
+                        to: from,
+                        blocks,
+                        proof,
+                    },
+                );
+            }
+            Event::SyncResponseDelivered { to, blocks, proof } => {
+                info!("Catching up on {} blocks.", blocks.len());
+                match proof {
+                    Some(proof) => self.deliver_batch(to, blocks, &proof),
+                    None => {
+                        for block in blocks {
+                            self.deliver_block(to, block);
+                        }
+                    }
+                }
+            }
+            Event::UtxoSyncRequestDelivered { bridge_id, user_id } => {
+                let utxos = self.bridges[bridge_id].utxos_of(user_id);
+                self.send(
+                    NodeId::Bridge(bridge_id),
+                    Event::UtxoSyncResponseDelivered { user_id, utxos },
+                );
+            }
+            Event::UtxoSyncResponseDelivered { user_id, utxos } => {
+                if self.users[user_id].resync(utxos) {
+                    self.scheduler.schedule(
+                        Duration::from_millis(0),
+                        Event::IssueTransaction { user_id },
+                    );
+                }
+            }
+            Event::WitnessRefreshRequestDelivered { bridge_id, user_id } => {
+                let bridge = &self.bridges[bridge_id];
+                let utxos = bridge.utxos_of(user_id);
+                let witnesses = bridge.membership_witnesses(&utxos).unwrap_or_default();
+                let height = bridge.block_height();
+                self.send(
+                    NodeId::Bridge(bridge_id),
+                    Event::WitnessRefreshResponseDelivered {
+                        user_id,
+                        height,
+                        witnesses,
+                    },
+                );
+            }
+            Event::WitnessRefreshResponseDelivered {
+                user_id,
+                height,
+                witnesses,
+            } => {
+                self.users[user_id].refresh_witnesses(witnesses, height);
+            }
+            Event::Signed { .. } => unreachable!("Signatures are checked on arrival"),
+        }
+    }
+}
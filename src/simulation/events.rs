@@ -0,0 +1,191 @@
+use super::config::Config;
+use super::state::NodeId;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, LineWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// The ID that follows one spend from the user's witness request to the update confirming its
+/// transaction: the request's ID, which the bridge's response carries back.
+pub type TraceId = Uuid;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+/// Something a component did, as it reported it to the run's metrics. Transactions are named by
+/// their ID in hex, so a transaction's events can be followed from user to miner, and the events
+/// of a spend carry its trace ID, so its whole journey can be put back together. Miners learn of
+/// a transaction's trace through the metrics, so it is missing where the user reported to another
+/// process's.
+pub enum RunEvent {
+    /// A user asked its bridge for witnesses to the UTXO it is about to spend.
+    WitnessRequested {
+        user_id: usize,
+        bridge_id: usize,
+        trace: TraceId,
+    },
+    /// A bridge answered a user's witness request.
+    WitnessServed {
+        bridge_id: usize,
+        user_id: usize,
+        trace: TraceId,
+    },
+    /// A user issued a transaction.
+    TxIssued {
+        user_id: usize,
+        txid: String,
+        trace: TraceId,
+    },
+    /// A miner took a transaction it had not seen into its mempool.
+    TxAccepted {
+        miner_id: usize,
+        txid: String,
+        trace: Option<TraceId>,
+    },
+    /// A miner forged a block including a transaction.
+    TxIncluded {
+        miner_id: usize,
+        height: u64,
+        txid: String,
+        trace: Option<TraceId>,
+    },
+    /// A user's UTXO update confirmed the transaction it was waiting on.
+    TxConfirmed {
+        user_id: usize,
+        trace: Option<TraceId>,
+    },
+    BlockForged {
+        miner_id: usize,
+        height: u64,
+        transactions: usize,
+    },
+    /// A miner or bridge checked a block and applied it to its chain.
+    BlockApplied { node: NodeId, height: u64 },
+}
+
+impl RunEvent {
+    /// The spend the event is part of, if it is part of one and its trace is known.
+    pub fn trace(&self) -> Option<TraceId> {
+        match self {
+            RunEvent::WitnessRequested { trace, .. }
+            | RunEvent::WitnessServed { trace, .. }
+            | RunEvent::TxIssued { trace, .. } => Some(*trace),
+            RunEvent::TxAccepted { trace, .. }
+            | RunEvent::TxIncluded { trace, .. }
+            | RunEvent::TxConfirmed { trace, .. } => *trace,
+            RunEvent::BlockForged { .. } | RunEvent::BlockApplied { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+/// An event and when it happened, in virtual time: microseconds since the start of the run.
+pub struct EventRecord {
+    pub at_us: u64,
+    #[serde(flatten)]
+    pub event: RunEvent,
+}
+
+impl EventRecord {
+    pub fn at(&self) -> Duration {
+        Duration::from_micros(self.at_us)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Every event of a run, in the order components reported them. Metrics keep one per run and
+/// write it out with the rest of their output, as `events.jsonl`, one JSON record per line; any
+/// analysis of a run after the fact can start from it.
+pub struct EventLog {
+    records: Vec<EventRecord>,
+}
+
+impl EventLog {
+    /// Adds `event`, which happened at `at`, and hands back its record.
+    pub fn append(&mut self, at: Duration, event: RunEvent) -> &EventRecord {
+        self.records.push(EventRecord {
+            at_us: at.as_micros() as u64,
+            event,
+        });
+        self.records.last().unwrap()
+    }
+
+    pub fn records(&self) -> &[EventRecord] {
+        &self.records
+    }
+
+    /// The events of the spend traced by `trace`, from its witness request to its confirmation.
+    pub fn journey(&self, trace: TraceId) -> Vec<&EventRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.event.trace() == Some(trace))
+            .collect()
+    }
+
+    pub fn write_jsonl<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for record in &self.records {
+            serde_json::to_writer(&mut out, record)?;
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    /// Reads back a log written by `write_jsonl`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let records = fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+            .collect::<io::Result<Vec<EventRecord>>>()?;
+        Ok(Self { records })
+    }
+}
+
+/// Writes each event as a JSON line, in the format of `events.jsonl`, the moment it is reported,
+/// so tools such as `jq` or a dashboard can follow a run as it goes rather than wait for its
+/// output files. Lines are flushed as they are written, so a pipe sees each one at once.
+pub struct EventSink {
+    out: LineWriter<Box<dyn Write + Send>>,
+}
+
+impl EventSink {
+    /// The path naming standard output rather than a file.
+    pub const STDOUT: &'static str = "-";
+
+    /// A sink writing to the file at `path`, or to standard output if it is `-`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let out: Box<dyn Write + Send> = if path == Path::new(Self::STDOUT) {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        Ok(Self {
+            out: LineWriter::new(out),
+        })
+    }
+
+    /// The sink `config` asks for, if any. Failing to create it is logged rather than fatal.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let path = config.event_stream.as_ref()?;
+        match Self::create(path) {
+            Ok(sink) => Some(sink),
+            Err(err) => {
+                error!("Failed to create {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn write(&mut self, record: &EventRecord) {
+        let result = serde_json::to_writer(&mut self.out, record)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(self.out));
+        if let Err(err) = result {
+            warn!("Failed to write to the event stream: {}", err);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use super::config::{Config, GroupKind, Mode, ModulusSource};
+use super::engine::EventSimulation;
+use super::modulus::{self, CustomRsa};
+use super::report::Summary;
+use super::trapdoor::{self, TrapdoorRsa};
+use accumulator::group::{ClassGroup, Rsa100, Rsa2048, UnknownOrderGroup};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, Deserialize)]
+/// One configuration under comparison, which also picks the group its accumulator works in.
+pub struct Variant {
+    pub name: String,
+    #[serde(default)]
+    pub config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+/// A set of variants run under the same seed so their metrics can be compared, e.g.
+///
+/// ```yaml
+/// seed: 7
+/// parallel: true
+/// variants:
+///   - name: one-bridge
+///     config: { users_per_bridge: [12], max_blocks: 20 }
+///   - name: four-bridges
+///     config: { users_per_bridge: [3, 3, 3, 3], max_blocks: 20 }
+/// ```
+pub struct Experiment {
+    #[serde(default)]
+    pub seed: u64,
+    /// Whether variants run concurrently, one per thread, rather than one after another.
+    #[serde(default)]
+    pub parallel: bool,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug)]
+pub enum ExperimentError {
+    Io(io::Error),
+    Parse(serde_yaml::Error),
+    /// A variant sets neither `max_blocks` nor `max_duration_ms`, so it would never finish.
+    Unbounded(String),
+    /// A sweep varies one or two parameters, not this many.
+    Axes(usize),
+}
+
+impl From<io::Error> for ExperimentError {
+    fn from(err: io::Error) -> Self {
+        ExperimentError::Io(err)
+    }
+}
+
+impl From<serde_yaml::Error> for ExperimentError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ExperimentError::Parse(err)
+    }
+}
+
+impl Experiment {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ExperimentError> {
+        let contents = fs::read_to_string(path)?;
+        let experiment: Self = serde_yaml::from_str(&contents)?;
+        let unbounded = experiment.variants.iter().find(|variant| {
+            variant.config.max_blocks.is_none() && variant.config.max_duration_ms.is_none()
+        });
+        match unbounded {
+            Some(variant) => Err(ExperimentError::Unbounded(variant.name.clone())),
+            None => Ok(experiment),
+        }
+    }
+
+    /// Runs every variant to completion under the discrete-event scheduler and the experiment's
+    /// seed, and collects their summaries in variant order. Variants run quietly, so that only the
+    /// comparison is printed. Variants in the `rsa` group with different moduli run in sequence
+    /// even if the experiment is parallel, since a process works under one modulus at a time.
+    pub fn run(self) -> Comparison {
+        let moduli: Vec<&ModulusSource> = self
+            .variants
+            .iter()
+            .filter(|variant| variant.config.group == GroupKind::Rsa)
+            .map(|variant| &variant.config.rsa_modulus)
+            .collect();
+        let parallel = self.parallel && moduli.windows(2).all(|pair| pair[0] == pair[1]);
+        if self.parallel && !parallel {
+            warn!("Variants need different RSA moduli, one at a time, so they run in sequence.");
+        }
+        let seed = self.seed;
+        let variants = self.variants.into_iter().map(|variant| Variant {
+            config: Config {
+                mode: Mode::DiscreteEvent,
+                seed,
+                quiet: true,
+                ..variant.config
+            },
+            ..variant
+        });
+        let rows = if parallel {
+            let threads: Vec<_> = variants
+                .map(|variant| thread::spawn(move || run_variant(variant)))
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        } else {
+            variants.map(run_variant).collect()
+        };
+        Comparison { rows }
+    }
+}
+
+fn run_variant(variant: Variant) -> (String, Summary) {
+    info!("Running variant {}.", variant.name);
+    let summary = match variant.config.group {
+        GroupKind::Rsa100 => run_in_group::<Rsa100>(variant.config),
+        GroupKind::Rsa2048 => run_in_group::<Rsa2048>(variant.config),
+        GroupKind::Rsa => {
+            modulus::install(&variant.config.rsa_modulus)
+                .expect("Failed to set up the RSA modulus");
+            run_in_group::<CustomRsa>(variant.config)
+        }
+        GroupKind::ClassGroup => run_in_group::<ClassGroup>(variant.config),
+        GroupKind::Trapdoor => {
+            trapdoor::install(variant.config.seed);
+            run_in_group::<TrapdoorRsa>(variant.config)
+        }
+    };
+    (variant.name, summary)
+}
+
+fn run_in_group<G: UnknownOrderGroup>(config: Config) -> Summary {
+    EventSimulation::<G>::new(config).run().summary
+}
+
+/// Key metrics of each variant of an experiment, displayed as a table.
+pub struct Comparison {
+    pub rows: Vec<(String, Summary)>,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name_width = self
+            .rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("variant".len());
+        writeln!(
+            f,
+            "{:<w$}  {:>7}  {:>8}  {:>9}  {:>12}  {:>12}  {:>12}  {:>12}  {:>7}",
+            "variant",
+            "blocks",
+            "txs",
+            "tx/s",
+            "confirm mean",
+            "confirm p95",
+            "proof gen",
+            "verification",
+            "dropped",
+            w = name_width
+        )?;
+        for (name, summary) in &self.rows {
+            writeln!(
+                f,
+                "{:<w$}  {:>7}  {:>8}  {:>9.2}  {:>12}  {:>12}  {:>12}  {:>12}  {:>7}",
+                name,
+                summary.blocks,
+                summary.transactions,
+                summary.transactions_per_sec(),
+                format!("{:.1?}", summary.mean_confirmation_latency),
+                format!("{:.1?}", summary.p95_confirmation_latency),
+                format!("{:.1?}", summary.proof_generation_time),
+                format!("{:.1?}", summary.verification_time),
+                summary.dropped_messages,
+                w = name_width
+            )?;
+        }
+        Ok(())
+    }
+}
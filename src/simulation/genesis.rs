@@ -0,0 +1,91 @@
+use super::state::Utxo;
+use super::util;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, Witness};
+use rand::Rng;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::RwLock;
+
+/// The value of each user's first UTXO.
+pub const GENESIS_VALUE: u64 = 100;
+
+#[derive(Clone, Debug)]
+/// The initial chain state: every user owns a single UTXO, and each bridge holds a witness for the
+/// UTXOs of the users assigned to it.
+pub struct Genesis<G: UnknownOrderGroup> {
+    pub user_utxos: Vec<Utxo>,
+    pub acc: Accumulator<G, Utxo>,
+    /// User IDs assigned to each bridge.
+    pub bridge_users: Vec<Range<usize>>,
+    /// Each bridge's witness, i.e. the accumulator without the UTXOs of the bridge's users.
+    pub bridge_witnesses: Vec<Witness<G, Utxo>>,
+}
+
+impl<G: UnknownOrderGroup> Genesis<G> {
+    pub fn new<R: Rng>(users_per_bridge: &[usize], rng: &mut R) -> Self {
+        let num_users = users_per_bridge.iter().sum();
+        let user_utxos: Vec<Utxo> = (0..num_users)
+            .map(|user_id| Utxo {
+                id: util::new_uuid(rng),
+                user_id,
+                value: GENESIS_VALUE,
+            })
+            .collect();
+        let acc = Accumulator::<G, Utxo>::empty().add(&user_utxos);
+
+        let mut bridge_users = Vec::new();
+        let mut bridge_witnesses = Vec::new();
+        let mut user_idx = 0;
+        for num_users_for_bridge in users_per_bridge {
+            let users = user_idx..user_idx + num_users_for_bridge;
+            let untracked_utxos: Vec<Utxo> = user_utxos
+                .iter()
+                .filter(|utxo| !users.contains(&utxo.user_id))
+                .cloned()
+                .collect();
+            bridge_witnesses.push(Witness(Accumulator::empty().add(&untracked_utxos)));
+            bridge_users.push(users);
+            user_idx += num_users_for_bridge;
+        }
+
+        Self {
+            user_utxos,
+            acc,
+            bridge_users,
+            bridge_witnesses,
+        }
+    }
+
+    /// The UTXOs initially tracked by bridge `bridge_id`.
+    pub fn bridge_utxos(&self, bridge_id: usize) -> Vec<Utxo> {
+        self.user_utxos[self.bridge_users[bridge_id].clone()].to_vec()
+    }
+}
+
+#[derive(Debug)]
+/// The UTXOs the run has authorized to be minted, e.g. the first UTXO of each user added
+/// mid-run, shared by the components that validate transactions. A transaction spending nothing
+/// is only valid if it creates one of these; any other would create value from nothing.
+pub struct Mints<T: Eq + Hash> {
+    authorized: RwLock<HashSet<T>>,
+}
+
+impl<T: Eq + Hash> Default for Mints<T> {
+    fn default() -> Self {
+        Self {
+            authorized: RwLock::new(HashSet::new()),
+        }
+    }
+}
+
+impl<T: Eq + Hash> Mints<T> {
+    pub fn authorize(&self, elem: T) {
+        self.authorized.write().unwrap().insert(elem);
+    }
+
+    pub fn is_authorized(&self, elem: &T) -> bool {
+        self.authorized.read().unwrap().contains(elem)
+    }
+}
@@ -0,0 +1,140 @@
+use super::codec::Encode;
+use super::miner::Miner;
+use super::state::{Block, Transaction, Utxo};
+use super::wire::{self, WireMessage};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+/// Types generated from `proto/miner.proto`.
+pub mod proto {
+    tonic::include_proto!("accumulator_demo.miner");
+}
+
+use proto::miner_server::MinerServer;
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Status> {
+    value
+        .to_bytes()
+        .map_err(|err| Status::internal(err.to_string()))
+}
+
+fn utxo_message(utxo: &Utxo) -> proto::Utxo {
+    proto::Utxo {
+        id: utxo.id.to_hyphenated().to_string(),
+        user_id: utxo.user_id as u64,
+        value: utxo.value,
+    }
+}
+
+/// The gRPC API of a single running miner.
+pub struct MinerApi<G: UnknownOrderGroup> {
+    miner: Arc<Miner<G, Utxo>>,
+}
+
+impl<G: UnknownOrderGroup> MinerApi<G> {
+    pub fn new(miner: Arc<Miner<G, Utxo>>) -> Self {
+        Self { miner }
+    }
+}
+
+#[tonic::async_trait]
+impl<G: UnknownOrderGroup> proto::miner_server::Miner for MinerApi<G>
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+{
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::SubmitTransactionRequest>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let transaction = wire::decode(&request.into_inner().transaction)
+            .map_err(|err| Status::invalid_argument(format!("Bad transaction: {}", err)))?;
+        self.miner.add_transaction(Arc::new(transaction));
+        Ok(Response::new(proto::SubmitTransactionResponse {}))
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let height = request.into_inner().height;
+        let block = self
+            .miner
+            .block_at(height)
+            .ok_or_else(|| Status::not_found(format!("No block at height {}", height)))?;
+        let transactions = block
+            .transactions
+            .iter()
+            .map(|transaction| proto::Transaction {
+                utxos_created: transaction.utxos_created.iter().map(utxo_message).collect(),
+                utxos_spent: transaction
+                    .utxos_spent_with_witnesses
+                    .iter()
+                    .map(|(utxo, _)| utxo_message(utxo))
+                    .collect(),
+            })
+            .collect();
+        Ok(Response::new(proto::Block {
+            height,
+            transactions,
+            encoded: wire::encode(block.as_ref())
+                .map_err(|err| Status::internal(err.to_string()))?,
+            acc_commitment: block.acc_commitment.to_vec(),
+        }))
+    }
+
+    async fn get_tip(
+        &self,
+        _: Request<proto::GetTipRequest>,
+    ) -> Result<Response<proto::Tip>, Status> {
+        Ok(Response::new(proto::Tip {
+            height: self.miner.block_height(),
+            pending_transactions: self.miner.pending_transactions().len() as u64,
+        }))
+    }
+
+    async fn get_accumulator(
+        &self,
+        _: Request<proto::GetAccumulatorRequest>,
+    ) -> Result<Response<proto::Accumulator>, Status> {
+        Ok(Response::new(proto::Accumulator {
+            height: self.miner.block_height(),
+            encoded: encode(&self.miner.acc())?,
+        }))
+    }
+}
+
+/// Serves `miner`'s gRPC API at `addr` on a thread of its own, until the process exits.
+pub fn serve<G: UnknownOrderGroup>(addr: SocketAddr, miner: Arc<Miner<G, Utxo>>) -> JoinHandle<()>
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+{
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                error!("Failed to start the gRPC server on {}: {}", addr, err);
+                return;
+            }
+        };
+        info!("Serving the miner gRPC API on {}.", addr);
+        let server = tonic::transport::Server::builder()
+            .add_service(MinerServer::new(MinerApi::new(miner)))
+            .serve(addr);
+        if let Err(err) = runtime.block_on(server) {
+            error!("The gRPC server on {} failed: {}", addr, err);
+        }
+    })
+}
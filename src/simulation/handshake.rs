@@ -0,0 +1,218 @@
+use super::wire::{WireKind, WIRE_VERSION};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+
+/// The newest version of the peer protocol this build speaks: the handshake, and the framing of
+/// the messages that follow it. Bump it whenever either changes.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// The oldest version of the peer protocol this build still speaks. Raise it when support for an
+/// old version is dropped, so peers still on it are turned away rather than misunderstood.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Every handshake starts with these bytes, so that something other than a peer connecting, like
+/// a port scanner or a browser, is told apart from a peer on another protocol version.
+const MAGIC: [u8; 4] = *b"ACC\x01";
+/// Handshake messages longer than this are taken to be garbage.
+const MAX_MESSAGE_BYTES: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize, Serialize)]
+/// Optional parts of the protocol a peer supports. Each side of a connection only uses those both
+/// sides support.
+pub struct Features {
+    /// Takes blocks compressed with zstd. Needs the `compression` feature.
+    pub compact_blocks: bool,
+    /// Takes blocks whose membership proofs are aggregated into a single proof. No build sends
+    /// such blocks yet; the flag is there so that one that does can tell which peers take them.
+    pub aggregated_proofs: bool,
+}
+
+impl Features {
+    /// The features this build supports.
+    pub fn supported() -> Self {
+        Self {
+            compact_blocks: cfg!(feature = "compression"),
+            aggregated_proofs: false,
+        }
+    }
+
+    /// The features both `self` and `other` support.
+    pub fn common(self, other: Self) -> Self {
+        Self {
+            compact_blocks: self.compact_blocks && other.compact_blocks,
+            aggregated_proofs: self.aggregated_proofs && other.aggregated_proofs,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+/// What each side of a connection says about itself as it opens. Later protocol versions may add
+/// fields at the end, which builds that do not know them ignore.
+pub struct Hello {
+    /// The newest protocol version the peer speaks.
+    pub version: u16,
+    /// The oldest protocol version the peer speaks.
+    pub min_version: u16,
+    /// The version of the wire format the peer's messages are in.
+    pub wire_version: u16,
+    /// The type of message the connection carries, so a peer that connects to the wrong port is
+    /// turned away up front.
+    pub kind: WireKind,
+    pub features: Features,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The listening side's answer to a `Hello`.
+enum Reply {
+    Accept(Hello),
+    /// Why the peer is turned away.
+    Reject(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// What the two sides of a connection agreed on.
+pub struct Agreement {
+    /// The newest protocol version both speak, which the connection uses.
+    pub version: u16,
+    pub features: Features,
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Io(io::Error),
+    /// The other side did not send a handshake this build can read.
+    Malformed(String),
+    /// The listening side turned this one away, saying why.
+    Rejected(String),
+    /// This side turned the other away, for the reason given.
+    Incompatible(String),
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(err: io::Error) -> Self {
+        HandshakeError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for HandshakeError {
+    fn from(err: bincode::Error) -> Self {
+        HandshakeError::Malformed(err.to_string())
+    }
+}
+
+impl Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandshakeError::Io(err) => write!(f, "{}", err),
+            HandshakeError::Malformed(reason) => write!(f, "Malformed handshake: {}", reason),
+            HandshakeError::Rejected(reason) => write!(f, "Rejected by the peer: {}", reason),
+            HandshakeError::Incompatible(reason) => write!(f, "Incompatible peer: {}", reason),
+        }
+    }
+}
+
+impl Error for HandshakeError {}
+
+impl Hello {
+    /// What this build says about itself on a connection carrying `kind` messages.
+    pub fn new(kind: WireKind) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            wire_version: WIRE_VERSION,
+            kind,
+            features: Features::supported(),
+        }
+    }
+
+    /// The terms on which a peer saying `theirs` can talk to one saying `self`, or why it cannot.
+    pub fn agree(&self, theirs: &Hello) -> Result<Agreement, String> {
+        if theirs.wire_version != self.wire_version {
+            return Err(format!(
+                "Wire format version {} is not version {}",
+                theirs.wire_version, self.wire_version
+            ));
+        }
+        if theirs.kind != self.kind {
+            return Err(format!(
+                "Expected {:?} messages, not {:?} messages",
+                self.kind, theirs.kind
+            ));
+        }
+        let version = self.version.min(theirs.version);
+        if version < self.min_version.max(theirs.min_version) {
+            return Err(format!(
+                "Protocol versions {}-{} and {}-{} do not overlap",
+                self.min_version, self.version, theirs.min_version, theirs.version
+            ));
+        }
+        Ok(Agreement {
+            version,
+            features: self.features.common(theirs.features),
+        })
+    }
+}
+
+fn write_message<W: Write, M: Serialize>(
+    writer: &mut W,
+    message: &M,
+) -> Result<(), HandshakeError> {
+    let bytes = bincode::serialize(message)?;
+    writer.write_all(&(bytes.len() as u16).to_be_bytes())?;
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+fn read_message<R: Read, M: DeserializeOwned>(reader: &mut R) -> Result<M, HandshakeError> {
+    let mut len = [0; 2];
+    reader.read_exact(&mut len)?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_BYTES {
+        return Err(HandshakeError::Malformed(format!(
+            "A {}-byte handshake message is too large",
+            len
+        )));
+    }
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+/// Opens a connection from the connecting side: says `ours`, and waits for the listening side to
+/// accept or reject it.
+pub fn initiate<S: Read + Write>(
+    stream: &mut S,
+    ours: &Hello,
+) -> Result<Agreement, HandshakeError> {
+    stream.write_all(&MAGIC)?;
+    write_message(stream, ours)?;
+    match read_message(stream)? {
+        Reply::Accept(theirs) => ours.agree(&theirs).map_err(HandshakeError::Incompatible),
+        Reply::Reject(reason) => Err(HandshakeError::Rejected(reason)),
+    }
+}
+
+/// Answers a connection on the listening side: reads the connecting side's `Hello`, and accepts it
+/// with `ours` if the two are compatible, or tells it why not.
+pub fn respond<S: Read + Write>(stream: &mut S, ours: &Hello) -> Result<Agreement, HandshakeError> {
+    let mut magic = [0; 4];
+    stream.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(HandshakeError::Malformed(
+            "The peer does not speak this protocol".to_string(),
+        ));
+    }
+    let theirs: Hello = read_message(stream)?;
+    match ours.agree(&theirs) {
+        Ok(agreement) => {
+            write_message(stream, &Reply::Accept(ours.clone()))?;
+            Ok(agreement)
+        }
+        Err(reason) => {
+            write_message(stream, &Reply::Reject(reason.clone()))?;
+            Err(HandshakeError::Incompatible(reason))
+        }
+    }
+}
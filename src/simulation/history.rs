@@ -0,0 +1,155 @@
+use super::checkpoint::Checkpoint;
+use super::state::{Block, BlockHeader, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{AccError, Accumulator, MembershipProof, Witness};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "MembershipProof<G, Utxo>: Serialize",
+    deserialize = "MembershipProof<G, Utxo>: Deserialize<'de>"
+))]
+/// A receipt that `utxo` was in the accumulator as of the block at `height`, whether or not it has
+/// been spent since.
+pub struct HistoricalProof<G: UnknownOrderGroup> {
+    pub utxo: Utxo,
+    pub height: u64,
+    pub proof: MembershipProof<G, Utxo>,
+}
+
+impl<G: UnknownOrderGroup> HistoricalProof<G> {
+    /// Whether this proves that `acc` held the UTXO. The verifier takes `acc`, the accumulator as
+    /// of `height`, from a chain it trusts, e.g. the block at that height.
+    pub fn verify(&self, acc: &Accumulator<G, Utxo>) -> bool {
+        acc.verify_membership(&self.utxo, &self.proof)
+    }
+
+    /// Whether this proves that `acc` held the UTXO, for a verifier that only holds headers: `acc`
+    /// comes with the proof, and `header`, that of the block at `height`, must commit to it.
+    pub fn verify_against(&self, acc: &Accumulator<G, Utxo>, header: &BlockHeader) -> bool {
+        header.height == self.height && header.commits_to(acc) && self.verify(acc)
+    }
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    /// The history does not reach back, or forward, to this height.
+    UnknownHeight(u64),
+    /// The UTXO was not in the accumulator at the height asked about.
+    NotMember,
+    Proof(AccError),
+}
+
+impl From<AccError> for HistoryError {
+    fn from(err: AccError) -> Self {
+        HistoryError::Proof(err)
+    }
+}
+
+/// A chain from some base height on: the UTXO set at the base, and every block since. That is
+/// enough to recover the UTXO set and accumulator as of any block in it, and so to prove what the
+/// accumulator held then.
+pub struct ChainHistory<G: UnknownOrderGroup> {
+    base_height: u64,
+    base_utxos: Vec<Utxo>,
+    /// The blocks above the base height, in order.
+    blocks: Vec<Block<G, Utxo>>,
+    /// The heights of blocks whose accumulators were never checked, e.g. those applied in a batch
+    /// below its last. The history proves nothing against them.
+    unverified: HashSet<u64>,
+}
+
+impl<G: UnknownOrderGroup> ChainHistory<G> {
+    pub fn new(
+        base_height: u64,
+        base_utxos: Vec<Utxo>,
+        blocks: Vec<Block<G, Utxo>>,
+        unverified: HashSet<u64>,
+    ) -> Self {
+        Self {
+            base_height,
+            base_utxos,
+            blocks,
+            unverified,
+        }
+    }
+
+    pub fn base_height(&self) -> u64 {
+        self.base_height
+    }
+
+    pub fn tip_height(&self) -> u64 {
+        self.blocks
+            .last()
+            .map_or(self.base_height, |block| block.height)
+    }
+
+    /// The headers of the blocks above the base height, in order: all a header-only client keeps.
+    pub fn headers(&self) -> Vec<BlockHeader> {
+        self.blocks.iter().map(Block::header).collect()
+    }
+
+    /// The UTXO set as of the block at `height`, if the history covers it.
+    pub fn utxos_at(&self, height: u64) -> Option<Vec<Utxo>> {
+        if height < self.base_height || height > self.tip_height() {
+            return None;
+        }
+        let applied = self
+            .blocks
+            .iter()
+            .take_while(|block| block.height <= height)
+            .count();
+        Some(Checkpoint::apply_blocks(
+            self.base_utxos.clone(),
+            &self.blocks[..applied],
+        ))
+    }
+
+    /// The accumulator as of the block at `height`: the one the block committed to, or at the base
+    /// height, the one over the base UTXO set. None if the block's accumulator is unverified.
+    pub fn acc_at(&self, height: u64) -> Option<Accumulator<G, Utxo>> {
+        if height == self.base_height {
+            return Some(Accumulator::empty().add(&self.base_utxos));
+        }
+        if self.unverified.contains(&height) {
+            return None;
+        }
+        self.blocks
+            .iter()
+            .find(|block| block.height == height)
+            .map(|block| block.acc_new.clone())
+    }
+
+    /// A proof that `utxo` was in the accumulator as of the block at `height`. Its witness is the
+    /// accumulator over every other UTXO of the time, so this takes as long as the UTXO set is big.
+    pub fn prove_membership_at(
+        &self,
+        utxo: &Utxo,
+        height: u64,
+    ) -> Result<HistoricalProof<G>, HistoryError> {
+        let utxos = self
+            .utxos_at(height)
+            .ok_or(HistoryError::UnknownHeight(height))?;
+        let acc = self
+            .acc_at(height)
+            .ok_or(HistoryError::UnknownHeight(height))?;
+        if !utxos.contains(utxo) {
+            return Err(HistoryError::NotMember);
+        }
+        let others: Vec<Utxo> = utxos.into_iter().filter(|other| other != utxo).collect();
+        let witness = Witness(Accumulator::empty().add(&others));
+        let proof = acc.prove_membership(&[(utxo.clone(), witness)])?;
+        Ok(HistoricalProof {
+            utxo: utxo.clone(),
+            height,
+            proof,
+        })
+    }
+
+    /// Whether `proof` holds against the accumulator this history has at its height.
+    pub fn verify_membership_at(&self, proof: &HistoricalProof<G>) -> bool {
+        self.acc_at(proof.height)
+            .map_or(false, |acc| proof.verify(&acc))
+    }
+}
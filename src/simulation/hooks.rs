@@ -0,0 +1,124 @@
+use super::bridge::{Bridge, WitnessRequest};
+use super::control::RunControl;
+use super::miner::Miner;
+use super::state::{Block, Transaction, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use std::sync::{Arc, Mutex};
+
+type Callback<T> = Mutex<Box<dyn FnMut(&T, &RunControl) + Send>>;
+type MinerCallback<G> = Mutex<Box<dyn FnMut(usize, &Arc<Miner<G, Utxo>>) + Send>>;
+type BridgeCallback<G> = Mutex<Box<dyn FnMut(usize, &Arc<Mutex<Bridge<G>>>) + Send>>;
+
+/// Callbacks through which code embedding a simulation observes it as it runs, and steers it
+/// through the run control each is passed (e.g. stopping it once some condition holds). In
+/// real-time mode they are called from the simulation's threads, so a slow callback holds up the
+/// run.
+pub struct Hooks<G: UnknownOrderGroup> {
+    on_block: Option<Callback<Block<G, Utxo>>>,
+    on_transaction: Option<Callback<Transaction<G, Utxo>>>,
+    on_witness_request: Option<Callback<WitnessRequest>>,
+    on_miner_started: Option<MinerCallback<G>>,
+    on_bridge_started: Option<BridgeCallback<G>>,
+}
+
+impl<G: UnknownOrderGroup> Default for Hooks<G> {
+    fn default() -> Self {
+        Self {
+            on_block: None,
+            on_transaction: None,
+            on_witness_request: None,
+            on_miner_started: None,
+            on_bridge_started: None,
+        }
+    }
+}
+
+impl<G: UnknownOrderGroup> Hooks<G> {
+    /// Calls `callback` with every block forged, including Byzantine miners' corrupt ones.
+    pub fn on_block<F: FnMut(&Block<G, Utxo>, &RunControl) + Send + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.on_block = Some(Mutex::new(Box::new(callback)));
+    }
+
+    /// Calls `callback` with every transaction a user sends to the miners. In real-time mode,
+    /// transactions lost on the way are not seen.
+    pub fn on_transaction<F: FnMut(&Transaction<G, Utxo>, &RunControl) + Send + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.on_transaction = Some(Mutex::new(Box::new(callback)));
+    }
+
+    /// Calls `callback` with every witness request that reaches a bridge.
+    pub fn on_witness_request<F: FnMut(&WitnessRequest, &RunControl) + Send + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.on_witness_request = Some(Mutex::new(Box::new(callback)));
+    }
+
+    /// In real-time mode, calls `callback` with each miner's ID and the miner itself as it starts,
+    /// e.g. to serve an API on it. Discrete-event runs step their miners on the scheduler's thread
+    /// and do not hand them out.
+    pub fn on_miner_started<F: FnMut(usize, &Arc<Miner<G, Utxo>>) + Send + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.on_miner_started = Some(Mutex::new(Box::new(callback)));
+    }
+
+    /// Like `on_miner_started`, for bridges.
+    pub fn on_bridge_started<F: FnMut(usize, &Arc<Mutex<Bridge<G>>>) + Send + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.on_bridge_started = Some(Mutex::new(Box::new(callback)));
+    }
+
+    pub fn has_block(&self) -> bool {
+        self.on_block.is_some()
+    }
+
+    pub fn has_transaction(&self) -> bool {
+        self.on_transaction.is_some()
+    }
+
+    pub fn has_witness_request(&self) -> bool {
+        self.on_witness_request.is_some()
+    }
+
+    pub fn block(&self, block: &Block<G, Utxo>, control: &RunControl) {
+        call(&self.on_block, block, control);
+    }
+
+    pub fn transaction(&self, transaction: &Transaction<G, Utxo>, control: &RunControl) {
+        call(&self.on_transaction, transaction, control);
+    }
+
+    pub fn witness_request(&self, request: &WitnessRequest, control: &RunControl) {
+        call(&self.on_witness_request, request, control);
+    }
+
+    pub fn miner_started(&self, miner_id: usize, miner: &Arc<Miner<G, Utxo>>) {
+        if let Some(callback) = &self.on_miner_started {
+            let mut callback = callback.lock().unwrap();
+            (*callback)(miner_id, miner);
+        }
+    }
+
+    pub fn bridge_started(&self, bridge_id: usize, bridge: &Arc<Mutex<Bridge<G>>>) {
+        if let Some(callback) = &self.on_bridge_started {
+            let mut callback = callback.lock().unwrap();
+            (*callback)(bridge_id, bridge);
+        }
+    }
+}
+
+fn call<T>(callback: &Option<Callback<T>>, arg: &T, control: &RunControl) {
+    if let Some(callback) = callback {
+        let mut callback = callback.lock().unwrap();
+        (*callback)(arg, control);
+    }
+}
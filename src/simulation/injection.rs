@@ -0,0 +1,234 @@
+use super::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::clock::Clock;
+use super::metrics::Metrics;
+use super::state::{Block, NodeId, Transaction, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, Witness};
+use serde::Deserialize;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Something that goes wrong with a component.
+pub enum Fault {
+    /// The component stops for good.
+    Crash,
+    /// From then on, handling each message takes this much longer.
+    DelayMs(u64),
+    /// The next message the component sends is corrupted, so its recipient rejects it or cannot
+    /// use it.
+    Corrupt,
+    /// The component does nothing for this long, then carries on. Messages sent to it meanwhile
+    /// wait until it thaws.
+    FreezeMs(u64),
+    /// From then on, the component claims that the messages it signs come from this other
+    /// component. Recipients that check signatures reject them.
+    ForgeSender(NodeId),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+/// A fault that strikes `node` once the chain reaches `at_block`, e.g.
+/// `{ node: { bridge: 1 }, at_block: 10, fault: { freeze_ms: 5000 } }`.
+pub struct InjectedFault {
+    pub node: NodeId,
+    pub at_block: u64,
+    pub fault: Fault,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// What the faults striking a component do to it when it next checks in.
+pub struct Interruption {
+    pub crashed: bool,
+    /// How long to stay frozen, starting now.
+    pub freeze: Duration,
+    /// How much longer each message takes to handle.
+    pub delay: Duration,
+}
+
+#[derive(Default)]
+struct InjectorState {
+    /// Faults yet to strike.
+    pending: Vec<InjectedFault>,
+    crashed: Vec<NodeId>,
+    delays: Vec<(NodeId, Duration)>,
+    /// Components whose next message is to be corrupted.
+    corrupting: Vec<NodeId>,
+    /// Components posing as others, and who they claim to be.
+    forging: Vec<(NodeId, NodeId)>,
+}
+
+/// Injects the faults in a run's config into its components. Components check in with the
+/// injector as they go: before handling a message, to find out whether they have crashed, must
+/// freeze or are slowed down, and before sending one, to find out whether to corrupt it. Faults
+/// strike once the longest chain any component has applied reaches their block.
+pub struct FaultInjector {
+    metrics: Arc<Metrics>,
+    state: Mutex<InjectorState>,
+}
+
+impl FaultInjector {
+    pub fn new(faults: Vec<InjectedFault>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics,
+            state: Mutex::new(InjectorState {
+                pending: faults,
+                ..InjectorState::default()
+            }),
+        }
+    }
+
+    /// The faults that have struck `node` by now. Crashes and freezes are reported once; delays
+    /// every time.
+    pub fn check(&self, node: NodeId) -> Interruption {
+        let mut state = self.state.lock().unwrap();
+        let mut freeze = Duration::from_millis(0);
+        if state.pending.iter().any(|fault| fault.node == node) {
+            let height = self.metrics.chain_height();
+            let (due, pending): (Vec<_>, Vec<_>) = state
+                .pending
+                .drain(..)
+                .partition(|fault| fault.node == node && fault.at_block <= height);
+            state.pending = pending;
+            for fault in due {
+                info!("Injecting {:?} into {:?}.", fault.fault, node);
+                match fault.fault {
+                    Fault::Crash => state.crashed.push(node),
+                    Fault::DelayMs(ms) => state.delays.push((node, Duration::from_millis(ms))),
+                    Fault::Corrupt => state.corrupting.push(node),
+                    Fault::FreezeMs(ms) => freeze += Duration::from_millis(ms),
+                    Fault::ForgeSender(claimed) => state.forging.push((node, claimed)),
+                }
+            }
+        }
+        Interruption {
+            crashed: state.crashed.contains(&node),
+            freeze,
+            delay: delay_of(&state, node),
+        }
+    }
+
+    /// How much longer `node` takes to handle each message, without striking any due faults.
+    pub fn delay(&self, node: NodeId) -> Duration {
+        delay_of(&self.state.lock().unwrap(), node)
+    }
+
+    /// For components polling on their own threads, once per iteration: freezes and delays the
+    /// calling thread as `node`'s faults say, then returns whether `node` is still up.
+    pub fn interrupt(&self, node: NodeId, clock: &dyn Clock) -> bool {
+        let interruption = self.check(node);
+        if interruption.crashed {
+            return false;
+        }
+        clock.sleep(interruption.freeze + interruption.delay);
+        true
+    }
+
+    /// `message`, corrupted if `node`'s next message is to be.
+    pub fn tamper<T: Corrupt>(&self, node: NodeId, mut message: T) -> T {
+        if self.corrupts(node) {
+            message.corrupt();
+        }
+        message
+    }
+
+    /// Whether the next message `node` sends is to be corrupted. Each `corrupt` fault corrupts a
+    /// single message.
+    pub fn corrupts(&self, node: NodeId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state
+            .corrupting
+            .iter()
+            .position(|corrupting| *corrupting == node)
+        {
+            Some(index) => {
+                state.corrupting.remove(index);
+                info!("Corrupting a message from {:?}.", node);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Who `node` claims to be on the messages it signs: itself, unless it is forging another
+    /// component's identity.
+    pub fn claimed_sender(&self, node: NodeId) -> NodeId {
+        let state = self.state.lock().unwrap();
+        state
+            .forging
+            .iter()
+            .rev()
+            .find(|(forger, _)| *forger == node)
+            .map_or(node, |(_, claimed)| *claimed)
+    }
+}
+
+fn delay_of(state: &InjectorState, node: NodeId) -> Duration {
+    state
+        .delays
+        .iter()
+        .filter(|(delayed, _)| *delayed == node)
+        .map(|(_, delay)| *delay)
+        .sum()
+}
+
+/// A message a faulty component can garble.
+pub trait Corrupt {
+    fn corrupt(&mut self);
+}
+
+/// A witness to nothing, which proves no UTXO is unspent.
+fn bogus_witness<G: UnknownOrderGroup>() -> Witness<G, Utxo> {
+    Witness(Accumulator::empty())
+}
+
+impl<G: UnknownOrderGroup> Corrupt for Block<G, Utxo> {
+    /// Includes the first transaction twice, like a Byzantine miner, or skips a height if there
+    /// is none.
+    fn corrupt(&mut self) {
+        match self.transactions.first().cloned() {
+            Some(transaction) => self.transactions.push(transaction),
+            None => self.height += 1,
+        }
+    }
+}
+
+impl<G: UnknownOrderGroup> Corrupt for Transaction<G, Utxo> {
+    fn corrupt(&mut self) {
+        for (_, witness) in &mut self.utxos_spent_with_witnesses {
+            *witness = bogus_witness();
+        }
+    }
+}
+
+impl Corrupt for WitnessRequest {
+    /// Garbles the request's ID, so the user does not recognize the response.
+    fn corrupt(&mut self) {
+        self.request_id = Uuid::nil();
+    }
+}
+
+impl<G: UnknownOrderGroup> Corrupt for WitnessResponse<G, Utxo> {
+    fn corrupt(&mut self) {
+        for (_, witness) in &mut self.utxos_with_witnesses {
+            *witness = bogus_witness();
+        }
+    }
+}
+
+impl<G: UnknownOrderGroup> Corrupt for UserUpdate<G> {
+    /// Swaps what was added and deleted.
+    fn corrupt(&mut self) {
+        mem::swap(&mut self.utxos_added, &mut self.utxos_deleted);
+    }
+}
+
+impl<M: Corrupt + Clone> Corrupt for Arc<M> {
+    /// Corrupts a copy of the message, leaving anyone else sharing it with the original.
+    fn corrupt(&mut self) {
+        Arc::make_mut(self).corrupt();
+    }
+}
@@ -0,0 +1,191 @@
+use super::batch::BatchProof;
+use super::deletion::DeletionProof;
+use super::proofs::ProofOp;
+use super::state::Block;
+use super::swap::SwapProof;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{AccError, Accumulator, MembershipProof, NonmembershipProof, Witness};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// A proof made or checked: what it was for, what it was over, and how long it took.
+pub struct ProofEvent {
+    pub op: ProofOp,
+    /// Elements the proof is about, e.g. the UTXOs a block deletes, or blocks for a sync batch.
+    pub elems: usize,
+    /// Elements of the set the prover had to hash besides, for proofs that need the whole set.
+    pub set_elems: usize,
+    /// Estimated bytes of the proof on the wire.
+    pub bytes: usize,
+    pub time: Duration,
+}
+
+/// Something told of every proof `Instrumented` makes or checks, like `Metrics`.
+pub trait ProofObserver: Send + Sync {
+    fn proof_generated(&self, event: &ProofEvent);
+    fn proof_verified(&self, event: &ProofEvent);
+}
+
+/// The accumulator's proving and verifying operations, each timed and reported to an observer,
+/// so components call these rather than time the operations themselves.
+pub struct Instrumented<'a> {
+    observer: &'a dyn ProofObserver,
+}
+
+impl<'a> Instrumented<'a> {
+    pub fn new(observer: &'a dyn ProofObserver) -> Self {
+        Self { observer }
+    }
+
+    /// Deletes `elem_witnesses` from `acc`, with a proof of the deletion.
+    pub fn prove_deletion<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        acc: &Accumulator<G, T>,
+        elem_witnesses: &[(T, Witness<G, T>)],
+    ) -> Result<(Accumulator<G, T>, DeletionProof<G, T>), AccError> {
+        self.generated(ProofOp::BlockDeletions, elem_witnesses.len(), 0, || {
+            DeletionProof::prove(acc, elem_witnesses)
+        })
+    }
+
+    /// Adds `elems` to `acc`, with a proof that they are in the result.
+    pub fn add_with_proof<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        acc: Accumulator<G, T>,
+        elems: &[T],
+    ) -> (Accumulator<G, T>, MembershipProof<G, T>) {
+        self.generated(ProofOp::BlockAdditions, elems.len(), 0, || {
+            acc.add_with_proof(elems)
+        })
+    }
+
+    /// Swaps `elem_witnesses` in `acc` for `added`, with a proof of the swap.
+    pub fn prove_swap<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        acc: &Accumulator<G, T>,
+        elem_witnesses: &[(T, Witness<G, T>)],
+        added: &[T],
+    ) -> Result<(Accumulator<G, T>, SwapProof<G>), AccError> {
+        let elems = elem_witnesses.len() + added.len();
+        self.generated(ProofOp::BlockSwap, elems, 0, || {
+            SwapProof::prove(acc, elem_witnesses, added)
+        })
+    }
+
+    /// A proof for `op` that `elems` are not in `acc`, which holds `set`.
+    pub fn prove_nonmembership<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        op: ProofOp,
+        acc: &Accumulator<G, T>,
+        set: &[T],
+        elems: &[T],
+    ) -> Result<NonmembershipProof<G, T>, AccError> {
+        self.generated(op, elems.len(), set.len(), || {
+            acc.prove_nonmembership(set, elems)
+        })
+    }
+
+    /// A proof of the updates of `blocks`, which follow `acc`. See `BatchProof::prove`.
+    pub fn prove_batch<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        acc: &Accumulator<G, T>,
+        blocks: &[Block<G, T>],
+        utxos: &HashSet<T>,
+    ) -> BatchProof<G, T> {
+        self.generated(ProofOp::SyncBatch, blocks.len(), utxos.len(), || {
+            BatchProof::prove(acc, blocks, utxos)
+        })
+    }
+
+    pub fn verify_deletion<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        proof: &DeletionProof<G, T>,
+        acc: &Accumulator<G, T>,
+        elems: &[T],
+    ) -> bool {
+        self.verified(ProofOp::BlockDeletions, elems.len(), || {
+            proof.verify(acc, elems)
+        })
+    }
+
+    pub fn verify_additions<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        acc_new: &Accumulator<G, T>,
+        elems: &[T],
+        proof: &MembershipProof<G, T>,
+    ) -> bool {
+        self.verified(ProofOp::BlockAdditions, elems.len(), || {
+            acc_new.verify_membership_batch(elems, proof)
+        })
+    }
+
+    pub fn verify_swap<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        proof: &SwapProof<G>,
+        acc: &Accumulator<G, T>,
+        acc_new: &Accumulator<G, T>,
+        deleted: &[T],
+        added: &[T],
+    ) -> bool {
+        let elems = deleted.len() + added.len();
+        self.verified(ProofOp::BlockSwap, elems, || {
+            proof.verify(acc, acc_new, deleted, added)
+        })
+    }
+
+    pub fn verify_nonmembership<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        op: ProofOp,
+        acc: &Accumulator<G, T>,
+        elems: &[T],
+        proof: &NonmembershipProof<G, T>,
+    ) -> bool {
+        self.verified(op, elems.len(), || acc.verify_nonmembership(elems, proof))
+    }
+
+    pub fn verify_batch<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+        &self,
+        proof: &BatchProof<G, T>,
+        acc: &Accumulator<G, T>,
+        blocks: &[Block<G, T>],
+    ) -> bool {
+        self.verified(ProofOp::SyncBatch, blocks.len(), || {
+            proof.verify(acc, blocks)
+        })
+    }
+
+    fn generated<R, F: FnOnce() -> R>(
+        &self,
+        op: ProofOp,
+        elems: usize,
+        set_elems: usize,
+        prove: F,
+    ) -> R {
+        let start = Instant::now();
+        let result = prove();
+        self.observer.proof_generated(&ProofEvent {
+            op,
+            elems,
+            set_elems,
+            bytes: op.proof_bytes(),
+            time: start.elapsed(),
+        });
+        result
+    }
+
+    fn verified<F: FnOnce() -> bool>(&self, op: ProofOp, elems: usize, verify: F) -> bool {
+        let start = Instant::now();
+        let is_valid = verify();
+        self.observer.proof_verified(&ProofEvent {
+            op,
+            elems,
+            set_elems: 0,
+            bytes: op.proof_bytes(),
+            time: start.elapsed(),
+        });
+        is_valid
+    }
+}
@@ -0,0 +1,96 @@
+use rug::{Assign, Integer};
+use std::cell::RefCell;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// How many spare integers each thread keeps. Proving or checking a block takes a few at once.
+const CAPACITY: usize = 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static FRESH: AtomicU64 = AtomicU64::new(0);
+static REUSED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static SPARE: RefCell<Vec<Integer>> = RefCell::new(Vec::new());
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+/// Integers handed out by `take`, over the whole process so far.
+pub struct IntegerPoolStats {
+    /// Integers started afresh, whose limbs are allocated as they grow.
+    pub fresh: u64,
+    /// Integers taken from the pool, with the limbs a block before grew them to.
+    pub reused: u64,
+}
+
+/// An integer taken from the pool, which goes back to it when dropped. The products of a block's
+/// primes run to thousands of limbs, so an integer that held one last block seldom has to grow
+/// for the next.
+pub struct PooledInteger(Integer);
+
+impl Deref for PooledInteger {
+    type Target = Integer;
+
+    fn deref(&self) -> &Integer {
+        &self.0
+    }
+}
+
+impl DerefMut for PooledInteger {
+    fn deref_mut(&mut self) -> &mut Integer {
+        &mut self.0
+    }
+}
+
+impl Drop for PooledInteger {
+    fn drop(&mut self) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let value = mem::take(&mut self.0);
+        // The thread may be exiting, its pool gone already.
+        let _ = SPARE.try_with(|spare| {
+            let mut spare = spare.borrow_mut();
+            if spare.len() < CAPACITY {
+                spare.push(value);
+            }
+        });
+    }
+}
+
+/// Has the integers proofs work with kept for reuse once they are done with, rather than freed.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Has integers freed once they are done with again, and frees this thread's spares.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+    let _ = SPARE.try_with(|spare| spare.borrow_mut().clear());
+}
+
+/// An integer of value zero: a spare of this thread's if pooling is enabled and there is one, and
+/// otherwise a fresh one.
+pub fn take() -> PooledInteger {
+    if ENABLED.load(Ordering::Relaxed) {
+        let spare = SPARE
+            .try_with(|spare| spare.borrow_mut().pop())
+            .ok()
+            .flatten();
+        if let Some(mut value) = spare {
+            REUSED.fetch_add(1, Ordering::Relaxed);
+            value.assign(0);
+            return PooledInteger(value);
+        }
+    }
+    FRESH.fetch_add(1, Ordering::Relaxed);
+    PooledInteger(Integer::new())
+}
+
+pub fn stats() -> IntegerPoolStats {
+    IntegerPoolStats {
+        fresh: FRESH.load(Ordering::Relaxed),
+        reused: REUSED.load(Ordering::Relaxed),
+    }
+}
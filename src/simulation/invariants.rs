@@ -0,0 +1,353 @@
+use super::bridge::Bridge;
+use super::commitment::SetCommitment;
+use super::state::{Block, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, Witness};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display};
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+/// How a bridge's UTXOs, and its witnesses to them, differ from the plain UTXO set at a height.
+/// Each list is ordered by UTXO ID.
+pub struct UtxoDiff {
+    /// Unspent UTXOs of the bridge's users that the bridge does not hold.
+    pub missing: Vec<Utxo>,
+    /// UTXOs the bridge holds that are spent, or were never created.
+    pub extra: Vec<Utxo>,
+    /// Unspent UTXOs whose witness from the bridge does not verify against its accumulator.
+    pub unverifiable: Vec<Utxo>,
+    /// Spent UTXOs that a witness still shows to be in the bridge's accumulator.
+    pub still_provable: Vec<Utxo>,
+}
+
+impl UtxoDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.unverifiable.is_empty()
+            && self.still_provable.is_empty()
+    }
+}
+
+impl Display for UtxoDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lists = [
+            ("- unspent, not held by the bridge", &self.missing),
+            ("+ held by the bridge, not unspent", &self.extra),
+            ("! unspent, witness does not verify", &self.unverifiable),
+            ("! spent, witness still verifies", &self.still_provable),
+        ];
+        for (label, utxos) in lists.iter() {
+            for utxo in utxos.iter() {
+                write!(f, "\n  {}: {:?}", label, utxo)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// A run in which the accumulator no longer means what the chain says.
+pub enum InvariantError {
+    /// The block at `height` spends a UTXO that was not unspent.
+    UnknownSpend { height: u64, utxo: Utxo },
+    /// Bridge `bridge_id`'s accumulator as of `height` is not that of the chain's block there.
+    Mismatch { bridge_id: usize, height: u64 },
+    /// Bridge `bridge_id`'s UTXOs or witnesses as of `height` differ from the UTXO set.
+    Diverged {
+        bridge_id: usize,
+        height: u64,
+        diff: UtxoDiff,
+    },
+}
+
+impl Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantError::UnknownSpend { height, utxo } => write!(
+                f,
+                "Block {} spends {:?}, which is not in the UTXO set",
+                height, utxo
+            ),
+            InvariantError::Mismatch { bridge_id, height } => write!(
+                f,
+                "Bridge {}'s accumulator at block {} is not the chain's",
+                bridge_id, height
+            ),
+            InvariantError::Diverged {
+                bridge_id,
+                height,
+                diff,
+            } => write!(
+                f,
+                "Bridge {} diverges from the UTXO set at block {}:{}",
+                bridge_id, height, diff
+            ),
+        }
+    }
+}
+
+impl Error for InvariantError {}
+
+/// Test support asserting that the accumulator and the UTXO set agree: follows every block of
+/// the chain into a plain `HashSet` model of the UTXO set, and each time a bridge applies a block,
+/// checks that every unspent UTXO of the bridge's users has a witness from the bridge that
+/// verifies against the bridge's accumulator, and that no UTXO of theirs spent since the bridge
+/// was last checked has one. For the latter it tries the one witness a spent UTXO could have, the
+/// accumulator of every other UTXO, which takes recomputing the accumulator at each height, as
+/// the auditor does, so it is meant for small deterministic runs in tests rather than long ones.
+pub struct InvariantChecker<G: UnknownOrderGroup> {
+    base_height: u64,
+    height: u64,
+    /// The height at which each UTXO of the run was created, and at which it was spent, if it was.
+    created_at: HashMap<Utxo, u64>,
+    spent_at: HashMap<Utxo, u64>,
+    /// The height each bridge was last checked at.
+    checked_at: HashMap<usize, u64>,
+    /// The accumulator of the unspent UTXOs at the last height it was needed at.
+    unspent_acc: Option<(u64, Accumulator<G, Utxo>)>,
+}
+
+impl<G: UnknownOrderGroup> InvariantChecker<G> {
+    /// A checker following the chain from `base_height`, where the UTXO set is `base_utxos`.
+    pub fn new(base_height: u64, base_utxos: Vec<Utxo>) -> Self {
+        Self {
+            base_height,
+            height: base_height,
+            created_at: base_utxos
+                .into_iter()
+                .map(|utxo| (utxo, base_height))
+                .collect(),
+            spent_at: HashMap::new(),
+            checked_at: HashMap::new(),
+            unspent_acc: None,
+        }
+    }
+
+    /// The height of the last block the checker has followed.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Applies each of `blocks` that extends the chain the checker has followed, in order, to its
+    /// UTXO set. Blocks at or below its height are skipped, and so is everything after a gap.
+    pub fn follow(&mut self, blocks: &[Block<G, Utxo>]) -> Result<(), InvariantError> {
+        for block in blocks {
+            if block.height <= self.height {
+                continue;
+            }
+            if block.height != self.height + 1 {
+                break;
+            }
+            for transaction in &block.transactions {
+                for (utxo, _) in &transaction.utxos_spent_with_witnesses {
+                    if !self.is_unspent_at(utxo, block.height - 1) {
+                        return Err(InvariantError::UnknownSpend {
+                            height: block.height,
+                            utxo: utxo.clone(),
+                        });
+                    }
+                    self.spent_at.insert(utxo.clone(), block.height);
+                }
+                for utxo in &transaction.utxos_created {
+                    self.created_at.insert(utxo.clone(), block.height);
+                }
+            }
+            self.height = block.height;
+        }
+        Ok(())
+    }
+
+    /// Checks bridge `bridge_id` at the height it has applied blocks up to, against `chain_acc`,
+    /// the accumulator of the chain's block at that height if there is one. A bridge ahead of the
+    /// blocks the checker has followed is left for later.
+    pub fn check(
+        &mut self,
+        bridge_id: usize,
+        bridge: &Bridge<G>,
+        chain_acc: Option<&Accumulator<G, Utxo>>,
+    ) -> Result<(), InvariantError> {
+        let height = bridge.block_height();
+        if height > self.height {
+            return Ok(());
+        }
+        let acc = bridge.acc();
+        if chain_acc.map_or(false, |chain_acc| chain_acc != acc) {
+            return Err(InvariantError::Mismatch { bridge_id, height });
+        }
+
+        let users: HashSet<usize> = bridge.user_ids().into_iter().collect();
+        let expected: HashSet<Utxo> = self
+            .created_at
+            .keys()
+            .filter(|utxo| users.contains(&utxo.user_id) && self.is_unspent_at(utxo, height))
+            .cloned()
+            .collect();
+        let held: HashSet<Utxo> = users
+            .iter()
+            .flat_map(|user_id| bridge.utxos_of(*user_id))
+            .collect();
+        let mut diff = UtxoDiff {
+            missing: expected.difference(&held).cloned().collect(),
+            extra: held.difference(&expected).cloned().collect(),
+            ..UtxoDiff::default()
+        };
+
+        let unspent: Vec<Utxo> = expected.intersection(&held).cloned().collect();
+        let witnesses = bridge
+            .membership_witnesses(&unspent)
+            .expect("The bridge holds every UTXO asked for");
+        for (utxo, witness) in witnesses {
+            if !verifies(acc, &utxo, &witness) {
+                diff.unverifiable.push(utxo);
+            }
+        }
+
+        let last_checked = self
+            .checked_at
+            .insert(bridge_id, height)
+            .unwrap_or(self.base_height);
+        let spent: Vec<Utxo> = self
+            .spent_at
+            .iter()
+            .filter(|(utxo, spent_at)| {
+                users.contains(&utxo.user_id) && **spent_at > last_checked && **spent_at <= height
+            })
+            .map(|(utxo, _)| utxo.clone())
+            .collect();
+        if !spent.is_empty() {
+            // A spent UTXO still in the accumulator would have every unspent one as its witness.
+            let others = Witness(self.unspent_acc(height));
+            diff.still_provable = spent
+                .into_iter()
+                .filter(|utxo| verifies(acc, utxo, &others))
+                .collect();
+        }
+
+        if diff.is_empty() {
+            return Ok(());
+        }
+        for utxos in [
+            &mut diff.missing,
+            &mut diff.extra,
+            &mut diff.unverifiable,
+            &mut diff.still_provable,
+        ]
+        .iter_mut()
+        {
+            utxos.sort_by_key(|utxo| utxo.id);
+        }
+        Err(InvariantError::Diverged {
+            bridge_id,
+            height,
+            diff,
+        })
+    }
+
+    /// The accumulator of the UTXOs unspent as of the block at `height`.
+    fn unspent_acc(&mut self, height: u64) -> Accumulator<G, Utxo> {
+        match &self.unspent_acc {
+            Some((at, acc)) if *at == height => acc.clone(),
+            _ => {
+                let unspent: Vec<Utxo> = self
+                    .created_at
+                    .keys()
+                    .filter(|utxo| self.is_unspent_at(utxo, height))
+                    .cloned()
+                    .collect();
+                let acc = Accumulator::<G, Utxo>::empty().add(&unspent);
+                self.unspent_acc = Some((height, acc.clone()));
+                acc
+            }
+        }
+    }
+
+    /// Whether `utxo` had been created and not spent as of the block at `height`.
+    fn is_unspent_at(&self, utxo: &Utxo, height: u64) -> bool {
+        let created = self
+            .created_at
+            .get(utxo)
+            .map_or(false, |created_at| *created_at <= height);
+        let spent = self
+            .spent_at
+            .get(utxo)
+            .map_or(false, |spent_at| *spent_at <= height);
+        created && !spent
+    }
+}
+
+/// Whether `witness` shows `utxo` to be in `acc`.
+fn verifies<G: UnknownOrderGroup>(
+    acc: &Accumulator<G, Utxo>,
+    utxo: &Utxo,
+    witness: &Witness<G, Utxo>,
+) -> bool {
+    <Accumulator<G, Utxo> as SetCommitment<Utxo>>::verify_membership(acc, utxo, witness)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::clock::ManualClock;
+    use crate::simulation::metrics::Metrics;
+    use accumulator::group::Rsa2048;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn utxo(user_id: usize) -> Utxo {
+        Utxo {
+            id: Uuid::new_v4(),
+            user_id,
+            value: 1,
+        }
+    }
+
+    /// Bridge 0, serving user 0, at the genesis of a chain holding `utxos`, tracking `held` of
+    /// them with the witness to all of them from the rest.
+    fn bridge(utxos: &[Utxo], held: &[Utxo]) -> Bridge<Rsa2048> {
+        let acc = Accumulator::<Rsa2048, Utxo>::empty().add(utxos);
+        let rest: Vec<Utxo> = utxos
+            .iter()
+            .filter(|utxo| !held.contains(utxo))
+            .cloned()
+            .collect();
+        let witness = Witness(Accumulator::<Rsa2048, Utxo>::empty().add(&rest));
+        let metrics = Arc::new(Metrics::new(Arc::new(ManualClock::new())));
+        Bridge::new(
+            0,
+            acc,
+            witness,
+            held.to_vec(),
+            vec![0].into_iter().collect(),
+            metrics,
+        )
+    }
+
+    #[test]
+    fn test_consistent_bridge() {
+        let utxos = vec![utxo(0), utxo(0), utxo(1)];
+        let bridge = bridge(&utxos, &utxos[..2]);
+        let mut checker = InvariantChecker::new(0, utxos.clone());
+        let chain_acc = Accumulator::<Rsa2048, Utxo>::empty().add(&utxos);
+        assert_eq!(checker.check(0, &bridge, Some(&chain_acc)), Ok(()));
+    }
+
+    #[test]
+    fn test_dropped_utxo_diverges() {
+        let utxos = vec![utxo(0), utxo(0), utxo(1)];
+        let bridge = bridge(&utxos, &utxos[..1]);
+        let mut checker = InvariantChecker::new(0, utxos.clone());
+        assert_eq!(
+            checker.check(0, &bridge, None),
+            Err(InvariantError::Diverged {
+                bridge_id: 0,
+                height: 0,
+                diff: UtxoDiff {
+                    missing: vec![utxos[1].clone()],
+                    ..UtxoDiff::default()
+                },
+            })
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use super::codec::Encode;
+use super::miner::Miner;
+use super::state::{Block, Transaction, Utxo};
+use super::util::{self, ConnectionLimit};
+use super::wire::{self, WireMessage};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long a connection may go without reading or writing anything before it is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request bodies longer than this are refused rather than read.
+const MAX_BODY_BYTES: usize = 64 << 20;
+
+/// Request and header lines longer than this are refused rather than read.
+const MAX_LINE_BYTES: usize = 8 << 10;
+
+/// Requests with more header lines than this are refused.
+const MAX_HEADERS: usize = 100;
+
+/// Connections served at once. Clients beyond this are answered 503 and disconnected.
+const MAX_CONNECTIONS: usize = 64;
+
+// Error codes from the JSON-RPC spec, plus Bitcoin Core's code for a bad parameter value.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const INVALID_PARAMETER: i64 = -8;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new<M: Into<String>>(code: i64, message: M) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Serves Bitcoin-style JSON-RPC for `miner` at `addr`, over HTTP POST, until the process exits:
+///
+/// - `getblockcount`: the height of the miner's latest block.
+/// - `getblock height [verbosity=1]`: the block at `height`, hex-encoded in the wire format with
+///   verbosity 0, or as JSON listing each transaction's created and spent UTXOs with verbosity 1.
+/// - `sendrawtransaction hex`: adds a transaction, hex-encoded in the wire format, to the miner's
+///   mempool.
+/// - `getaccumulator`: the miner's accumulator, as hex-encoded bincode.
+pub fn serve<G: UnknownOrderGroup, A: ToSocketAddrs>(
+    addr: A,
+    miner: Arc<Miner<G, Utxo>>,
+) -> io::Result<JoinHandle<()>>
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+{
+    let listener = TcpListener::bind(addr)?;
+    info!(
+        "Serving the miner JSON-RPC API on {}.",
+        listener.local_addr()?
+    );
+    let limit = ConnectionLimit::new(MAX_CONNECTIONS);
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("JSON-RPC server error: {}", err);
+                    continue;
+                }
+            };
+            let slot = match limit.acquire() {
+                Some(slot) => slot,
+                None => {
+                    warn!(
+                        "Turning away a JSON-RPC client: {} are connected.",
+                        MAX_CONNECTIONS
+                    );
+                    let busy = reply(
+                        Value::Null,
+                        Err(RpcError::new(INTERNAL_ERROR, "Too many connections")),
+                    );
+                    let _ = stream.set_write_timeout(Some(IO_TIMEOUT)).and_then(|()| {
+                        write_response(&mut stream, "503 Service Unavailable", &busy)
+                    });
+                    continue;
+                }
+            };
+            // Each connection gets its own thread, so a slow client holds up no one else.
+            let miner = Arc::clone(&miner);
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(err) = respond(stream, &miner) {
+                    warn!("JSON-RPC server error: {}", err);
+                }
+            });
+        }
+    }))
+}
+
+fn respond<G: UnknownOrderGroup>(stream: TcpStream, miner: &Miner<G, Utxo>) -> io::Result<()>
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+{
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let body = read_body(&mut reader)?;
+    let response = match serde_json::from_slice::<Value>(&body) {
+        Ok(request) => handle(&request, miner),
+        Err(err) => reply(
+            Value::Null,
+            Err(RpcError::new(PARSE_ERROR, err.to_string())),
+        ),
+    };
+    write_response(reader.get_mut(), "200 OK", &response)
+}
+
+fn write_response<W: Write>(out: &mut W, status: &str, body: &Value) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        out,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Reads past the request line and headers of an HTTP request, then reads its body.
+fn read_body<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut content_length = 0;
+    for headers in 0.. {
+        let mut line = String::new();
+        if read_line(reader, &mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        // The request line comes first.
+        if headers > MAX_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Too many headers",
+            ));
+        }
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or("");
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = header
+                .next()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Bad Content-Length"))?;
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("A {}-byte request is too large", content_length),
+        ));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Reads a line into `line` like `BufRead::read_line`, refusing one longer than `MAX_LINE_BYTES`
+/// rather than reading on.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_BYTES as u64)
+        .read_line(line)?;
+    if read == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Line too long"));
+    }
+    Ok(read)
+}
+
+fn reply(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "result": result, "error": null, "id": id }),
+        Err(err) => json!({
+            "result": null,
+            "error": { "code": err.code, "message": err.message },
+            "id": id,
+        }),
+    }
+}
+
+fn handle<G: UnknownOrderGroup>(request: &Value, miner: &Miner<G, Utxo>) -> Value
+where
+    Block<G, Utxo>: WireMessage,
+    Transaction<G, Utxo>: WireMessage,
+    Accumulator<G, Utxo>: Serialize,
+{
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        None => return reply(id, Err(RpcError::new(INVALID_REQUEST, "Missing method"))),
+    };
+    let params = match request.get("params") {
+        Some(Value::Array(params)) => params.as_slice(),
+        Some(Value::Null) | None => &[],
+        Some(_) => {
+            return reply(
+                id,
+                Err(RpcError::new(INVALID_PARAMS, "Params must be a list")),
+            )
+        }
+    };
+    let result = match method {
+        "getblockcount" => Ok(json!(miner.block_height())),
+        "getblock" => get_block(params, miner),
+        "sendrawtransaction" => send_raw_transaction(params, miner),
+        "getaccumulator" => miner
+            .acc()
+            .to_hex()
+            .map(Value::from)
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err.to_string())),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Method not found: {}", method),
+        )),
+    };
+    reply(id, result)
+}
+
+fn get_block<G: UnknownOrderGroup>(
+    params: &[Value],
+    miner: &Miner<G, Utxo>,
+) -> Result<Value, RpcError>
+where
+    Block<G, Utxo>: WireMessage,
+{
+    let height = params
+        .get(0)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "getblock takes a block height"))?;
+    let verbosity = match params.get(1) {
+        Some(verbosity) => verbosity
+            .as_u64()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Verbosity must be 0 or 1"))?,
+        None => 1,
+    };
+    let block = miner
+        .block_at(height)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMETER, "Block height out of range"))?;
+    if verbosity == 0 {
+        return wire::encode(block.as_ref())
+            .map(|bytes| Value::from(util::to_hex(&bytes)))
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err.to_string()));
+    }
+    let transactions: Vec<Value> = block
+        .transactions
+        .iter()
+        .map(|transaction| {
+            let spent: Vec<&Utxo> = transaction
+                .utxos_spent_with_witnesses
+                .iter()
+                .map(|(utxo, _)| utxo)
+                .collect();
+            json!({ "utxos_created": transaction.utxos_created, "utxos_spent": spent })
+        })
+        .collect();
+    Ok(json!({
+        "height": block.height,
+        "ntx": transactions.len(),
+        "tx": transactions,
+    }))
+}
+
+fn send_raw_transaction<G: UnknownOrderGroup>(
+    params: &[Value],
+    miner: &Miner<G, Utxo>,
+) -> Result<Value, RpcError>
+where
+    Transaction<G, Utxo>: WireMessage,
+{
+    let hex = params
+        .get(0)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "sendrawtransaction takes a hex string"))?;
+    let bytes = util::from_hex(hex)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMETER, "Transaction is not valid hex"))?;
+    let transaction = wire::decode(&bytes)
+        .map_err(|err| RpcError::new(INVALID_PARAMETER, format!("Bad transaction: {}", err)))?;
+    miner.add_transaction(Arc::new(transaction));
+    Ok(Value::Null)
+}
@@ -0,0 +1,369 @@
+use super::auth;
+use super::config::{ClusterTransport, Config, GroupKind, ModulusSource};
+use super::discovery::Peer;
+use super::modulus;
+use super::state::NodeId;
+use serde::Serialize;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long bridges and user swarms get to start listening before the miners start sending.
+const STARTUP_GRACE: Duration = Duration::from_millis(500);
+
+/// The metrics files each node process exports, merged across processes once they all exit.
+const METRICS_FILES: [&str; 2] = ["blocks.csv", "components.csv"];
+
+#[derive(Serialize)]
+/// The part of a `Config` a generated deployment file spells out; the rest take their defaults.
+struct DeploymentConfig<'a> {
+    group: GroupKind,
+    rsa_modulus: ModulusSource,
+    seed: u64,
+    num_miners: usize,
+    users_per_bridge: &'a [usize],
+    block_interval_ms: u64,
+    speedup: u32,
+    retry_after_blocks: u64,
+    authenticate_messages: bool,
+    prove_fresh_utxos: bool,
+    compress_blocks: bool,
+    encrypt_links: bool,
+    noise_keys: Option<PathBuf>,
+    transport: ClusterTransport,
+    log_filter: &'a str,
+    max_blocks: Option<u64>,
+    max_duration_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct DeploymentFile<'a> {
+    config: DeploymentConfig<'a>,
+    peers: Vec<Peer>,
+}
+
+/// One node process of a launched cluster.
+struct NodeProcess {
+    name: String,
+    child: Child,
+    logs: Vec<JoinHandle<()>>,
+}
+
+/// A cluster deployment on this machine, started with one command: generates the deployment file,
+/// spawns the `miner`, `bridge` and `user-swarm` binaries as child processes in the order they
+/// need, and collects what they log and the metrics they export into one directory.
+///
+/// The directory ends up holding `deployment.yaml`, the RSA modulus if one was generated, the
+/// Noise pre-shared key in `noise/` if one was, `cluster.log` with every process's output, each
+/// line prefixed with the process it came from, a subdirectory of metrics per process, and
+/// `blocks.csv` and `components.csv` merging them, with a leading `process` column.
+pub struct LocalCluster {
+    config: Config,
+    output_dir: PathBuf,
+    base_port: u16,
+    /// Where the node binaries are: next to the running executable.
+    bin_dir: PathBuf,
+}
+
+impl LocalCluster {
+    /// A cluster running under `config` and collecting its output in `output_dir`. Its processes
+    /// listen on consecutive ports of 127.0.0.1 from `base_port`.
+    pub fn new(config: Config, output_dir: PathBuf, base_port: u16) -> io::Result<Self> {
+        let exe = env::current_exe()?;
+        let bin_dir = exe.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(Self {
+            config,
+            output_dir,
+            base_port,
+            bin_dir,
+        })
+    }
+
+    /// The peer list of the cluster: two ports for each miner and bridge, and two for each user
+    /// of a swarm, as `cluster::Deployment` lays them out.
+    fn peers(&self) -> Vec<Peer> {
+        let mut port = self.base_port;
+        let mut next_addr = |ports: usize| {
+            let addr = format!("127.0.0.1:{}", port);
+            port += ports as u16;
+            addr
+        };
+        let mut peers = Vec::new();
+        for miner_id in 0..self.config.num_miners {
+            let addr = next_addr(2);
+            peers.push(Peer {
+                node: NodeId::Miner(miner_id),
+                addr,
+            });
+        }
+        for bridge_id in 0..self.config.num_bridges() {
+            let addr = next_addr(2);
+            peers.push(Peer {
+                node: NodeId::Bridge(bridge_id),
+                addr,
+            });
+        }
+        let mut first_user = 0;
+        for &num_users in &self.config.users_per_bridge {
+            let addr = next_addr(2 * num_users);
+            peers.push(Peer {
+                node: NodeId::User(first_user),
+                addr,
+            });
+            first_user += num_users;
+        }
+        peers
+    }
+
+    /// Where the processes get the `rsa` group's modulus from: one the config asks to generate is
+    /// generated here, once, and written to `modulus.txt` for them all to load.
+    fn rsa_modulus(&self) -> io::Result<ModulusSource> {
+        match (self.config.group, &self.config.rsa_modulus) {
+            (GroupKind::Rsa, ModulusSource::Generate(bits)) => {
+                let path = self.output_dir.join("modulus.txt");
+                modulus::save(&path, &modulus::generate(*bits))?;
+                info!(
+                    "Generated a {}-bit RSA modulus, discarding its factors, in {}.",
+                    bits,
+                    path.display()
+                );
+                Ok(ModulusSource::File(path))
+            }
+            (_, source) => Ok(source.clone()),
+        }
+    }
+
+    /// Where the processes get their Noise keys from, if they encrypt their links: the directory
+    /// in the config, or else `noise/`, where a pre-shared key for them all is generated once.
+    /// Each process then picks a random static key of its own.
+    fn noise_keys(&self) -> io::Result<Option<PathBuf>> {
+        if !self.config.encrypt_links || self.config.noise_keys.is_some() {
+            return Ok(self.config.noise_keys.clone());
+        }
+        let dir = self.output_dir.join("noise");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("psk");
+        auth::write_key(&path, &auth::random_key())?;
+        info!("Generated a Noise pre-shared key in {}.", path.display());
+        Ok(Some(dir))
+    }
+
+    fn write_deployment(&self, path: &Path) -> io::Result<()> {
+        let config = &self.config;
+        let deployment = DeploymentFile {
+            config: DeploymentConfig {
+                group: config.group,
+                rsa_modulus: self.rsa_modulus()?,
+                seed: config.seed,
+                num_miners: config.num_miners,
+                users_per_bridge: &config.users_per_bridge,
+                block_interval_ms: config.block_interval_ms,
+                speedup: config.speedup,
+                retry_after_blocks: config.retry_after_blocks,
+                authenticate_messages: config.authenticate_messages,
+                prove_fresh_utxos: config.prove_fresh_utxos,
+                compress_blocks: config.compress_blocks,
+                encrypt_links: config.encrypt_links,
+                noise_keys: self.noise_keys()?,
+                transport: config.transport,
+                log_filter: &config.log_filter,
+                max_blocks: config.max_blocks,
+                max_duration_ms: config.max_duration_ms,
+            },
+            peers: self.peers(),
+        };
+        let yaml = serde_yaml::to_string(&deployment)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, yaml)
+    }
+
+    /// Starts node binary `bin` as process `name`, copying its output to `log`.
+    fn spawn(
+        &self,
+        bin: &str,
+        name: String,
+        args: &[String],
+        log: &Arc<Mutex<File>>,
+    ) -> io::Result<NodeProcess> {
+        let path = self
+            .bin_dir
+            .join(format!("{}{}", bin, env::consts::EXE_SUFFIX));
+        let mut child = Command::new(&path)
+            .args(args)
+            .arg("--output")
+            .arg(self.output_dir.join(&name))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!(
+                        "failed to start {} (build it with `cargo build --bins`): {}",
+                        path.display(),
+                        err
+                    ),
+                )
+            })?;
+        let mut logs = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            logs.push(self.collect_output(name.clone(), stdout, log.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            logs.push(self.collect_output(name.clone(), stderr, log.clone()));
+        }
+        info!("Started {} (pid {}).", name, child.id());
+        Ok(NodeProcess { name, child, logs })
+    }
+
+    /// Copies each line of `output` to `log`, and to stdout unless the config is quiet.
+    fn collect_output<R: Read + Send + 'static>(
+        &self,
+        name: String,
+        output: R,
+        log: Arc<Mutex<File>>,
+    ) -> JoinHandle<()> {
+        let quiet = self.config.quiet;
+        thread::spawn(move || {
+            for line in BufReader::new(output).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if let Err(err) = writeln!(log.lock().unwrap(), "[{}] {}", name, line) {
+                    warn!(
+                        "Failed to write {}'s output to the cluster log: {}",
+                        name, err
+                    );
+                }
+                if !quiet {
+                    println!("[{}] {}", name, line);
+                }
+            }
+        })
+    }
+
+    /// Starts every process: the user swarms and bridges first, since messages to a process that
+    /// is not up yet are lost, then the miners, the initial leader last. If one fails to start,
+    /// those already running are killed.
+    fn start(&self, deployment: &Path, log: &Arc<Mutex<File>>) -> io::Result<Vec<NodeProcess>> {
+        let mut processes = Vec::new();
+        if let Err(err) = self.start_into(deployment, log, &mut processes) {
+            for process in &mut processes {
+                let _ = process.child.kill();
+            }
+            return Err(err);
+        }
+        Ok(processes)
+    }
+
+    fn start_into(
+        &self,
+        deployment: &Path,
+        log: &Arc<Mutex<File>>,
+        processes: &mut Vec<NodeProcess>,
+    ) -> io::Result<()> {
+        let args = |id_flag: &str, id: usize| {
+            vec![
+                "--deployment".to_string(),
+                deployment.display().to_string(),
+                id_flag.to_string(),
+                id.to_string(),
+            ]
+        };
+        for bridge_id in 0..self.config.num_bridges() {
+            let name = format!("users-{}", bridge_id);
+            processes.push(self.spawn("user-swarm", name, &args("--bridge", bridge_id), log)?);
+        }
+        for bridge_id in 0..self.config.num_bridges() {
+            let name = format!("bridge-{}", bridge_id);
+            processes.push(self.spawn("bridge", name, &args("--id", bridge_id), log)?);
+        }
+        thread::sleep(STARTUP_GRACE);
+        for miner_id in (0..self.config.num_miners).rev() {
+            let name = format!("miner-{}", miner_id);
+            processes.push(self.spawn("miner", name, &args("--id", miner_id), log)?);
+        }
+        Ok(())
+    }
+
+    /// Runs the cluster until every process exits, returning each one's name and exit status.
+    /// The processes stop once the chain reaches the bounds in the config, so without any they
+    /// run until killed.
+    pub fn run(&self) -> io::Result<Vec<(String, ExitStatus)>> {
+        fs::create_dir_all(&self.output_dir)?;
+        let deployment = self.output_dir.join("deployment.yaml");
+        self.write_deployment(&deployment)?;
+        let log = Arc::new(Mutex::new(File::create(
+            self.output_dir.join("cluster.log"),
+        )?));
+        info!(
+            "Launching {} miners, {} bridges and {} users from {}.",
+            self.config.num_miners,
+            self.config.num_bridges(),
+            self.config.num_users(),
+            deployment.display()
+        );
+        let processes = self.start(&deployment, &log)?;
+        let mut statuses = Vec::new();
+        for mut process in processes {
+            let status = process.child.wait()?;
+            for handle in process.logs {
+                let _ = handle.join();
+            }
+            if !status.success() {
+                warn!("{} exited with {}.", process.name, status);
+            }
+            statuses.push((process.name, status));
+        }
+        let names: Vec<&str> = statuses.iter().map(|(name, _)| name.as_str()).collect();
+        for file in &METRICS_FILES {
+            self.merge_metrics(file, &names)?;
+        }
+        info!("Cluster output collected in {}.", self.output_dir.display());
+        Ok(statuses)
+    }
+
+    /// Merges the metrics file `file` each of the processes `names` exported into one, adding a
+    /// leading column for the process each row came from.
+    fn merge_metrics(&self, file: &str, names: &[&str]) -> io::Result<()> {
+        let mut output = None;
+        for name in names {
+            let path = self.output_dir.join(name).join(file);
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(_) => {
+                    warn!("{} exported no {}.", name, file);
+                    continue;
+                }
+            };
+            let mut lines = contents.lines();
+            let header = lines.next().unwrap_or("");
+            if output.is_none() {
+                let mut merged = File::create(self.output_dir.join(file))?;
+                writeln!(merged, "process,{}", header)?;
+                output = Some(merged);
+            }
+            if let Some(merged) = &mut output {
+                for line in lines {
+                    writeln!(merged, "{},{}", name, line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `num_users` users spread as evenly as possible over `num_bridges` bridges.
+pub fn users_per_bridge(num_users: usize, num_bridges: usize) -> Vec<usize> {
+    (0..num_bridges)
+        .map(|bridge_id| num_users / num_bridges + (bridge_id < num_users % num_bridges) as usize)
+        .collect()
+}
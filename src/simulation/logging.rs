@@ -0,0 +1,42 @@
+use super::events::TraceId;
+use super::state::NodeId;
+use std::io;
+use tracing::{info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global log subscriber. `RUST_LOG`, if set, takes precedence over `default_filter`.
+/// Both use `EnvFilter` directive syntax, so output can be narrowed by level, module or component
+/// span, e.g. `warn,[bridge{id=1}]=debug`.
+pub fn init(default_filter: &str) {
+    tracing_subscriber::fmt()
+        .with_env_filter(filter(default_filter))
+        .init();
+}
+
+/// Installs the global log subscriber as `init` does, but logging to standard error, for runs
+/// whose standard output carries something else, such as the event stream.
+pub fn init_on_stderr(default_filter: &str) {
+    tracing_subscriber::fmt()
+        .with_env_filter(filter(default_filter))
+        .with_writer(io::stderr)
+        .init();
+}
+
+fn filter(default_filter: &str) -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter))
+}
+
+/// The span under which the work on one spend is logged, e.g. `tx{trace=5f0c...}`, so its logs can
+/// be matched with its events, or picked out with a filter such as `[tx{trace=5f0c...}]=debug`.
+pub fn trace_span(trace: TraceId) -> Span {
+    info_span!("tx", trace = %trace)
+}
+
+/// The span under which a component's work is logged, e.g. `miner{id=3}`.
+pub fn node_span(node: NodeId) -> Span {
+    match node {
+        NodeId::Miner(id) => info_span!("miner", id),
+        NodeId::Bridge(id) => info_span!("bridge", id),
+        NodeId::User(id) => info_span!("user", id),
+    }
+}
@@ -0,0 +1,347 @@
+use super::commitment::{CommitmentError, SetCommitment, WitnessStore};
+use super::util;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+/// Levels below the root: one per bit of an element's key.
+const DEPTH: usize = 256;
+
+/// Bytes of a hash, a key or a root.
+pub const HASH_BYTES: usize = 32;
+
+pub type Hash32 = [u8; 32];
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// The hashes beside the path from a leaf to the root, from the leaf up. Empty subtrees, which most
+/// of a sparse tree's are, are left out and counted by a 256-bit map.
+pub struct MerklePath {
+    pub siblings: Vec<Option<Hash32>>,
+}
+
+impl MerklePath {
+    /// Bytes of the map of empty siblings and of the others' hashes.
+    pub fn bytes(&self) -> usize {
+        DEPTH / 8 + HASH_BYTES * self.siblings.iter().filter(|hash| hash.is_some()).count()
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A sparse Merkle tree of SHA-256 hashes, as a baseline for the accumulator. Each element sits at
+/// the leaf its hash names, out of 2^256, holding that hash; every other leaf is empty. A witness
+/// is the path to an element's leaf, and a block proves its update with the path to each leaf it
+/// changes, one after another, so nodes need only the root to check it. Keeping the root current
+/// needs only the blocks, but handing out witnesses needs the whole tree.
+///
+/// The tree keeps the hash of every internal node above a leaf, so a path is looked up rather than
+/// worked out, and a change rehashes only the nodes on its path.
+pub struct MerkleTree<T: Hash> {
+    leaves: BTreeSet<Hash32>,
+    /// The hash of each internal node that is not the root of an empty subtree, by its height and
+    /// the bits of the keys below it, the rest cleared. See `prefix`.
+    nodes: HashMap<(usize, Hash32), Hash32>,
+    root: Hash32,
+    elems: PhantomData<T>,
+}
+
+impl<T: Hash> MerkleTree<T> {
+    fn empty() -> Self {
+        Self {
+            leaves: BTreeSet::new(),
+            nodes: HashMap::new(),
+            root: empty_hashes()[DEPTH],
+            elems: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn contains(&self, elem: &T) -> bool {
+        self.leaves.contains(&key(elem))
+    }
+
+    /// The path to the leaf at `key`, whether or not it is empty.
+    pub fn path(&self, key: &Hash32) -> MerklePath {
+        let siblings = (0..DEPTH)
+            .map(|height| {
+                let mut sibling = prefix(key, height);
+                flip_bit(&mut sibling, DEPTH - 1 - height);
+                self.node_at(height, &sibling)
+            })
+            .collect();
+        MerklePath { siblings }
+    }
+
+    /// The hash of the node at `height` above the leaves whose keys start with `prefix`, or None
+    /// if the subtree under it is empty.
+    fn node_at(&self, height: usize, prefix: &Hash32) -> Option<Hash32> {
+        if height == 0 {
+            return self.leaves.get(prefix).cloned();
+        }
+        self.nodes.get(&(height, *prefix)).cloned()
+    }
+
+    /// Sets the leaf at `key` to hold it, or to be empty, and returns the path to it, which the
+    /// change leaves as it was.
+    fn set(&mut self, key: Hash32, is_present: bool) -> Result<MerklePath, CommitmentError> {
+        match (self.leaves.contains(&key), is_present) {
+            (true, true) => return Err(CommitmentError::Present),
+            (false, false) => return Err(CommitmentError::Missing),
+            _ => (),
+        }
+        let path = self.path(&key);
+        if is_present {
+            self.leaves.insert(key);
+        } else {
+            self.leaves.remove(&key);
+        }
+        let empty = empty_hashes();
+        let mut hash = leaf(&key, is_present);
+        for (height, sibling) in path.siblings.iter().enumerate() {
+            hash = parent(&key, height, &hash, sibling, empty);
+            let node = (height + 1, prefix(&key, height + 1));
+            if hash == empty[height + 1] {
+                self.nodes.remove(&node);
+            } else {
+                self.nodes.insert(node, hash);
+            }
+        }
+        self.root = hash;
+        Ok(path)
+    }
+
+    /// Applies the changes of a block, without proving them.
+    fn apply(&mut self, added: &[T], deleted: &[T]) -> Result<(), CommitmentError> {
+        for elem in deleted {
+            self.set(key(elem), false)?;
+        }
+        for elem in added {
+            self.set(key(elem), true)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone + Hash> SetCommitment<T> for MerkleTree<T> {
+    type Digest = Hash32;
+    type Witness = MerklePath;
+    /// The path to each leaf changed, deletions first, as it was before the change.
+    type Proof = Vec<MerklePath>;
+    type Store = MerkleTree<T>;
+
+    const NAME: &'static str = "merkle tree";
+
+    fn commit(elems: &[T]) -> Self {
+        let mut tree = Self::empty();
+        for elem in elems {
+            let key = key(elem);
+            if !tree.leaves.contains(&key) {
+                tree.set(key, true).expect("The leaf is empty");
+            }
+        }
+        tree
+    }
+
+    fn digest(&self) -> Hash32 {
+        self.root
+    }
+
+    fn update(
+        &mut self,
+        added: &[T],
+        deleted: &[(T, MerklePath)],
+    ) -> Result<Vec<MerklePath>, CommitmentError> {
+        let mut proof = Vec::new();
+        for (elem, _witness) in deleted {
+            proof.push(self.set(key(elem), false)?);
+        }
+        for elem in added {
+            proof.push(self.set(key(elem), true)?);
+        }
+        Ok(proof)
+    }
+
+    fn verify_update(
+        root: &Hash32,
+        new_root: &Hash32,
+        added: &[T],
+        deleted: &[T],
+        proof: &Vec<MerklePath>,
+    ) -> bool {
+        if proof.len() != deleted.len() + added.len() {
+            return false;
+        }
+        let empty = empty_hashes();
+        let changes = deleted
+            .iter()
+            .map(|elem| (elem, false))
+            .chain(added.iter().map(|elem| (elem, true)));
+        let mut root = *root;
+        for ((elem, is_present), path) in changes.zip(proof) {
+            let key = key(elem);
+            if root_from_path(&key, &leaf(&key, !is_present), path, empty) != Some(root) {
+                return false;
+            }
+            root = match root_from_path(&key, &leaf(&key, is_present), path, empty) {
+                Some(new_root) => new_root,
+                None => return false,
+            };
+        }
+        root == *new_root
+    }
+
+    fn verify_membership(root: &Hash32, elem: &T, path: &MerklePath) -> bool {
+        let key = key(elem);
+        root_from_path(&key, &key, path, empty_hashes()) == Some(*root)
+    }
+
+    fn digest_bytes() -> usize {
+        HASH_BYTES
+    }
+
+    fn witness_bytes(path: &MerklePath) -> usize {
+        path.bytes()
+    }
+
+    fn proof_bytes(proof: &Vec<MerklePath>) -> usize {
+        proof.iter().map(MerklePath::bytes).sum()
+    }
+
+    /// The tree's leaves: every element's hash.
+    fn state_bytes(&self) -> usize {
+        HASH_BYTES * self.len()
+    }
+}
+
+/// A bridge keeps the whole tree, since a path runs past leaves of every other bridge's users.
+impl<T: Clone + Hash> WitnessStore<T, MerkleTree<T>> for MerkleTree<T> {
+    fn new(all: &[T], _tracked: &[T]) -> Self {
+        Self::commit(all)
+    }
+
+    fn update(
+        &mut self,
+        root: &Hash32,
+        added: &[T],
+        deleted: &[T],
+        _is_tracked: &dyn Fn(&T) -> bool,
+    ) -> Result<(), CommitmentError> {
+        self.apply(added, deleted)?;
+        if self.root != *root {
+            return Err(CommitmentError::Mismatch);
+        }
+        Ok(())
+    }
+
+    fn witnesses(&self, elems: &[T]) -> Result<Vec<(T, MerklePath)>, CommitmentError> {
+        elems
+            .iter()
+            .map(|elem| {
+                if !self.contains(elem) {
+                    return Err(CommitmentError::Missing);
+                }
+                Ok((elem.clone(), self.path(&key(elem))))
+            })
+            .collect()
+    }
+
+    fn state_bytes(&self) -> usize {
+        HASH_BYTES * self.len()
+    }
+}
+
+/// The leaf an element sits at, and the hash it holds there.
+fn key<T: Hash>(elem: &T) -> Hash32 {
+    util::sha256(elem)
+}
+
+/// What the leaf at `key` holds: the key if the element is present, else zeros.
+fn leaf(key: &Hash32, is_present: bool) -> Hash32 {
+    if is_present {
+        *key
+    } else {
+        [0; 32]
+    }
+}
+
+fn node(left: &Hash32, right: &Hash32) -> Hash32 {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The root of an empty subtree of each height, from an empty leaf up to an empty tree, worked
+/// out once per process.
+fn empty_hashes() -> &'static [Hash32] {
+    static EMPTY: OnceLock<Vec<Hash32>> = OnceLock::new();
+    EMPTY.get_or_init(|| {
+        let mut hashes = vec![[0; 32]];
+        for height in 0..DEPTH {
+            hashes.push(node(&hashes[height], &hashes[height]));
+        }
+        hashes
+    })
+}
+
+/// Bit `index` of `key`, most significant first. The bits of a key pick its path from the root.
+fn bit_at(key: &Hash32, index: usize) -> bool {
+    (key[index / 8] >> (7 - index % 8)) & 1 == 1
+}
+
+fn flip_bit(key: &mut Hash32, index: usize) {
+    key[index / 8] ^= 1 << (7 - index % 8);
+}
+
+/// The bits of `key` that pick the node at `height` above its leaf, with the `height` bits below
+/// cleared: the same for every leaf under that node.
+fn prefix(key: &Hash32, height: usize) -> Hash32 {
+    let mut prefix = *key;
+    for index in DEPTH - height..DEPTH {
+        prefix[index / 8] &= !(1 << (7 - index % 8));
+    }
+    prefix
+}
+
+/// The hash of the node at `height + 1` on the path to the leaf at `key`, from that of its child
+/// on the path, `hash`, and of the child's sibling, empty if None.
+fn parent(
+    key: &Hash32,
+    height: usize,
+    hash: &Hash32,
+    sibling: &Option<Hash32>,
+    empty: &[Hash32],
+) -> Hash32 {
+    let sibling = sibling.unwrap_or(empty[height]);
+    if bit_at(key, DEPTH - 1 - height) {
+        node(&sibling, hash)
+    } else {
+        node(hash, &sibling)
+    }
+}
+
+/// The root a tree would have with `leaf` at `key` and `path` beside it, or None if the path is
+/// not as long as the tree is deep.
+fn root_from_path(
+    key: &Hash32,
+    leaf: &Hash32,
+    path: &MerklePath,
+    empty: &[Hash32],
+) -> Option<Hash32> {
+    if path.siblings.len() != DEPTH {
+        return None;
+    }
+    let mut hash = *leaf;
+    for (height, sibling) in path.siblings.iter().enumerate() {
+        hash = parent(key, height, &hash, sibling, empty);
+    }
+    Some(hash)
+}
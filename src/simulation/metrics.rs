@@ -0,0 +1,1124 @@
+use super::chain_stats::ChainStats;
+use super::clock::Clock;
+use super::events::{EventLog, EventRecord, EventSink, RunEvent, TraceId};
+use super::instrument::{ProofEvent, ProofObserver};
+use super::network::MessageKind;
+use super::prime_cache;
+use super::proofs::{ProofOp, ProofStats};
+use super::report::percentile;
+use super::state::{NodeId, TxId};
+use super::transport::{Channel, QueueDepth};
+use super::util;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+/// Statistics for a single forged block.
+pub struct BlockRecord {
+    pub height: u64,
+    /// Simulated time at which the block was forged.
+    pub forged_at: Duration,
+    pub transactions: usize,
+    pub elems_added: usize,
+    pub elems_deleted: usize,
+    /// Wall-clock time spent forging the block and its proofs.
+    pub forge_time: Duration,
+}
+
+/// Approximate size of a UTXO in memory: its UUID, owner and value.
+const UTXO_BYTES: usize = 32;
+
+/// Approximate size of a group element, e.g. a witness, sized for RSA-2048.
+const ELEMENT_BYTES: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Roughly what a component holds in memory.
+pub struct ResourceUsage {
+    /// UTXOs tracked, whether owned (users) or witnessed (bridges).
+    pub utxos: usize,
+    /// Witnesses held, including those attached to pending transactions.
+    pub witnesses: usize,
+    pub mempool_entries: usize,
+    /// Blocks kept for nodes catching up.
+    pub blocks: usize,
+    /// Estimated bytes for all of the above, counting each block as its transactions plus the
+    /// accumulator and proofs.
+    pub approx_bytes: usize,
+}
+
+impl ResourceUsage {
+    /// Usage of `utxos` UTXOs and `witnesses` group elements, plus `blocks` holding `block_utxos`
+    /// UTXOs and `block_witnesses` witnesses between them.
+    pub fn estimate(
+        utxos: usize,
+        witnesses: usize,
+        mempool_entries: usize,
+        blocks: usize,
+        block_utxos: usize,
+        block_witnesses: usize,
+    ) -> Self {
+        // An accumulator value, a membership proof of two elements and a deletion proof of three.
+        let block_elements = 6 * blocks + block_witnesses;
+        Self {
+            utxos,
+            witnesses,
+            mempool_entries,
+            blocks,
+            approx_bytes: (utxos + block_utxos) * UTXO_BYTES
+                + (witnesses + block_elements) * ELEMENT_BYTES,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Bytes of one kind of message a component sent and received. Messages crossing a TCP link are
+/// counted as the frames written and read; those on in-process links, which are never encoded,
+/// at their estimated size.
+pub struct Bandwidth {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl Bandwidth {
+    pub fn add(&mut self, other: Bandwidth) {
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// What mapping elements to primes by the configured strategy took.
+pub struct PrimeStats {
+    pub elements: u64,
+    /// Candidates tested for primality, one or more per element.
+    pub primality_tests: u64,
+    /// Elements given a prime that already represented another element.
+    pub collisions: u64,
+    pub time: Duration,
+}
+
+impl PrimeStats {
+    pub fn tests_per_element(&self) -> f64 {
+        if self.elements == 0 {
+            return 0.0;
+        }
+        self.primality_tests as f64 / self.elements as f64
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// How often deletion and swap proofs found elements' primes in the cache rather than derived them.
+pub struct PrimeCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl PrimeCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// What became of the messages sent on one channel of a real-time run.
+pub struct ChannelStats {
+    /// Copies of messages that made it into the channel's queue.
+    pub queued: u64,
+    /// Messages the queue turned away because it stayed full, whether their senders then dropped
+    /// them or backed off to try again.
+    pub refused: u64,
+    /// Messages lost on the link, or dropped by their senders once the queue turned them away.
+    pub dropped: u64,
+}
+
+impl ChannelStats {
+    /// The share of the messages sent on the channel that were dropped.
+    pub fn drop_rate(&self) -> f64 {
+        let sent = self.queued + self.dropped;
+        if sent == 0 {
+            return 0.0;
+        }
+        self.dropped as f64 / sent as f64
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// How full the queues of one channel of a real-time run are, and have been.
+pub struct QueueDepthStats {
+    /// Messages the channel's fullest queue holds now.
+    pub depth: u64,
+    /// The most messages any of the channel's queues has held at once.
+    pub high_watermark: u64,
+    /// The channel's queues: one per bridge or user on channels that link them one to one.
+    pub queues: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+/// How many blocks old the accumulator each served witness was computed against had grown by the
+/// time the transaction spending it was mined: the bridge architecture's key health measure, as a
+/// user whose witnesses fall far behind pays for it in updates, or in rejected transactions.
+pub struct WitnessStaleness {
+    /// Mined transactions, by how many blocks old their witnesses were.
+    pub transactions_by_age: BTreeMap<u64, u64>,
+}
+
+impl WitnessStaleness {
+    pub fn transactions(&self) -> u64 {
+        self.transactions_by_age.values().sum()
+    }
+
+    /// The mean age in blocks, or zero if no transaction was mined.
+    pub fn mean(&self) -> f64 {
+        let transactions = self.transactions();
+        if transactions == 0 {
+            return 0.0;
+        }
+        let total: u64 = self
+            .transactions_by_age
+            .iter()
+            .map(|(age, count)| age * count)
+            .sum();
+        total as f64 / transactions as f64
+    }
+
+    /// The nearest-rank `p`th percentile of the ages, or zero if no transaction was mined.
+    pub fn percentile(&self, p: u64) -> u64 {
+        let rank = ((p * self.transactions() + 99) / 100).max(1);
+        let mut seen = 0;
+        for (age, count) in &self.transactions_by_age {
+            seen += count;
+            if seen >= rank {
+                return *age;
+            }
+        }
+        0
+    }
+
+    pub fn max(&self) -> u64 {
+        self.transactions_by_age.keys().last().copied().unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// The nearest-rank median, 95th and 99th percentiles of some latencies.
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl LatencyPercentiles {
+    pub fn of(latencies: &[Duration]) -> Self {
+        let mut sorted = latencies.to_vec();
+        sorted.sort();
+        Self {
+            p50: percentile(&sorted, 50),
+            p95: percentile(&sorted, 95),
+            p99: percentile(&sorted, 99),
+        }
+    }
+}
+
+impl fmt::Display for LatencyPercentiles {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1?} p50, {:.1?} p95, {:.1?} p99",
+            self.p50, self.p95, self.p99
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// How long the witness requests to one bridge took, measured two ways, so the cost of the
+/// bridge's own work can be told apart from the time requests and responses spend queued and on
+/// the network.
+pub struct WitnessLatencies {
+    /// From a user sending a request to the response reaching it, in simulated time.
+    pub end_to_end: Vec<Duration>,
+    /// The bridge's time computing the witnesses for a request, wall-clock.
+    pub processing: Vec<Duration>,
+}
+
+impl WitnessLatencies {
+    pub fn stats(&self) -> WitnessLatencyStats {
+        WitnessLatencyStats {
+            requests: self.processing.len(),
+            end_to_end: LatencyPercentiles::of(&self.end_to_end),
+            processing: LatencyPercentiles::of(&self.processing),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Percentiles of the witness request latencies of one bridge.
+pub struct WitnessLatencyStats {
+    /// Requests the bridge served.
+    pub requests: usize,
+    pub end_to_end: LatencyPercentiles,
+    pub processing: LatencyPercentiles,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Percentiles of how long some transactions took from being issued to being confirmed, in
+/// simulated time: one user's, the users' of one bridge, or every user's.
+pub struct ConfirmationLatencyStats {
+    /// Transactions confirmed.
+    pub confirmed: usize,
+    pub percentiles: LatencyPercentiles,
+}
+
+impl ConfirmationLatencyStats {
+    pub fn of(latencies: &[Duration]) -> Self {
+        Self {
+            confirmed: latencies.len(),
+            percentiles: LatencyPercentiles::of(latencies),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// How often components found a block's proofs already checked by another in the same process.
+pub struct ProofCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Time the checks answered from the cache took the first time round.
+    pub time_saved: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Counters and timings for a single miner, bridge or user. Proof times are wall-clock; latencies
+/// are measured in simulated time.
+pub struct ComponentMetrics {
+    pub blocks_forged: u64,
+    pub blocks_applied: u64,
+    /// Invalid blocks received and not applied.
+    pub blocks_rejected: u64,
+    pub transactions_received: u64,
+    pub transactions_issued: u64,
+    pub transactions_confirmed: u64,
+    pub witnesses_served: u64,
+    pub proof_time: Duration,
+    /// Time spent validating or applying blocks.
+    pub block_processing_time: Duration,
+    pub witness_latencies: Vec<Duration>,
+    pub confirmation_latencies: Vec<Duration>,
+    /// Wall-clock time spent handling blocks, transactions and requests, as an estimate of the
+    /// CPU time the component uses.
+    pub cpu_time: Duration,
+    /// What the component held in memory when it last reported.
+    pub resources: ResourceUsage,
+    /// Traffic the component sent and received, by kind of message.
+    pub bandwidth: BTreeMap<MessageKind, Bandwidth>,
+}
+
+/// Upper bounds, in seconds, of the buckets used for duration histograms.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+
+#[derive(Clone, Debug)]
+/// A cumulative histogram of durations in seconds, in the shape Prometheus expects.
+pub struct Histogram {
+    /// Each bucket's upper bound and the number of observations at or below it.
+    pub buckets: Vec<(f64, u64)>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: DURATION_BUCKETS.iter().map(|bound| (*bound, 0)).collect(),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bound, count) in &mut self.buckets {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// How much compressing blocks on the wire has saved.
+pub struct CompressionStats {
+    /// Bytes the compressed blocks would have taken up uncompressed.
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl CompressionStats {
+    pub fn bytes_saved(&self) -> u64 {
+        self.raw_bytes - self.compressed_bytes
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// The current state of a run, as opposed to the cumulative counters in `ComponentMetrics`.
+pub struct LiveMetrics {
+    pub block_heights: BTreeMap<NodeId, u64>,
+    /// Pending transactions held by each miner.
+    pub mempool_depths: BTreeMap<usize, usize>,
+    /// Witness requests sent to each bridge but not yet served.
+    pub bridge_queue_lengths: BTreeMap<usize, u64>,
+    pub forge_durations: Histogram,
+    pub block_processing_durations: Histogram,
+    pub witness_durations: Histogram,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    blocks: Vec<BlockRecord>,
+    live: LiveMetrics,
+    components: BTreeMap<NodeId, ComponentMetrics>,
+    /// When each user's outstanding witness request was sent, and to which bridge.
+    witness_requested_at: HashMap<usize, (Duration, usize)>,
+    /// When each user's outstanding transaction was issued.
+    transaction_issued_at: HashMap<usize, Duration>,
+    /// The height of the accumulator behind each user's latest witnesses.
+    witness_heights: HashMap<usize, u64>,
+    /// The height of the accumulator behind the witnesses of each transaction not yet mined.
+    pending_witness_heights: HashMap<TxId, u64>,
+    /// The trace of each transaction issued and not yet confirmed.
+    tx_traces: HashMap<TxId, TraceId>,
+    /// The trace and ID of each user's outstanding transaction.
+    outstanding_traces: HashMap<usize, (TraceId, TxId)>,
+    witness_staleness: WitnessStaleness,
+    witness_latencies: BTreeMap<usize, WitnessLatencies>,
+    /// The bridge each user last asked for witnesses.
+    user_bridges: BTreeMap<usize, usize>,
+    dropped_messages: u64,
+    forged_messages: u64,
+    refused_messages: u64,
+    stalls: u64,
+    channels: BTreeMap<Channel, ChannelStats>,
+    compression: CompressionStats,
+    proofs: BTreeMap<ProofOp, ProofStats>,
+    proof_cache: ProofCacheStats,
+    primes: PrimeStats,
+    events: EventLog,
+    /// Where events are streamed as they are reported, if anywhere.
+    event_sink: Option<EventSink>,
+}
+
+impl MetricsState {
+    /// Logs `event`, which happened at `at`, and streams it to the event sink if there is one.
+    fn record_event(&mut self, at: Duration, event: RunEvent) {
+        let record = self.events.append(at, event);
+        if let Some(sink) = &mut self.event_sink {
+            sink.write(record);
+        }
+    }
+}
+
+/// Metrics shared by every component of a run. Components report what they do as they do it, and
+/// the collected metrics, and the log of what was reported when, can be exported for offline
+/// analysis.
+pub struct Metrics {
+    clock: Arc<dyn Clock>,
+    state: Mutex<MetricsState>,
+    /// The process's prime cache lookups before the run began.
+    prime_cache_baseline: PrimeCacheStats,
+    /// Observers told of every proof besides the metrics themselves.
+    proof_observers: Mutex<Vec<Arc<dyn ProofObserver>>>,
+    chain_stats: ChainStats,
+    /// The depth of every queue between real-time components, with its channel.
+    queue_depths: Mutex<Vec<(Channel, Arc<QueueDepth>)>>,
+}
+
+impl Metrics {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            state: Mutex::new(MetricsState::default()),
+            prime_cache_baseline: prime_cache::stats(),
+            proof_observers: Mutex::new(Vec::new()),
+            chain_stats: ChainStats::default(),
+            queue_depths: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The current simulated time.
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    pub fn block_forged(&self, miner_id: usize, record: BlockRecord) {
+        let mut state = self.state.lock().unwrap();
+        let miner = state.components.entry(NodeId::Miner(miner_id)).or_default();
+        miner.blocks_forged += 1;
+        miner.proof_time += record.forge_time;
+        state.live.forge_durations.observe(record.forge_time);
+        state.record_event(
+            record.forged_at,
+            RunEvent::BlockForged {
+                miner_id,
+                height: record.height,
+                transactions: record.transactions,
+            },
+        );
+        state.blocks.push(record);
+    }
+
+    pub fn block_applied(&self, node: NodeId, height: u64, processing_time: Duration) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        state.record_event(now, RunEvent::BlockApplied { node, height });
+        let component = state.components.entry(node).or_default();
+        component.blocks_applied += 1;
+        component.block_processing_time += processing_time;
+        state.live.block_heights.insert(node, height);
+        state
+            .live
+            .block_processing_durations
+            .observe(processing_time);
+    }
+
+    pub fn block_rejected(&self, node: NodeId) {
+        let mut state = self.state.lock().unwrap();
+        state.components.entry(node).or_default().blocks_rejected += 1;
+    }
+
+    pub fn mempool_changed(&self, miner_id: usize, depth: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.live.mempool_depths.insert(miner_id, depth);
+    }
+
+    /// Counts transaction `txid`, new to miner `miner_id`, taken into its mempool.
+    pub fn transaction_received(&self, miner_id: usize, txid: TxId) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let trace = state.tx_traces.get(&txid).copied();
+        state.record_event(
+            now,
+            RunEvent::TxAccepted {
+                miner_id,
+                txid: util::to_hex(&txid),
+                trace,
+            },
+        );
+        state
+            .components
+            .entry(NodeId::Miner(miner_id))
+            .or_default()
+            .transactions_received += 1;
+    }
+
+    /// Counts witnesses bridge `bridge_id` served to user `user_id` for the request traced by
+    /// `trace`, computed in `proof_time` against its accumulator at `height`.
+    pub fn witness_served(
+        &self,
+        bridge_id: usize,
+        user_id: usize,
+        trace: TraceId,
+        height: u64,
+        proof_time: Duration,
+    ) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        state.witness_heights.insert(user_id, height);
+        state.record_event(
+            now,
+            RunEvent::WitnessServed {
+                bridge_id,
+                user_id,
+                trace,
+            },
+        );
+        let bridge = state
+            .components
+            .entry(NodeId::Bridge(bridge_id))
+            .or_default();
+        bridge.witnesses_served += 1;
+        bridge.proof_time += proof_time;
+        state
+            .witness_latencies
+            .entry(bridge_id)
+            .or_default()
+            .processing
+            .push(proof_time);
+        state.live.witness_durations.observe(proof_time);
+        let queue_length = state
+            .live
+            .bridge_queue_lengths
+            .entry(bridge_id)
+            .or_default();
+        *queue_length = queue_length.saturating_sub(1);
+    }
+
+    /// Counts a witness request from user `user_id` to bridge `bridge_id`, which starts the spend
+    /// traced by `trace`.
+    pub fn witness_requested(&self, user_id: usize, bridge_id: usize, trace: TraceId) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        state.witness_requested_at.insert(user_id, (now, bridge_id));
+        state.user_bridges.insert(user_id, bridge_id);
+        state.record_event(
+            now,
+            RunEvent::WitnessRequested {
+                user_id,
+                bridge_id,
+                trace,
+            },
+        );
+        *state
+            .live
+            .bridge_queue_lengths
+            .entry(bridge_id)
+            .or_default() += 1;
+    }
+
+    pub fn witness_received(&self, user_id: usize) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        if let Some((requested_at, bridge_id)) = state.witness_requested_at.remove(&user_id) {
+            let latency = now - requested_at;
+            state
+                .components
+                .entry(NodeId::User(user_id))
+                .or_default()
+                .witness_latencies
+                .push(latency);
+            state
+                .witness_latencies
+                .entry(bridge_id)
+                .or_default()
+                .end_to_end
+                .push(latency);
+        }
+    }
+
+    /// Counts transaction `txid` issued by user `user_id` in the spend traced by `trace`.
+    pub fn transaction_issued(&self, user_id: usize, txid: TxId, trace: TraceId) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        state.transaction_issued_at.insert(user_id, now);
+        if let Some(height) = state.witness_heights.get(&user_id).copied() {
+            state.pending_witness_heights.insert(txid, height);
+        }
+        state.tx_traces.insert(txid, trace);
+        state.outstanding_traces.insert(user_id, (trace, txid));
+        state.record_event(
+            now,
+            RunEvent::TxIssued {
+                user_id,
+                txid: util::to_hex(&txid),
+                trace,
+            },
+        );
+        state
+            .components
+            .entry(NodeId::User(user_id))
+            .or_default()
+            .transactions_issued += 1;
+    }
+
+    pub fn transaction_confirmed(&self, user_id: usize) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let trace = match state.outstanding_traces.remove(&user_id) {
+            Some((trace, txid)) => {
+                state.tx_traces.remove(&txid);
+                Some(trace)
+            }
+            None => None,
+        };
+        state.record_event(now, RunEvent::TxConfirmed { user_id, trace });
+        if let Some(issued_at) = state.transaction_issued_at.remove(&user_id) {
+            let user = state.components.entry(NodeId::User(user_id)).or_default();
+            user.transactions_confirmed += 1;
+            user.confirmation_latencies.push(now - issued_at);
+        }
+    }
+
+    /// Logs the transactions `txids` as included in the block miner `miner_id` forged at `height`,
+    /// and counts how stale their witnesses were. Transactions mined before are not counted again.
+    pub fn transactions_mined<I: IntoIterator<Item = TxId>>(
+        &self,
+        miner_id: usize,
+        height: u64,
+        txids: I,
+    ) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        for txid in txids {
+            let trace = state.tx_traces.get(&txid).copied();
+            state.record_event(
+                now,
+                RunEvent::TxIncluded {
+                    miner_id,
+                    height,
+                    txid: util::to_hex(&txid),
+                    trace,
+                },
+            );
+            if let Some(witness_height) = state.pending_witness_heights.remove(&txid) {
+                // Witnesses against the accumulator the block builds on are no blocks old.
+                let age = height.saturating_sub(witness_height + 1);
+                *state
+                    .witness_staleness
+                    .transactions_by_age
+                    .entry(age)
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    pub fn cpu_used(&self, node: NodeId, time: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.components.entry(node).or_default().cpu_time += time;
+    }
+
+    pub fn resources_changed(&self, node: NodeId, resources: ResourceUsage) {
+        let mut state = self.state.lock().unwrap();
+        state.components.entry(node).or_default().resources = resources;
+    }
+
+    /// Counts `bytes` of `kind` traffic sent by `node`, whether or not it arrives.
+    pub fn bytes_sent(&self, node: NodeId, kind: MessageKind, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        let component = state.components.entry(node).or_default();
+        component.bandwidth.entry(kind).or_default().bytes_sent += bytes as u64;
+    }
+
+    /// Counts `bytes` of `kind` traffic that reached `node`.
+    pub fn bytes_received(&self, node: NodeId, kind: MessageKind, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        let component = state.components.entry(node).or_default();
+        component.bandwidth.entry(kind).or_default().bytes_received += bytes as u64;
+    }
+
+    /// Counts a message that was lost before reaching its destination.
+    pub fn message_dropped(&self) {
+        self.state.lock().unwrap().dropped_messages += 1;
+    }
+
+    /// Counts a message lost on `channel` or dropped by its sender, as `message_dropped` does, and
+    /// against the channel.
+    pub fn message_dropped_on(&self, channel: Channel) {
+        let mut state = self.state.lock().unwrap();
+        state.dropped_messages += 1;
+        state.channels.entry(channel).or_default().dropped += 1;
+    }
+
+    /// Counts a copy of a message that made it into `channel`'s queue.
+    pub fn message_queued(&self, channel: Channel) {
+        self.state
+            .lock()
+            .unwrap()
+            .channels
+            .entry(channel)
+            .or_default()
+            .queued += 1;
+    }
+
+    /// Counts a message `channel` would not take because its queue stayed full, whether the sender
+    /// then dropped it or backed off to try again.
+    pub fn send_refused(&self, channel: Channel) {
+        let mut state = self.state.lock().unwrap();
+        state.refused_messages += 1;
+        state.channels.entry(channel).or_default().refused += 1;
+    }
+
+    /// Counts a message rejected because it was not signed, as it arrived, by the component it
+    /// claims to come from.
+    pub fn message_forged(&self) {
+        self.state.lock().unwrap().forged_messages += 1;
+    }
+
+    /// Counts a block sent compressed to `compressed_bytes` rather than `raw_bytes`.
+    pub fn block_compressed(&self, raw_bytes: usize, compressed_bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.compression.raw_bytes += raw_bytes as u64;
+        state.compression.compressed_bytes += compressed_bytes as u64;
+    }
+
+    /// Has `observer` told of every proof made and checked from now on, besides these metrics.
+    pub fn add_proof_observer(&self, observer: Arc<dyn ProofObserver>) {
+        self.proof_observers.lock().unwrap().push(observer);
+    }
+
+    /// Counts a block whose proofs were already checked, saving `time_saved`.
+    pub fn proof_cache_hit(&self, time_saved: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.proof_cache.hits += 1;
+        state.proof_cache.time_saved += time_saved;
+    }
+
+    /// Counts a block whose proofs were checked and cached.
+    pub fn proof_cache_miss(&self) {
+        self.state.lock().unwrap().proof_cache.misses += 1;
+    }
+
+    /// Counts an element mapped to a prime in `time`, after `primality_tests` candidates, and
+    /// whether the prime was already taken.
+    pub fn prime_hashed(&self, primality_tests: u64, collided: bool, time: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.primes.elements += 1;
+        state.primes.primality_tests += primality_tests;
+        state.primes.collisions += collided as u64;
+        state.primes.time += time;
+    }
+
+    /// Every block forged so far.
+    pub fn blocks(&self) -> Vec<BlockRecord> {
+        self.state.lock().unwrap().blocks.clone()
+    }
+
+    /// Cumulative metrics for every component that has reported any.
+    pub fn components(&self) -> BTreeMap<NodeId, ComponentMetrics> {
+        self.state.lock().unwrap().components.clone()
+    }
+
+    pub fn dropped_messages(&self) -> u64 {
+        self.state.lock().unwrap().dropped_messages
+    }
+
+    pub fn refused_messages(&self) -> u64 {
+        self.state.lock().unwrap().refused_messages
+    }
+
+    /// Counts a stall the watchdog alerted on.
+    pub fn stall_detected(&self) {
+        self.state.lock().unwrap().stalls += 1;
+    }
+
+    pub fn stalls(&self) -> u64 {
+        self.state.lock().unwrap().stalls
+    }
+
+    /// When each user with a transaction waiting to be confirmed issued it, by user.
+    pub fn outstanding_transactions(&self) -> BTreeMap<usize, Duration> {
+        let state = self.state.lock().unwrap();
+        state
+            .transaction_issued_at
+            .iter()
+            .map(|(user_id, issued_at)| (*user_id, *issued_at))
+            .collect()
+    }
+
+    /// What became of the messages sent on each channel of a real-time run.
+    pub fn channels(&self) -> BTreeMap<Channel, ChannelStats> {
+        self.state.lock().unwrap().channels.clone()
+    }
+
+    /// Has the metrics follow the depth of a queue on `channel`.
+    pub fn watch_queue(&self, channel: Channel, depth: Arc<QueueDepth>) {
+        self.queue_depths.lock().unwrap().push((channel, depth));
+    }
+
+    /// How full the queues of each channel of a real-time run are, and have been.
+    pub fn queue_depths(&self) -> BTreeMap<Channel, QueueDepthStats> {
+        let mut depths = BTreeMap::<Channel, QueueDepthStats>::new();
+        for (channel, depth) in self.queue_depths.lock().unwrap().iter() {
+            let stats = depths.entry(*channel).or_default();
+            stats.depth = stats.depth.max(depth.depth());
+            stats.high_watermark = stats.high_watermark.max(depth.high_watermark());
+            stats.queues += 1;
+        }
+        depths
+    }
+
+    pub fn forged_messages(&self) -> u64 {
+        self.state.lock().unwrap().forged_messages
+    }
+
+    /// Traffic summed over every component, by kind of message.
+    pub fn bandwidth(&self) -> BTreeMap<MessageKind, Bandwidth> {
+        let state = self.state.lock().unwrap();
+        let mut totals: BTreeMap<MessageKind, Bandwidth> = BTreeMap::new();
+        for component in state.components.values() {
+            for (kind, traffic) in &component.bandwidth {
+                totals.entry(*kind).or_default().add(*traffic);
+            }
+        }
+        totals
+    }
+
+    pub fn witness_staleness(&self) -> WitnessStaleness {
+        self.state.lock().unwrap().witness_staleness.clone()
+    }
+
+    pub fn compression(&self) -> CompressionStats {
+        self.state.lock().unwrap().compression
+    }
+
+    pub fn proof_cache(&self) -> ProofCacheStats {
+        self.state.lock().unwrap().proof_cache
+    }
+
+    pub fn primes(&self) -> PrimeStats {
+        self.state.lock().unwrap().primes
+    }
+
+    /// Lookups in the prime cache since the run began, all of them by deletion and swap proofs.
+    /// The cache is shared by the whole process, so runs side by side in one process, like an
+    /// experiment's variants, count each other's.
+    pub fn proof_prime_cache(&self) -> PrimeCacheStats {
+        let stats = prime_cache::stats();
+        PrimeCacheStats {
+            hits: stats.hits - self.prime_cache_baseline.hits,
+            misses: stats.misses - self.prime_cache_baseline.misses,
+        }
+    }
+
+    /// Proofs made and checked so far, by what they are for.
+    pub fn proofs(&self) -> BTreeMap<ProofOp, ProofStats> {
+        self.state.lock().unwrap().proofs.clone()
+    }
+
+    /// A snapshot of the run's current state.
+    pub fn live(&self) -> LiveMetrics {
+        self.state.lock().unwrap().live.clone()
+    }
+
+    /// The height of the longest chain any miner or bridge has applied.
+    pub fn chain_height(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state
+            .live
+            .block_heights
+            .values()
+            .cloned()
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The rolling averages of the blocks on the run's block stream.
+    pub fn chain_stats(&self) -> &ChainStats {
+        &self.chain_stats
+    }
+
+    /// Streams every event reported from now on to `sink`, as well as logging it.
+    pub fn stream_events(&self, sink: EventSink) {
+        self.state.lock().unwrap().event_sink = Some(sink);
+    }
+
+    /// What components reported so far, in the order they reported it.
+    pub fn events(&self) -> Vec<EventRecord> {
+        self.state.lock().unwrap().events.records().to_vec()
+    }
+
+    /// How long the witness requests to each bridge took, by bridge.
+    pub fn witness_latencies(&self) -> BTreeMap<usize, WitnessLatencies> {
+        self.state.lock().unwrap().witness_latencies.clone()
+    }
+
+    /// The bridge each user last asked for witnesses, by user.
+    pub fn user_bridges(&self) -> BTreeMap<usize, usize> {
+        self.state.lock().unwrap().user_bridges.clone()
+    }
+
+    /// The number of blocks forged so far.
+    pub fn num_blocks(&self) -> usize {
+        self.state.lock().unwrap().blocks.len()
+    }
+
+    /// Writes `blocks.csv` (one row per forged block), `components.csv` (one row per miner, bridge
+    /// and user), `witness_staleness.csv` (mined transactions by the age of their witnesses in
+    /// blocks), `witness_latency.csv` (each bridge's witness request latency percentiles),
+    /// `confirmation_latency.csv` (each user's confirmation latency percentiles) and `events.jsonl`
+    /// (the run's event log) to `dir`, creating it if necessary.
+    pub fn write_csv<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let state = self.state.lock().unwrap();
+        state.events.write_jsonl(dir.join("events.jsonl"))?;
+
+        let mut staleness = File::create(dir.join("witness_staleness.csv"))?;
+        writeln!(staleness, "blocks_old,transactions")?;
+        for (age, transactions) in &state.witness_staleness.transactions_by_age {
+            writeln!(staleness, "{},{}", age, transactions)?;
+        }
+
+        let mut latency = File::create(dir.join("witness_latency.csv"))?;
+        writeln!(
+            latency,
+            "bridge_id,requests,end_to_end_p50_ms,end_to_end_p95_ms,end_to_end_p99_ms,\
+             processing_p50_us,processing_p95_us,processing_p99_us"
+        )?;
+        for (bridge_id, latencies) in &state.witness_latencies {
+            let stats = latencies.stats();
+            writeln!(
+                latency,
+                "{},{},{},{},{},{},{},{}",
+                bridge_id,
+                stats.requests,
+                stats.end_to_end.p50.as_millis(),
+                stats.end_to_end.p95.as_millis(),
+                stats.end_to_end.p99.as_millis(),
+                stats.processing.p50.as_micros(),
+                stats.processing.p95.as_micros(),
+                stats.processing.p99.as_micros()
+            )?;
+        }
+
+        let mut confirmation = File::create(dir.join("confirmation_latency.csv"))?;
+        writeln!(
+            confirmation,
+            "user_id,bridge_id,confirmed,p50_ms,p95_ms,p99_ms"
+        )?;
+        for (node, metrics) in &state.components {
+            let user_id = match node {
+                NodeId::User(id) => id,
+                _ => continue,
+            };
+            let bridge_id = state
+                .user_bridges
+                .get(user_id)
+                .map_or(String::new(), |bridge_id| bridge_id.to_string());
+            let stats = ConfirmationLatencyStats::of(&metrics.confirmation_latencies);
+            writeln!(
+                confirmation,
+                "{},{},{},{},{},{}",
+                user_id,
+                bridge_id,
+                stats.confirmed,
+                stats.percentiles.p50.as_millis(),
+                stats.percentiles.p95.as_millis(),
+                stats.percentiles.p99.as_millis()
+            )?;
+        }
+
+        let mut blocks = File::create(dir.join("blocks.csv"))?;
+        writeln!(
+            blocks,
+            "height,forged_at_ms,transactions,elems_added,elems_deleted,forge_time_us"
+        )?;
+        for block in &state.blocks {
+            writeln!(
+                blocks,
+                "{},{},{},{},{},{}",
+                block.height,
+                block.forged_at.as_millis(),
+                block.transactions,
+                block.elems_added,
+                block.elems_deleted,
+                block.forge_time.as_micros()
+            )?;
+        }
+
+        let mut components = File::create(dir.join("components.csv"))?;
+        writeln!(
+            components,
+            "kind,id,blocks_forged,blocks_applied,blocks_rejected,transactions_received,\
+             transactions_issued,transactions_confirmed,witnesses_served,proof_time_us,\
+             block_processing_time_us,mean_witness_latency_ms,mean_confirmation_latency_ms,\
+             cpu_time_us,utxos,witnesses,mempool_entries,blocks_stored,approx_memory_bytes,\
+             block_bytes_sent,block_bytes_received,transaction_bytes_sent,\
+             transaction_bytes_received,witness_bytes_sent,witness_bytes_received"
+        )?;
+        for (node, metrics) in &state.components {
+            let (kind, id) = match node {
+                NodeId::Miner(id) => ("miner", id),
+                NodeId::Bridge(id) => ("bridge", id),
+                NodeId::User(id) => ("user", id),
+            };
+            let bandwidth: Vec<String> = MessageKind::ALL
+                .iter()
+                .flat_map(|kind| {
+                    let traffic = metrics.bandwidth.get(kind).copied().unwrap_or_default();
+                    vec![
+                        traffic.bytes_sent.to_string(),
+                        traffic.bytes_received.to_string(),
+                    ]
+                })
+                .collect();
+            writeln!(
+                components,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                kind,
+                id,
+                metrics.blocks_forged,
+                metrics.blocks_applied,
+                metrics.blocks_rejected,
+                metrics.transactions_received,
+                metrics.transactions_issued,
+                metrics.transactions_confirmed,
+                metrics.witnesses_served,
+                metrics.proof_time.as_micros(),
+                metrics.block_processing_time.as_micros(),
+                mean_ms(&metrics.witness_latencies),
+                mean_ms(&metrics.confirmation_latencies),
+                metrics.cpu_time.as_micros(),
+                metrics.resources.utxos,
+                metrics.resources.witnesses,
+                metrics.resources.mempool_entries,
+                metrics.resources.blocks,
+                metrics.resources.approx_bytes,
+                bandwidth.join(",")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ProofObserver for Metrics {
+    /// Counts a proof made, and tells the observers added.
+    fn proof_generated(&self, event: &ProofEvent) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let stats = state.proofs.entry(event.op).or_default();
+            stats.generated += 1;
+            stats.elems += event.elems as u64;
+            stats.bytes += event.bytes as u64;
+            stats.argument_bytes += event.op.argument_bytes() as u64;
+            stats.generation_time += event.time;
+        }
+        for observer in self.proof_observers.lock().unwrap().iter() {
+            observer.proof_generated(event);
+        }
+    }
+
+    /// Counts a proof checked, and tells the observers added.
+    fn proof_verified(&self, event: &ProofEvent) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let stats = state.proofs.entry(event.op).or_default();
+            stats.verified += 1;
+            stats.verification_time += event.time;
+        }
+        for observer in self.proof_observers.lock().unwrap().iter() {
+            observer.proof_verified(event);
+        }
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("blocks", &self.num_blocks())
+            .finish()
+    }
+}
+
+/// The mean of `durations` in fractional milliseconds, or an empty string if there are none.
+fn mean_ms(durations: &[Duration]) -> String {
+    if durations.is_empty() {
+        return String::new();
+    }
+    let total: Duration = durations.iter().sum();
+    format!(
+        "{:.3}",
+        total.as_secs_f64() * 1000.0 / durations.len() as f64
+    )
+}
@@ -1,132 +1,694 @@
-use super::state::{Block, Transaction};
+use super::batch::BatchProof;
+use super::byzantine::Behavior;
+use super::clock::Clock;
+use super::control::RunControl;
+use super::genesis::Mints;
+use super::injection::{Corrupt, FaultInjector};
+use super::instrument::Instrumented;
+use super::logging;
+use super::metrics::{BlockRecord, Metrics, ResourceUsage};
+use super::pipeline::ValidationPipeline;
+use super::primes::PrimeHasher;
+use super::proof_cache::ProofCache;
+use super::proofs::ProofOp;
+use super::shutdown::FinalState;
+use super::state::NodeId;
+use super::state::{self, Block, Transaction, TxId, UpdateProof, Valued};
+use super::transport::{BlockTopic, Receiver, Sender, TxTopic, RECEIVE_TIMEOUT};
 use super::util;
+use super::validation::{self, BlockError};
 use accumulator::group::UnknownOrderGroup;
-use accumulator::{AccError, Accumulator};
-use multiqueue::{BroadcastReceiver, BroadcastSender};
+use accumulator::{AccError, Accumulator, NonmembershipProof};
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::slice;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn};
 
-/// A stateless miner in our system.
-pub struct Miner<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+/// The next block, staged by a miner: the transactions it includes, shared with the mempool rather
+/// than copied out of it, and its accumulator update, proven against the miner's accumulator. It
+/// only becomes a `Block` once every proof has been made.
+pub struct StagedBlock<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    pub height: u64,
+    pub transactions: Vec<Arc<Transaction<G, T>>>,
+    /// The UTXOs the transactions create.
+    pub elems_added: Vec<T>,
+    /// How many UTXOs the transactions spend.
+    pub elems_deleted: usize,
+    acc_new: Accumulator<G, T>,
+    proof_update: UpdateProof<G, T>,
+    proof_fresh: Option<NonmembershipProof<G, T>>,
+    prev_acc_commitment: [u8; 32],
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> StagedBlock<G, T> {
+    /// The block, ready to broadcast.
+    pub fn into_block(self) -> Block<G, T> {
+        Block {
+            height: self.height,
+            transactions: self.transactions,
+            acc_commitment: state::acc_commitment(&self.acc_new),
+            acc_new: self.acc_new,
+            proof_update: self.proof_update,
+            proof_fresh: self.proof_fresh,
+            prev_acc_commitment: self.prev_acc_commitment,
+        }
+    }
+}
+
+/// What a miner has of the chain: its accumulator and the blocks it has applied.
+struct ChainState<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
     acc: Accumulator<G, T>,
+    /// The accumulator the miner started from, below the first block in `blocks`.
+    base_acc: Accumulator<G, T>,
     block_height: u64,
-    pending_transactions: Vec<Transaction<G, T>>,
+    /// Every block applied so far, for nodes that need to catch up, shared with the links that
+    /// delivered them.
+    blocks: Vec<Arc<Block<G, T>>>,
+    /// Every UTXO in the accumulator, if this miner proves that the UTXOs its blocks create are
+    /// not already in it. Blocks from other miners must then prove it too.
+    utxo_set: Option<HashSet<T>>,
+    /// The heights of blocks applied in a batch below its last one. The batch proof only covers
+    /// the accumulator after the last, so theirs are unchecked: they are passed on to nodes
+    /// catching up, which check them for themselves, but not served as the chain's state.
+    unverified: HashSet<u64>,
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> ChainState<G, T> {
+    fn block_at(&self, height: u64) -> Option<&Arc<Block<G, T>>> {
+        self.blocks.iter().find(|block| block.height == height)
+    }
+
+    fn blocks_since(&self, height: u64) -> Vec<Block<G, T>> {
+        self.blocks
+            .iter()
+            .filter(|block| block.height > height)
+            .map(|block| block.as_ref().clone())
+            .collect()
+    }
+}
+
+/// A miner's transactions waiting for a block, each shared with the blocks that include it.
+struct Mempool<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    transactions: Vec<Arc<Transaction<G, T>>>,
+    /// The IDs of `transactions`, so a duplicate is turned away without comparing it to each.
+    txids: HashSet<TxId>,
+    /// The UTXOs and witnesses in `transactions`, counted as they come in, so reporting them
+    /// does not go over every transaction.
+    utxos: usize,
+    witnesses: usize,
+    /// Blocks in the miner's chain, and the UTXOs and witnesses in them, counted as blocks are
+    /// applied, so the mempool can report the miner's resources without locking the chain.
+    chain_blocks: usize,
+    chain_utxos: usize,
+    chain_witnesses: usize,
 }
 
-impl<G: UnknownOrderGroup, T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send> Miner<G, T> {
-    /// Runs a miner's simulation loop.
-    // Assumes all miners are online from genesis. We may want to implement syncing later.
-    pub fn start(
+/// A stateless miner in our system. Its chain and its mempool are locked apart, so the threads
+/// taking transactions, validating blocks and forging share it without waiting on one another:
+/// a transaction only locks the mempool, and a block only reads the chain until it is found
+/// valid and applied. Whatever locks both locks the chain first.
+pub struct Miner<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    id: usize,
+    chain: RwLock<ChainState<G, T>>,
+    mempool: Mutex<Mempool<G, T>>,
+    /// Results of checking blocks' proofs, shared with the other components of the process.
+    proof_cache: Option<Arc<ProofCache<G, T>>>,
+    /// The mints the run has authorized, if it mints any. Without them, no transaction may
+    /// spend nothing.
+    mints: Option<Arc<Mints<T>>>,
+    /// A hash-to-prime strategy to measure over the UTXOs this miner's blocks create.
+    prime_hasher: Option<Arc<PrimeHasher>>,
+    /// Whether blocks prove their accumulator update with a single swap proof.
+    multiswap: bool,
+    /// Whether blocks received are validated by a pipeline of threads rather than one by one.
+    pipelined: bool,
+    metrics: Arc<Metrics>,
+}
+
+impl<
+        G: UnknownOrderGroup,
+        T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+    > Miner<G, T>
+{
+    pub fn new(id: usize, acc: Accumulator<G, T>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            id,
+            chain: RwLock::new(ChainState {
+                base_acc: acc.clone(),
+                acc,
+                block_height: 0,
+                blocks: Vec::new(),
+                utxo_set: None,
+                unverified: HashSet::new(),
+            }),
+            mempool: Mutex::new(Mempool {
+                transactions: Vec::new(),
+                txids: HashSet::new(),
+                utxos: 0,
+                witnesses: 0,
+                chain_blocks: 0,
+                chain_utxos: 0,
+                chain_witnesses: 0,
+            }),
+            proof_cache: None,
+            mints: None,
+            prime_hasher: None,
+            multiswap: false,
+            pipelined: false,
+            metrics,
+        }
+    }
+
+    /// Has this miner keep track of every UTXO in the accumulator, starting from `utxos`, so its
+    /// blocks can prove that the UTXOs they create are not in it, and only accept blocks that do.
+    pub fn keep_utxo_set(&mut self, utxos: Vec<T>) {
+        self.chain.get_mut().unwrap().utxo_set = Some(utxos.into_iter().collect());
+    }
+
+    /// Has this miner look up blocks' proofs in `cache` before checking them, and add what it
+    /// checks.
+    pub fn share_proof_cache(&mut self, cache: Arc<ProofCache<G, T>>) {
+        self.proof_cache = Some(cache);
+    }
+
+    /// Has this miner accept the mints of `mints`, which the run adds to as it authorizes them.
+    pub fn accept_mints(&mut self, mints: Arc<Mints<T>>) {
+        self.mints = Some(mints);
+    }
+
+    /// Has this miner map every UTXO its blocks create to a prime with `hasher`, which records
+    /// what that takes.
+    pub fn hash_to_primes(&mut self, hasher: Arc<PrimeHasher>) {
+        self.prime_hasher = Some(hasher);
+    }
+
+    /// Has this miner prove each block's accumulator update with a single swap of the UTXOs spent
+    /// for those created, rather than a deletion and an addition. See `swap::SwapProof`.
+    pub fn prove_multiswaps(&mut self) {
+        self.multiswap = true;
+    }
+
+    /// Has this miner validate the blocks it receives in a pipeline, checking the next block's
+    /// structure and deletions while the proofs of the one before are still being checked. See
+    /// `pipeline::ValidationPipeline`.
+    pub fn pipeline_validation(&mut self) {
+        self.pipelined = true;
+    }
+
+    pub fn pipelines_validation(&self) -> bool {
+        self.pipelined
+    }
+
+    /// Runs a miner's simulation loop until the run is stopped or the miner crashes, then returns
+    /// where it got to. The miner is shared, so other code (e.g. an API) can look at it meanwhile.
+    // A miner that misses blocks here does not catch up on them: only the discrete-event
+    // scheduler has restarted nodes sync (see `blocks_since` and `apply_batch`).
+    pub fn start<N: BlockTopic<G, T> + TxTopic<G, T>>(
+        miner_ref: Arc<Self>,
         is_leader: bool,
-        acc: Accumulator<G, T>,
+        byzantine: Option<Behavior>,
         block_interval_ms: u64,
-        block_sender: &BroadcastSender<Block<G, T>>,
-        block_receiver: BroadcastReceiver<Block<G, T>>,
-        tx_receiver: BroadcastReceiver<Transaction<G, T>>,
-    ) {
-        let miner_ref = Arc::new(Mutex::new(Self {
-            acc,
-            block_height: 0,
-            pending_transactions: Vec::new(),
-        }));
+        clock: Arc<dyn Clock>,
+        control: Arc<RunControl>,
+        faults: Arc<FaultInjector>,
+        block_sender: &N::BlockSender,
+        block_receiver: N::BlockReceiver,
+        tx_receiver: N::TxReceiver,
+    ) -> FinalState
+    where
+        Block<G, T>: Corrupt,
+    {
+        let node = NodeId::Miner(miner_ref.id);
+        let _span = logging::node_span(node).entered();
 
         // Transaction processor thread.
         let miner = miner_ref.clone();
-        let transaction_thread = thread::spawn(move || loop {
-            match tx_receiver.try_recv() {
-                Ok(tx) => miner.lock().unwrap().add_transaction(tx),
-                Err(_) => (),
+        let tx_clock = clock.clone();
+        let tx_control = control.clone();
+        let tx_faults = faults.clone();
+        let transaction_thread = thread::spawn(move || {
+            let _span = logging::node_span(node).entered();
+            while tx_control.wait_while_paused() && tx_faults.interrupt(node, tx_clock.as_ref()) {
+                if let Some(tx) = tx_receiver.receive_timeout(RECEIVE_TIMEOUT) {
+                    miner.add_transaction(tx);
+                }
             }
-            sleep(Duration::from_millis(10));
         });
 
-        // Block validation thread.
+        // Block validation thread, which hands blocks on to a pipeline if the miner has one.
         let miner = miner_ref.clone();
-        let validate_thread = thread::spawn(move || loop {
-            match block_receiver.try_recv() {
-                Ok(block) => miner.lock().unwrap().validate_block(block),
-                Err(_) => (),
+        let validate_clock = clock.clone();
+        let validate_control = control.clone();
+        let validate_faults = faults.clone();
+        let validate_thread = thread::spawn(move || {
+            let _span = logging::node_span(node).entered();
+            let pipeline = if miner.pipelines_validation() {
+                Some(ValidationPipeline::start(miner.clone()))
+            } else {
+                None
+            };
+            while validate_control.wait_while_paused()
+                && validate_faults.interrupt(node, validate_clock.as_ref())
+            {
+                if let Some(block) = block_receiver.receive_timeout(RECEIVE_TIMEOUT) {
+                    match &pipeline {
+                        Some(pipeline) => pipeline.submit(block),
+                        None => miner.validate_block(block),
+                    }
+                }
+            }
+            if let Some(pipeline) = pipeline {
+                pipeline.finish();
             }
-            sleep(Duration::from_millis(10));
         });
 
         // Block creation on an interval.
         if is_leader {
             loop {
-                sleep(Duration::from_millis(block_interval_ms));
-                let new_block = miner_ref.lock().unwrap().forge_block();
+                clock.sleep(Duration::from_millis(block_interval_ms));
+                if !control.wait_for_block_permit() || !faults.interrupt(node, clock.as_ref()) {
+                    break;
+                }
+                let new_block = miner_ref.forge_block();
                 if let Ok(block) = new_block {
-                    block_sender.try_send(block).unwrap();
+                    block_sender.send(Arc::new(faults.tamper(node, block)));
                 } else {
-                    println!("Fail on forging block");
+                    error!("Fail on forging block");
                 }
             }
+        } else if let Some(behavior) = byzantine {
+            // Forge halfway between the leader's blocks, so corrupt blocks race honest ones.
+            clock.sleep(Duration::from_millis(block_interval_ms / 2));
+            while control.wait_while_paused() && faults.interrupt(node, clock.as_ref()) {
+                let new_block = miner_ref.forge_byzantine_block(behavior);
+                if let Ok(block) = new_block {
+                    block_sender.send(Arc::new(faults.tamper(node, block)));
+                }
+                clock.sleep(Duration::from_millis(block_interval_ms));
+            }
         }
 
         transaction_thread.join().unwrap();
         validate_thread.join().unwrap();
+        miner_ref.final_state()
     }
 
-    fn add_transaction(&mut self, transaction: Transaction<G, T>) {
-        // This `contains` check could incur overhead; ideally we'd use a set but Rust `HashSet` is
-        // kind of a pain to use here.
-        if !self.pending_transactions.contains(&transaction) {
-            self.pending_transactions.push(transaction);
+    /// Adds `transaction` to the mempool, locking nothing else, so it never waits on a block
+    /// being validated or forged. The transaction is shared with the link it came in on.
+    pub fn add_transaction(&self, transaction: Arc<Transaction<G, T>>) {
+        let start = Instant::now();
+        let txid = transaction.id();
+        let mut mempool = self.mempool.lock().unwrap();
+        if mempool.txids.insert(txid) {
+            let (utxos, witnesses) = count_utxos_and_witnesses(slice::from_ref(&transaction));
+            mempool.utxos += utxos;
+            mempool.witnesses += witnesses;
+            mempool.transactions.push(transaction);
+            self.metrics.transaction_received(self.id, txid);
+            self.metrics
+                .mempool_changed(self.id, mempool.transactions.len());
+            self.report_resources(&mempool);
         }
+        drop(mempool);
+        self.metrics
+            .cpu_used(NodeId::Miner(self.id), start.elapsed());
     }
 
-    fn forge_block(&self) -> Result<Block<G, T>, AccError> {
-        let (elems_added, elems_deleted) =
-            util::elems_from_transactions(&self.pending_transactions);
-        println!(
-            "Forging block {} with {} elems added and {} elems deleted.",
-            self.block_height + 1,
-            elems_added.len(),
-            elems_deleted.len()
+    pub fn forge_block(&self) -> Result<Block<G, T>, AccError> {
+        let start = Instant::now();
+        let staged = self.stage_block()?;
+        info!(
+            "Forged block {} with {} elems added and {} elems deleted.",
+            staged.height,
+            staged.elems_added.len(),
+            staged.elems_deleted
         );
-        let (witness_deleted, proof_deleted) =
-            self.acc.clone().delete_with_proof(&elems_deleted)?;
-        let (acc_new, proof_added) = witness_deleted.clone().add_with_proof(&elems_added);
-        let new_block = Block {
-            height: self.block_height + 1,
-            transactions: self.pending_transactions.clone(),
+        if let Some(hasher) = &self.prime_hasher {
+            for elem in &staged.elems_added {
+                hasher.represent(elem);
+            }
+        }
+        self.metrics.block_forged(
+            self.id,
+            BlockRecord {
+                height: staged.height,
+                forged_at: self.metrics.now(),
+                transactions: staged.transactions.len(),
+                elems_added: staged.elems_added.len(),
+                elems_deleted: staged.elems_deleted,
+                forge_time: start.elapsed(),
+            },
+        );
+        self.metrics.transactions_mined(
+            self.id,
+            staged.height,
+            staged
+                .transactions
+                .iter()
+                .map(|transaction| transaction.id()),
+        );
+        let new_block = staged.into_block();
+        trace!("No.{} forged block: {:#?}", new_block.height, new_block);
+        self.metrics
+            .cpu_used(NodeId::Miner(self.id), start.elapsed());
+        Ok(new_block)
+    }
+
+    /// Builds the next block from the pending transactions. See `stage_block`.
+    pub(super) fn build_block(&self) -> Result<Block<G, T>, AccError> {
+        Ok(self.stage_block()?.into_block())
+    }
+
+    /// Stages the next block from the pending transactions, with proofs for the accumulator update
+    /// and, if this miner keeps every UTXO, a proof that the UTXOs created are fresh. The proofs
+    /// are made against this miner's accumulator in place, and the transactions are shared with
+    /// its mempool, so nothing of the miner's state is copied. The chain is read-locked while the
+    /// proofs are made, and the mempool only while its transactions are listed.
+    pub fn stage_block(&self) -> Result<StagedBlock<G, T>, AccError> {
+        let chain = self.chain.read().unwrap();
+        let transactions = self.includable_transactions(&chain);
+        let (elems_added, elems_deleted) = util::elems_from_transactions(&transactions);
+        let instrumented = Instrumented::new(self.metrics.as_ref());
+        let (acc_new, proof_update) = if self.multiswap {
+            let (acc_new, proof) =
+                instrumented.prove_swap(&chain.acc, &elems_deleted, &elems_added)?;
+            (acc_new, UpdateProof::Swap(proof))
+        } else {
+            let (witness_deleted, deleted) =
+                instrumented.prove_deletion(&chain.acc, &elems_deleted)?;
+            let (acc_new, added) = instrumented.add_with_proof(witness_deleted, &elems_added);
+            (acc_new, UpdateProof::Sequential { deleted, added })
+        };
+        let proof_fresh = match &chain.utxo_set {
+            Some(utxo_set) if !elems_added.is_empty() => {
+                let acc_set: Vec<T> = utxo_set.iter().cloned().collect();
+                Some(instrumented.prove_nonmembership(
+                    ProofOp::FreshUtxos,
+                    &chain.acc,
+                    &acc_set,
+                    &elems_added,
+                )?)
+            }
+            _ => None,
+        };
+        Ok(StagedBlock {
+            height: chain.block_height + 1,
+            transactions,
+            elems_added,
+            elems_deleted: elems_deleted.len(),
             acc_new,
-            proof_added,
-            proof_deleted,
+            proof_update,
+            proof_fresh,
+            prev_acc_commitment: state::acc_commitment(&chain.acc),
+        })
+    }
+
+    /// The pending transactions that can go into the next block: those spending only UTXOs that
+    /// are in the accumulator (i.e. whose witnesses are current) and not spent by an earlier
+    /// transaction. Lost, delayed and duplicated messages make the others stale or conflicting.
+    /// Transactions creating more value than they spend, including mints the run did not
+    /// authorize, are left out, and a miner keeping every
+    /// UTXO also leaves out transactions creating one already in it.
+    fn includable_transactions(&self, chain: &ChainState<G, T>) -> Vec<Arc<Transaction<G, T>>> {
+        let pending = self.mempool.lock().unwrap().transactions.clone();
+        let mut spent = HashSet::new();
+        let mut transactions = Vec::new();
+        for transaction in pending {
+            let is_fresh = chain.utxo_set.as_ref().map_or(true, |utxo_set| {
+                transaction
+                    .utxos_created
+                    .iter()
+                    .all(|utxo| !utxo_set.contains(utxo))
+            });
+            let is_includable = is_fresh
+                && transaction.conserves_value(self.mints.as_deref())
+                && transaction
+                    .utxos_spent_with_witnesses
+                    .iter()
+                    .all(|(utxo, witness)| {
+                        !spent.contains(utxo) && witness.0.clone().add(&[utxo.clone()]) == chain.acc
+                    });
+            if is_includable {
+                for (utxo, _) in &transaction.utxos_spent_with_witnesses {
+                    spent.insert(utxo.clone());
+                }
+                transactions.push(transaction);
+            } else {
+                debug!("Leaving out a stale or conflicting transaction.");
+            }
+        }
+        transactions
+    }
+
+    /// Applies `block` if it validly extends this miner's chain. Invalid blocks are rejected and
+    /// counted; stale ones, e.g. duplicates, are ignored. The block is checked under a read lock
+    /// on the chain, so forging and reads go on meanwhile, and only applying it writes.
+    pub fn validate_block(&self, block: Arc<Block<G, T>>) {
+        let start = Instant::now();
+        let (acc, validity) = {
+            let chain = self.chain.read().unwrap();
+            let validity = validation::validate_block(
+                &chain.acc,
+                chain.block_height,
+                &block,
+                chain.utxo_set.is_some(),
+                self.mints.as_deref(),
+                &self.metrics,
+                self.proof_cache.as_deref(),
+            );
+            (chain.acc.clone(), validity)
         };
-//        println!(
-//            "No.{} forged block: {:#?}",
-//            self.block_height + 1,
-//            new_block
-//        );
-        Ok(new_block)
+        match validity {
+            Ok(()) => self.apply_block(block, &acc, start),
+            Err(err) => self.reject_block(&block, err, start),
+        }
     }
 
-    fn validate_block(&mut self, block: Block<G, T>) {
-        // Preserves idempotency if multiple miners are leaders.
-        if block.height != self.block_height + 1 {
+    /// Counts `block` rejected for `err`, unless it was only stale. `start` is when its validation
+    /// started.
+    pub(super) fn reject_block(&self, block: &Block<G, T>, err: BlockError, start: Instant) {
+        if err == BlockError::Stale {
             return;
         }
+        let node = NodeId::Miner(self.id);
+        warn!("Rejected block {}: {:?}.", block.height, err);
+        self.metrics.block_rejected(node);
+        self.metrics.cpu_used(node, start.elapsed());
+    }
 
-        let (elems_added, elem_witnesses_deleted) =
-            util::elems_from_transactions(&block.transactions);
-        let elems_deleted: Vec<T> = elem_witnesses_deleted
-            .iter()
-            .map(|(u, _wit)| u.clone())
-            .collect();
-        assert!(self
-            .acc
-            .verify_membership_batch(&elems_deleted, &block.proof_deleted));
-        assert!(block
-            .acc_new
-            .verify_membership_batch(&elems_added, &block.proof_added));
-        assert_eq!(block.proof_deleted.witness, block.proof_added.witness);
-        self.acc = block.acc_new.clone();
-        self.block_height = block.height;
-        self.pending_transactions.clear();
+    /// The height and accumulator of this miner's chain, and whether blocks must prove the UTXOs
+    /// they create are fresh, to check the next block against.
+    pub(super) fn chain_tip(&self) -> (u64, Accumulator<G, T>, bool) {
+        let chain = self.chain.read().unwrap();
+        (
+            chain.block_height,
+            chain.acc.clone(),
+            chain.utxo_set.is_some(),
+        )
     }
+
+    pub(super) fn proof_cache(&self) -> Option<&ProofCache<G, T>> {
+        self.proof_cache.as_deref()
+    }
+
+    pub(super) fn mints(&self) -> Option<&Mints<T>> {
+        self.mints.as_deref()
+    }
+
+    pub(super) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Applies `blocks`, fetched to catch up, if `proof` shows they validly extend this miner's
+    /// chain, checking it in place of each block's own proofs. Returns whether it did; if not,
+    /// the blocks can still be validated one by one. The blocks below the last are marked
+    /// unverified, as the proof does not cover their accumulators.
+    pub fn apply_batch(&self, blocks: &[Block<G, T>], proof: &BatchProof<G, T>) -> bool {
+        let start = Instant::now();
+        let (acc, validity) = {
+            let chain = self.chain.read().unwrap();
+            let validity = validation::validate_batch(
+                &chain.acc,
+                chain.block_height,
+                blocks,
+                proof,
+                chain.utxo_set.is_some(),
+                self.mints.as_deref(),
+                &self.metrics,
+                self.proof_cache.as_deref(),
+            );
+            (chain.acc.clone(), validity)
+        };
+        if let Err(err) = validity {
+            debug!(
+                "Not applying a batch of {} blocks: {:?}.",
+                blocks.len(),
+                err
+            );
+            self.metrics
+                .cpu_used(NodeId::Miner(self.id), start.elapsed());
+            return false;
+        }
+        info!("Caught up on {} blocks with one proof.", blocks.len());
+        let mut acc_before = acc;
+        for block in blocks {
+            self.apply_block(Arc::new(block.clone()), &acc_before, start);
+            acc_before = block.acc_new.clone();
+        }
+        let mut chain = self.chain.write().unwrap();
+        for block in &blocks[..blocks.len() - 1] {
+            if chain
+                .block_at(block.height)
+                .map_or(false, |applied| **applied == *block)
+            {
+                chain.unverified.insert(block.height);
+            }
+        }
+        true
+    }
+
+    /// Applies a block that has been validated against `acc_before`. `start` is when its
+    /// validation started. A block at the same height may have been applied while this one was
+    /// validated, or the block this one was validated after turned down, in which case this one
+    /// is stale.
+    pub(super) fn apply_block(
+        &self,
+        block: Arc<Block<G, T>>,
+        acc_before: &Accumulator<G, T>,
+        start: Instant,
+    ) {
+        let node = NodeId::Miner(self.id);
+        let mut chain = self.chain.write().unwrap();
+        if block.height != chain.block_height + 1 || chain.acc != *acc_before {
+            debug!("Not applying block {}, which is stale.", block.height);
+            return;
+        }
+        if let Some(utxo_set) = &mut chain.utxo_set {
+            let (elems_added, elems_deleted) = util::elems_from_transactions(&block.transactions);
+            for (utxo, _witness) in elems_deleted {
+                utxo_set.remove(&utxo);
+            }
+            utxo_set.extend(elems_added);
+        }
+        chain.acc = block.acc_new.clone();
+        chain.block_height = block.height;
+        let (utxos, witnesses) = count_utxos_and_witnesses(&block.transactions);
+        chain.blocks.push(block);
+        let mut mempool = self.mempool.lock().unwrap();
+        mempool.transactions.clear();
+        mempool.txids.clear();
+        mempool.utxos = 0;
+        mempool.witnesses = 0;
+        mempool.chain_blocks += 1;
+        mempool.chain_utxos += utxos;
+        mempool.chain_witnesses += witnesses;
+        self.metrics
+            .block_applied(node, chain.block_height, start.elapsed());
+        self.metrics.mempool_changed(self.id, 0);
+        self.report_resources(&mempool);
+        self.metrics.cpu_used(node, start.elapsed());
+    }
+
+    /// Reports the mempool and stored chain, the only state a stateless miner grows.
+    fn report_resources(&self, mempool: &Mempool<G, T>) {
+        self.metrics.resources_changed(
+            NodeId::Miner(self.id),
+            ResourceUsage::estimate(
+                mempool.utxos,
+                mempool.witnesses,
+                mempool.transactions.len(),
+                mempool.chain_blocks,
+                mempool.chain_utxos,
+                mempool.chain_witnesses,
+            ),
+        );
+    }
+
+    /// Picks up the chain at `block_height`, e.g. when restoring a checkpoint. This miner has no
+    /// blocks below it to share with nodes catching up.
+    pub fn resume_at(&mut self, block_height: u64) {
+        self.chain.get_mut().unwrap().block_height = block_height;
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn block_height(&self) -> u64 {
+        self.chain.read().unwrap().block_height
+    }
+
+    /// The block at `height`, if this miner has applied it and checked its accumulator.
+    pub fn block_at(&self, height: u64) -> Option<Arc<Block<G, T>>> {
+        let chain = self.chain.read().unwrap();
+        if chain.unverified.contains(&height) {
+            return None;
+        }
+        chain.block_at(height).cloned()
+    }
+
+    /// The heights of the blocks this miner applied in a batch without checking their
+    /// accumulators. See `apply_batch`.
+    pub fn unverified_heights(&self) -> HashSet<u64> {
+        self.chain.read().unwrap().unverified.clone()
+    }
+
+    pub fn final_state(&self) -> FinalState {
+        FinalState::Miner {
+            miner_id: self.id,
+            block_height: self.block_height(),
+            pending_transactions: self.mempool.lock().unwrap().transactions.len(),
+        }
+    }
+
+    /// The blocks this miner has applied above `height`, in order.
+    pub fn blocks_since(&self, height: u64) -> Vec<Block<G, T>> {
+        self.chain.read().unwrap().blocks_since(height)
+    }
+
+    /// The blocks this miner has applied above `height`, and a proof of all their updates
+    /// together if this miner keeps every UTXO, which the proof needs, and has every block above
+    /// `height`.
+    pub fn batch_since(&self, height: u64) -> (Vec<Block<G, T>>, Option<BatchProof<G, T>>) {
+        let chain = self.chain.read().unwrap();
+        let blocks = chain.blocks_since(height);
+        let proof = match (&chain.utxo_set, blocks.first()) {
+            (Some(utxo_set), Some(first)) if first.height == height + 1 => {
+                let acc = chain
+                    .block_at(height)
+                    .map_or(&chain.base_acc, |block| &block.acc_new);
+                Some(Instrumented::new(self.metrics.as_ref()).prove_batch(acc, &blocks, utxo_set))
+            }
+            _ => None,
+        };
+        (blocks, proof)
+    }
+
+    pub fn pending_transactions(&self) -> Vec<Arc<Transaction<G, T>>> {
+        self.mempool.lock().unwrap().transactions.clone()
+    }
+
+    /// A copy of this miner's accumulator.
+    pub fn acc(&self) -> Accumulator<G, T> {
+        self.chain.read().unwrap().acc.clone()
+    }
+}
+
+/// The UTXOs created or spent by `transactions`, and the witnesses they carry.
+fn count_utxos_and_witnesses<G: UnknownOrderGroup, T: Hash + Debug>(
+    transactions: &[Arc<Transaction<G, T>>],
+) -> (usize, usize) {
+    transactions
+        .iter()
+        .fold((0, 0), |(utxos, witnesses), transaction| {
+            let spent = transaction.utxos_spent_with_witnesses.len();
+            (
+                utxos + transaction.utxos_created.len() + spent,
+                witnesses + spent,
+            )
+        })
 }
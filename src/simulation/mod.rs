@@ -1,8 +1,92 @@
+pub mod arith;
+pub mod auditor;
+pub mod auth;
+pub mod baseline;
+pub mod batch;
 pub mod bridge;
 pub use bridge::*;
+pub mod builder;
+pub use builder::SimulationBuilder;
+pub mod byzantine;
+pub mod chain_stats;
+pub mod checkpoint;
+pub mod clock;
+pub mod cluster;
+pub mod codec;
+pub mod commitment;
+pub mod config;
+pub mod control;
+pub use config::*;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod deletion;
+pub mod diagram;
+pub mod discovery;
+pub mod embed;
+pub use embed::Simulation;
+pub mod engine;
+pub use engine::*;
+pub mod events;
+pub mod experiment;
+pub mod genesis;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handshake;
+pub mod history;
+pub mod hooks;
+pub mod injection;
+pub mod instrument;
+pub mod int_pool;
+pub mod invariants;
+pub mod jsonrpc;
+pub mod launcher;
+pub mod logging;
+pub mod merkle;
+pub mod metrics;
 pub mod miner;
 pub use miner::*;
+pub mod modulus;
+pub mod multiexp;
+pub mod network;
+pub mod noise;
+#[cfg(feature = "p2p")]
+pub mod p2p;
+pub mod pipeline;
+pub mod prime_cache;
+pub mod primes;
+#[cfg(feature = "profiling")]
+pub mod profile;
+pub mod prometheus;
+pub mod proof_cache;
+pub mod proofs;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod realtime;
+pub use realtime::*;
+pub mod report;
+pub mod rest;
+pub mod rpc;
+pub mod scenario;
+pub mod scheduler;
+pub mod shutdown;
 pub mod state;
+pub mod swap;
+pub mod sweep;
+#[cfg(feature = "async-runtime")]
+pub mod tasks;
+pub mod tcp;
+pub mod trace;
+pub mod transport;
+pub mod trapdoor;
 pub mod user;
 pub use user::*;
 mod util;
+pub mod validation;
+pub mod vector;
+pub mod viz;
+pub mod watchdog;
+pub mod websocket;
+pub mod wire;
+pub mod witness_update;
+pub mod witnesses;
+pub mod zk;
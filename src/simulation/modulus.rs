@@ -0,0 +1,248 @@
+use super::arith;
+use super::config::ModulusSource;
+use accumulator::group::{ElemFrom, Group, TypeRep, UnknownOrderGroup};
+use rand::RngCore;
+use rug::integer::Order;
+use rug::Integer;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::RwLock;
+use tracing::{info, warn};
+
+/// Moduli shorter than this are easy to factor, so anyone could learn the group's order.
+const MIN_SECURE_BITS: u32 = 2048;
+
+/// The modulus `CustomRsa` works under, and where it came from.
+static MODULUS: RwLock<Option<Installed>> = RwLock::new(None);
+
+/// A modulus installed by `install`. Moduli are never freed, so that elements can refer to the
+/// one they were made under for as long as the process lives.
+struct Installed {
+    source: ModulusSource,
+    modulus: &'static Modulus,
+}
+
+/// An RSA modulus, and half of it, around which group elements are reduced.
+pub struct Modulus {
+    pub n: Integer,
+    half: Integer,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+/// An RSA group whose modulus is set up when the process starts, from a file or freshly
+/// generated, rather than baked into the library like `Rsa2048`'s. Install the modulus with
+/// `install` before using the group.
+pub enum CustomRsa {}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// An element of `CustomRsa`: an integer modulo N, identified with its negation (the quotient by
+/// ±1 that `Rsa2048` also works in), kept as whichever of the two is at most N/2.
+pub struct CustomRsaElem(Integer);
+
+impl TypeRep for CustomRsa {
+    type Rep = Modulus;
+
+    fn rep() -> &'static Modulus {
+        MODULUS
+            .read()
+            .unwrap()
+            .as_ref()
+            .expect("No RSA modulus installed: call modulus::install first")
+            .modulus
+    }
+}
+
+impl Group for CustomRsa {
+    type Elem = CustomRsaElem;
+
+    fn op_(modulus: &Modulus, a: &CustomRsaElem, b: &CustomRsaElem) -> CustomRsaElem {
+        reduce(modulus, arith::mul_mod(&a.0, &b.0, &modulus.n))
+    }
+
+    fn id_(_: &Modulus) -> CustomRsaElem {
+        CustomRsaElem(Integer::from(1))
+    }
+
+    fn inv_(modulus: &Modulus, x: &CustomRsaElem) -> CustomRsaElem {
+        let inverse = Integer::from(x.0.invert_ref(&modulus.n).expect("Element is invertible"));
+        reduce(modulus, inverse)
+    }
+
+    fn exp_(modulus: &Modulus, x: &CustomRsaElem, n: &Integer) -> CustomRsaElem {
+        let power = arith::pow_mod(&x.0, n, &modulus.n).expect("Element is invertible");
+        reduce(modulus, power)
+    }
+}
+
+impl<T> ElemFrom<T> for CustomRsa
+where
+    Integer: From<T>,
+{
+    fn elem(t: T) -> CustomRsaElem {
+        reduce(Self::rep(), Integer::from(t))
+    }
+}
+
+impl UnknownOrderGroup for CustomRsa {
+    fn unknown_order_elem_(_: &Modulus) -> CustomRsaElem {
+        CustomRsaElem(Integer::from(2))
+    }
+}
+
+/// `value` modulo N, as the element it stands for.
+fn reduce(modulus: &Modulus, value: Integer) -> CustomRsaElem {
+    let value = value.rem_euc(&modulus.n);
+    if value > modulus.half {
+        CustomRsaElem(Integer::from(&modulus.n - value))
+    } else {
+        CustomRsaElem(value)
+    }
+}
+
+impl Serialize for CustomRsaElem {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string_radix(16))
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomRsaElem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let value = Integer::from_str_radix(&hex, 16).map_err(de::Error::custom)?;
+        Ok(CustomRsa::elem(value))
+    }
+}
+
+#[derive(Debug)]
+pub enum ModulusError {
+    Io(io::Error),
+    /// The modulus file does not hold a usable modulus, for the reason given.
+    Invalid(String),
+}
+
+impl From<io::Error> for ModulusError {
+    fn from(err: io::Error) -> Self {
+        ModulusError::Io(err)
+    }
+}
+
+impl Display for ModulusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModulusError::Io(err) => write!(f, "{}", err),
+            ModulusError::Invalid(reason) => write!(f, "Invalid RSA modulus: {}", reason),
+        }
+    }
+}
+
+impl Error for ModulusError {}
+
+/// Sets up the modulus `CustomRsa` works under from `source`. Calls with the source already
+/// installed keep its modulus, so a generated one is generated once per process; a different
+/// source replaces it, e.g. for the next run of an experiment comparing modulus sizes. Runs in
+/// the group cannot overlap with a replacement, whose elements are not the previous modulus's.
+pub fn install(source: &ModulusSource) -> Result<(), ModulusError> {
+    if let Some(installed) = &*MODULUS.read().unwrap() {
+        if installed.source == *source {
+            return Ok(());
+        }
+    }
+    let n = match source {
+        ModulusSource::Generate(bits) => {
+            let n = generate(*bits);
+            info!(
+                "Generated a {}-bit RSA modulus and discarded its factors.",
+                n.significant_bits()
+            );
+            n
+        }
+        ModulusSource::File(path) => {
+            let n = load(path)?;
+            info!(
+                "Loaded a {}-bit RSA modulus from {}.",
+                n.significant_bits(),
+                path.display()
+            );
+            n
+        }
+    };
+    if n.significant_bits() < MIN_SECURE_BITS {
+        warn!(
+            "A {}-bit RSA modulus is easy to factor; use at least {} bits for security.",
+            n.significant_bits(),
+            MIN_SECURE_BITS
+        );
+    }
+    let half = Integer::from(&n >> 1);
+    let modulus = Box::leak(Box::new(Modulus { n, half }));
+    let mut installed = MODULUS.write().unwrap();
+    if installed.is_some() {
+        info!("Replacing the previous RSA modulus.");
+    }
+    *installed = Some(Installed {
+        source: source.clone(),
+        modulus,
+    });
+    Ok(())
+}
+
+/// The bits of the installed modulus, if there is one.
+pub fn installed_bits() -> Option<u32> {
+    MODULUS
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|installed| installed.modulus.n.significant_bits())
+}
+
+/// A `bits`-bit modulus: the product of two fresh random primes, which are dropped as soon as it
+/// is computed, so nobody (this process included) knows the group's order. This stands in for a
+/// setup ceremony; the memory the primes took up is not wiped.
+pub fn generate(bits: u32) -> Integer {
+    let mut rng = rand::thread_rng();
+    let p = random_prime(bits / 2, &mut rng);
+    let mut q = random_prime(bits - bits / 2, &mut rng);
+    while q == p {
+        q = random_prime(bits - bits / 2, &mut rng);
+    }
+    Integer::from(&p * &q)
+}
+
+/// A random prime of `bits` bits, the top two set so that the product of two has all the bits of
+/// both.
+pub(super) fn random_prime<R: RngCore>(bits: u32, rng: &mut R) -> Integer {
+    let mut bytes = vec![0; (bits as usize + 7) / 8];
+    rng.fill_bytes(&mut bytes);
+    let mut candidate = Integer::from_digits(&bytes, Order::Msf);
+    candidate.keep_bits_mut(bits);
+    candidate.set_bit(bits - 1, true);
+    candidate.set_bit(bits - 2, true);
+    candidate.next_prime()
+}
+
+/// Reads a modulus from `path`, in decimal or, prefixed with `0x`, in hex.
+pub fn load(path: &Path) -> Result<Integer, ModulusError> {
+    let contents = fs::read_to_string(path)?;
+    let contents = contents.trim();
+    let parsed = match contents.strip_prefix("0x") {
+        Some(hex) => Integer::from_str_radix(hex, 16),
+        None => Integer::from_str_radix(contents, 10),
+    };
+    let n = parsed.map_err(|err| ModulusError::Invalid(err.to_string()))?;
+    if n <= 1 || n.is_even() {
+        return Err(ModulusError::Invalid(
+            "An RSA modulus is an odd number above 1".to_string(),
+        ));
+    }
+    Ok(n)
+}
+
+/// Writes `n` to `path` in hex, as `load` reads it.
+pub fn save(path: &Path, n: &Integer) -> io::Result<()> {
+    fs::write(path, format!("0x{}\n", n.to_string_radix(16)))
+}
@@ -0,0 +1,57 @@
+use accumulator::group::UnknownOrderGroup;
+use rug::Integer;
+
+/// The product of each base raised to its exponent, computed at once rather than one power at a
+/// time (Shamir's trick): a single pass over the bits of the longest exponent squares one running
+/// product, multiplying in the product of the bases whose exponents have the bit set. Every
+/// exponentiation of a check thus shares the same squarings, so a check of k powers costs about
+/// one exponentiation's squarings plus a multiplication per bit, rather than k exponentiations.
+///
+/// The products of every subset of the bases are worked out first, so this is for the handful of
+/// powers in a proof's check. Negative exponents raise the base's inverse.
+pub fn multi_exp<G: UnknownOrderGroup>(terms: &[(&G::Elem, &Integer)]) -> G::Elem {
+    let terms: Vec<(G::Elem, Integer)> = terms
+        .iter()
+        .map(|(base, exp)| {
+            if **exp < 0 {
+                (G::inv(base), Integer::from(-*exp))
+            } else {
+                ((*base).clone(), (*exp).clone())
+            }
+        })
+        .collect();
+
+    // The product of the bases in each subset, indexed by the subset's bitmask.
+    let mut subsets = vec![G::unknown_order_elem(); 1 << terms.len()];
+    for mask in 1..subsets.len() {
+        let lowest = mask.trailing_zeros() as usize;
+        let rest = mask & (mask - 1);
+        subsets[mask] = if rest == 0 {
+            terms[lowest].0.clone()
+        } else {
+            G::op(&subsets[rest], &terms[lowest].0)
+        };
+    }
+
+    let bits = terms
+        .iter()
+        .map(|(_base, exp)| exp.significant_bits())
+        .max()
+        .unwrap_or(0);
+    let mut result: Option<G::Elem> = None;
+    for bit in (0..bits).rev() {
+        let mask = terms
+            .iter()
+            .enumerate()
+            .filter(|(_index, (_base, exp))| exp.get_bit(bit))
+            .fold(0, |mask, (index, _term)| mask | 1 << index);
+        result = match (result, mask) {
+            (None, 0) => None,
+            (None, mask) => Some(subsets[mask].clone()),
+            (Some(product), 0) => Some(G::op(&product, &product)),
+            (Some(product), mask) => Some(G::op(&G::op(&product, &product), &subsets[mask])),
+        };
+    }
+    // Every exponent was zero: the empty product, which raising any base to zero gives too.
+    result.unwrap_or_else(|| G::exp(&G::unknown_order_elem(), &Integer::new()))
+}
@@ -0,0 +1,624 @@
+use super::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::metrics::Metrics;
+use super::proofs::{
+    DELETION_PROOF_BYTES, MEMBERSHIP_PROOF_BYTES, NONMEMBERSHIP_PROOF_BYTES, SWAP_PROOF_BYTES,
+};
+use super::state::{Block, NodeId, Transaction, UpdateProof};
+use super::transport::{Channel, QueueSender, SendError};
+use super::witness_update::WitnessUpdate;
+use accumulator::group::UnknownOrderGroup;
+use rand::distributions::{Distribution, Normal};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::mpsc::TrySendError;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How often a sender waiting on a full queue checks for room.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Rough sizes of what messages carry, for links with limited bandwidth and for counting traffic
+/// that never gets encoded: a UTXO is a UUID, a user ID and a value, and a group element (an
+/// accumulator, witness or proof) is a 2048-bit number.
+pub const UTXO_BYTES: usize = 32;
+pub const ELEM_BYTES: usize = 256;
+/// Framing and addressing, on top of what a message carries.
+pub const MESSAGE_HEADER_BYTES: usize = 40;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// The kinds of traffic whose delivery can be configured separately.
+pub enum MessageKind {
+    Block,
+    Transaction,
+    /// Witness requests and responses, and the UTXO updates bridges push to users.
+    Witness,
+}
+
+impl MessageKind {
+    pub const ALL: [MessageKind; 3] = [
+        MessageKind::Block,
+        MessageKind::Transaction,
+        MessageKind::Witness,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageKind::Block => "block",
+            MessageKind::Transaction => "transaction",
+            MessageKind::Witness => "witness",
+        }
+    }
+}
+
+/// A message whose size on the wire can be estimated without encoding it.
+pub trait Payload {
+    /// Roughly how many bytes the message carries, not counting framing.
+    fn payload_bytes(&self) -> usize;
+}
+
+impl<G: UnknownOrderGroup, T: Hash + Debug> Payload for Transaction<G, T> {
+    fn payload_bytes(&self) -> usize {
+        self.utxos_created.len() * UTXO_BYTES
+            + self.utxos_spent_with_witnesses.len() * (UTXO_BYTES + ELEM_BYTES)
+    }
+}
+
+impl<G: UnknownOrderGroup, T: Hash + Debug> Payload for Block<G, T> {
+    /// The height, the transactions, an accumulator and the commitment to it, a deletion proof
+    /// and a membership proof or else a swap proof, and any non-membership proof.
+    fn payload_bytes(&self) -> usize {
+        8 + ELEM_BYTES
+            + 32
+            + match self.proof_update {
+                UpdateProof::Sequential { .. } => DELETION_PROOF_BYTES + MEMBERSHIP_PROOF_BYTES,
+                UpdateProof::Swap(_) => SWAP_PROOF_BYTES,
+            }
+            + self
+                .proof_fresh
+                .as_ref()
+                .map_or(0, |_| NONMEMBERSHIP_PROOF_BYTES)
+            + self
+                .transactions
+                .iter()
+                .map(|transaction| transaction.payload_bytes())
+                .sum::<usize>()
+    }
+}
+
+impl<M: Payload> Payload for Arc<M> {
+    fn payload_bytes(&self) -> usize {
+        self.as_ref().payload_bytes()
+    }
+}
+
+impl Payload for WitnessRequest {
+    fn payload_bytes(&self) -> usize {
+        self.utxos.len() * UTXO_BYTES
+    }
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash> Payload for WitnessResponse<G, T> {
+    fn payload_bytes(&self) -> usize {
+        self.utxos_with_witnesses.len() * (UTXO_BYTES + ELEM_BYTES)
+    }
+}
+
+impl<G: UnknownOrderGroup> Payload for UserUpdate<G> {
+    /// The UTXOs, and any receipt: the spent UTXOs again, an accumulator and a non-membership
+    /// proof.
+    fn payload_bytes(&self) -> usize {
+        (self.utxos_added.len() + self.utxos_deleted.len()) * UTXO_BYTES
+            + self.spent_receipt.as_ref().map_or(0, |receipt| {
+                8 + receipt.utxos.len() * UTXO_BYTES + ELEM_BYTES + NONMEMBERSHIP_PROOF_BYTES
+            })
+    }
+}
+
+impl<G: UnknownOrderGroup> Payload for WitnessUpdate<G> {
+    /// The height, the UTXOs and two accumulators.
+    fn payload_bytes(&self) -> usize {
+        8 + (self.deleted.len() + self.added.len()) * UTXO_BYTES + 2 * ELEM_BYTES
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How long a message takes to cross a link. Samples are clamped at zero.
+pub enum Latency {
+    Fixed { ms: u64 },
+    Uniform { min_ms: u64, max_ms: u64 },
+    Normal { mean_ms: f64, std_dev_ms: f64 },
+}
+
+impl Latency {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        match *self {
+            Latency::Fixed { ms } => Duration::from_millis(ms),
+            Latency::Uniform { min_ms, max_ms } => {
+                Duration::from_millis(rng.gen_range(min_ms, max_ms + 1))
+            }
+            Latency::Normal {
+                mean_ms,
+                std_dev_ms,
+            } => {
+                let ms = Normal::new(mean_ms, std_dev_ms).sample(rng).max(0.0);
+                Duration::from_micros((ms * 1000.0) as u64)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+/// How unreliable a link is: the fraction of messages it loses, and of the rest, the fraction it
+/// delivers twice.
+pub struct Faults {
+    pub loss: f64,
+    pub duplication: f64,
+}
+
+impl Faults {
+    /// The faults of a route through a link with these faults and then one with `next`'s: a
+    /// message is lost if either link loses it, and duplicated if either duplicates it.
+    pub fn then(self, next: Faults) -> Faults {
+        Faults {
+            loss: 1.0 - (1.0 - self.loss) * (1.0 - next.loss),
+            duplication: 1.0 - (1.0 - self.duplication) * (1.0 - next.duplication),
+        }
+    }
+
+    /// How many copies of a message arrive: zero, one or two.
+    pub fn copies<R: Rng>(&self, rng: &mut R) -> usize {
+        if self.loss > 0.0 && rng.gen_bool(self.loss) {
+            0
+        } else if self.duplication > 0.0 && rng.gen_bool(self.duplication) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+/// Overrides for the messages a link matches. Omitted `from`, `to` and `kind` fields match
+/// anything, so a link can cover a single pair of nodes, all traffic to one node, or one kind of
+/// message. Omitted properties fall through to later links and then to the network defaults.
+pub struct Link {
+    #[serde(default)]
+    pub from: Option<NodeId>,
+    #[serde(default)]
+    pub to: Option<NodeId>,
+    #[serde(default)]
+    pub kind: Option<MessageKind>,
+    #[serde(default)]
+    pub latency: Option<Latency>,
+    #[serde(default)]
+    pub loss: Option<f64>,
+    #[serde(default)]
+    pub duplication: Option<f64>,
+    /// How fast the link sends messages, in kilobits per second.
+    #[serde(default)]
+    pub bandwidth_kbps: Option<f64>,
+}
+
+impl Link {
+    /// Whether this link carries messages of `kind` from `from` to `to`. A broadcast, whose
+    /// recipients are not known, has no `to` and only matches links that do not name one.
+    fn matches(&self, from: NodeId, to: Option<NodeId>, kind: MessageKind) -> bool {
+        self.from.map_or(true, |node| node == from)
+            && self.to.map_or(true, |node| Some(node) == to)
+            && self.kind.map_or(true, |k| k == kind)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+/// The link between the two clusters of a two-cluster topology. Omitted properties fall back to the
+/// network defaults; links that match a message still take precedence.
+pub struct Backbone {
+    pub latency: Option<Latency>,
+    pub loss: Option<f64>,
+    pub bandwidth_kbps: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How nodes are wired together. A message between nodes that are not directly connected is
+/// relayed hop by hop, and each hop is a link with its own latency, loss and bandwidth.
+pub enum Topology {
+    /// Every node reaches every other directly.
+    Full,
+    /// Nodes other than `hub` only reach each other through it.
+    Star { hub: NodeId },
+    /// A message between two nodes on the ring goes the shorter way round, relayed by every node
+    /// in between. Nodes not on the ring reach every other node directly.
+    Ring { nodes: Vec<NodeId> },
+    /// The nodes in `cluster` form one cluster and all other nodes another, joined by a backbone.
+    TwoCluster {
+        cluster: Vec<NodeId>,
+        #[serde(default)]
+        backbone: Backbone,
+    },
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology::Full
+    }
+}
+
+impl Topology {
+    /// The links a message from `from` to `to` crosses, in order.
+    pub fn route(&self, from: NodeId, to: NodeId) -> Vec<(NodeId, NodeId)> {
+        match self {
+            Topology::Star { hub } if from != *hub && to != *hub => {
+                vec![(from, *hub), (*hub, to)]
+            }
+            Topology::Ring { nodes } => {
+                let position = |node| nodes.iter().position(|&n| n == node);
+                let (start, end) = match (position(from), position(to)) {
+                    (Some(start), Some(end)) => (start, end),
+                    _ => return vec![(from, to)],
+                };
+                let len = nodes.len();
+                let forward = (end + len - start) % len;
+                let path: Vec<NodeId> = if forward <= len - forward {
+                    (0..=forward).map(|i| nodes[(start + i) % len]).collect()
+                } else {
+                    (0..=len - forward)
+                        .map(|i| nodes[(start + len - i) % len])
+                        .collect()
+                };
+                if path.len() < 2 {
+                    return vec![(from, to)];
+                }
+                path.windows(2).map(|hop| (hop[0], hop[1])).collect()
+            }
+            _ => vec![(from, to)],
+        }
+    }
+
+    /// The backbone between `from` and `to`, if they are in different clusters.
+    fn backbone(&self, from: NodeId, to: Option<NodeId>) -> Option<&Backbone> {
+        match (self, to) {
+            (Topology::TwoCluster { cluster, backbone }, Some(to))
+                if cluster.contains(&from) != cluster.contains(&to) =>
+            {
+                Some(backbone)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+/// How messages travel between nodes. Latency, bandwidth and topology are only simulated in
+/// discrete-event mode; loss and duplication also apply in real-time mode, where each queue is a
+/// link. For example:
+///
+/// ```yaml
+/// network:
+///   latency: { fixed: { ms: 10 } }
+///   bandwidth_kbps: 10000
+///   topology:
+///     two_cluster:
+///       cluster: [{ miner: 0 }, { miner: 1 }, { bridge: 0 }]
+///       backbone: { latency: { fixed: { ms: 150 } }, bandwidth_kbps: 1000 }
+///   links:
+///     - from: { miner: 0 }
+///       to: { bridge: 1 }
+///       latency: { normal: { mean_ms: 200, std_dev_ms: 50 } }
+///     - kind: block
+///       latency: { uniform: { min_ms: 20, max_ms: 80 } }
+///       loss: 0.05
+/// ```
+pub struct NetworkConfig {
+    /// Latency of messages not matched by any link.
+    pub latency: Latency,
+    /// Fraction of messages lost, unless a link says otherwise.
+    pub loss: f64,
+    /// Fraction of delivered messages that arrive twice, unless a link says otherwise.
+    pub duplication: f64,
+    /// How fast links send messages, in kilobits per second, unless a link says otherwise. A link
+    /// sends one message at a time, so a large block holds up what is sent after it. Unlimited
+    /// if unset.
+    pub bandwidth_kbps: Option<f64>,
+    pub topology: Topology,
+    /// Overrides, of which the first matching one that sets a property applies.
+    pub links: Vec<Link>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            latency: Latency::Fixed { ms: 10 },
+            loss: 0.0,
+            duplication: 0.0,
+            bandwidth_kbps: None,
+            topology: Topology::Full,
+            links: Vec::new(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn latency(&self, from: NodeId, to: NodeId, kind: MessageKind) -> &Latency {
+        let backbone = self.topology.backbone(from, Some(to));
+        self.links
+            .iter()
+            .filter(|link| link.matches(from, Some(to), kind))
+            .find_map(|link| link.latency.as_ref())
+            .or_else(|| backbone.and_then(|backbone| backbone.latency.as_ref()))
+            .unwrap_or(&self.latency)
+    }
+
+    /// The bandwidth of the link carrying `kind` messages from `from` to `to`, if limited.
+    pub fn bandwidth_kbps(&self, from: NodeId, to: NodeId, kind: MessageKind) -> Option<f64> {
+        let backbone = self.topology.backbone(from, Some(to));
+        self.links
+            .iter()
+            .filter(|link| link.matches(from, Some(to), kind))
+            .find_map(|link| link.bandwidth_kbps)
+            .or_else(|| backbone.and_then(|backbone| backbone.bandwidth_kbps))
+            .or(self.bandwidth_kbps)
+    }
+
+    /// The faults on the link carrying `kind` messages from `from` to `to`, or to every recipient
+    /// of a broadcast if `to` is `None`.
+    pub fn faults(&self, from: NodeId, to: Option<NodeId>, kind: MessageKind) -> Faults {
+        let mut links = self
+            .links
+            .iter()
+            .filter(|link| link.matches(from, to, kind));
+        let backbone = self.topology.backbone(from, to);
+        Faults {
+            loss: links
+                .clone()
+                .find_map(|link| link.loss)
+                .or_else(|| backbone.and_then(|backbone| backbone.loss))
+                .unwrap_or(self.loss),
+            duplication: links
+                .find_map(|link| link.duplication)
+                .unwrap_or(self.duplication),
+        }
+    }
+}
+
+/// Samples delivery times for messages between simulated nodes. Each pair of nodes delivers in
+/// order, as a TCP connection would: a message never overtakes one sent before it between the same
+/// two nodes. A node's messages to itself are never lost.
+pub struct Network {
+    config: NetworkConfig,
+    /// When the latest message between each pair of nodes is due to arrive.
+    last_delivery: HashMap<(NodeId, NodeId), Duration>,
+    /// When each link with limited bandwidth is done sending the messages queued on it.
+    link_free: HashMap<(NodeId, NodeId), Duration>,
+    /// While the network is partitioned, the groups of nodes that can only reach each other.
+    partition: Option<Vec<HashSet<NodeId>>>,
+}
+
+impl Network {
+    pub fn new(config: NetworkConfig) -> Self {
+        Self {
+            config,
+            last_delivery: HashMap::new(),
+            link_free: HashMap::new(),
+            partition: None,
+        }
+    }
+
+    /// Splits the network so that messages only get through between nodes in the same group.
+    /// Nodes not listed in any group form one more group together.
+    pub fn partition(&mut self, groups: Vec<Vec<NodeId>>) {
+        self.partition = Some(
+            groups
+                .into_iter()
+                .map(|group| group.into_iter().collect())
+                .collect(),
+        );
+    }
+
+    /// Ends a partition. Messages lost while it lasted stay lost.
+    pub fn heal(&mut self) {
+        self.partition = None;
+    }
+
+    /// Whether the current partition, if any, keeps `from` and `to` apart.
+    fn are_separated(&self, from: NodeId, to: NodeId) -> bool {
+        match &self.partition {
+            Some(groups) => {
+                let group_of = |node| groups.iter().position(|group| group.contains(&node));
+                group_of(from) != group_of(to)
+            }
+            None => false,
+        }
+    }
+
+    /// The times at which copies of a message of `kind` and `size_bytes` sent from `from` to `to`
+    /// at `now` arrive. Empty if the message is lost, e.g. because a partition separates the two
+    /// nodes.
+    pub fn delivery_times<R: Rng>(
+        &mut self,
+        now: Duration,
+        from: NodeId,
+        to: NodeId,
+        kind: MessageKind,
+        size_bytes: usize,
+        rng: &mut R,
+    ) -> Vec<Duration> {
+        let hops = self.config.topology.route(from, to);
+        let copies = if from == to {
+            1
+        } else if self.are_separated(from, to) {
+            0
+        } else {
+            let mut faults = hops
+                .iter()
+                .map(|&(hop_from, hop_to)| self.config.faults(hop_from, Some(hop_to), kind));
+            let first = faults.next().expect("Routes have at least one hop");
+            faults.fold(first, Faults::then).copies(rng)
+        };
+        let mut deliveries = Vec::with_capacity(copies);
+        for _ in 0..copies {
+            let mut at = now;
+            for &(hop_from, hop_to) in &hops {
+                at = self.cross(at, hop_from, hop_to, kind, size_bytes, rng);
+            }
+            let last = self.last_delivery.entry((from, to)).or_default();
+            *last = (*last).max(at);
+            deliveries.push(*last);
+        }
+        deliveries
+    }
+
+    /// When a message that reaches `from` at `at` arrives at `to`: it waits for the link to finish
+    /// sending what was queued on it before, takes its own time to send at the link's bandwidth,
+    /// then crosses the link.
+    fn cross<R: Rng>(
+        &mut self,
+        at: Duration,
+        from: NodeId,
+        to: NodeId,
+        kind: MessageKind,
+        size_bytes: usize,
+        rng: &mut R,
+    ) -> Duration {
+        let sent = match self.config.bandwidth_kbps(from, to, kind) {
+            Some(kbps) if kbps > 0.0 => {
+                let free = self.link_free.entry((from, to)).or_default();
+                let send_time = size_bytes as f64 * 8.0 / (kbps * 1000.0);
+                *free = (*free).max(at) + Duration::from_secs_f64(send_time);
+                *free
+            }
+            _ => at,
+        };
+        sent + self.config.latency(from, to, kind).sample(rng)
+    }
+}
+
+/// The sending end of a real-time queue that loses and duplicates messages according to its link's
+/// faults. A send waits up to `wait` for room in a full queue before giving up on the message.
+pub struct LossySender<T: Clone> {
+    inner: QueueSender<T>,
+    channel: Channel,
+    faults: Faults,
+    wait: Duration,
+    metrics: Arc<Metrics>,
+    traffic: Option<Traffic>,
+}
+
+#[derive(Clone)]
+/// Who sends over a link and who reads what it carries, so its traffic can be counted.
+struct Traffic {
+    from: NodeId,
+    recipients: Vec<NodeId>,
+    kind: MessageKind,
+}
+
+impl<T: Clone + Payload> LossySender<T> {
+    /// A sender onto `inner`, a queue of `channel`, whose traffic it counts against the channel.
+    pub fn new(
+        inner: QueueSender<T>,
+        channel: Channel,
+        faults: Faults,
+        wait: Duration,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            inner,
+            channel,
+            faults,
+            wait,
+            metrics,
+            traffic: None,
+        }
+    }
+
+    /// Counts every message queued from now on as `kind` traffic sent by `from` and received by
+    /// each of `recipients`, at its estimated size.
+    pub fn metered(mut self, from: NodeId, recipients: Vec<NodeId>, kind: MessageKind) -> Self {
+        self.traffic = Some(Traffic {
+            from,
+            recipients,
+            kind,
+        });
+        self
+    }
+
+    /// Counts a copy of a `bytes`-byte message that made it into the queue.
+    fn queued(&self, bytes: usize) {
+        self.metrics.message_queued(self.channel);
+        if let Some(traffic) = &self.traffic {
+            self.metrics.bytes_sent(traffic.from, traffic.kind, bytes);
+            for &to in &traffic.recipients {
+                self.metrics.bytes_received(to, traffic.kind, bytes);
+            }
+        }
+    }
+
+    /// Queues up `message`, unless the link loses it, or hands it back if the queue stays full.
+    /// Duplicates are only queued if there is room for them.
+    pub fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        let copies = self.faults.copies(&mut rand::thread_rng());
+        if copies == 0 {
+            self.metrics.message_dropped_on(self.channel);
+            return Ok(());
+        }
+        let bytes = MESSAGE_HEADER_BYTES + message.payload_bytes();
+        for _ in 1..copies {
+            if self.inner.try_send(message.clone()).is_err() {
+                self.metrics.message_dropped_on(self.channel);
+            } else {
+                self.queued(bytes);
+            }
+        }
+        let deadline = Instant::now() + self.wait;
+        let mut message = message;
+        loop {
+            match self.inner.try_send(message) {
+                Ok(()) => {
+                    self.queued(bytes);
+                    return Ok(());
+                }
+                Err(TrySendError::Full(returned)) if Instant::now() < deadline => {
+                    message = returned;
+                    thread::sleep(QUEUE_POLL_INTERVAL);
+                }
+                Err(TrySendError::Full(returned)) => {
+                    self.metrics.send_refused(self.channel);
+                    return Err(SendError::Full(returned));
+                }
+                Err(TrySendError::Disconnected(returned)) => {
+                    return Err(SendError::Closed(returned))
+                }
+            }
+        }
+    }
+
+    /// Queues up `message`, dropping it if the queue stays full.
+    pub fn send(&self, message: T) {
+        if let Err(err) = self.try_send(message) {
+            warn!("Dropping a message on {}: {}.", self.channel.name(), err);
+            self.metrics.message_dropped_on(self.channel);
+        }
+    }
+}
+
+impl<T: Clone> Clone for LossySender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            channel: self.channel,
+            faults: self.faults,
+            wait: self.wait,
+            metrics: self.metrics.clone(),
+            traffic: self.traffic.clone(),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::io::{self, Read, Write};
+
+/// The Noise protocol connections speak: the XX handshake, so neither side needs the other's
+/// static key in advance, with the pre-shared key mixed in at the end.
+#[cfg(feature = "encryption")]
+const NOISE_PARAMS: &str = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s";
+/// The longest Noise message, and the longest plaintext that fits in one.
+#[cfg(feature = "encryption")]
+const MAX_MESSAGE_BYTES: usize = 65535;
+#[cfg(feature = "encryption")]
+const MAX_PLAINTEXT_BYTES: usize = MAX_MESSAGE_BYTES - 16;
+
+#[derive(Clone)]
+/// One side's key material: its static private key, and the key every side of the deployment
+/// shares.
+pub struct NoiseKeys {
+    pub private_key: [u8; 32],
+    pub psk: [u8; 32],
+}
+
+/// An encrypted channel over `stream`, for the TCP transport. Both sides prove they hold the
+/// deployment's pre-shared key, which keeps out processes that do not; see
+/// `auth::load_noise_keys`. Writes are sent as Noise messages, each prefixed with its length as a
+/// big-endian `u16`, and reads decrypt them in turn.
+#[cfg(feature = "encryption")]
+pub struct NoiseStream<S> {
+    stream: S,
+    transport: snow::TransportState,
+    /// Plaintext decrypted but not read yet.
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+#[cfg(feature = "encryption")]
+fn noise_error(err: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(feature = "encryption")]
+fn write_message<W: Write>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+    writer.write_all(&(message.len() as u16).to_be_bytes())?;
+    writer.write_all(message)
+}
+
+#[cfg(feature = "encryption")]
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0; 2];
+    reader.read_exact(&mut len)?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+#[cfg(feature = "encryption")]
+impl<S: Read + Write> NoiseStream<S> {
+    fn builder<'a>(keys: &'a NoiseKeys) -> snow::Builder<'a> {
+        snow::Builder::new(NOISE_PARAMS.parse().expect("Noise parameters are valid"))
+            .local_private_key(&keys.private_key)
+            .psk(3, &keys.psk)
+    }
+
+    /// Opens an encrypted channel from the connecting side.
+    pub fn initiate(mut stream: S, keys: &NoiseKeys) -> io::Result<Self> {
+        let mut handshake = Self::builder(keys).build_initiator().map_err(noise_error)?;
+        let mut buf = vec![0; MAX_MESSAGE_BYTES];
+        // -> e
+        let len = handshake
+            .write_message(&[], &mut buf)
+            .map_err(noise_error)?;
+        write_message(&mut stream, &buf[..len])?;
+        // <- e, ee, s, es
+        let message = read_message(&mut stream)?;
+        handshake
+            .read_message(&message, &mut buf)
+            .map_err(noise_error)?;
+        // -> s, se, psk
+        let len = handshake
+            .write_message(&[], &mut buf)
+            .map_err(noise_error)?;
+        write_message(&mut stream, &buf[..len])?;
+        Self::established(stream, handshake)
+    }
+
+    /// Accepts an encrypted channel on the listening side. A peer without the pre-shared key fails
+    /// the handshake.
+    pub fn respond(mut stream: S, keys: &NoiseKeys) -> io::Result<Self> {
+        let mut handshake = Self::builder(keys).build_responder().map_err(noise_error)?;
+        let mut buf = vec![0; MAX_MESSAGE_BYTES];
+        // -> e
+        let message = read_message(&mut stream)?;
+        handshake
+            .read_message(&message, &mut buf)
+            .map_err(noise_error)?;
+        // <- e, ee, s, es
+        let len = handshake
+            .write_message(&[], &mut buf)
+            .map_err(noise_error)?;
+        write_message(&mut stream, &buf[..len])?;
+        // -> s, se, psk
+        let message = read_message(&mut stream)?;
+        handshake
+            .read_message(&message, &mut buf)
+            .map_err(noise_error)?;
+        Self::established(stream, handshake)
+    }
+
+    fn established(stream: S, handshake: snow::HandshakeState) -> io::Result<Self> {
+        Ok(Self {
+            stream,
+            transport: handshake.into_transport_mode().map_err(noise_error)?,
+            pending: Vec::new(),
+            pending_pos: 0,
+        })
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<S: Read> Read for NoiseStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending_pos == self.pending.len() {
+            let message = read_message(&mut self.stream)?;
+            let mut plaintext = vec![0; message.len()];
+            let len = self
+                .transport
+                .read_message(&message, &mut plaintext)
+                .map_err(noise_error)?;
+            plaintext.truncate(len);
+            self.pending = plaintext;
+            self.pending_pos = 0;
+        }
+        let len = buf.len().min(self.pending.len() - self.pending_pos);
+        buf[..len].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + len]);
+        self.pending_pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<S: Write> Write for NoiseStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_PLAINTEXT_BYTES);
+        let mut message = vec![0; MAX_MESSAGE_BYTES];
+        let message_len = self
+            .transport
+            .write_message(&buf[..len], &mut message)
+            .map_err(noise_error)?;
+        write_message(&mut self.stream, &message[..message_len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// A stream that is either sent as is or encrypted.
+pub trait Stream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> Stream for S {}
+
+/// `stream` encrypted with `keys` from the connecting side, or as is without keys.
+pub fn initiate<S: Stream + 'static>(
+    stream: S,
+    keys: Option<&NoiseKeys>,
+) -> io::Result<Box<dyn Stream>> {
+    match keys {
+        #[cfg(feature = "encryption")]
+        Some(keys) => Ok(Box::new(NoiseStream::initiate(stream, keys)?)),
+        #[cfg(not(feature = "encryption"))]
+        Some(_) => Err(unsupported()),
+        None => Ok(Box::new(stream)),
+    }
+}
+
+/// `stream` encrypted with `keys` from the listening side, or as is without keys.
+pub fn respond<S: Stream + 'static>(
+    stream: S,
+    keys: Option<&NoiseKeys>,
+) -> io::Result<Box<dyn Stream>> {
+    match keys {
+        #[cfg(feature = "encryption")]
+        Some(keys) => Ok(Box::new(NoiseStream::respond(stream, keys)?)),
+        #[cfg(not(feature = "encryption"))]
+        Some(_) => Err(unsupported()),
+        None => Ok(Box::new(stream)),
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "Encrypted connections need the encryption feature",
+    )
+}
@@ -0,0 +1,466 @@
+use super::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::metrics::Metrics;
+use super::state::{Block, Transaction, Utxo};
+use super::transport::{BlockTopic, Receiver, SendError, Sender, TxTopic, WitnessChannel};
+use super::wire::{self, WireMessage};
+use accumulator::group::UnknownOrderGroup;
+use libp2p::futures::StreamExt;
+use libp2p::gossipsub::{self, IdentTopic, TopicHash};
+use libp2p::mdns;
+use libp2p::request_response::{self, ProtocolSupport, ResponseChannel};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+use libp2p::{noise, tcp, yamux, Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// The gossipsub topic miners publish blocks on.
+pub const BLOCK_TOPIC: &str = "accumulator-demo/blocks";
+/// The gossipsub topic users publish transactions on.
+pub const TX_TOPIC: &str = "accumulator-demo/transactions";
+/// The request/response protocol users ask their bridge for witnesses over.
+const WITNESS_PROTOCOL: &str = "/accumulator-demo/witness/1";
+/// Blocks carry every transaction's witnesses, so they outgrow gossipsub's default 64 KiB limit.
+const MAX_MESSAGE_BYTES: usize = 16 << 20;
+
+/// The gossipsub topic a bridge publishes `user_id`'s UTXO updates on.
+pub fn update_topic(user_id: usize) -> String {
+    format!("accumulator-demo/updates/{}", user_id)
+}
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    gossipsub: gossipsub::Behaviour,
+    witness: request_response::cbor::Behaviour<Vec<u8>, Vec<u8>>,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// Where a node hands on the messages it receives.
+enum Inbox {
+    Topic(TopicHash),
+    /// Witness requests from users, at a bridge.
+    Requests,
+    /// Witness responses from the bridge, at a user.
+    Responses,
+}
+
+/// What the swarm thread is asked to do.
+enum Command {
+    Register(Inbox, mpsc::Sender<Vec<u8>>),
+    Publish(IdentTopic, Vec<u8>),
+    Request(PeerId, Vec<u8>),
+    Respond(Uuid, Vec<u8>),
+}
+
+/// How a sender's messages get where they are going.
+enum Route<T> {
+    Topic(IdentTopic),
+    Request(PeerId),
+    /// Answers the request with the ID this function picks out of the message.
+    Respond(fn(&T) -> Uuid),
+}
+
+/// A libp2p node: blocks, transactions and UTXO updates are gossiped on pubsub topics, and users
+/// ask their bridge for witnesses over a request/response protocol, with Noise-encrypted, yamux
+/// multiplexed TCP connections underneath. The swarm runs on a thread of its own; the senders and
+/// receivers handed out here talk to it over channels.
+pub struct P2pNode {
+    peer_id: PeerId,
+    commands: UnboundedSender<Command>,
+    metrics: Arc<Metrics>,
+}
+
+impl P2pNode {
+    /// Starts a node listening on `listen`, e.g. `/ip4/0.0.0.0/tcp/4001`, and dials `peers`. With
+    /// `mdns`, it also finds and dials peers on the local network as they come up.
+    pub fn start(
+        listen: Multiaddr,
+        peers: Vec<Multiaddr>,
+        mdns: bool,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Self> {
+        let (commands, command_receiver) = unbounded_channel();
+        let (started_sender, started) = mpsc::channel();
+        let swarm_metrics = metrics.clone();
+        thread::spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(err) => {
+                    let _ = started_sender.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            runtime.block_on(async move {
+                let mut swarm = match build_swarm(listen, &peers, mdns) {
+                    Ok(swarm) => swarm,
+                    Err(err) => {
+                        let _ = started_sender.send(Err(err.to_string()));
+                        return;
+                    }
+                };
+                let _ = started_sender.send(Ok(*swarm.local_peer_id()));
+                run_swarm(&mut swarm, command_receiver, &swarm_metrics).await;
+            });
+        });
+        let peer_id = started
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "The swarm thread died"))?
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        info!("Started libp2p node {}.", peer_id);
+        Ok(Self {
+            peer_id,
+            commands,
+            metrics,
+        })
+    }
+
+    /// The ID other nodes address this one by, e.g. users their bridge.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Publishes messages on `topic`.
+    pub fn publisher<T>(&self, topic: &str) -> P2pSender<T> {
+        self.sender(Route::Topic(IdentTopic::new(topic)))
+    }
+
+    /// The messages published on `topic`, including this node's own.
+    pub fn subscribe<T>(&self, topic: &str) -> P2pReceiver<T> {
+        self.receiver(Inbox::Topic(IdentTopic::new(topic).hash()))
+    }
+
+    /// Sends witness requests to `peer`, whose responses arrive on `witness_responses`.
+    pub fn witness_requester(&self, peer: PeerId) -> P2pSender<WitnessRequest> {
+        self.sender(Route::Request(peer))
+    }
+
+    pub fn witness_requests(&self) -> P2pReceiver<WitnessRequest> {
+        self.receiver(Inbox::Requests)
+    }
+
+    /// Answers the witness requests received on `witness_requests`.
+    pub fn witness_responder<G: UnknownOrderGroup>(&self) -> P2pSender<WitnessResponse<G, Utxo>> {
+        self.sender(Route::Respond(|response: &WitnessResponse<G, Utxo>| {
+            response.request_id
+        }))
+    }
+
+    pub fn witness_responses<G: UnknownOrderGroup>(&self) -> P2pReceiver<WitnessResponse<G, Utxo>> {
+        self.receiver(Inbox::Responses)
+    }
+
+    fn sender<T>(&self, route: Route<T>) -> P2pSender<T> {
+        P2pSender {
+            commands: self.commands.clone(),
+            route,
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    fn receiver<T>(&self, inbox: Inbox) -> P2pReceiver<T> {
+        let (sender, messages) = mpsc::channel();
+        let _ = self.commands.send(Command::Register(inbox, sender));
+        P2pReceiver {
+            messages,
+            message: PhantomData,
+        }
+    }
+}
+
+fn build_swarm(
+    listen: Multiaddr,
+    peers: &[Multiaddr],
+    mdns: bool,
+) -> Result<Swarm<Behaviour>, Box<dyn Error + Send + Sync>> {
+    let mut swarm = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_behaviour(|key| {
+            let config = gossipsub::ConfigBuilder::default()
+                .max_transmit_size(MAX_MESSAGE_BYTES)
+                .build()
+                .map_err(|err| err.to_string())?;
+            let gossipsub = gossipsub::Behaviour::new(
+                gossipsub::MessageAuthenticity::Signed(key.clone()),
+                config,
+            )?;
+            let witness = request_response::cbor::Behaviour::new(
+                [(StreamProtocol::new(WITNESS_PROTOCOL), ProtocolSupport::Full)],
+                request_response::Config::default(),
+            );
+            let mdns = if mdns {
+                let peer_id = key.public().to_peer_id();
+                Some(mdns::tokio::Behaviour::new(
+                    mdns::Config::default(),
+                    peer_id,
+                )?)
+            } else {
+                None
+            };
+            Ok(Behaviour {
+                gossipsub,
+                witness,
+                mdns: Toggle::from(mdns),
+            })
+        })?
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+    swarm.listen_on(listen)?;
+    for peer in peers {
+        if let Err(err) = swarm.dial(peer.clone()) {
+            warn!("Failed to dial {}: {}", peer, err);
+        }
+    }
+    Ok(swarm)
+}
+
+/// Drives `swarm` until every handle on the node has been dropped.
+async fn run_swarm(
+    swarm: &mut Swarm<Behaviour>,
+    mut commands: UnboundedReceiver<Command>,
+    metrics: &Metrics,
+) {
+    let mut inboxes: HashMap<Inbox, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut pending: HashMap<Uuid, ResponseChannel<Vec<u8>>> = HashMap::new();
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => {
+                    handle_command(swarm, command, &mut inboxes, &mut pending, metrics)
+                }
+                None => break,
+            },
+            event = swarm.select_next_some() => {
+                handle_event(swarm, event, &inboxes, &mut pending, metrics);
+            }
+        }
+    }
+}
+
+fn deliver(inboxes: &HashMap<Inbox, mpsc::Sender<Vec<u8>>>, inbox: &Inbox, data: Vec<u8>) {
+    if let Some(sender) = inboxes.get(inbox) {
+        let _ = sender.send(data);
+    }
+}
+
+fn handle_command(
+    swarm: &mut Swarm<Behaviour>,
+    command: Command,
+    inboxes: &mut HashMap<Inbox, mpsc::Sender<Vec<u8>>>,
+    pending: &mut HashMap<Uuid, ResponseChannel<Vec<u8>>>,
+    metrics: &Metrics,
+) {
+    let behaviour = swarm.behaviour_mut();
+    match command {
+        Command::Register(inbox, sender) => {
+            if let Inbox::Topic(topic) = &inbox {
+                let topic = IdentTopic::new(topic.as_str());
+                if let Err(err) = behaviour.gossipsub.subscribe(&topic) {
+                    warn!("Failed to subscribe to {}: {:?}", topic, err);
+                }
+            }
+            inboxes.insert(inbox, sender);
+        }
+        Command::Publish(topic, data) => {
+            // Gossipsub does not deliver a node's own messages back to it, but the in-process
+            // queues do, and a miner applies its own blocks as it receives them.
+            deliver(inboxes, &Inbox::Topic(topic.hash()), data.clone());
+            if let Err(err) = behaviour.gossipsub.publish(topic, data) {
+                debug!("Failed to publish a message: {:?}", err);
+                metrics.message_dropped();
+            }
+        }
+        Command::Request(peer, data) => {
+            behaviour.witness.send_request(&peer, data);
+        }
+        Command::Respond(request_id, data) => match pending.remove(&request_id) {
+            Some(channel) => {
+                if behaviour.witness.send_response(channel, data).is_err() {
+                    metrics.message_dropped();
+                }
+            }
+            None => {
+                debug!("No request {} to respond to.", request_id);
+                metrics.message_dropped();
+            }
+        },
+    }
+}
+
+fn handle_event(
+    swarm: &mut Swarm<Behaviour>,
+    event: SwarmEvent<BehaviourEvent>,
+    inboxes: &HashMap<Inbox, mpsc::Sender<Vec<u8>>>,
+    pending: &mut HashMap<Uuid, ResponseChannel<Vec<u8>>>,
+    metrics: &Metrics,
+) {
+    match event {
+        SwarmEvent::NewListenAddr { address, .. } => info!("Listening on {}.", address),
+        SwarmEvent::ConnectionEstablished { peer_id, .. } => debug!("Connected to {}.", peer_id),
+        SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message,
+            ..
+        })) => deliver(inboxes, &Inbox::Topic(message.topic), message.data),
+        SwarmEvent::Behaviour(BehaviourEvent::Witness(request_response::Event::Message {
+            message,
+            ..
+        })) => match message {
+            request_response::Message::Request {
+                request, channel, ..
+            } => match wire::decode::<WitnessRequest>(&request) {
+                Ok(decoded) => {
+                    pending.insert(decoded.request_id, channel);
+                    deliver(inboxes, &Inbox::Requests, request);
+                }
+                Err(err) => warn!("Dropping an undecodable witness request: {}", err),
+            },
+            request_response::Message::Response { response, .. } => {
+                deliver(inboxes, &Inbox::Responses, response)
+            }
+        },
+        SwarmEvent::Behaviour(BehaviourEvent::Witness(
+            request_response::Event::OutboundFailure { peer, error, .. },
+        )) => {
+            debug!("A witness request to {} failed: {}", peer, error);
+            metrics.message_dropped();
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+            for (peer, addr) in peers {
+                debug!("Discovered {} at {}.", peer, addr);
+                swarm.add_peer_address(peer, addr.clone());
+                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+                if let Err(err) = swarm.dial(addr.clone()) {
+                    debug!("Failed to dial {}: {}", addr, err);
+                }
+            }
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+            for (peer, _) in peers {
+                swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Sends messages through a `P2pNode`, in the wire format.
+pub struct P2pSender<T> {
+    commands: UnboundedSender<Command>,
+    route: Route<T>,
+    metrics: Arc<Metrics>,
+}
+
+impl<T: WireMessage> P2pSender<T> {
+    pub fn send(&self, message: T) {
+        let data = match wire::encode(&message) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("Failed to encode a message: {}", err);
+                self.metrics.message_dropped();
+                return;
+            }
+        };
+        let command = match &self.route {
+            Route::Topic(topic) => Command::Publish(topic.clone(), data),
+            Route::Request(peer) => Command::Request(*peer, data),
+            Route::Respond(request_id) => Command::Respond(request_id(&message), data),
+        };
+        if self.commands.send(command).is_err() {
+            self.metrics.message_dropped();
+        }
+    }
+}
+
+/// Receives messages through a `P2pNode`. Messages that fail to decode are skipped.
+pub struct P2pReceiver<T> {
+    messages: mpsc::Receiver<Vec<u8>>,
+    message: PhantomData<fn() -> T>,
+}
+
+impl<T: WireMessage + 'static> Sender<T> for P2pSender<T> {
+    /// Messages queue up in the swarm's unbounded command channel, and libp2p's own flow control
+    /// takes it from there, so the sender never gets one back.
+    fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        P2pSender::send(self, message);
+        Ok(())
+    }
+
+    fn send(&self, message: T) {
+        P2pSender::send(self, message);
+    }
+}
+
+impl<T: WireMessage + 'static> Receiver<T> for P2pReceiver<T> {
+    fn try_receive(&self) -> Option<T> {
+        while let Ok(data) = self.messages.try_recv() {
+            match wire::decode(&data) {
+                Ok(message) => return Some(message),
+                Err(err) => warn!("Dropping an undecodable message: {}", err),
+            }
+        }
+        None
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let data = self.messages.recv_timeout(remaining).ok()?;
+            match wire::decode(&data) {
+                Ok(message) => return Some(message),
+                Err(err) => warn!("Dropping an undecodable message: {}", err),
+            }
+        }
+    }
+}
+
+/// Gossipsub for blocks, transactions and UTXO updates, and request/response for witnesses,
+/// through `P2pNode`s.
+pub struct P2p;
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> BlockTopic<G, T> for P2p
+where
+    Block<G, T>: WireMessage + 'static,
+{
+    type BlockSender = P2pSender<Arc<Block<G, T>>>;
+    type BlockReceiver = P2pReceiver<Arc<Block<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> TxTopic<G, T> for P2p
+where
+    Transaction<G, T>: WireMessage + 'static,
+{
+    type TxSender = P2pSender<Arc<Transaction<G, T>>>;
+    type TxReceiver = P2pReceiver<Arc<Transaction<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup> WitnessChannel<G> for P2p
+where
+    WitnessResponse<G, Utxo>: WireMessage + 'static,
+    UserUpdate<G>: WireMessage + 'static,
+{
+    type RequestSender = P2pSender<WitnessRequest>;
+    type RequestReceiver = P2pReceiver<WitnessRequest>;
+    type ResponseSender = P2pSender<WitnessResponse<G, Utxo>>;
+    type ResponseReceiver = P2pReceiver<WitnessResponse<G, Utxo>>;
+    type UpdateSender = P2pSender<UserUpdate<G>>;
+    type UpdateReceiver = P2pReceiver<UserUpdate<G>>;
+}
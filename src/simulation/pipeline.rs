@@ -0,0 +1,291 @@
+use super::logging;
+use super::miner::Miner;
+use super::state::{self, Block, NodeId, Valued};
+use super::validation::{self, BlockError};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How many blocks wait between one stage and the next. Blocks arrive about a block interval
+/// apart, so a stage seldom has more than one to catch up on.
+const STAGE_DEPTH: usize = 4;
+
+/// A block on its way down the pipeline, with what the stages before found.
+struct Stage<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    block: Arc<Block<G, T>>,
+    /// The accumulator the block is checked against: the miner's, or that of the block before it
+    /// if that one is still in the pipeline.
+    acc: Accumulator<G, T>,
+    elems_added: Vec<T>,
+    elems_deleted: Vec<T>,
+    /// The result of checking the block's proofs, once known: from the proof cache, or from the
+    /// last proof stage.
+    result: Option<Result<(), BlockError>>,
+    /// How long checking the block's proofs has taken so far.
+    proof_time: Duration,
+    /// When the block entered the pipeline.
+    start: Instant,
+}
+
+/// Blocks past the first stage and not yet applied or turned down, in the order they entered.
+type InFlight<G, T> = Arc<Mutex<Vec<Arc<Block<G, T>>>>>;
+
+/// Validates the blocks a miner receives in four stages, each on a thread of its own: the cheap
+/// checks of `validation::check_structure`, the deletion proof, the addition and freshness proofs,
+/// and applying the block. So while one block's proofs are checked, the next one's structure and
+/// deletions are already being checked, and validating a run of blocks takes about as long as
+/// its costliest stage rather than every stage, block after block.
+///
+/// A block is checked against the block it extends while that one is still in the pipeline, not
+/// yet applied, telling it apart from competing blocks at the same height by the accumulator the
+/// block claims to extend. If that one is then turned down, the block is found stale when it
+/// comes to be applied, and is not. A block extending one this miner does not hold, e.g. one that
+/// lost to a competing block, is stale too rather than invalid. Locks are taken in-flight list
+/// first, then the miner's chain.
+pub struct ValidationPipeline<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    sender: SyncSender<Arc<Block<G, T>>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<
+        G: UnknownOrderGroup,
+        T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+    > ValidationPipeline<G, T>
+{
+    /// Starts the stages' threads, which validate blocks for `miner` until the pipeline is
+    /// finished.
+    pub fn start(miner: Arc<Miner<G, T>>) -> Self {
+        let node = NodeId::Miner(miner.id());
+        let in_flight: InFlight<G, T> = Arc::new(Mutex::new(Vec::new()));
+        let (sender, blocks) = mpsc::sync_channel(STAGE_DEPTH);
+        let (structure_sender, structured) = mpsc::sync_channel(STAGE_DEPTH);
+        let (deletions_sender, deletions_checked) = mpsc::sync_channel(STAGE_DEPTH);
+        let (additions_sender, additions_checked) = mpsc::sync_channel(STAGE_DEPTH);
+
+        let threads = vec![
+            spawn_stage(node, {
+                let (miner, in_flight) = (miner.clone(), in_flight.clone());
+                move || check_structure(&miner, &in_flight, blocks, structure_sender)
+            }),
+            spawn_stage(node, {
+                let (miner, in_flight) = (miner.clone(), in_flight.clone());
+                move || verify_deletions(&miner, &in_flight, structured, deletions_sender)
+            }),
+            spawn_stage(node, {
+                let (miner, in_flight) = (miner.clone(), in_flight.clone());
+                move || verify_additions(&miner, &in_flight, deletions_checked, additions_sender)
+            }),
+            spawn_stage(node, move || apply(&miner, &in_flight, additions_checked)),
+        ];
+        Self { sender, threads }
+    }
+
+    /// Hands `block` to the first stage, waiting while the pipeline is full.
+    pub fn submit(&self, block: Arc<Block<G, T>>) {
+        let _ = self.sender.send(block);
+    }
+
+    /// Validates the blocks already submitted, then stops the stages' threads.
+    pub fn finish(self) {
+        drop(self.sender);
+        for thread in self.threads {
+            thread.join().unwrap();
+        }
+    }
+}
+
+fn spawn_stage<F: FnOnce() + Send + 'static>(node: NodeId, stage: F) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let _span = logging::node_span(node).entered();
+        stage();
+    })
+}
+
+/// The first stage: picks what each block is checked against, the miner's chain or the block it
+/// extends in the pipeline, and checks the block's structure.
+fn check_structure<G, T>(
+    miner: &Miner<G, T>,
+    in_flight: &InFlight<G, T>,
+    blocks: Receiver<Arc<Block<G, T>>>,
+    next: SyncSender<Stage<G, T>>,
+) where
+    G: UnknownOrderGroup,
+    T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+{
+    for block in blocks {
+        let start = Instant::now();
+        let mut in_flight_blocks = in_flight.lock().unwrap();
+        let (mut height, mut acc, require_fresh) = miner.chain_tip();
+        let parent = in_flight_blocks.iter().find(|parent| {
+            parent.height + 1 == block.height && parent.acc_commitment == block.prev_acc_commitment
+        });
+        if let Some(parent) = parent {
+            height = parent.height;
+            acc = parent.acc_new.clone();
+        }
+        let extends_other = if block.height == height + 1 {
+            block.prev_acc_commitment != state::acc_commitment(&acc)
+        } else {
+            parent.is_none()
+                && in_flight_blocks
+                    .iter()
+                    .any(|other| other.height + 1 == block.height)
+        };
+        let structure = if extends_other {
+            Err(BlockError::Stale)
+        } else {
+            validation::check_structure(height, &block, require_fresh, miner.mints())
+        };
+        match structure {
+            Ok((elems_added, elems_deleted)) => {
+                in_flight_blocks.push(block.clone());
+                drop(in_flight_blocks);
+                let stage = Stage {
+                    block,
+                    acc,
+                    elems_added,
+                    elems_deleted,
+                    result: None,
+                    proof_time: Duration::default(),
+                    start,
+                };
+                if next.send(stage).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                drop(in_flight_blocks);
+                miner.reject_block(&block, err, start);
+            }
+        }
+    }
+}
+
+/// The second stage: takes each block's result from the proof cache if another component has
+/// checked it, and otherwise checks its deletion proof, or its swap proof.
+fn verify_deletions<G, T>(
+    miner: &Miner<G, T>,
+    in_flight: &InFlight<G, T>,
+    stages: Receiver<Stage<G, T>>,
+    next: SyncSender<Stage<G, T>>,
+) where
+    G: UnknownOrderGroup,
+    T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+{
+    for mut stage in stages {
+        stage.result = miner
+            .proof_cache()
+            .and_then(|cache| cache.lookup(&stage.acc, &stage.block, miner.metrics()));
+        if stage.result.is_none() {
+            let start = Instant::now();
+            let result = validation::verify_deletions(
+                &stage.acc,
+                &stage.block,
+                &stage.elems_added,
+                &stage.elems_deleted,
+                miner.metrics(),
+            );
+            stage.proof_time += start.elapsed();
+            if result.is_err() {
+                stage.result = Some(result);
+                cache_result(miner, &stage);
+            }
+        }
+        match stage.result {
+            Some(Err(err)) => turn_down(miner, in_flight, &stage, err),
+            _ => {
+                if next.send(stage).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The third stage: checks each block's membership proof for the UTXOs it creates and any proof
+/// that they are fresh, unless the block's result is known already.
+fn verify_additions<G, T>(
+    miner: &Miner<G, T>,
+    in_flight: &InFlight<G, T>,
+    stages: Receiver<Stage<G, T>>,
+    next: SyncSender<Stage<G, T>>,
+) where
+    G: UnknownOrderGroup,
+    T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+{
+    for mut stage in stages {
+        if stage.result.is_none() {
+            let start = Instant::now();
+            let result = validation::verify_additions(
+                &stage.acc,
+                &stage.block,
+                &stage.elems_added,
+                miner.metrics(),
+            );
+            stage.proof_time += start.elapsed();
+            stage.result = Some(result);
+            cache_result(miner, &stage);
+        }
+        match stage.result {
+            Some(Err(err)) => turn_down(miner, in_flight, &stage, err),
+            _ => {
+                if next.send(stage).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The last stage: applies each valid block, unless the block it was checked after was turned
+/// down.
+fn apply<G, T>(miner: &Miner<G, T>, in_flight: &InFlight<G, T>, stages: Receiver<Stage<G, T>>)
+where
+    G: UnknownOrderGroup,
+    T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+{
+    for stage in stages {
+        let mut in_flight_blocks = in_flight.lock().unwrap();
+        miner.apply_block(stage.block.clone(), &stage.acc, stage.start);
+        in_flight_blocks.retain(|block| !Arc::ptr_eq(block, &stage.block));
+    }
+}
+
+/// Adds the result of checking `stage`'s proofs to the miner's proof cache, if it shares one.
+fn cache_result<G, T>(miner: &Miner<G, T>, stage: &Stage<G, T>)
+where
+    G: UnknownOrderGroup,
+    T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+{
+    if let (Some(cache), Some(result)) = (miner.proof_cache(), stage.result) {
+        cache.insert(
+            &stage.acc,
+            &stage.block,
+            miner.metrics(),
+            result,
+            stage.proof_time,
+        );
+    }
+}
+
+/// Takes `stage`'s block, found invalid for `err`, out of the pipeline.
+fn turn_down<G, T>(
+    miner: &Miner<G, T>,
+    in_flight: &InFlight<G, T>,
+    stage: &Stage<G, T>,
+    err: BlockError,
+) where
+    G: UnknownOrderGroup,
+    T: 'static + Clone + Eq + Hash + Debug + PartialEq + Send + Sync + Valued,
+{
+    in_flight
+        .lock()
+        .unwrap()
+        .retain(|block| !Arc::ptr_eq(block, &stage.block));
+    miner.reject_block(&stage.block, err, stage.start);
+}
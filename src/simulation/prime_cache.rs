@@ -0,0 +1,109 @@
+use super::metrics::PrimeCacheStats;
+use super::util;
+use accumulator::hash;
+use rug::Integer;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// How many primes the cache keeps, around 40 MB of them. Each UTXO's prime is looked up while the
+/// block spending it is proved and checked, so only recent ones are ever hit.
+const CAPACITY: usize = 1 << 18;
+
+/// The cache shared by every run in the process, once enabled.
+static CACHE: OnceLock<PrimeCache> = OnceLock::new();
+
+/// The primes representing elements, keyed by SHA-256 of the element, shared by every component
+/// of a process. Finding an element's prime takes a search with a primality test per candidate,
+/// and every node checking a block's deletion proof needs the prime of each UTXO it spends, so
+/// with the cache only the first of them searches. Only deletion and swap proofs use it: the
+/// accumulator crate's own additions, deletions, witnesses and membership checks still hash
+/// elements themselves, so the hit rate says nothing of the primes they derive.
+struct PrimeCache {
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct CacheState {
+    primes: HashMap<[u8; 32], Integer>,
+    /// Keys in the order they were added, oldest first, to evict by.
+    order: VecDeque<[u8; 32]>,
+}
+
+impl PrimeCache {
+    fn prime<T: Hash + ?Sized>(&self, elem: &T) -> Integer {
+        let key = util::sha256(elem);
+        if let Some(prime) = self.state.lock().unwrap().primes.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return prime.clone();
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let prime = hash::hash_to_prime(elem);
+
+        let mut state = self.state.lock().unwrap();
+        if state.primes.contains_key(&key) {
+            return prime;
+        }
+        if state.order.len() >= CAPACITY {
+            if let Some(oldest) = state.order.pop_front() {
+                state.primes.remove(&oldest);
+            }
+        }
+        state.order.push_back(key);
+        state.primes.insert(key, prime.clone());
+        prime
+    }
+
+    fn multiply<T: Hash + ?Sized>(&self, product: &mut Integer, elem: &T) {
+        let key = util::sha256(elem);
+        if let Some(prime) = self.state.lock().unwrap().primes.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            *product *= prime;
+            return;
+        }
+        *product *= self.prime(elem);
+    }
+}
+
+/// Has elements' primes cached for the rest of the process. Later calls do nothing.
+pub fn enable() {
+    CACHE.get_or_init(|| PrimeCache {
+        state: Mutex::new(CacheState {
+            primes: HashMap::new(),
+            order: VecDeque::new(),
+        }),
+        hits: AtomicU64::new(0),
+        misses: AtomicU64::new(0),
+    });
+}
+
+/// The prime representing `elem`, as the accumulator crate derives it: from the cache if it is
+/// enabled and holds it, and otherwise derived afresh.
+pub fn hash_to_prime<T: Hash + ?Sized>(elem: &T) -> Integer {
+    match CACHE.get() {
+        Some(cache) => cache.prime(elem),
+        None => hash::hash_to_prime(elem),
+    }
+}
+
+/// Multiplies `product` by the prime representing `elem`, as `hash_to_prime` derives it, without
+/// copying a cached prime.
+pub fn multiply_by_prime<T: Hash + ?Sized>(product: &mut Integer, elem: &T) {
+    match CACHE.get() {
+        Some(cache) => cache.multiply(product, elem),
+        None => *product *= hash::hash_to_prime(elem),
+    }
+}
+
+/// Lookups answered from the cache and not, over the whole process so far.
+pub fn stats() -> PrimeCacheStats {
+    match CACHE.get() {
+        Some(cache) => PrimeCacheStats {
+            hits: cache.hits.load(Ordering::Relaxed),
+            misses: cache.misses.load(Ordering::Relaxed),
+        },
+        None => PrimeCacheStats::default(),
+    }
+}
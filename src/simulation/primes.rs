@@ -0,0 +1,119 @@
+use super::config::{HashToPrime, PrimeStrategy};
+use super::metrics::Metrics;
+use super::util;
+use accumulator::hash::blake2b;
+use rug::integer::{IsPrime, Order};
+use rug::Integer;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Miller-Rabin rounds per primality test, as many as the accumulator crate runs.
+const PRIMALITY_REPS: u32 = 25;
+
+/// Maps elements to primes by a configurable strategy, and records in the metrics what that costs:
+/// the candidates tested for primality, the time taken, and any two elements that share a prime.
+///
+/// The accumulator crate always represents elements by its own hash to prime, so this runs
+/// alongside it, over every element the chain creates, for strategies to be compared on the same
+/// workload.
+pub struct PrimeHasher {
+    config: HashToPrime,
+    /// Each prime handed out so far, and a fingerprint of the element it represents.
+    seen: Mutex<HashMap<Integer, u64>>,
+    metrics: Arc<Metrics>,
+}
+
+impl PrimeHasher {
+    pub fn new(config: HashToPrime, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            seen: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    /// The prime representing `elem`.
+    pub fn represent<T: Hash>(&self, elem: &T) -> Integer {
+        let start = Instant::now();
+        let mut tests = 0;
+        let prime = match self.config.strategy {
+            PrimeStrategy::Blake2bRehash => {
+                let mut counter = 0u64;
+                loop {
+                    let candidate = self.candidate(blake2b(&(elem, counter)));
+                    tests += 1;
+                    if is_prime(&candidate) {
+                        break candidate;
+                    }
+                    counter += 1;
+                }
+            }
+            PrimeStrategy::Sha256Rehash => {
+                let digest = sha256(elem);
+                let mut counter = 0u64;
+                loop {
+                    let candidate = self.candidate(sha256(&(&digest, counter)));
+                    tests += 1;
+                    if is_prime(&candidate) {
+                        break candidate;
+                    }
+                    counter += 1;
+                }
+            }
+            PrimeStrategy::Sha256NextPrime => {
+                let mut candidate = self.candidate(sha256(elem));
+                loop {
+                    tests += 1;
+                    if is_prime(&candidate) {
+                        break candidate;
+                    }
+                    candidate += 2;
+                }
+            }
+        };
+        let collided = self.record(&prime, fingerprint(elem));
+        self.metrics.prime_hashed(tests, collided, start.elapsed());
+        prime
+    }
+
+    /// `hash` cut down to an odd number of exactly the configured bits, held between 2 and the
+    /// 256 the hashes have.
+    fn candidate(&self, mut hash: Integer) -> Integer {
+        let bits = self.config.bits.max(2).min(256);
+        hash.keep_bits_mut(bits);
+        hash.set_bit(bits - 1, true);
+        hash.set_bit(0, true);
+        hash
+    }
+
+    /// Notes that `prime` represents the element with `fingerprint`. Returns whether it already
+    /// represented another.
+    fn record(&self, prime: &Integer, fingerprint: u64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        match seen.get(prime) {
+            Some(other) => *other != fingerprint,
+            None => {
+                seen.insert(prime.clone(), fingerprint);
+                false
+            }
+        }
+    }
+}
+
+fn is_prime(candidate: &Integer) -> bool {
+    candidate.is_probably_prime(PRIMALITY_REPS) != IsPrime::No
+}
+
+/// SHA-256 of whatever `value` feeds its hasher, as an integer.
+fn sha256<T: Hash>(value: &T) -> Integer {
+    Integer::from_digits(&util::sha256(value), Order::Msf)
+}
+
+fn fingerprint<T: Hash>(elem: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    elem.hash(&mut hasher);
+    hasher.finish()
+}
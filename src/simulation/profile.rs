@@ -0,0 +1,93 @@
+//! A standard harness for performance work: `profile` runs a fixed workload under a sampling
+//! profiler and writes what it found to an output directory. The workload is a discrete-event run
+//! in `Rsa2048` with a fixed seed, so every run forges, validates and updates witnesses for the
+//! same blocks, and profiles taken before and after a change can be compared.
+use super::config::{Config, GroupKind, Mode};
+use super::embed::Simulation;
+use super::report::Summary;
+use accumulator::group::Rsa2048;
+use pprof::protos::Message;
+use pprof::ProfilerGuardBuilder;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use tracing::info;
+
+/// Samples a second taken by default: often enough to see a block's costlier steps, and prime so
+/// sampling does not fall in step with periodic work.
+pub const DEFAULT_FREQUENCY: i32 = 997;
+
+/// Blocks the workload runs for by default.
+pub const DEFAULT_BLOCKS: u64 = 20;
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(io::Error),
+    /// The profiler could not be started, or its samples not made into a report.
+    Profiler(pprof::Error),
+}
+
+impl From<io::Error> for ProfileError {
+    fn from(err: io::Error) -> Self {
+        ProfileError::Io(err)
+    }
+}
+
+impl From<pprof::Error> for ProfileError {
+    fn from(err: pprof::Error) -> Self {
+        ProfileError::Profiler(err)
+    }
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::Io(err) => write!(f, "{}", err),
+            ProfileError::Profiler(err) => write!(f, "Profiler failed: {}", err),
+        }
+    }
+}
+
+impl Error for ProfileError {}
+
+/// The workload profiled: `blocks` blocks of a discrete-event run with two miners and two bridges
+/// of 100 users each, who keep spending their UTXOs.
+pub fn workload(blocks: u64) -> Config {
+    Config {
+        mode: Mode::DiscreteEvent,
+        group: GroupKind::Rsa2048,
+        num_miners: 2,
+        users_per_bridge: vec![100; 2],
+        seed: 0,
+        max_blocks: Some(blocks),
+        quiet: true,
+        ..Config::default()
+    }
+}
+
+/// Runs `config` while sampling every thread of the process `frequency` times a second, then
+/// writes to `output_dir` a flame graph of the samples (`flamegraph.svg`), the samples in pprof's
+/// format for `go tool pprof` and similar tools (`profile.pb`) and the run's metrics as CSV.
+pub fn run(mut config: Config, output_dir: &Path, frequency: i32) -> Result<Summary, ProfileError> {
+    fs::create_dir_all(output_dir)?;
+    config.output_dir = Some(output_dir.to_path_buf());
+    info!("Profiling the run at {} Hz.", frequency);
+    let guard = ProfilerGuardBuilder::default()
+        .frequency(frequency)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()?;
+    let result = Simulation::<Rsa2048>::new(config).wait();
+    let report = guard.report().build()?;
+
+    report.flamegraph(File::create(output_dir.join("flamegraph.svg"))?)?;
+    let mut encoded = Vec::new();
+    report
+        .pprof()?
+        .encode(&mut encoded)
+        .expect("Encoding into a vector does not fail");
+    fs::write(output_dir.join("profile.pb"), encoded)?;
+    info!("Profile written to {}.", output_dir.display());
+    Ok(result.summary)
+}
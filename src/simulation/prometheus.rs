@@ -0,0 +1,370 @@
+use super::metrics::{ChannelStats, Histogram, LatencyPercentiles, Metrics};
+use super::state::NodeId;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Upper bounds, in blocks, of the buckets of the witness staleness histogram.
+const STALENESS_BUCKETS: [u64; 7] = [0, 1, 2, 4, 8, 16, 32];
+
+/// Serves live metrics in the Prometheus text exposition format at `addr`, answering every HTTP
+/// request (whatever its path) with the current values. Runs until the process exits.
+pub fn serve<A: ToSocketAddrs>(addr: A, metrics: Arc<Metrics>) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Serving Prometheus metrics on {}.", listener.local_addr()?);
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &metrics));
+            if let Err(err) = result {
+                warn!("Prometheus exporter error: {}", err);
+            }
+        }
+    }))
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    // We serve the same page for every request, so only drain what the client sent.
+    let mut request = [0; 1024];
+    let _ = stream.read(&mut request)?;
+    let body = render(metrics);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+/// Renders `metrics` in the Prometheus text exposition format.
+pub fn render(metrics: &Metrics) -> String {
+    let live = metrics.live();
+    let mut out = String::new();
+
+    out.push_str("# HELP accumulator_block_height Height of the chain as seen by a component.\n");
+    out.push_str("# TYPE accumulator_block_height gauge\n");
+    for (node, height) in &live.block_heights {
+        let (kind, id) = match node {
+            NodeId::Miner(id) => ("miner", id),
+            NodeId::Bridge(id) => ("bridge", id),
+            NodeId::User(id) => ("user", id),
+        };
+        writeln!(
+            out,
+            "accumulator_block_height{{kind=\"{}\",id=\"{}\"}} {}",
+            kind, id, height
+        )
+        .unwrap();
+    }
+
+    out.push_str("# HELP accumulator_mempool_depth Pending transactions held by a miner.\n");
+    out.push_str("# TYPE accumulator_mempool_depth gauge\n");
+    for (miner_id, depth) in &live.mempool_depths {
+        writeln!(
+            out,
+            "accumulator_mempool_depth{{miner=\"{}\"}} {}",
+            miner_id, depth
+        )
+        .unwrap();
+    }
+
+    out.push_str("# HELP accumulator_bridge_queue_length Witness requests awaiting a bridge.\n");
+    out.push_str("# TYPE accumulator_bridge_queue_length gauge\n");
+    for (bridge_id, length) in &live.bridge_queue_lengths {
+        writeln!(
+            out,
+            "accumulator_bridge_queue_length{{bridge=\"{}\"}} {}",
+            bridge_id, length
+        )
+        .unwrap();
+    }
+
+    let chain = metrics.chain_stats().snapshot();
+    for (name, help, value) in &[
+        (
+            "accumulator_block_interval_seconds",
+            "Mean time between the latest blocks.",
+            chain.block_interval.as_secs_f64(),
+        ),
+        (
+            "accumulator_block_transactions",
+            "Mean transactions in the latest blocks.",
+            chain.transactions_per_block,
+        ),
+        (
+            "accumulator_block_elems_added",
+            "Mean UTXOs the latest blocks created.",
+            chain.elems_added_per_block,
+        ),
+        (
+            "accumulator_block_elems_deleted",
+            "Mean UTXOs the latest blocks spent.",
+            chain.elems_deleted_per_block,
+        ),
+        (
+            "accumulator_block_proof_bytes",
+            "Mean bytes of the latest blocks' proofs.",
+            chain.proof_bytes_per_block,
+        ),
+    ] {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        writeln!(out, "{} {}", name, value).unwrap();
+    }
+
+    for (name, help, count) in &[
+        (
+            "accumulator_dropped_messages_total",
+            "Messages lost before reaching their destination.",
+            metrics.dropped_messages(),
+        ),
+        (
+            "accumulator_refused_messages_total",
+            "Messages turned away by a full queue.",
+            metrics.refused_messages(),
+        ),
+    ] {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} counter", name).unwrap();
+        writeln!(out, "{} {}", name, count).unwrap();
+    }
+
+    let channels = metrics.channels();
+    let per_channel = |count: fn(&ChannelStats) -> u64| {
+        channels
+            .iter()
+            .map(|(channel, stats)| (channel.name(), count(stats)))
+            .collect::<Vec<_>>()
+    };
+    for (name, help, counts) in &[
+        (
+            "accumulator_channel_queued_total",
+            "Messages that made it into a channel's queue.",
+            per_channel(|stats| stats.queued),
+        ),
+        (
+            "accumulator_channel_refused_total",
+            "Messages turned away by a channel's full queue.",
+            per_channel(|stats| stats.refused),
+        ),
+        (
+            "accumulator_channel_dropped_total",
+            "Messages lost on a channel or dropped by their senders.",
+            per_channel(|stats| stats.dropped),
+        ),
+    ] {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} counter", name).unwrap();
+        for (channel, count) in counts {
+            writeln!(out, "{}{{channel=\"{}\"}} {}", name, channel, count).unwrap();
+        }
+    }
+    out.push_str("# HELP accumulator_channel_drop_rate Share of a channel's messages dropped.\n");
+    out.push_str("# TYPE accumulator_channel_drop_rate gauge\n");
+    for (channel, stats) in &channels {
+        writeln!(
+            out,
+            "accumulator_channel_drop_rate{{channel=\"{}\"}} {}",
+            channel.name(),
+            stats.drop_rate()
+        )
+        .unwrap();
+    }
+
+    let queue_depths = metrics.queue_depths();
+    for (name, help, depth) in &[
+        (
+            "accumulator_queue_depth",
+            "Messages a channel's fullest queue holds.",
+            queue_depths
+                .iter()
+                .map(|(channel, depth)| (channel.name(), depth.depth))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "accumulator_queue_depth_high_watermark",
+            "The most messages any of a channel's queues has held.",
+            queue_depths
+                .iter()
+                .map(|(channel, depth)| (channel.name(), depth.high_watermark))
+                .collect(),
+        ),
+    ] {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        for (channel, depth) in depth {
+            writeln!(out, "{}{{channel=\"{}\"}} {}", name, channel, depth).unwrap();
+        }
+    }
+
+    let staleness = metrics.witness_staleness();
+    out.push_str(
+        "# HELP accumulator_witness_staleness_blocks Blocks old a mined transaction's witnesses \
+         were.\n",
+    );
+    out.push_str("# TYPE accumulator_witness_staleness_blocks histogram\n");
+    for bound in &STALENESS_BUCKETS {
+        let count: u64 = staleness
+            .transactions_by_age
+            .range(..=*bound)
+            .map(|(_age, count)| count)
+            .sum();
+        writeln!(
+            out,
+            "accumulator_witness_staleness_blocks_bucket{{le=\"{}\"}} {}",
+            bound, count
+        )
+        .unwrap();
+    }
+    let transactions = staleness.transactions();
+    writeln!(
+        out,
+        "accumulator_witness_staleness_blocks_bucket{{le=\"+Inf\"}} {}",
+        transactions
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "accumulator_witness_staleness_blocks_sum {}",
+        staleness.mean() * transactions as f64
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "accumulator_witness_staleness_blocks_count {}",
+        transactions
+    )
+    .unwrap();
+
+    let bandwidth = metrics.bandwidth();
+    out.push_str("# HELP accumulator_bytes_sent_total Bytes sent, by kind of message.\n");
+    out.push_str("# TYPE accumulator_bytes_sent_total counter\n");
+    for (kind, traffic) in &bandwidth {
+        writeln!(
+            out,
+            "accumulator_bytes_sent_total{{kind=\"{}\"}} {}",
+            kind.name(),
+            traffic.bytes_sent
+        )
+        .unwrap();
+    }
+    out.push_str("# HELP accumulator_bytes_received_total Bytes received, by kind of message.\n");
+    out.push_str("# TYPE accumulator_bytes_received_total counter\n");
+    for (kind, traffic) in &bandwidth {
+        writeln!(
+            out,
+            "accumulator_bytes_received_total{{kind=\"{}\"}} {}",
+            kind.name(),
+            traffic.bytes_received
+        )
+        .unwrap();
+    }
+
+    out.push_str("# HELP accumulator_block_bytes_saved_total Bytes saved by compressing blocks.\n");
+    out.push_str("# TYPE accumulator_block_bytes_saved_total counter\n");
+    writeln!(
+        out,
+        "accumulator_block_bytes_saved_total {}",
+        metrics.compression().bytes_saved()
+    )
+    .unwrap();
+
+    render_histogram(
+        &mut out,
+        "accumulator_forge_seconds",
+        "Time spent forging a block and its proofs.",
+        &live.forge_durations,
+    );
+    render_histogram(
+        &mut out,
+        "accumulator_block_processing_seconds",
+        "Time spent validating or applying a block.",
+        &live.block_processing_durations,
+    );
+    render_histogram(
+        &mut out,
+        "accumulator_witness_seconds",
+        "Time spent generating witnesses for a request.",
+        &live.witness_durations,
+    );
+
+    let witness_latencies = metrics.witness_latencies();
+    for (name, help, latencies) in &[
+        (
+            "accumulator_witness_request_seconds",
+            "Time from a user sending a witness request to the response reaching it, by bridge.",
+            witness_latencies
+                .iter()
+                .map(|(bridge_id, latencies)| (*bridge_id, &latencies.end_to_end))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "accumulator_witness_processing_seconds",
+            "Time a bridge spent computing the witnesses for a request, by bridge.",
+            witness_latencies
+                .iter()
+                .map(|(bridge_id, latencies)| (*bridge_id, &latencies.processing))
+                .collect(),
+        ),
+    ] {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} summary", name).unwrap();
+        for (bridge_id, latencies) in latencies {
+            render_quantiles(&mut out, name, *bridge_id, latencies);
+        }
+    }
+    out
+}
+
+/// The p50, p95 and p99 of `latencies`, and their sum and count, as the samples of a summary.
+fn render_quantiles(out: &mut String, name: &str, bridge_id: usize, latencies: &[Duration]) {
+    let percentiles = LatencyPercentiles::of(latencies);
+    for (quantile, latency) in &[
+        ("0.5", percentiles.p50),
+        ("0.95", percentiles.p95),
+        ("0.99", percentiles.p99),
+    ] {
+        writeln!(
+            out,
+            "{}{{bridge=\"{}\",quantile=\"{}\"}} {}",
+            name,
+            bridge_id,
+            quantile,
+            latency.as_secs_f64()
+        )
+        .unwrap();
+    }
+    let sum: Duration = latencies.iter().sum();
+    writeln!(
+        out,
+        "{}_sum{{bridge=\"{}\"}} {}",
+        name,
+        bridge_id,
+        sum.as_secs_f64()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}_count{{bridge=\"{}\"}} {}",
+        name,
+        bridge_id,
+        latencies.len()
+    )
+    .unwrap();
+}
+
+fn render_histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} histogram", name).unwrap();
+    for (bound, count) in &histogram.buckets {
+        writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count).unwrap();
+    }
+    writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count).unwrap();
+    writeln!(out, "{}_sum {}", name, histogram.sum).unwrap();
+    writeln!(out, "{}_count {}", name, histogram.count).unwrap();
+}
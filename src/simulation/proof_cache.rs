@@ -0,0 +1,163 @@
+use super::batch::BatchProof;
+use super::metrics::Metrics;
+use super::state::{self, Block};
+use super::util;
+use super::validation::BlockError;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many blocks' results the cache keeps. Components of a process receive each block at about
+/// the same time, so only the latest few are ever looked up.
+const CAPACITY: usize = 64;
+
+/// What a result is cached under: commitments to the accumulator the proofs are checked against
+/// and to what is checked, a block or a batch. Hashes rather than the accumulator and block
+/// themselves, so looking a block up neither copies it nor compares it in full.
+type Key = ([u8; 32], [u8; 32]);
+
+fn key<G: UnknownOrderGroup, T: Hash>(acc: &Accumulator<G, T>, checked: [u8; 32]) -> Key {
+    (state::acc_commitment(acc), checked)
+}
+
+#[derive(Debug)]
+/// The outcome of checking a block's proofs against an accumulator, and how long that took.
+struct Checked {
+    result: Result<(), BlockError>,
+    time: Duration,
+}
+
+#[derive(Debug)]
+/// Results of checking blocks' proofs, shared by the components of one process. Every miner and
+/// bridge receives each block and checks the same proofs against the same accumulator, so with a
+/// shared cache only the first of them does the work, and the rest reuse its result. The same
+/// goes for the batch proofs of nodes catching up.
+pub struct ProofCache<G: UnknownOrderGroup, T: Hash + Debug> {
+    state: Mutex<CacheState>,
+    _checked: PhantomData<fn() -> (G, T)>,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    results: HashMap<Key, Checked>,
+    /// Keys in the order they were added, oldest first, to evict by.
+    order: VecDeque<Key>,
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug> ProofCache<G, T> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(CacheState {
+                results: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            _checked: PhantomData,
+        }
+    }
+
+    /// The result of checking `block`'s proofs against `acc`: the cached one if any component has
+    /// checked them already, or else what `check` returns, which is then cached. Hits are counted
+    /// in `metrics` with the time they saved.
+    pub fn check<F: FnOnce() -> Result<(), BlockError>>(
+        &self,
+        acc: &Accumulator<G, T>,
+        block: &Block<G, T>,
+        metrics: &Metrics,
+        check: F,
+    ) -> Result<(), BlockError> {
+        self.check_key(key(acc, block.hash()), metrics, check)
+    }
+
+    /// The result of checking `proof` for `blocks`, fetched to catch up from `acc`, as `check`
+    /// caches a block's: components catching up on the same blocks from the same height check
+    /// the batch once between them.
+    pub fn check_batch<F: FnOnce() -> Result<(), BlockError>>(
+        &self,
+        acc: &Accumulator<G, T>,
+        blocks: &[Block<G, T>],
+        proof: &BatchProof<G, T>,
+        metrics: &Metrics,
+        check: F,
+    ) -> Result<(), BlockError> {
+        self.check_key(key(acc, util::sha256(&(blocks, proof))), metrics, check)
+    }
+
+    /// The cached result of checking `block`'s proofs against `acc`, if any component has checked
+    /// them already, counting the hit in `metrics`. For checks made in stages, which `insert` the
+    /// result once they are done.
+    pub fn lookup(
+        &self,
+        acc: &Accumulator<G, T>,
+        block: &Block<G, T>,
+        metrics: &Metrics,
+    ) -> Option<Result<(), BlockError>> {
+        self.lookup_key(&key(acc, block.hash()), metrics)
+    }
+
+    /// Caches `result`, which checking `block`'s proofs against `acc` took `time` to find, and
+    /// counts the miss in `metrics`.
+    pub fn insert(
+        &self,
+        acc: &Accumulator<G, T>,
+        block: &Block<G, T>,
+        metrics: &Metrics,
+        result: Result<(), BlockError>,
+        time: Duration,
+    ) {
+        self.insert_key(key(acc, block.hash()), metrics, result, time);
+    }
+
+    fn check_key<F: FnOnce() -> Result<(), BlockError>>(
+        &self,
+        key: Key,
+        metrics: &Metrics,
+        check: F,
+    ) -> Result<(), BlockError> {
+        if let Some(result) = self.lookup_key(&key, metrics) {
+            return result;
+        }
+        let start = Instant::now();
+        let result = check();
+        self.insert_key(key, metrics, result, start.elapsed());
+        result
+    }
+
+    fn lookup_key(&self, key: &Key, metrics: &Metrics) -> Option<Result<(), BlockError>> {
+        let state = self.state.lock().unwrap();
+        let checked = state.results.get(key)?;
+        metrics.proof_cache_hit(checked.time);
+        Some(checked.result)
+    }
+
+    fn insert_key(
+        &self,
+        key: Key,
+        metrics: &Metrics,
+        result: Result<(), BlockError>,
+        time: Duration,
+    ) {
+        metrics.proof_cache_miss();
+        let mut state = self.state.lock().unwrap();
+        if state.results.contains_key(&key) {
+            return;
+        }
+        if state.order.len() >= CAPACITY {
+            if let Some(oldest) = state.order.pop_front() {
+                state.results.remove(&oldest);
+            }
+        }
+        state.order.push_back(key);
+        state.results.insert(key, Checked { result, time });
+    }
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug> Default for ProofCache<G, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,119 @@
+use super::network::ELEM_BYTES;
+use std::time::Duration;
+
+/// Bytes of a membership proof: a witness and a proof of exponentiation (PoE), a group element
+/// each.
+pub const MEMBERSHIP_PROOF_BYTES: usize = 2 * ELEM_BYTES;
+/// Bytes of a non-membership proof: three group elements, a proof of knowledge of co-prime roots
+/// (PoKE2) of two elements and a 128-bit integer, and a PoE.
+pub const NONMEMBERSHIP_PROOF_BYTES: usize = 6 * ELEM_BYTES + 16;
+/// Bytes of a deletion proof: the accumulator without the deleted elements and a proof of
+/// knowledge of exponent (PoKE2) of two group elements and a 128-bit integer.
+pub const DELETION_PROOF_BYTES: usize = 3 * ELEM_BYTES + 16;
+/// Bytes of a swap proof: two group elements, together a PoE.
+pub const SWAP_PROOF_BYTES: usize = 2 * ELEM_BYTES;
+/// Bytes of a batch proof: a deletion proof and a membership proof.
+pub const BATCH_PROOF_BYTES: usize = DELETION_PROOF_BYTES + MEMBERSHIP_PROOF_BYTES;
+/// Bytes of the PoE that every membership and non-membership proof ends with.
+pub const POE_BYTES: usize = ELEM_BYTES;
+/// Bytes of the PoKE2 a deletion proof ends with.
+pub const POKE_BYTES: usize = 2 * ELEM_BYTES + 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+/// What a proof made during a run is for.
+pub enum ProofOp {
+    /// A block's proof that deleting the UTXOs it spends gives the accumulator it builds on.
+    BlockDeletions,
+    /// A block's proof that the UTXOs it creates are in the accumulator after it.
+    BlockAdditions,
+    /// A block's single proof that it swaps the UTXOs it spends for those it creates.
+    BlockSwap,
+    /// A block's proof that the UTXOs it creates were not in the accumulator before it.
+    FreshUtxos,
+    /// A bridge's proof that a spent UTXO is no longer in the accumulator.
+    SpentUtxo,
+    /// A miner's proof of the updates of every block a node catching up has missed.
+    SyncBatch,
+}
+
+impl ProofOp {
+    pub const ALL: [ProofOp; 6] = [
+        ProofOp::BlockDeletions,
+        ProofOp::BlockAdditions,
+        ProofOp::BlockSwap,
+        ProofOp::FreshUtxos,
+        ProofOp::SpentUtxo,
+        ProofOp::SyncBatch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProofOp::BlockDeletions => "block deletions",
+            ProofOp::BlockAdditions => "block additions",
+            ProofOp::BlockSwap => "block swap",
+            ProofOp::FreshUtxos => "fresh UTXOs",
+            ProofOp::SpentUtxo => "spent UTXO",
+            ProofOp::SyncBatch => "sync batch",
+        }
+    }
+
+    /// Bytes of one proof of this kind.
+    pub fn proof_bytes(self) -> usize {
+        match self {
+            ProofOp::BlockDeletions => DELETION_PROOF_BYTES,
+            ProofOp::BlockAdditions => MEMBERSHIP_PROOF_BYTES,
+            ProofOp::BlockSwap => SWAP_PROOF_BYTES,
+            ProofOp::FreshUtxos | ProofOp::SpentUtxo => NONMEMBERSHIP_PROOF_BYTES,
+            ProofOp::SyncBatch => BATCH_PROOF_BYTES,
+        }
+    }
+
+    /// Bytes of the succinct argument, a PoE or a PoKE2, that one proof of this kind ends with.
+    pub fn argument_bytes(self) -> usize {
+        match self {
+            ProofOp::BlockDeletions => POKE_BYTES,
+            ProofOp::BlockSwap => SWAP_PROOF_BYTES,
+            ProofOp::SyncBatch => POKE_BYTES + POE_BYTES,
+            _ => POE_BYTES,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// How many proofs of one kind were made and checked over a run, how big they were, and how long
+/// that took.
+pub struct ProofStats {
+    pub generated: u64,
+    pub verified: u64,
+    /// Elements the proofs made were about, e.g. the UTXOs a block deletes.
+    pub elems: u64,
+    pub bytes: u64,
+    /// Bytes taken up by the PoEs and PoKE2s the proofs end with.
+    pub argument_bytes: u64,
+    pub generation_time: Duration,
+    pub verification_time: Duration,
+}
+
+impl ProofStats {
+    pub fn mean_elems(&self) -> f64 {
+        if self.generated == 0 {
+            return 0.0;
+        }
+        self.elems as f64 / self.generated as f64
+    }
+
+    pub fn mean_generation_time(&self) -> Duration {
+        mean(self.generation_time, self.generated)
+    }
+
+    pub fn mean_verification_time(&self) -> Duration {
+        mean(self.verification_time, self.verified)
+    }
+}
+
+fn mean(total: Duration, count: u64) -> Duration {
+    if count == 0 {
+        return Duration::from_secs(0);
+    }
+    total / count as u32
+}
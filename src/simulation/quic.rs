@@ -0,0 +1,361 @@
+use super::auth::Signer;
+use super::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::handshake::PROTOCOL_VERSION;
+use super::metrics::Metrics;
+use super::state::{Block, NodeId, Transaction, Utxo};
+use super::tcp::{Meter, Verifier};
+use super::transport::{BlockTopic, Receiver, SendError, Sender, TxTopic, WitnessChannel};
+use super::wire::{self, WireMessage};
+use accumulator::group::UnknownOrderGroup;
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Runtime;
+use tracing::{debug, warn};
+
+/// Messages longer than this are taken to be garbage.
+const MAX_MESSAGE_BYTES: usize = 64 << 20;
+/// A peer that takes longer than this to connect and take a message is given up on for that
+/// message, as TCP senders give up on a stuck peer.
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+/// The name senders expect in a peer's certificate. Certificates are not checked, so any will do.
+const SERVER_NAME: &str = "accumulator-demo";
+
+fn other<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// The ALPN protocol connections negotiate, naming the peer protocol version, so that a peer on
+/// another version fails to connect rather than being misunderstood.
+fn alpn() -> Vec<u8> {
+    format!("accumulator-demo/{}", PROTOCOL_VERSION).into_bytes()
+}
+
+/// Accepts whatever certificate a peer presents. QUIC insists on TLS, but cluster processes have
+/// no certificate authority to check each other's certificates against; connections are still
+/// encrypted, and signed messages still prove who sent them.
+struct AcceptAnyCertificate;
+
+impl rustls::client::ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+fn client_config() -> ClientConfig {
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![alpn()];
+    ClientConfig::new(Arc::new(crypto))
+}
+
+/// A config for listening endpoints, with a certificate of their own made up on the spot.
+fn server_config() -> io::Result<ServerConfig> {
+    let certificate =
+        rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).map_err(other)?;
+    let key = rustls::PrivateKey(certificate.serialize_private_key_der());
+    let chain = vec![rustls::Certificate(
+        certificate.serialize_der().map_err(other)?,
+    )];
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(other)?;
+    crypto.alpn_protocols = vec![alpn()];
+    Ok(ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// The QUIC side of a cluster process: a runtime for quinn to run on, and the endpoint it
+/// connects to peers from. A sender keeps one connection to each of its peers and sends every
+/// message on a stream of its own, so a message lost on the way only holds up its own stream,
+/// and setting a stream up costs no round trip. Connections are always encrypted with TLS, which
+/// QUIC is built on.
+pub struct QuicNode {
+    runtime: Arc<Runtime>,
+    client: Endpoint,
+    server_config: ServerConfig,
+    metrics: Arc<Metrics>,
+}
+
+impl QuicNode {
+    pub fn new(metrics: Arc<Metrics>) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let mut client = {
+            let _runtime = runtime.enter();
+            Endpoint::client(SocketAddr::from(([0, 0, 0, 0], 0)))?
+        };
+        client.set_default_client_config(client_config());
+        Ok(Self {
+            runtime: Arc::new(runtime),
+            client,
+            server_config: server_config()?,
+            metrics,
+        })
+    }
+
+    /// A sender from `node` to `peers`.
+    pub fn sender<T: WireMessage>(&self, node: NodeId, peers: Vec<SocketAddr>) -> QuicSender<T> {
+        QuicSender {
+            node,
+            peers,
+            runtime: self.runtime.clone(),
+            endpoint: self.client.clone(),
+            connections: Mutex::new(HashMap::new()),
+            metrics: self.metrics.clone(),
+            signer: None,
+            message: PhantomData,
+        }
+    }
+
+    /// A receiver for `node` listening on `addr`, checking signatures with `verifier` if given.
+    pub fn receiver<T: WireMessage + Send + 'static>(
+        &self,
+        node: NodeId,
+        addr: SocketAddr,
+        verifier: Option<Verifier>,
+    ) -> io::Result<QuicReceiver<T>> {
+        let endpoint = {
+            let _runtime = self.runtime.enter();
+            Endpoint::server(self.server_config.clone(), addr)?
+        };
+        let local_addr = endpoint.local_addr()?;
+        let (sender, messages) = mpsc::channel();
+        let meter = Meter::new(node, self.metrics.clone());
+        self.runtime.spawn(async move {
+            while let Some(connecting) = endpoint.accept().await {
+                let sender = sender.clone();
+                let verifier = verifier.clone();
+                let meter = meter.clone();
+                tokio::spawn(async move {
+                    match connecting.await {
+                        Ok(connection) => read_streams(connection, sender, verifier, meter).await,
+                        Err(err) => debug!("Failed to accept a connection: {}", err),
+                    }
+                });
+            }
+        });
+        Ok(QuicReceiver {
+            local_addr,
+            messages,
+        })
+    }
+}
+
+/// Queues up the message on each stream the peer opens on `connection`, in the order the streams
+/// were opened, until the peer hangs up.
+async fn read_streams<T: WireMessage>(
+    connection: Connection,
+    messages: mpsc::Sender<T>,
+    verifier: Option<Verifier>,
+    meter: Meter,
+) {
+    let peer = connection.remote_address().to_string();
+    loop {
+        let mut stream = match connection.accept_uni().await {
+            Ok(stream) => stream,
+            Err(err) => {
+                debug!("{} hung up: {}", peer, err);
+                break;
+            }
+        };
+        let body = match stream.read_to_end(MAX_MESSAGE_BYTES).await {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Dropping a message from {}: {}", peer, err);
+                continue;
+            }
+        };
+        meter.received::<T>(body.len());
+        let decoded = match &verifier {
+            Some(verifier) => verifier.decode(&body, &peer),
+            None => wire::decode(&body).map(Some),
+        };
+        match decoded {
+            Ok(Some(message)) => {
+                if messages.send(message).is_err() {
+                    // Nobody is receiving any more.
+                    break;
+                }
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Dropping a message from {}: {}", peer, err),
+        }
+    }
+}
+
+/// Sends every message to each of a fixed set of peers over QUIC, one stream per message. A
+/// message is only sent once the peer has taken the one before it, so messages arrive in order.
+/// Connections are made on first use and remade after they fail; a message a peer cannot be
+/// reached for is dropped, like one lost on a lossy link.
+pub struct QuicSender<T> {
+    /// The component sending, whose traffic the messages sent count towards.
+    node: NodeId,
+    peers: Vec<SocketAddr>,
+    runtime: Arc<Runtime>,
+    endpoint: Endpoint,
+    connections: Mutex<HashMap<SocketAddr, Connection>>,
+    metrics: Arc<Metrics>,
+    signer: Option<Signer>,
+    message: PhantomData<fn(T)>,
+}
+
+impl<T: WireMessage> QuicSender<T> {
+    /// Signs every message sent from now on with `signer`.
+    pub fn signed(mut self, signer: Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn send(&self, message: T) {
+        let encoded = match &self.signer {
+            Some(signer) => wire::encode_signed(&message, signer),
+            None => wire::encode(&message),
+        };
+        let body = match encoded {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Failed to encode a message: {}", err);
+                self.metrics.message_dropped();
+                return;
+            }
+        };
+        let mut connections = self.connections.lock().unwrap();
+        for peer in &self.peers {
+            let connection = connections
+                .get(peer)
+                .filter(|connection| connection.close_reason().is_none())
+                .cloned();
+            let sent = self.runtime.block_on(async {
+                tokio::time::timeout(
+                    SEND_TIMEOUT,
+                    send_stream(&self.endpoint, *peer, connection, &body),
+                )
+                .await
+                .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out")))
+            });
+            match sent {
+                Ok(connection) => {
+                    connections.insert(*peer, connection);
+                    self.metrics
+                        .bytes_sent(self.node, T::KIND.traffic(), body.len());
+                }
+                Err(err) => {
+                    debug!("Dropping a message to {}: {}", peer, err);
+                    connections.remove(peer);
+                    self.metrics.message_dropped();
+                }
+            }
+        }
+    }
+}
+
+/// Sends `body` to `peer` on a stream of its own over `connection`, or a new connection without
+/// one, and waits for the peer to take all of it. Returns the connection used.
+async fn send_stream(
+    endpoint: &Endpoint,
+    peer: SocketAddr,
+    connection: Option<Connection>,
+    body: &[u8],
+) -> io::Result<Connection> {
+    let connection = match connection {
+        Some(connection) => connection,
+        None => endpoint
+            .connect(peer, SERVER_NAME)
+            .map_err(other)?
+            .await
+            .map_err(other)?,
+    };
+    let mut stream = connection.open_uni().await.map_err(other)?;
+    stream.write_all(body).await.map_err(other)?;
+    stream.finish().await.map_err(other)?;
+    Ok(connection)
+}
+
+/// Receives messages from any peer that connects to the address it listens on.
+pub struct QuicReceiver<T> {
+    local_addr: SocketAddr,
+    messages: mpsc::Receiver<T>,
+}
+
+impl<T> QuicReceiver<T> {
+    /// The address peers should send to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl<T: WireMessage + 'static> Sender<T> for QuicSender<T> {
+    /// Each stream waits until the peer takes the message, or times out and drops it, so the
+    /// sender never gets one back.
+    fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        QuicSender::send(self, message);
+        Ok(())
+    }
+
+    fn send(&self, message: T) {
+        QuicSender::send(self, message);
+    }
+}
+
+impl<T: WireMessage + Send + 'static> Receiver<T> for QuicReceiver<T> {
+    fn try_receive(&self) -> Option<T> {
+        self.messages.try_recv().ok()
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        self.messages.recv_timeout(timeout).ok()
+    }
+}
+
+/// Wire-format messages over QUIC, one stream per message, so cluster processes can talk over
+/// multiplexed connections instead of a TCP connection per link.
+pub struct Quic;
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> BlockTopic<G, T> for Quic
+where
+    Block<G, T>: WireMessage + Send + Sync + 'static,
+{
+    type BlockSender = QuicSender<Arc<Block<G, T>>>;
+    type BlockReceiver = QuicReceiver<Arc<Block<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> TxTopic<G, T> for Quic
+where
+    Transaction<G, T>: WireMessage + Send + Sync + 'static,
+{
+    type TxSender = QuicSender<Arc<Transaction<G, T>>>;
+    type TxReceiver = QuicReceiver<Arc<Transaction<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup> WitnessChannel<G> for Quic
+where
+    WitnessResponse<G, Utxo>: WireMessage + Send + 'static,
+    UserUpdate<G>: WireMessage + Send + 'static,
+{
+    type RequestSender = QuicSender<WitnessRequest>;
+    type RequestReceiver = QuicReceiver<WitnessRequest>;
+    type ResponseSender = QuicSender<WitnessResponse<G, Utxo>>;
+    type ResponseReceiver = QuicReceiver<WitnessResponse<G, Utxo>>;
+    type UpdateSender = QuicSender<UserUpdate<G>>;
+    type UpdateReceiver = QuicReceiver<UserUpdate<G>>;
+}
@@ -0,0 +1,231 @@
+use super::builder::SimulationBuilder;
+use super::clock::{Clock, SystemClock};
+use super::config::{ComponentRuntime, Config};
+use super::control::RunControl;
+use super::events::EventSink;
+use super::hooks::Hooks;
+use super::injection::FaultInjector;
+use super::metrics::Metrics;
+use super::report::{RunResult, Summary};
+use super::transport::{QueueReceiver, Receiver, RECEIVE_TIMEOUT};
+use super::viz::Feed;
+use super::watchdog::Watchdog;
+use super::websocket::WebSocketFeed;
+use accumulator::group::UnknownOrderGroup;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tracing::{error, info, warn};
+
+fn export_metrics(metrics: &Metrics, dir: &Path) {
+    if let Err(err) = metrics.write_csv(dir) {
+        error!("Failed to export metrics to {}: {}", dir.display(), err);
+    }
+}
+
+/// Calls `hook` with everything that arrives on `receiver` until the run is stopped.
+fn spawn_tap<T: Clone + Send + 'static, F: Fn(&T, &RunControl) + Send + 'static>(
+    receiver: QueueReceiver<T>,
+    control: Arc<RunControl>,
+    hook: F,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while !control.is_stopped() {
+            if let Some(message) = receiver.receive_timeout(RECEIVE_TIMEOUT) {
+                hook(&message, &control);
+            }
+        }
+    })
+}
+
+/// Runs each component on its own threads, communicating over broadcast queues in wall-clock time.
+pub struct RealTimeSimulation<G: UnknownOrderGroup> {
+    config: Config,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<Metrics>,
+    control: Arc<RunControl>,
+    hooks: Arc<Hooks<G>>,
+}
+
+impl<G: UnknownOrderGroup> RealTimeSimulation<G> {
+    pub fn new(config: Config) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock::new(config.speedup));
+        let metrics = Arc::new(Metrics::new(clock.clone()));
+        metrics.chain_stats().set_window(config.chain_stats_window);
+        if let Some(sink) = EventSink::from_config(&config) {
+            metrics.stream_events(sink);
+        }
+        Self {
+            config,
+            clock,
+            metrics,
+            control: Arc::new(RunControl::new()),
+            hooks: Arc::new(Hooks::default()),
+        }
+    }
+
+    /// The run state an operator can use to pause, step, resume and stop this simulation.
+    pub fn control(&self) -> Arc<RunControl> {
+        self.control.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Registers callbacks to be called as the run goes on, replacing any registered before.
+    pub fn set_hooks(&mut self, hooks: Hooks<G>) {
+        self.hooks = Arc::new(hooks);
+    }
+
+    /// Starts every component and runs until the run is stopped or reaches the bounds in its
+    /// config, then exports metrics, prints a summary unless quiet, and returns the final state.
+    pub fn run(self) -> RunResult<G> {
+        let Self {
+            config,
+            clock,
+            metrics,
+            control,
+            hooks,
+        } = self;
+        let viz = Feed::from_config(&config);
+        let websocket = WebSocketFeed::from_config(&config).map(Arc::new);
+        let builder = SimulationBuilder::<G>::new(&config, metrics.clone());
+        let block_receiver = builder.subscribe_blocks();
+        let mut taps = Vec::new();
+        let (stats_metrics, stats_clock) = (metrics.clone(), clock.clone());
+        taps.push(spawn_tap(
+            builder.subscribe_blocks(),
+            control.clone(),
+            move |block, _| {
+                stats_metrics
+                    .chain_stats()
+                    .observe(stats_clock.now(), block)
+            },
+        ));
+        if hooks.has_transaction() {
+            let hooks = hooks.clone();
+            taps.push(spawn_tap(
+                builder.subscribe_transactions(),
+                control.clone(),
+                move |transaction, control| hooks.transaction(transaction, control),
+            ));
+        }
+        if hooks.has_witness_request() {
+            for bridge_id in 0..config.num_bridges() {
+                let hooks = hooks.clone();
+                taps.push(spawn_tap(
+                    builder.subscribe_witness_requests(bridge_id),
+                    control.clone(),
+                    move |request, control| hooks.witness_request(request, control),
+                ));
+            }
+        }
+        let components = builder.build();
+        if let Some(websocket) = &websocket {
+            for user in &components.users {
+                let websocket = websocket.clone();
+                let user_id = user.user_id;
+                let tap_clock = clock.clone();
+                taps.push(spawn_tap(
+                    user.user_update_receiver.add_stream(),
+                    control.clone(),
+                    move |update, _| websocket.user_update(tap_clock.now(), user_id, update),
+                ));
+            }
+        }
+        let genesis_acc = components.genesis.acc.clone();
+        let faults = Arc::new(FaultInjector::new(
+            config.injected_faults.clone(),
+            metrics.clone(),
+        ));
+        let running = match config.runtime {
+            #[cfg(feature = "async-runtime")]
+            ComponentRuntime::Tokio => {
+                components.spawn_tasks(&clock, &control, &metrics, &faults, &hooks)
+            }
+            #[cfg(not(feature = "async-runtime"))]
+            ComponentRuntime::Tokio => {
+                warn!("Components run on threads: this build lacks the async-runtime feature.");
+                components.spawn(&clock, &control, &metrics, &faults, &hooks)
+            }
+            ComponentRuntime::Threads => {
+                components.spawn(&clock, &control, &metrics, &faults, &hooks)
+            }
+        };
+
+        info!("Simulation running.");
+        let watchdog = Watchdog::from_config(&config)
+            .map(|watchdog| watchdog.spawn(clock.clone(), control.clone(), metrics.clone()));
+        let bounds = config.clone();
+        let logger_clock = clock.clone();
+        let logger_control = control.clone();
+        let logger_metrics = metrics.clone();
+        let block_logger = thread::spawn(move || {
+            let mut last_block = None;
+            while !logger_control.is_stopped() {
+                // Wake up now and then even without a block, to check the run's bounds.
+                if let Some(block) = block_receiver.receive_timeout(RECEIVE_TIMEOUT) {
+                    info!(
+                        "Block {} has {} transactions.",
+                        block.height,
+                        block.transactions.len()
+                    );
+                    hooks.block(&block, &logger_control);
+                    if let Some(viz) = &viz {
+                        // Which miner forged a block is not known here.
+                        let transactions = block.transactions.len();
+                        viz.block(logger_clock.now(), None, block.height, transactions, false);
+                    }
+                    if let Some(websocket) = &websocket {
+                        websocket.block(logger_clock.now(), &block);
+                    }
+                    // Export metrics periodically, so they survive a run that is killed.
+                    let interval = bounds.metrics_interval_blocks;
+                    if let Some(dir) = &bounds.output_dir {
+                        if interval > 0 && block.height % interval == 0 {
+                            export_metrics(&logger_metrics, dir);
+                        }
+                    }
+                    last_block = Some(block);
+                }
+                let block_height = last_block.as_ref().map_or(0, |block| block.height);
+                if bounds.is_finished(block_height, logger_clock.now()) {
+                    info!("Run bounds reached at block {}.", block_height);
+                    logger_control.stop();
+                }
+            }
+            last_block
+        });
+
+        let last_block = block_logger.join().unwrap();
+        if let Some(watchdog) = watchdog {
+            watchdog.join().unwrap();
+        }
+        let components = running.shut_down(&control, config.shutdown_timeout(), &metrics);
+        for tap in taps {
+            if tap.join().is_err() {
+                warn!("A hook panicked while shutting down.");
+            }
+        }
+
+        if let Some(dir) = &config.output_dir {
+            export_metrics(&metrics, dir);
+        }
+        let summary = Summary::new(&metrics);
+        if !config.quiet {
+            println!("{}", summary);
+        }
+        let (block_height, acc) = match last_block {
+            Some(block) => (block.height, block.acc_new.clone()),
+            None => (0, genesis_acc),
+        };
+        RunResult {
+            block_height,
+            acc,
+            metrics,
+            summary,
+            components,
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use super::chain_stats::ChainStatsSnapshot;
+use super::metrics::{
+    Bandwidth, ChannelStats, ConfirmationLatencyStats, LatencyPercentiles, Metrics,
+    PrimeCacheStats, PrimeStats, ProofCacheStats, QueueDepthStats, WitnessLatencyStats,
+    WitnessStaleness,
+};
+use super::network::MessageKind;
+use super::proofs::{ProofOp, ProofStats};
+use super::shutdown::ComponentReport;
+use super::state::{self, NodeId, Utxo};
+use super::transport::Channel;
+use super::util;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What a finished run leaves behind: the final chain state, and the metrics collected on the way.
+pub struct RunResult<G: UnknownOrderGroup> {
+    pub block_height: u64,
+    pub acc: Accumulator<G, Utxo>,
+    pub metrics: Arc<Metrics>,
+    pub summary: Summary,
+    /// Where each component got to, by node.
+    pub components: Vec<ComponentReport>,
+}
+
+impl<G: UnknownOrderGroup> RunResult<G> {
+    /// What the run came to, for a test to assert on.
+    pub fn outcome(&self) -> Outcome {
+        Outcome {
+            block_height: self.block_height,
+            acc_commitment: state::acc_commitment(&self.acc),
+            elapsed: self.metrics.now(),
+            blocks: self
+                .metrics
+                .blocks()
+                .iter()
+                .map(|block| (block.height, block.transactions))
+                .collect(),
+            transactions_confirmed: self
+                .metrics
+                .components()
+                .values()
+                .map(|component| component.transactions_confirmed)
+                .sum(),
+            events_digest: util::sha256(&self.metrics.events()),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// What a run came to, leaving out everything timed on the wall clock. Discrete-event runs of the
+/// same config and seed come to the same outcome, so a test can compare it to another run's, or
+/// to what it expects, exactly.
+pub struct Outcome {
+    pub block_height: u64,
+    /// The commitment to the final accumulator.
+    pub acc_commitment: [u8; 32],
+    /// Simulated time covered by the run.
+    pub elapsed: Duration,
+    /// The height and transactions of each block forged, in the order they were forged.
+    pub blocks: Vec<(u64, usize)>,
+    pub transactions_confirmed: u64,
+    /// SHA-256 of the run's event log, which has every witness request, transaction and block
+    /// of the run at its virtual time.
+    pub events_digest: [u8; 32],
+}
+
+#[derive(Clone, Debug)]
+/// Totals for a finished run, aggregated from every component's metrics.
+pub struct Summary {
+    pub blocks: usize,
+    pub transactions: usize,
+    /// Simulated time covered by the run.
+    pub elapsed: Duration,
+    pub mean_confirmation_latency: Duration,
+    pub p95_confirmation_latency: Duration,
+    /// Percentiles of confirmation latency over every user's transactions.
+    pub confirmation_latency: LatencyPercentiles,
+    /// Percentiles of confirmation latency by user, and over the users of each bridge, to see
+    /// whether some users or bridges are served worse than others.
+    pub user_confirmation_latency: BTreeMap<usize, ConfirmationLatencyStats>,
+    pub bridge_confirmation_latency: BTreeMap<usize, ConfirmationLatencyStats>,
+    /// The longest stretch of simulated time without a new block, a measure of chain liveness.
+    pub longest_block_gap: Duration,
+    /// The rolling averages of the last blocks of the run.
+    pub chain: ChainStatsSnapshot,
+    /// How many blocks old the witnesses of mined transactions were.
+    pub witness_staleness: WitnessStaleness,
+    /// Percentiles of how long witness requests took end to end, and of the bridge's own part in
+    /// that, by bridge.
+    pub witness_latency: BTreeMap<usize, WitnessLatencyStats>,
+    /// Wall-clock time spent forging blocks and generating witnesses.
+    pub proof_generation_time: Duration,
+    /// Wall-clock time spent validating and applying blocks.
+    pub verification_time: Duration,
+    pub dropped_messages: u64,
+    /// Messages turned away by full queues, which their senders dropped or sent again later.
+    pub refused_messages: u64,
+    /// Stalls the watchdog alerted on.
+    pub stalls: u64,
+    /// What became of the messages sent on each channel between real-time components.
+    pub channels: BTreeMap<Channel, ChannelStats>,
+    /// How full each channel's queues were at the end of the run, and at their fullest.
+    pub queue_depths: BTreeMap<Channel, QueueDepthStats>,
+    /// Messages rejected for not being signed by the component they claim to come from.
+    pub forged_messages: u64,
+    /// Invalid blocks rejected, summed over every miner and bridge.
+    pub rejected_blocks: u64,
+    /// Bytes sent and received, summed over every component, by kind of message.
+    pub bandwidth: BTreeMap<MessageKind, Bandwidth>,
+    /// Membership and non-membership proofs made and checked, by what they were for.
+    pub proofs: BTreeMap<ProofOp, ProofStats>,
+    /// Blocks whose proofs were found already checked, if components shared a cache of them.
+    pub proof_cache: ProofCacheStats,
+    /// What mapping created UTXOs to primes took, if the config named a strategy to measure.
+    pub primes: PrimeStats,
+    /// Elements' primes that deletion and swap proofs found in the cache rather than derived, if
+    /// primes were cached. Nothing else looks primes up in it.
+    pub proof_prime_cache: PrimeCacheStats,
+}
+
+impl Summary {
+    pub fn new(metrics: &Metrics) -> Self {
+        let blocks = metrics.blocks();
+        let components = metrics.components();
+
+        let mut confirmation_latencies: Vec<Duration> = components
+            .values()
+            .flat_map(|component| component.confirmation_latencies.iter().cloned())
+            .collect();
+        confirmation_latencies.sort();
+
+        let user_bridges = metrics.user_bridges();
+        let mut user_confirmation_latency = BTreeMap::new();
+        let mut bridge_latencies: BTreeMap<usize, Vec<Duration>> = BTreeMap::new();
+        for (node, component) in &components {
+            let user_id = match node {
+                NodeId::User(id) => *id,
+                _ => continue,
+            };
+            let latencies = &component.confirmation_latencies;
+            user_confirmation_latency.insert(user_id, ConfirmationLatencyStats::of(latencies));
+            if let Some(bridge_id) = user_bridges.get(&user_id) {
+                bridge_latencies
+                    .entry(*bridge_id)
+                    .or_default()
+                    .extend(latencies);
+            }
+        }
+
+        Self {
+            blocks: blocks.len(),
+            transactions: blocks.iter().map(|block| block.transactions).sum(),
+            elapsed: metrics.now(),
+            mean_confirmation_latency: mean(&confirmation_latencies),
+            p95_confirmation_latency: percentile(&confirmation_latencies, 95),
+            confirmation_latency: LatencyPercentiles::of(&confirmation_latencies),
+            user_confirmation_latency,
+            bridge_confirmation_latency: bridge_latencies
+                .iter()
+                .map(|(bridge_id, latencies)| (*bridge_id, ConfirmationLatencyStats::of(latencies)))
+                .collect(),
+            longest_block_gap: blocks
+                .iter()
+                .map(|block| block.forged_at)
+                .chain(Some(metrics.now()))
+                .scan(Duration::from_secs(0), |last, at| {
+                    let gap = at.checked_sub(*last).unwrap_or_default();
+                    *last = at;
+                    Some(gap)
+                })
+                .max()
+                .unwrap_or_default(),
+            chain: metrics.chain_stats().snapshot(),
+            witness_staleness: metrics.witness_staleness(),
+            witness_latency: metrics
+                .witness_latencies()
+                .iter()
+                .map(|(bridge_id, latencies)| (*bridge_id, latencies.stats()))
+                .collect(),
+            proof_generation_time: components.values().map(|c| c.proof_time).sum(),
+            verification_time: components.values().map(|c| c.block_processing_time).sum(),
+            dropped_messages: metrics.dropped_messages(),
+            refused_messages: metrics.refused_messages(),
+            stalls: metrics.stalls(),
+            channels: metrics.channels(),
+            queue_depths: metrics.queue_depths(),
+            forged_messages: metrics.forged_messages(),
+            rejected_blocks: components.values().map(|c| c.blocks_rejected).sum(),
+            bandwidth: metrics.bandwidth(),
+            proofs: metrics.proofs(),
+            proof_cache: metrics.proof_cache(),
+            primes: metrics.primes(),
+            proof_prime_cache: metrics.proof_prime_cache(),
+        }
+    }
+
+    /// Transactions confirmed per second of simulated time.
+    pub fn transactions_per_sec(&self) -> f64 {
+        if self.elapsed == Duration::from_secs(0) {
+            return 0.0;
+        }
+        self.transactions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The median confirmation latencies of the users that had a transaction confirmed, fastest
+    /// first.
+    pub fn user_medians(&self) -> Vec<Duration> {
+        let mut medians: Vec<Duration> = self
+            .user_confirmation_latency
+            .values()
+            .filter(|stats| stats.confirmed > 0)
+            .map(|stats| stats.percentiles.p50)
+            .collect();
+        medians.sort();
+        medians
+    }
+
+    /// Bytes of `kind` traffic sent over the run.
+    pub fn bytes_sent(&self, kind: MessageKind) -> u64 {
+        self.bandwidth
+            .get(&kind)
+            .map_or(0, |traffic| traffic.bytes_sent)
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Run summary")?;
+        writeln!(f, "  simulated time:          {:.1?}", self.elapsed)?;
+        writeln!(f, "  blocks:                  {}", self.blocks)?;
+        writeln!(f, "  transactions:            {}", self.transactions)?;
+        writeln!(
+            f,
+            "  throughput:              {:.2} tx/s",
+            self.transactions_per_sec()
+        )?;
+        writeln!(
+            f,
+            "  confirmation latency:    {:.1?} mean, {}",
+            self.mean_confirmation_latency, self.confirmation_latency
+        )?;
+        for (bridge_id, latency) in &self.bridge_confirmation_latency {
+            writeln!(
+                f,
+                "  {:<25}{} transactions, {}",
+                format!("bridge {} confirmations:", bridge_id),
+                latency.confirmed,
+                latency.percentiles
+            )?;
+        }
+        let medians = self.user_medians();
+        if let (Some(fastest), Some(slowest)) = (medians.first(), medians.last()) {
+            writeln!(
+                f,
+                "  user median latencies:   {:.1?} fastest, {:.1?} p50, {:.1?} slowest ({} users)",
+                fastest,
+                percentile(&medians, 50),
+                slowest,
+                medians.len()
+            )?;
+        }
+        writeln!(
+            f,
+            "  longest block gap:       {:.1?}",
+            self.longest_block_gap
+        )?;
+        if self.chain.window_blocks > 0 {
+            writeln!(f, "  recent blocks:           {}", self.chain)?;
+        }
+        if self.witness_staleness.transactions() > 0 {
+            writeln!(
+                f,
+                "  witness staleness:       {:.2} blocks mean, {} p50, {} p95, {} max",
+                self.witness_staleness.mean(),
+                self.witness_staleness.percentile(50),
+                self.witness_staleness.percentile(95),
+                self.witness_staleness.max()
+            )?;
+        }
+        for (bridge_id, latency) in &self.witness_latency {
+            writeln!(
+                f,
+                "  {:<25}{} requests, {} end to end; {} processing",
+                format!("bridge {} witnesses:", bridge_id),
+                latency.requests,
+                latency.end_to_end,
+                latency.processing
+            )?;
+        }
+        writeln!(
+            f,
+            "  proof generation time:   {:.1?}",
+            self.proof_generation_time
+        )?;
+        writeln!(
+            f,
+            "  verification time:       {:.1?}",
+            self.verification_time
+        )?;
+        writeln!(f, "  dropped messages:        {}", self.dropped_messages)?;
+        writeln!(f, "  refused messages:        {}", self.refused_messages)?;
+        if self.stalls > 0 {
+            writeln!(f, "  stalls:                  {}", self.stalls)?;
+        }
+        writeln!(f, "  forged messages:         {}", self.forged_messages)?;
+        write!(f, "  rejected blocks:         {}", self.rejected_blocks)?;
+        for kind in &MessageKind::ALL {
+            let traffic = self.bandwidth.get(kind).copied().unwrap_or_default();
+            write!(
+                f,
+                "\n  {:<25}{} bytes sent, {} received",
+                format!("{} traffic:", kind.name()),
+                traffic.bytes_sent,
+                traffic.bytes_received
+            )?;
+        }
+        for (channel, stats) in &self.channels {
+            write!(
+                f,
+                "\n  {:<25}{} queued, {} refused, {} dropped, {:.1}% drop rate",
+                format!("{} channel:", channel.name()),
+                stats.queued,
+                stats.refused,
+                stats.dropped,
+                100.0 * stats.drop_rate()
+            )?;
+        }
+        for (channel, depth) in &self.queue_depths {
+            write!(
+                f,
+                "\n  {:<25}{} deep, {} at most ({} queues)",
+                format!("{} queue:", channel.name()),
+                depth.depth,
+                depth.high_watermark,
+                depth.queues
+            )?;
+        }
+        if self.proof_cache.hits + self.proof_cache.misses > 0 {
+            write!(
+                f,
+                "\n  proof cache:             {} hits, {} misses, {:.1?} saved",
+                self.proof_cache.hits, self.proof_cache.misses, self.proof_cache.time_saved
+            )?;
+        }
+        if self.primes.elements > 0 {
+            write!(
+                f,
+                "\n  hash to prime:           {} elements, {:.1} tests each, {} collisions, {:.1?}",
+                self.primes.elements,
+                self.primes.tests_per_element(),
+                self.primes.collisions,
+                self.primes.time
+            )?;
+        }
+        if self.proof_prime_cache.hits + self.proof_prime_cache.misses > 0 {
+            write!(
+                f,
+                "\n  proof prime cache:       {} hits, {} misses, {:.1}% hit rate",
+                self.proof_prime_cache.hits,
+                self.proof_prime_cache.misses,
+                100.0 * self.proof_prime_cache.hit_rate()
+            )?;
+        }
+        for op in &ProofOp::ALL {
+            let stats = self.proofs.get(op).copied().unwrap_or_default();
+            write!(
+                f,
+                "\n  {:<25}{} made over {:.1} elements, {} bytes ({} PoE/PoKE2), {:.1?} each; \
+                 {} checked, {:.1?} each",
+                format!("{} proofs:", op.name()),
+                stats.generated,
+                stats.mean_elems(),
+                stats.bytes,
+                stats.argument_bytes,
+                stats.mean_generation_time(),
+                stats.verified,
+                stats.mean_verification_time()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn mean(durations: &[Duration]) -> Duration {
+    if durations.is_empty() {
+        return Duration::from_secs(0);
+    }
+    durations.iter().sum::<Duration>() / durations.len() as u32
+}
+
+/// The nearest-rank `p`th percentile of `sorted`, which must be in ascending order.
+pub fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_secs(0);
+    }
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
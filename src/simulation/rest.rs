@@ -0,0 +1,310 @@
+use super::bridge::{Bridge, UtxoStatus};
+use super::codec::Encode;
+use super::state::Utxo;
+use super::util::ConnectionLimit;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, NonmembershipProof, Witness};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// How long a connection may go without reading or writing anything before it is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request bodies longer than this are refused rather than read.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// Request and header lines longer than this are refused rather than read.
+const MAX_LINE_BYTES: usize = 8 << 10;
+
+/// Requests with more header lines than this are refused.
+const MAX_HEADERS: usize = 100;
+
+/// Connections served at once. Clients beyond this are answered 503 and disconnected.
+const MAX_CONNECTIONS: usize = 64;
+
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn new<M: Into<String>>(status: u16, message: M) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct WitnessesRequest {
+    utxos: Vec<Utxo>,
+}
+
+/// Serves `bridge`'s witness service as a JSON REST API at `addr`, until the process exits, so
+/// wallets and scripts outside the simulation can get witnesses and check them against the
+/// accumulator themselves. Witnesses, proofs and accumulators are hex-encoded bincode.
+///
+/// - `POST /witnesses` with `{"utxos": [...]}`: a witness to each of the UTXOs, which must be
+///   unspent and owned by the bridge's users, along with the accumulator they are for.
+/// - `GET /utxo/{id}/status`: whether a UTXO is unspent, or the height of the block that spent it.
+/// - `GET /non-membership/{id}`: a proof that a spent UTXO of one of the bridge's users is no
+///   longer in the accumulator. The bridge has to keep every UTXO to prove that.
+pub fn serve<G: UnknownOrderGroup, A: ToSocketAddrs>(
+    addr: A,
+    bridge: Arc<Mutex<Bridge<G>>>,
+) -> io::Result<JoinHandle<()>>
+where
+    Accumulator<G, Utxo>: Serialize,
+    Witness<G, Utxo>: Serialize,
+    NonmembershipProof<G, Utxo>: Serialize,
+{
+    let listener = TcpListener::bind(addr)?;
+    info!("Serving the bridge REST API on {}.", listener.local_addr()?);
+    let limit = ConnectionLimit::new(MAX_CONNECTIONS);
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("REST server error: {}", err);
+                    continue;
+                }
+            };
+            let slot = match limit.acquire() {
+                Some(slot) => slot,
+                None => {
+                    warn!(
+                        "Turning away a REST client: {} are connected.",
+                        MAX_CONNECTIONS
+                    );
+                    let busy = json!({ "error": "Too many connections" });
+                    let _ = stream
+                        .set_write_timeout(Some(IO_TIMEOUT))
+                        .and_then(|()| write_response(&mut stream, 503, &busy));
+                    continue;
+                }
+            };
+            // Each connection gets its own thread, so a slow client holds up no one else.
+            let bridge = Arc::clone(&bridge);
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(err) = respond(stream, &bridge) {
+                    warn!("REST server error: {}", err);
+                }
+            });
+        }
+    }))
+}
+
+fn respond<G: UnknownOrderGroup>(stream: TcpStream, bridge: &Mutex<Bridge<G>>) -> io::Result<()>
+where
+    Accumulator<G, Utxo>: Serialize,
+    Witness<G, Utxo>: Serialize,
+    NonmembershipProof<G, Utxo>: Serialize,
+{
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let (method, path, body) = read_request(&mut reader)?;
+    let (status, body) = match route(&method, &path, &body, bridge) {
+        Ok(body) => (200, body),
+        Err(err) => (err.status, json!({ "error": err.message })),
+    };
+    write_response(reader.get_mut(), status, &body)
+}
+
+fn write_response<W: Write>(out: &mut W, status: u16, body: &Value) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        out,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )
+}
+
+/// Reads the method, path and body of an HTTP request.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<(String, String, Vec<u8>)> {
+    let bad_request = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut request_line = String::new();
+    read_line(reader, &mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or_else(|| bad_request("Missing method"))?;
+    let path = parts.next().ok_or_else(|| bad_request("Missing path"))?;
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut content_length = 0;
+    for headers in 0.. {
+        let mut line = String::new();
+        if read_line(reader, &mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if headers == MAX_HEADERS {
+            return Err(bad_request("Too many headers"));
+        }
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or("");
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = header
+                .next()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| bad_request("Bad Content-Length"))?;
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(bad_request("Request body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((method, path, body))
+}
+
+/// Reads a line into `line` like `BufRead::read_line`, refusing one longer than `MAX_LINE_BYTES`
+/// rather than reading on.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_BYTES as u64)
+        .read_line(line)?;
+    if read == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Line too long"));
+    }
+    Ok(read)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn route<G: UnknownOrderGroup>(
+    method: &str,
+    path: &str,
+    body: &[u8],
+    bridge: &Mutex<Bridge<G>>,
+) -> Result<Value, HttpError>
+where
+    Accumulator<G, Utxo>: Serialize,
+    Witness<G, Utxo>: Serialize,
+    NonmembershipProof<G, Utxo>: Serialize,
+{
+    let path = path.split('?').next().unwrap_or("");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["witnesses"]) => witnesses(body, bridge),
+        ("GET", ["utxo", id, "status"]) => utxo_status(id, bridge),
+        ("GET", ["non-membership", id]) => non_membership(id, bridge),
+        (_, ["witnesses"]) | (_, ["utxo", _, "status"]) | (_, ["non-membership", _]) => {
+            Err(HttpError::new(405, "Method not allowed"))
+        }
+        _ => Err(HttpError::new(404, format!("No such resource: {}", path))),
+    }
+}
+
+/// `value` as hex-encoded bincode.
+fn to_hex<T: Serialize>(value: &T) -> Result<Value, HttpError> {
+    value
+        .to_hex()
+        .map(Value::from)
+        .map_err(|err| HttpError::new(500, err.to_string()))
+}
+
+fn parse_id(id: &str) -> Result<Uuid, HttpError> {
+    Uuid::parse_str(id).map_err(|err| HttpError::new(400, format!("Bad UTXO ID: {}", err)))
+}
+
+fn witnesses<G: UnknownOrderGroup>(
+    body: &[u8],
+    bridge: &Mutex<Bridge<G>>,
+) -> Result<Value, HttpError>
+where
+    Accumulator<G, Utxo>: Serialize,
+    Witness<G, Utxo>: Serialize,
+{
+    let request: WitnessesRequest = serde_json::from_slice(body)
+        .map_err(|err| HttpError::new(400, format!("Bad request body: {}", err)))?;
+    let bridge = bridge.lock().unwrap();
+    let witnesses = bridge
+        .membership_witnesses(&request.utxos)
+        .ok_or_else(|| HttpError::new(404, "The bridge does not hold all of these UTXOs unspent"))?
+        .iter()
+        .map(|(utxo, witness)| Ok(json!({ "utxo": utxo, "witness": to_hex(witness)? })))
+        .collect::<Result<Vec<Value>, HttpError>>()?;
+    Ok(json!({
+        "height": bridge.block_height(),
+        "accumulator": to_hex(bridge.acc())?,
+        "witnesses": witnesses,
+    }))
+}
+
+fn utxo_status<G: UnknownOrderGroup>(
+    id: &str,
+    bridge: &Mutex<Bridge<G>>,
+) -> Result<Value, HttpError> {
+    let id = parse_id(id)?;
+    let status = bridge
+        .lock()
+        .unwrap()
+        .utxo_status(id)
+        .ok_or_else(|| HttpError::new(404, "The bridge does not know this UTXO"))?;
+    serde_json::to_value(status).map_err(|err| HttpError::new(500, err.to_string()))
+}
+
+fn non_membership<G: UnknownOrderGroup>(
+    id: &str,
+    bridge: &Mutex<Bridge<G>>,
+) -> Result<Value, HttpError>
+where
+    Accumulator<G, Utxo>: Serialize,
+    NonmembershipProof<G, Utxo>: Serialize,
+{
+    let id = parse_id(id)?;
+    let bridge = bridge.lock().unwrap();
+    let utxo = match bridge.utxo_status(id) {
+        Some(UtxoStatus::Spent { utxo, .. }) => utxo,
+        Some(UtxoStatus::Unspent { .. }) => {
+            return Err(HttpError::new(
+                409,
+                "The UTXO is unspent, so in the accumulator",
+            ))
+        }
+        None => return Err(HttpError::new(404, "The bridge does not know this UTXO")),
+    };
+    if !bridge.keeps_all_utxos() {
+        return Err(HttpError::new(
+            501,
+            "The bridge does not keep every UTXO, so cannot prove non-membership",
+        ));
+    }
+    let proof = bridge
+        .prove_nonmembership(&utxo)
+        .ok_or_else(|| HttpError::new(500, "Failed to prove non-membership"))?;
+    Ok(json!({
+        "utxo": utxo,
+        "height": bridge.block_height(),
+        "accumulator": to_hex(bridge.acc())?,
+        "proof": to_hex(&proof)?,
+    }))
+}
@@ -0,0 +1,118 @@
+use super::bridge::{WitnessRequest, WitnessResponse};
+use super::transport::{Receiver, Sender};
+use accumulator::group::UnknownOrderGroup;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// A request, which says who sent it and which of their requests it is.
+pub trait Request {
+    fn requester(&self) -> usize;
+    fn request_id(&self) -> Uuid;
+}
+
+/// A response, which says which request it answers.
+pub trait Response {
+    fn request_id(&self) -> Uuid;
+}
+
+impl Request for WitnessRequest {
+    fn requester(&self) -> usize {
+        self.user_id
+    }
+
+    fn request_id(&self) -> Uuid {
+        self.request_id
+    }
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash> Response for WitnessResponse<G, T> {
+    fn request_id(&self) -> Uuid {
+        self.request_id
+    }
+}
+
+/// The requesting end of a request/response exchange: sends requests over one link, and takes
+/// responses from a link of the requester's own, handing back only the response to the request it
+/// awaits. Responses to anything else, e.g. late answers to requests it gave up on, are discarded
+/// rather than mistaken for the one awaited.
+pub struct Requester<'a, Req, Resp, S, R> {
+    requests: &'a S,
+    responses: &'a R,
+    awaiting: Option<Uuid>,
+    messages: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<'a, Req: Request, Resp: Response, S: Sender<Req>, R: Receiver<Resp>>
+    Requester<'a, Req, Resp, S, R>
+{
+    pub fn new(requests: &'a S, responses: &'a R) -> Self {
+        Self {
+            requests,
+            responses,
+            awaiting: None,
+            messages: PhantomData,
+        }
+    }
+
+    /// Awaits the response to `request` from now on, giving up on any request awaited before.
+    /// The request itself goes out over `requests`.
+    pub fn await_response_to(&mut self, request: &Req) {
+        self.awaiting = Some(request.request_id());
+    }
+
+    /// The link requests go out over.
+    pub fn requests(&self) -> &'a S {
+        self.requests
+    }
+
+    /// The response to the awaited request, waiting up to `timeout` for it to arrive.
+    pub fn receive_timeout(&mut self, timeout: Duration) -> Option<Resp> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let response = self.responses.receive_timeout(remaining)?;
+            if self.awaiting == Some(response.request_id()) {
+                self.awaiting = None;
+                return Some(response);
+            }
+            debug!(
+                "Discarding a response to request {}, which is not awaited.",
+                response.request_id()
+            );
+        }
+    }
+}
+
+/// The responding end of a request/response exchange: sends each response over the link to whoever
+/// sent the request it answers, and only to them.
+pub struct Responder<Resp, S> {
+    links: HashMap<usize, S>,
+    messages: PhantomData<fn(Resp)>,
+}
+
+impl<Resp, S: Sender<Resp>> Responder<Resp, S> {
+    /// A responder answering each requester over its link in `links`.
+    pub fn new(links: HashMap<usize, S>) -> Self {
+        Self {
+            links,
+            messages: PhantomData,
+        }
+    }
+
+    /// Sends `response` to whoever sent `request`. A response to a requester without a link is
+    /// dropped.
+    pub fn reply<Req: Request>(&self, request: &Req, response: Resp) {
+        match self.links.get(&request.requester()) {
+            Some(link) => link.send(response),
+            None => warn!(
+                "Dropping the response to request {} from unknown requester {}.",
+                request.request_id(),
+                request.requester()
+            ),
+        }
+    }
+}
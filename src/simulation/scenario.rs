@@ -0,0 +1,201 @@
+use super::config::{Config, Mode};
+use super::engine::EventSimulation;
+use super::report::RunResult;
+use super::state::NodeId;
+use accumulator::group::UnknownOrderGroup;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// When a scenario event fires: once the chain reaches a height, or once virtual time reaches some
+/// number of milliseconds.
+pub enum Trigger {
+    Block(u64),
+    TimeMs(u64),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Something a scenario does to a running simulation.
+pub enum Action {
+    StopMiner(usize),
+    /// Stops any node, e.g. `{ stop_node: { bridge: 1 } }`.
+    StopNode(NodeId),
+    /// Restarts a stopped node, which then catches up on what it missed.
+    StartNode(NodeId),
+    AddUsers {
+        bridge: usize,
+        count: usize,
+    },
+    /// Splits the network into groups of nodes that can only reach each other. Nodes not listed
+    /// form one more group.
+    Partition(Vec<Vec<NodeId>>),
+    /// Ends a partition.
+    Heal,
+    /// Ends the run.
+    Stop,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+pub struct ScenarioEvent {
+    pub at: Trigger,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+/// A repeatable experiment: a simulation config plus a script of timed events, e.g.
+///
+/// ```yaml
+/// config:
+///   seed: 7
+///   block_interval_ms: 1000
+/// events:
+///   - at: { block: 50 }
+///     action: { stop_miner: 2 }
+///   - at: { time_ms: 30000 }
+///     action: { add_users: { bridge: 0, count: 100 } }
+///   - at: { block: 100 }
+///     action: stop
+/// ```
+pub struct Scenario {
+    #[serde(default)]
+    pub config: Config,
+    pub events: Vec<ScenarioEvent>,
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(serde_yaml::Error),
+    /// An event names a node the run does not have.
+    UnknownNode(NodeId),
+}
+
+impl From<io::Error> for ScenarioError {
+    fn from(err: io::Error) -> Self {
+        ScenarioError::Io(err)
+    }
+}
+
+impl From<serde_yaml::Error> for ScenarioError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ScenarioError::Parse(err)
+    }
+}
+
+impl Scenario {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        let contents = fs::read_to_string(path)?;
+        let scenario: Self = serde_yaml::from_str(&contents)?;
+        match scenario.unknown_node() {
+            Some(node) => Err(ScenarioError::UnknownNode(node)),
+            None => Ok(scenario),
+        }
+    }
+
+    /// The first node an event names that the run does not have. Users added by the scenario
+    /// count, wherever their events are.
+    fn unknown_node(&self) -> Option<NodeId> {
+        let added_users: usize = self
+            .events
+            .iter()
+            .map(|event| match event.action {
+                Action::AddUsers { count, .. } => count,
+                _ => 0,
+            })
+            .sum();
+        let num_users = self.config.num_users() + added_users;
+        let exists = |node: &NodeId| match *node {
+            NodeId::Miner(miner_id) => miner_id < self.config.num_miners,
+            NodeId::Bridge(bridge_id) => bridge_id < self.config.num_bridges(),
+            NodeId::User(user_id) => user_id < num_users,
+        };
+        self.events
+            .iter()
+            .flat_map(|event| match &event.action {
+                Action::StopMiner(miner_id) => vec![NodeId::Miner(*miner_id)],
+                Action::StopNode(node) | Action::StartNode(node) => vec![*node],
+                Action::AddUsers { bridge, .. } => vec![NodeId::Bridge(*bridge)],
+                Action::Partition(groups) => groups.iter().flatten().copied().collect(),
+                Action::Heal | Action::Stop => Vec::new(),
+            })
+            .find(|node| !exists(node))
+    }
+}
+
+/// Drives a discrete-event simulation through a scenario, firing each event as soon as its trigger
+/// is reached.
+pub struct ScenarioRunner<G: UnknownOrderGroup> {
+    simulation: EventSimulation<G>,
+    pending_events: Vec<ScenarioEvent>,
+    stopped: bool,
+}
+
+impl<G: UnknownOrderGroup> ScenarioRunner<G> {
+    pub fn new(scenario: Scenario) -> Self {
+        // Scenarios are only repeatable under the discrete-event scheduler.
+        let config = Config {
+            mode: Mode::DiscreteEvent,
+            ..scenario.config
+        };
+        Self {
+            simulation: EventSimulation::new(config),
+            pending_events: scenario.events,
+            stopped: false,
+        }
+    }
+
+    /// Runs the scenario until a `stop` action fires, the config's bounds are reached or the
+    /// simulation runs out of events.
+    pub fn run(&mut self) -> RunResult<G> {
+        loop {
+            self.fire_due_events();
+            if self.stopped || self.simulation.is_finished() || !self.simulation.step() {
+                break;
+            }
+        }
+        self.simulation.finish()
+    }
+
+    fn fire_due_events(&mut self) {
+        let block_height = self.simulation.block_height();
+        // Time triggers fire before the clock would move past them.
+        let next_time = self.simulation.next_event_time();
+        let (due, pending): (Vec<_>, Vec<_>) =
+            self.pending_events
+                .drain(..)
+                .partition(|event| match event.at {
+                    Trigger::Block(height) => block_height >= height,
+                    Trigger::TimeMs(ms) => {
+                        next_time.map_or(true, |next| next >= Duration::from_millis(ms))
+                    }
+                });
+        self.pending_events = pending;
+        for event in due {
+            self.fire(event.action);
+        }
+    }
+
+    fn fire(&mut self, action: Action) {
+        match action {
+            Action::StopMiner(miner_id) => self.simulation.stop_miner(miner_id),
+            Action::StopNode(node) => self.simulation.stop_node(node),
+            Action::StartNode(node) => self.simulation.start_node(node),
+            Action::AddUsers { bridge, count } => self.simulation.add_users(bridge, count),
+            Action::Partition(groups) => self.simulation.partition(groups),
+            Action::Heal => self.simulation.heal(),
+            Action::Stop => {
+                info!(
+                    "Scenario stopping at block {}.",
+                    self.simulation.block_height()
+                );
+                self.stopped = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+/// An event waiting in the scheduler's queue. Events due at the same time are ordered by when they
+/// were scheduled, which keeps runs deterministic.
+struct Scheduled<E> {
+    at: Duration,
+    seq: u64,
+    event: E,
+}
+
+impl<E> PartialEq for Scheduled<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at && self.seq == other.seq
+    }
+}
+
+impl<E> Eq for Scheduled<E> {}
+
+impl<E> PartialOrd for Scheduled<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> Ord for Scheduled<E> {
+    // Reversed, since `BinaryHeap` is a max-heap and we want the earliest event first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+/// A discrete-event scheduler: a virtual clock plus a queue of pending events. Popping an event
+/// advances the clock to the time the event is due.
+pub struct Scheduler<E> {
+    now: Duration,
+    next_seq: u64,
+    queue: BinaryHeap<Scheduled<E>>,
+}
+
+impl<E> Default for Scheduler<E> {
+    fn default() -> Self {
+        Self {
+            now: Duration::from_millis(0),
+            next_seq: 0,
+            queue: BinaryHeap::new(),
+        }
+    }
+}
+
+impl<E> Scheduler<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current virtual time.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Schedules `event` to fire `delay` after the current virtual time.
+    pub fn schedule(&mut self, delay: Duration, event: E) {
+        self.schedule_at(self.now + delay, event);
+    }
+
+    /// Schedules `event` to fire at virtual time `at`. Times in the past fire immediately.
+    pub fn schedule_at(&mut self, at: Duration, event: E) {
+        let at = if at < self.now { self.now } else { at };
+        self.queue.push(Scheduled {
+            at,
+            seq: self.next_seq,
+            event,
+        });
+        self.next_seq += 1;
+    }
+
+    /// The time at which the next event is due, if any.
+    pub fn peek_time(&self) -> Option<Duration> {
+        self.queue.peek().map(|scheduled| scheduled.at)
+    }
+
+    /// Removes the next event and advances the clock to its due time.
+    pub fn pop(&mut self) -> Option<(Duration, E)> {
+        let scheduled = self.queue.pop()?;
+        self.now = scheduled.at;
+        Some((scheduled.at, scheduled.event))
+    }
+}
@@ -0,0 +1,185 @@
+use super::control::RunControl;
+use super::metrics::{ComponentMetrics, Metrics};
+use super::state::NodeId;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// What a component held when it shut down.
+pub enum FinalState {
+    Miner {
+        miner_id: usize,
+        block_height: u64,
+        pending_transactions: usize,
+    },
+    Bridge {
+        bridge_id: usize,
+        block_height: u64,
+        utxos: usize,
+    },
+    User {
+        user_id: usize,
+        utxos: usize,
+        /// Whether it was still waiting for a transaction to be confirmed.
+        waiting: bool,
+    },
+}
+
+impl FinalState {
+    pub fn node(&self) -> NodeId {
+        match self {
+            FinalState::Miner { miner_id, .. } => NodeId::Miner(*miner_id),
+            FinalState::Bridge { bridge_id, .. } => NodeId::Bridge(*bridge_id),
+            FinalState::User { user_id, .. } => NodeId::User(*user_id),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// How a single component ended the run.
+pub struct ComponentReport {
+    pub node: NodeId,
+    /// Its final state, or `None` if it did not shut down in time or panicked.
+    pub state: Option<FinalState>,
+    pub metrics: ComponentMetrics,
+}
+
+impl ComponentReport {
+    /// Reports for components that have shut down, with the metrics each collected, by node.
+    pub fn collect(states: Vec<FinalState>, metrics: &Metrics) -> Vec<Self> {
+        let mut components = metrics.components();
+        let mut reports: Vec<Self> = states
+            .into_iter()
+            .map(|state| Self {
+                node: state.node(),
+                metrics: components.remove(&state.node()).unwrap_or_default(),
+                state: Some(state),
+            })
+            .collect();
+        reports.sort_by_key(|report| report.node);
+        reports
+    }
+}
+
+/// Where a component runs, to be waited on as it shuts down.
+enum Component {
+    Thread(JoinHandle<FinalState>),
+    #[cfg(feature = "async-runtime")]
+    Task(tokio::task::JoinHandle<FinalState>),
+}
+
+/// The threads or tasks of a real-time run's components, which are shut down together.
+pub struct Running {
+    components: Vec<(NodeId, Component)>,
+    /// The runtime the components' tasks run on, if they are tasks.
+    #[cfg(feature = "async-runtime")]
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl Running {
+    pub fn new(threads: Vec<(NodeId, JoinHandle<FinalState>)>) -> Self {
+        Self {
+            components: threads
+                .into_iter()
+                .map(|(node, thread)| (node, Component::Thread(thread)))
+                .collect(),
+            #[cfg(feature = "async-runtime")]
+            runtime: None,
+        }
+    }
+
+    /// Components running as tasks on `runtime`, which is shut down along with them.
+    #[cfg(feature = "async-runtime")]
+    pub fn from_tasks(
+        tasks: Vec<(NodeId, tokio::task::JoinHandle<FinalState>)>,
+        runtime: tokio::runtime::Runtime,
+    ) -> Self {
+        Self {
+            components: tasks
+                .into_iter()
+                .map(|(node, task)| (node, Component::Task(task)))
+                .collect(),
+            runtime: Some(runtime),
+        }
+    }
+
+    /// Stops every component, then waits up to `timeout` of wall-clock time in all for their
+    /// threads or tasks to finish. Components that have not finished by then are left running,
+    /// and reported without a final state; tasks are dropped with their runtime.
+    pub fn shut_down(
+        self,
+        control: &RunControl,
+        timeout: Duration,
+        metrics: &Metrics,
+    ) -> Vec<ComponentReport> {
+        control.stop();
+        let deadline = Instant::now() + timeout;
+        let nodes: Vec<NodeId> = self.components.iter().map(|(node, _)| *node).collect();
+        let (done_sender, done_receiver) = mpsc::channel();
+        for (node, component) in self.components {
+            let done_sender = done_sender.clone();
+            // The receiver is gone if the deadline has passed; nobody is waiting any more.
+            match component {
+                Component::Thread(thread) => {
+                    thread::spawn(move || {
+                        let _ = done_sender.send((node, thread.join().ok()));
+                    });
+                }
+                #[cfg(feature = "async-runtime")]
+                Component::Task(task) => {
+                    let runtime = self.runtime.as_ref().expect("Tasks run on a runtime");
+                    runtime.spawn(async move {
+                        let _ = done_sender.send((node, task.await.ok()));
+                    });
+                }
+            }
+        }
+        drop(done_sender);
+
+        let mut states = HashMap::new();
+        while states.len() < nodes.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match done_receiver.recv_timeout(remaining) {
+                Ok((node, state)) => {
+                    if state.is_none() {
+                        warn!("{:?} panicked while shutting down.", node);
+                    }
+                    states.insert(node, state);
+                }
+                Err(_) => break,
+            }
+        }
+        info!(
+            "{} of {} components shut down.",
+            states.values().filter(|state| state.is_some()).count(),
+            nodes.len()
+        );
+        #[cfg(feature = "async-runtime")]
+        {
+            if let Some(runtime) = self.runtime {
+                runtime.shutdown_background();
+            }
+        }
+
+        let mut components = metrics.components();
+        let mut reports: Vec<ComponentReport> = nodes
+            .into_iter()
+            .map(|node| {
+                let state = states.remove(&node).unwrap_or_else(|| {
+                    warn!("{:?} did not shut down within {:?}.", node, timeout);
+                    None
+                });
+                ComponentReport {
+                    node,
+                    state,
+                    metrics: components.remove(&node).unwrap_or_default(),
+                }
+            })
+            .collect();
+        reports.sort_by_key(|report| report.node);
+        reports
+    }
+}
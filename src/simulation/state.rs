@@ -1,18 +1,51 @@
+use super::deletion::DeletionProof;
+use super::genesis::Mints;
+use super::swap::SwapProof;
+use super::util;
 use accumulator::group::UnknownOrderGroup;
-use accumulator::{Accumulator, MembershipProof, Witness};
+use accumulator::{Accumulator, MembershipProof, NonmembershipProof, Witness};
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::sync::Arc;
 use uuid::Uuid;
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
-/// A UTXO, defined by a UUID and the user who owns it. Note that our UTXOs do not have an
-/// associated value, since that is irrelevant to our simulation.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Identifies a simulated component, e.g. as the destination of a message.
+pub enum NodeId {
+    Miner(usize),
+    Bridge(usize),
+    User(usize),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+/// A UTXO, defined by a UUID, the user who owns it and its value. The accumulator holds the
+/// whole UTXO, so a proof that one is unspent also proves its value, and nobody can spend more than
+/// the committed state says they have.
 pub struct Utxo {
     pub id: Uuid,
     pub user_id: usize,
+    pub value: u64,
+}
+
+/// An element of the accumulator that carries a value, which transactions must not create more of
+/// than they spend.
+pub trait Valued {
+    fn value(&self) -> u64;
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+impl Valued for Utxo {
+    fn value(&self) -> u64 {
+        self.value
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "T: Serialize, Witness<G, T>: Serialize",
+    deserialize = "T: Deserialize<'de>, Witness<G, T>: Deserialize<'de>"
+))]
 /// A transaction, defined by UTXOs created and UTXOs spent. UTXOs being spent must come with a
 /// witness to prove that they are currently unspent.
 pub struct Transaction<G: UnknownOrderGroup, T: Hash + Debug> {
@@ -20,13 +53,142 @@ pub struct Transaction<G: UnknownOrderGroup, T: Hash + Debug> {
     pub utxos_spent_with_witnesses: Vec<(T, Witness<G, T>)>,
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// A transaction's ID: SHA-256 of the transaction as it feeds a hasher, which covers every UTXO
+/// it creates and spends and every witness.
+pub type TxId = [u8; 32];
+
+impl<G: UnknownOrderGroup, T: Hash + Debug> Transaction<G, T> {
+    pub fn id(&self) -> TxId {
+        util::sha256(self)
+    }
+}
+
+impl<G: UnknownOrderGroup, T: Valued + Eq + Hash + Debug> Transaction<G, T> {
+    /// Whether the transaction mints a UTXO of `mints`, e.g. a new user's first: it spends
+    /// nothing and creates that single UTXO. Mints are the only transactions that may create
+    /// value, and only the run can authorize them.
+    pub fn is_mint(&self, mints: Option<&Mints<T>>) -> bool {
+        self.utxos_spent_with_witnesses.is_empty()
+            && self.utxos_created.len() == 1
+            && mints.map_or(false, |mints| mints.is_authorized(&self.utxos_created[0]))
+    }
+
+    /// Whether the transaction is a mint of `mints` or creates no more value than it spends. Any
+    /// other transaction spending nothing creates value from nothing, and a transaction whose
+    /// total spent or created value overflows a u64 does not conserve value either.
+    pub fn conserves_value(&self, mints: Option<&Mints<T>>) -> bool {
+        if self.is_mint(mints) {
+            return true;
+        }
+        if self.utxos_spent_with_witnesses.is_empty() && !self.utxos_created.is_empty() {
+            return false;
+        }
+        let spent = total_value(self.utxos_spent_with_witnesses.iter().map(|(utxo, _)| utxo));
+        let created = total_value(self.utxos_created.iter());
+        match (spent, created) {
+            (Some(spent), Some(created)) => created <= spent,
+            _ => false,
+        }
+    }
+}
+
+/// The total value of `elems`, or None if it overflows a u64.
+pub fn total_value<'a, T: Valued + 'a>(mut elems: impl Iterator<Item = &'a T>) -> Option<u64> {
+    elems.try_fold(0u64, |total, elem| total.checked_add(elem.value()))
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "Transaction<G, T>: Serialize, Accumulator<G, T>: Serialize, \
+                 UpdateProof<G, T>: Serialize, NonmembershipProof<G, T>: Serialize",
+    deserialize = "Transaction<G, T>: Deserialize<'de>, Accumulator<G, T>: Deserialize<'de>, \
+                   UpdateProof<G, T>: Deserialize<'de>, NonmembershipProof<G, T>: Deserialize<'de>"
+))]
 /// A block, which gets cut by a miner. Blocks contain transactions, a new accumulator value, and
-/// proofs of correctness for the accumulator update.
+/// proofs of correctness for the accumulator update. The transactions are shared with the mempool
+/// they came from and with every copy of the block, so copying a block copies none of them.
 pub struct Block<G: UnknownOrderGroup, T: Hash + Debug> {
     pub height: u64,
-    pub transactions: Vec<Transaction<G, T>>,
+    pub transactions: Vec<Arc<Transaction<G, T>>>,
     pub acc_new: Accumulator<G, T>,
-    pub proof_added: MembershipProof<G, T>,
-    pub proof_deleted: MembershipProof<G, T>,
+    /// A proof that `acc_new` is the accumulator before the block with the spent UTXOs deleted
+    /// and the created ones added.
+    pub proof_update: UpdateProof<G, T>,
+    /// A proof that the UTXOs the block creates were not in the accumulator before it, so none is
+    /// created twice. Only miners that keep every UTXO can make one.
+    pub proof_fresh: Option<NonmembershipProof<G, T>>,
+    /// A commitment to `acc_new`, for the block's header. See `acc_commitment`.
+    pub acc_commitment: [u8; 32],
+    /// The commitment to the accumulator the block extends, that of its parent, which tells the
+    /// parent apart from competing blocks at the same height.
+    pub prev_acc_commitment: [u8; 32],
+}
+
+/// A block's hash: SHA-256 of the block as it feeds a hasher, which covers its transactions, its
+/// accumulator and its proofs.
+pub type BlockHash = [u8; 32];
+
+impl<G: UnknownOrderGroup, T: Hash + Debug> Block<G, T> {
+    pub fn hash(&self) -> BlockHash {
+        util::sha256(self)
+    }
+
+    /// What a header-only client keeps of the block.
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            height: self.height,
+            acc_commitment: self.acc_commitment,
+        }
+    }
+
+    /// The accumulator without the spent UTXOs, before the created ones are added, if the block's
+    /// proof carries it.
+    pub fn acc_deleted(&self) -> Option<&Accumulator<G, T>> {
+        match &self.proof_update {
+            UpdateProof::Sequential { deleted, .. } => Some(&deleted.witness.0),
+            UpdateProof::Swap(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "MembershipProof<G, T>: Serialize, DeletionProof<G, T>: Serialize, \
+                 SwapProof<G>: Serialize",
+    deserialize = "MembershipProof<G, T>: Deserialize<'de>, \
+                   DeletionProof<G, T>: Deserialize<'de>, SwapProof<G>: Deserialize<'de>"
+))]
+/// How a block proves its accumulator update.
+pub enum UpdateProof<G: UnknownOrderGroup, T: Hash + Debug> {
+    /// The spent UTXOs deleted, then the created ones added: a proof that the accumulator without
+    /// the spent UTXOs is the one before with them deleted, and a proof that the created UTXOs are
+    /// in the new accumulator whose witness is that same accumulator.
+    Sequential {
+        deleted: DeletionProof<G, T>,
+        added: MembershipProof<G, T>,
+    },
+    /// The spent UTXOs swapped for the created ones in one step, with a single proof.
+    Swap(SwapProof<G>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+/// A block's header: its height and a 32-byte commitment to its accumulator. A client following
+/// the chain by headers alone can check a proof against the accumulator as of some block, without
+/// holding every accumulator, by checking the accumulator the proof comes with against the header.
+pub struct BlockHeader {
+    pub height: u64,
+    pub acc_commitment: [u8; 32],
+}
+
+impl BlockHeader {
+    /// Whether this header commits to `acc`.
+    pub fn commits_to<G: UnknownOrderGroup, T: Hash>(&self, acc: &Accumulator<G, T>) -> bool {
+        acc_commitment(acc) == self.acc_commitment
+    }
+}
+
+/// A compact commitment to `acc`: SHA-256 of the accumulator as it feeds a hasher, which covers
+/// its group element.
+pub fn acc_commitment<G: UnknownOrderGroup, T: Hash>(acc: &Accumulator<G, T>) -> [u8; 32] {
+    util::sha256(acc)
 }
@@ -0,0 +1,182 @@
+use super::int_pool::{self, PooledInteger};
+use super::multiexp::multi_exp;
+use super::prime_cache;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::hash::hash_to_prime;
+use accumulator::{AccError, Accumulator, Witness};
+use rug::{Assign, Integer};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "G::Elem: Serialize",
+    deserialize = "G::Elem: Deserialize<'de>"
+))]
+/// A block's single proof that it swaps the UTXOs it spends for those it creates: that `acc_new`
+/// is `acc` with the spent UTXOs deleted and the created ones added, in one step rather than a
+/// deletion proof followed by a membership proof.
+///
+/// With x_d the product of the spent UTXOs' primes and x_a that of the created ones', the swap
+/// holds exactly when acc^x_a = acc_new^x_d: both are then the accumulator without the spent UTXOs
+/// raised to x_d · x_a, and since x_a and x_d are coprime, that accumulator is an x_d-th root of
+/// `acc` and an x_a-th root of `acc_new`. The proof is a proof of exponentiation (PoE, BBF V3
+/// Section 3.1) of the two sides at once: with ℓ a prime hashed from the statement, the prover
+/// sends Q_a = acc^⌊x_a/ℓ⌋ and Q_d = acc_new^⌊x_d/ℓ⌋, and the verifier checks that
+/// Q_a^ℓ · acc^(x_a mod ℓ) = Q_d^ℓ · acc_new^(x_d mod ℓ), never computing x_a or x_d in full. It
+/// is two group elements, and never needs the accumulator between the deletion and the addition.
+pub struct SwapProof<G: UnknownOrderGroup> {
+    pub q_added: G::Elem,
+    pub q_deleted: G::Elem,
+}
+
+impl<G: UnknownOrderGroup> SwapProof<G> {
+    /// Deletes `elem_witnesses` from `acc` and adds `added`, returning the accumulator after and a
+    /// proof of the swap.
+    pub fn prove<T: Clone + Eq + Hash + Debug>(
+        acc: &Accumulator<G, T>,
+        elem_witnesses: &[(T, Witness<G, T>)],
+        added: &[T],
+    ) -> Result<(Accumulator<G, T>, Self), AccError> {
+        let acc_new = acc.clone().delete(elem_witnesses)?.add(added);
+        let deleted: Vec<T> = elem_witnesses
+            .iter()
+            .map(|(elem, _witness)| elem.clone())
+            .collect();
+        let proof = Self::prove_from(acc, &acc_new, &deleted, added);
+        Ok((acc_new, proof))
+    }
+
+    /// A proof that `acc_new`, worked out by the caller, is `acc` with `deleted` swapped for
+    /// `added`.
+    pub fn prove_from<T: Clone + Eq + Hash + Debug>(
+        acc: &Accumulator<G, T>,
+        acc_new: &Accumulator<G, T>,
+        deleted: &[T],
+        added: &[T],
+    ) -> Self {
+        let l = challenge(acc, acc_new, deleted, added);
+        let mut quotient_added = int_pool::take();
+        quotient_added.assign(product(added).div_euc_ref(&l));
+        let mut quotient_deleted = int_pool::take();
+        quotient_deleted.assign(product(deleted).div_euc_ref(&l));
+        Self {
+            q_added: G::exp(acc.value(), &quotient_added),
+            q_deleted: G::exp(acc_new.value(), &quotient_deleted),
+        }
+    }
+
+    /// Whether this proves that `acc_new` is `acc` with `deleted` swapped for `added`. A UTXO both
+    /// deleted and added would make the exponents share a factor, so no such swap is proven. Each
+    /// side of the check is one multi-exponentiation, and no quotient from the network is
+    /// inverted, as that need not be possible.
+    pub fn verify<T: Clone + Eq + Hash + Debug>(
+        &self,
+        acc: &Accumulator<G, T>,
+        acc_new: &Accumulator<G, T>,
+        deleted: &[T],
+        added: &[T],
+    ) -> bool {
+        if deleted.iter().any(|elem| added.contains(elem)) {
+            return false;
+        }
+        let l = challenge(acc, acc_new, deleted, added);
+        let lhs = multi_exp::<G>(&[(&self.q_added, &l), (acc.value(), &*product_mod(added, &l))]);
+        let rhs = multi_exp::<G>(&[
+            (&self.q_deleted, &l),
+            (acc_new.value(), &*product_mod(deleted, &l)),
+        ]);
+        lhs == rhs
+    }
+}
+
+/// The product of the primes representing `elems`.
+fn product<T: Hash>(elems: &[T]) -> PooledInteger {
+    let mut product = int_pool::take();
+    product.assign(1);
+    for elem in elems {
+        prime_cache::multiply_by_prime(&mut product, elem);
+    }
+    product
+}
+
+/// The product of the primes representing `elems`, modulo `l`.
+fn product_mod<T: Hash>(elems: &[T], l: &Integer) -> PooledInteger {
+    let mut product = int_pool::take();
+    product.assign(1);
+    for elem in elems {
+        prime_cache::multiply_by_prime(&mut product, elem);
+        *product %= l;
+    }
+    product
+}
+
+/// The prime ℓ the verifier would have challenged the prover with, hashed from the whole
+/// statement: both accumulators and the elements swapped.
+fn challenge<G: UnknownOrderGroup, T: Hash>(
+    acc: &Accumulator<G, T>,
+    acc_new: &Accumulator<G, T>,
+    deleted: &[T],
+    added: &[T],
+) -> Integer {
+    hash_to_prime(&(acc.value(), acc_new.value(), deleted, added))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use accumulator::group::{ElemFrom, Group, Rsa2048};
+
+    type Acc = Accumulator<Rsa2048, &'static str>;
+
+    /// An accumulator, the one with "b" swapped for "c", and a proof of the swap.
+    fn swap() -> (Acc, Acc, SwapProof<Rsa2048>) {
+        let acc = Acc::empty().add(&["a", "b"]);
+        let acc_new = Acc::empty().add(&["a", "c"]);
+        let proof = SwapProof::prove_from(&acc, &acc_new, &["b"], &["c"]);
+        (acc, acc_new, proof)
+    }
+
+    #[test]
+    fn test_honest_proof_verifies() {
+        let (acc, acc_new, proof) = swap();
+        assert!(proof.verify(&acc, &acc_new, &["b"], &["c"]));
+    }
+
+    #[test]
+    fn test_tampered_proof_fails() {
+        let (acc, acc_new, proof) = swap();
+        let g = Rsa2048::unknown_order_elem();
+        let mut tampered = proof.clone();
+        tampered.q_added = Rsa2048::op(&tampered.q_added, &g);
+        assert!(!tampered.verify(&acc, &acc_new, &["b"], &["c"]));
+        let mut tampered = proof;
+        tampered.q_deleted = Rsa2048::op(&tampered.q_deleted, &g);
+        assert!(!tampered.verify(&acc, &acc_new, &["b"], &["c"]));
+    }
+
+    #[test]
+    fn test_non_unit_quotient_fails() {
+        let (acc, acc_new, proof) = swap();
+        let mut tampered = proof;
+        tampered.q_deleted = Rsa2048::elem(0);
+        assert!(!tampered.verify(&acc, &acc_new, &["b"], &["c"]));
+    }
+
+    #[test]
+    fn test_wrong_elements_fail() {
+        let (acc, acc_new, proof) = swap();
+        assert!(!proof.verify(&acc, &acc_new, &["a"], &["c"]));
+        assert!(!proof.verify(&acc, &acc_new, &["b"], &["d"]));
+        assert!(!proof.verify(&acc, &acc_new, &["b"], &["c", "d"]));
+        assert!(!proof.verify(&acc, &acc_new, &["b", "c"], &["c"]));
+    }
+
+    #[test]
+    fn test_swapped_accumulators_fail() {
+        let (acc, acc_new, proof) = swap();
+        assert!(!proof.verify(&acc_new, &acc, &["b"], &["c"]));
+        assert!(!proof.verify(&acc, &acc, &["b"], &["c"]));
+    }
+}
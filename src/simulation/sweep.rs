@@ -0,0 +1,281 @@
+use super::config::{Config, GroupKind, ModulusSource};
+use super::experiment::{Experiment, ExperimentError, Variant};
+use super::network::MessageKind;
+use super::report::Summary;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// A config parameter a sweep can vary.
+pub enum Parameter {
+    /// Total users, spread as evenly as possible over the bridges.
+    Users,
+    /// Number of bridges, over which the same users are spread.
+    Bridges,
+    Miners,
+    BlockIntervalMs,
+    /// Bits of a modulus generated for the `rsa` group, which the runs then work in.
+    ModulusBits,
+}
+
+impl Parameter {
+    fn name(self) -> &'static str {
+        match self {
+            Parameter::Users => "users",
+            Parameter::Bridges => "bridges",
+            Parameter::Miners => "miners",
+            Parameter::BlockIntervalMs => "block_interval_ms",
+            Parameter::ModulusBits => "modulus_bits",
+        }
+    }
+
+    fn apply(self, config: &mut Config, value: u64) {
+        let count = value as usize;
+        match self {
+            Parameter::Users => config.users_per_bridge = spread(count, config.num_bridges()),
+            Parameter::Bridges => config.users_per_bridge = spread(config.num_users(), count),
+            Parameter::Miners => config.num_miners = count,
+            Parameter::BlockIntervalMs => config.block_interval_ms = value,
+            Parameter::ModulusBits => {
+                config.group = GroupKind::Rsa;
+                config.rsa_modulus = ModulusSource::Generate(value as u32);
+            }
+        }
+    }
+}
+
+/// `users` spread over `bridges`, the first ones taking one more if they do not divide evenly.
+fn spread(users: usize, bridges: usize) -> Vec<usize> {
+    let bridges = bridges.max(1);
+    (0..bridges)
+        .map(|bridge| users / bridges + if bridge < users % bridges { 1 } else { 0 })
+        .collect()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// The values a parameter takes: listed, or `steps` values from `from` to `to` (inclusive) spaced
+/// evenly or geometrically (for which `from` must be positive).
+pub enum Values {
+    List(Vec<u64>),
+    Linear { from: u64, to: u64, steps: usize },
+    Log { from: u64, to: u64, steps: usize },
+}
+
+impl Values {
+    pub fn values(&self) -> Vec<u64> {
+        match self {
+            Values::List(values) => values.clone(),
+            Values::Linear { from, to, steps } => {
+                let (from, to) = (*from as f64, *to as f64);
+                fractions(*steps)
+                    .map(|fraction| (from + (to - from) * fraction).round() as u64)
+                    .collect()
+            }
+            Values::Log { from, to, steps } => {
+                let (from, to) = (*from as f64, *to as f64);
+                fractions(*steps)
+                    .map(|fraction| (from * (to / from).powf(fraction)).round() as u64)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// `steps` fractions spaced evenly from 0 to 1.
+fn fractions(steps: usize) -> impl Iterator<Item = f64> {
+    let intervals = steps.saturating_sub(1).max(1) as f64;
+    (0..steps).map(move |step| step as f64 / intervals)
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Axis {
+    pub parameter: Parameter,
+    pub values: Values,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+/// Runs every combination of the values of one or two parameters from a base config under the
+/// same seed, to see how a run scales with them. For example:
+///
+/// ```yaml
+/// seed: 7
+/// parallel: true
+/// output: sweep.csv
+/// base: { users_per_bridge: [0, 0, 0, 0, 0], max_blocks: 20 }
+/// axes:
+///   - parameter: users
+///     values: { log: { from: 10, to: 1000, steps: 3 } }
+///   - parameter: block_interval_ms
+///     values: { list: [100, 1000, 5000] }
+/// ```
+pub struct Sweep {
+    #[serde(default)]
+    pub seed: u64,
+    /// Whether combinations run concurrently, one per thread.
+    #[serde(default)]
+    pub parallel: bool,
+    /// CSV file to which every combination's metrics are written, if any.
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub base: Config,
+    pub axes: Vec<Axis>,
+}
+
+impl Sweep {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ExperimentError> {
+        let contents = fs::read_to_string(path)?;
+        let sweep: Self = serde_yaml::from_str(&contents)?;
+        if sweep.axes.is_empty() || sweep.axes.len() > 2 {
+            return Err(ExperimentError::Axes(sweep.axes.len()));
+        }
+        if sweep.base.max_blocks.is_none() && sweep.base.max_duration_ms.is_none() {
+            return Err(ExperimentError::Unbounded("base".to_string()));
+        }
+        Ok(sweep)
+    }
+
+    /// Runs every combination as a variant of an experiment, and lays their summaries out by
+    /// parameter value.
+    pub fn run(self) -> SweepResult {
+        let axes: Vec<(Parameter, Vec<u64>)> = self
+            .axes
+            .iter()
+            .map(|axis| (axis.parameter, axis.values.values()))
+            .collect();
+        let mut combinations: Vec<Vec<u64>> = vec![Vec::new()];
+        for (_, values) in &axes {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.push(*value);
+                        combination
+                    })
+                })
+                .collect();
+        }
+
+        let variants = combinations
+            .iter()
+            .map(|combination| {
+                let mut config = Config {
+                    // Runs would overwrite each other's metrics.
+                    output_dir: None,
+                    ..self.base.clone()
+                };
+                let mut name = Vec::new();
+                for ((parameter, _), value) in axes.iter().zip(combination) {
+                    parameter.apply(&mut config, *value);
+                    name.push(format!("{}={}", parameter.name(), value));
+                }
+                Variant {
+                    name: name.join(","),
+                    config,
+                }
+            })
+            .collect();
+        let comparison = Experiment {
+            seed: self.seed,
+            parallel: self.parallel,
+            variants,
+        }
+        .run();
+
+        let result = SweepResult {
+            axes,
+            cells: combinations
+                .into_iter()
+                .zip(comparison.rows.into_iter().map(|(_, summary)| summary))
+                .collect(),
+        };
+        if let Some(path) = &self.output {
+            if let Err(err) = result.write_csv(path) {
+                error!("Failed to write {}: {}", path.display(), err);
+            }
+        }
+        result
+    }
+}
+
+/// The summary of every combination of a sweep, displayed as a matrix of throughputs.
+pub struct SweepResult {
+    pub axes: Vec<(Parameter, Vec<u64>)>,
+    /// Each combination of parameter values, in axis order, and its summary.
+    pub cells: Vec<(Vec<u64>, Summary)>,
+}
+
+impl SweepResult {
+    /// Writes one row per combination: its parameter values, then its key metrics.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = File::create(path)?;
+        for (parameter, _) in &self.axes {
+            write!(out, "{},", parameter.name())?;
+        }
+        writeln!(
+            out,
+            "blocks,transactions,tx_per_sec,mean_confirmation_ms,p95_confirmation_ms,\
+             proof_generation_ms,verification_ms,dropped_messages,rejected_blocks,\
+             block_bytes_sent,transaction_bytes_sent,witness_bytes_sent"
+        )?;
+        for (combination, summary) in &self.cells {
+            for value in combination {
+                write!(out, "{},", value)?;
+            }
+            writeln!(
+                out,
+                "{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{},{},{},{},{}",
+                summary.blocks,
+                summary.transactions,
+                summary.transactions_per_sec(),
+                summary.mean_confirmation_latency.as_secs_f64() * 1000.0,
+                summary.p95_confirmation_latency.as_secs_f64() * 1000.0,
+                summary.proof_generation_time.as_secs_f64() * 1000.0,
+                summary.verification_time.as_secs_f64() * 1000.0,
+                summary.dropped_messages,
+                summary.rejected_blocks,
+                summary.bytes_sent(MessageKind::Block),
+                summary.bytes_sent(MessageKind::Transaction),
+                summary.bytes_sent(MessageKind::Witness)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SweepResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (rows, row_values) = &self.axes[0];
+        let columns = self.axes.get(1);
+        match columns {
+            Some((columns, _)) => writeln!(
+                f,
+                "tx/s by {} (rows) and {} (columns)",
+                rows.name(),
+                columns.name()
+            )?,
+            None => writeln!(f, "tx/s by {}", rows.name())?,
+        }
+        write!(f, "{:>10}", "")?;
+        for value in columns.map_or(&[][..], |(_, values)| &values[..]) {
+            write!(f, "  {:>10}", value)?;
+        }
+        writeln!(f)?;
+        let row_length = columns.map_or(1, |(_, values)| values.len());
+        for (row_value, row) in row_values.iter().zip(self.cells.chunks(row_length)) {
+            write!(f, "{:>10}", row_value)?;
+            for (_, summary) in row {
+                write!(f, "  {:>10.2}", summary.transactions_per_sec())?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+//! The miner, bridge and user loops of a real-time run as tasks on a tokio runtime, for
+//! `runtime: tokio`. Each component is a single task that waits on all of its links at once with
+//! `select!`, yielding its thread while it waits, so a run with thousands of users needs a thread
+//! per CPU rather than one per user. Proving and verifying are CPU-bound, so they run in
+//! `block_in_place`, which hands the worker's other tasks to another thread meanwhile.
+use super::bridge::{Bridge, WitnessBatching, WitnessRequest};
+use super::builder::{BridgeHandle, MinerHandle, UserHandle};
+use super::clock::Clock;
+use super::control::RunControl;
+use super::injection::FaultInjector;
+use super::metrics::Metrics;
+use super::miner::Miner;
+use super::pipeline::ValidationPipeline;
+use super::rpc::Responder;
+use super::shutdown::FinalState;
+use super::state::{NodeId, Utxo};
+use super::transport::{QueueReceiver, Receiver, SendError, RECEIVE_TIMEOUT};
+use super::user::User;
+use accumulator::group::UnknownOrderGroup;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task;
+use tracing::{debug, error};
+
+/// How often a task waiting on a clock that does not move with real time checks it again.
+const CLOCK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long a user waits before trying again to send a message a link would not take.
+const BACKOFF: Duration = Duration::from_millis(50);
+
+/// At most how many queued witness requests a bridge takes at once to serve together.
+const MAX_WITNESS_BURST: usize = 64;
+
+/// Waits until `duration` of simulated time has passed on `clock`.
+async fn sleep(clock: Arc<dyn Clock>, duration: Duration) {
+    match clock.real_time(duration) {
+        Some(real) => tokio::time::sleep(real).await,
+        None => {
+            let wake_at = clock.now() + duration;
+            while clock.now() < wake_at {
+                tokio::time::sleep(CLOCK_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// The task version of `FaultInjector::interrupt`: freezes and delays the task as `node`'s faults
+/// say, then returns whether `node` is still up.
+async fn interrupt(node: NodeId, faults: &FaultInjector, clock: &Arc<dyn Clock>) -> bool {
+    let interruption = faults.check(node);
+    if interruption.crashed {
+        return false;
+    }
+    sleep(clock.clone(), interruption.freeze + interruption.delay).await;
+    true
+}
+
+/// The task version of checking in at the top of a loop: waits while the run is paused, then
+/// applies `node`'s faults. Returns false once the run has been stopped or `node` has crashed.
+async fn check_in(
+    node: NodeId,
+    control: &RunControl,
+    faults: &FaultInjector,
+    clock: &Arc<dyn Clock>,
+) -> bool {
+    while control.is_paused() {
+        tokio::time::sleep(RECEIVE_TIMEOUT).await;
+    }
+    !control.is_stopped() && interrupt(node, faults, clock).await
+}
+
+/// Runs a miner until the run is stopped or the miner crashes, taking transactions and blocks as
+/// they arrive and, if it leads or is byzantine, forging on its interval. Blocks go to the miner's
+/// validation pipeline if it has one, whose stages run on threads of their own. See
+/// `Miner::start`.
+pub async fn miner<G: UnknownOrderGroup>(
+    miner_ref: Arc<Miner<G, Utxo>>,
+    handle: MinerHandle<G>,
+    clock: Arc<dyn Clock>,
+    control: Arc<RunControl>,
+    faults: Arc<FaultInjector>,
+) -> FinalState {
+    let MinerHandle {
+        miner_id,
+        is_leader,
+        byzantine,
+        block_interval_ms,
+        block_sender,
+        mut block_receiver,
+        mut tx_receiver,
+        ..
+    } = handle;
+    let node = NodeId::Miner(miner_id);
+    let interval = Duration::from_millis(block_interval_ms);
+    let forges = is_leader || byzantine.is_some();
+    // Byzantine miners forge halfway between the leader's blocks, so corrupt blocks race honest
+    // ones.
+    let first_forge = if is_leader { interval } else { interval / 2 };
+    let next_forge = sleep(clock.clone(), first_forge);
+    tokio::pin!(next_forge);
+    let pipeline = if miner_ref.pipelines_validation() {
+        Some(ValidationPipeline::start(miner_ref.clone()))
+    } else {
+        None
+    };
+
+    while check_in(node, &control, &faults, &clock).await {
+        tokio::select! {
+            Some(tx) = tx_receiver.receive_async(RECEIVE_TIMEOUT) => {
+                task::block_in_place(|| miner_ref.add_transaction(tx));
+            }
+            Some(block) = block_receiver.receive_async(RECEIVE_TIMEOUT) => {
+                task::block_in_place(|| match &pipeline {
+                    Some(pipeline) => pipeline.submit(block),
+                    None => miner_ref.validate_block(block),
+                });
+            }
+            _ = &mut next_forge, if forges => {
+                if is_leader && !task::block_in_place(|| control.wait_for_block_permit()) {
+                    break;
+                }
+                let new_block = task::block_in_place(|| match byzantine {
+                    Some(behavior) if !is_leader => miner_ref.forge_byzantine_block(behavior),
+                    _ => miner_ref.forge_block(),
+                });
+                match new_block {
+                    Ok(block) => {
+                        let block = faults.tamper(node, block);
+                        task::block_in_place(|| block_sender.send(block));
+                    }
+                    Err(_) if is_leader => error!("Fail on forging block"),
+                    Err(_) => (),
+                }
+                next_forge.set(sleep(clock.clone(), interval));
+            }
+            else => (),
+        }
+    }
+    if let Some(pipeline) = pipeline {
+        task::block_in_place(|| pipeline.finish());
+    }
+    miner_ref.final_state()
+}
+
+/// Runs a bridge until the run is stopped or the bridge crashes, applying blocks and answering
+/// witness requests as they arrive. Requests queued behind one another are served together. See
+/// `Bridge::start`.
+pub async fn bridge<G: UnknownOrderGroup>(
+    bridge_ref: Arc<Mutex<Bridge<G>>>,
+    handle: BridgeHandle<G>,
+    clock: Arc<dyn Clock>,
+    control: Arc<RunControl>,
+    faults: Arc<FaultInjector>,
+) -> FinalState {
+    let BridgeHandle {
+        bridge_id,
+        mut block_receiver,
+        mut witness_request_receiver,
+        witness_response_senders,
+        user_update_senders,
+        witness_batching,
+        ..
+    } = handle;
+    let node = NodeId::Bridge(bridge_id);
+    let responder = Responder::new(witness_response_senders);
+
+    while check_in(node, &control, &faults, &clock).await {
+        tokio::select! {
+            Some(block) = block_receiver.receive_async(RECEIVE_TIMEOUT) => {
+                task::block_in_place(|| {
+                    for (user_id, update) in bridge_ref.lock().unwrap().update(&block) {
+                        user_update_senders[&user_id].send(faults.tamper(node, update));
+                    }
+                });
+            }
+            Some(request) = witness_request_receiver.receive_async(RECEIVE_TIMEOUT) => {
+                let requests = gather_witness_requests(
+                    &mut witness_request_receiver,
+                    request,
+                    witness_batching,
+                )
+                .await;
+                task::block_in_place(|| {
+                    let responses = bridge_ref.lock().unwrap().handle_witness_requests(&requests);
+                    for (request, response) in requests.iter().zip(responses) {
+                        responder.reply(request, faults.tamper(node, response));
+                    }
+                });
+            }
+            else => (),
+        }
+    }
+    let final_state = bridge_ref.lock().unwrap().final_state();
+    final_state
+}
+
+/// The task version of the bridge's gathering of the witness requests to serve along with
+/// `first`: with `batching`, those arriving within its window, and otherwise those already queued
+/// behind it.
+async fn gather_witness_requests(
+    receiver: &mut QueueReceiver<WitnessRequest>,
+    first: WitnessRequest,
+    batching: Option<WitnessBatching>,
+) -> Vec<WitnessRequest> {
+    let mut requests = vec![first];
+    match batching {
+        Some(batching) => {
+            let deadline = Instant::now() + batching.window;
+            while requests.len() < batching.max_requests {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                match receiver.receive_async(deadline - now).await {
+                    Some(request) => requests.push(request),
+                    None => break,
+                }
+            }
+        }
+        None => {
+            while requests.len() < MAX_WITNESS_BURST {
+                match receiver.try_receive() {
+                    Some(request) => requests.push(request),
+                    None => break,
+                }
+            }
+        }
+    }
+    requests
+}
+
+/// Runs a user until the run is stopped or the user crashes: asks its bridge for a witness, spends
+/// its UTXO, waits for the update confirming it, and starts over after a while if any of those is
+/// lost. See `User::start`.
+pub async fn user<G: UnknownOrderGroup>(
+    handle: UserHandle<G>,
+    clock: Arc<dyn Clock>,
+    control: Arc<RunControl>,
+    metrics: Arc<Metrics>,
+    faults: Arc<FaultInjector>,
+) -> FinalState {
+    let UserHandle {
+        user_id,
+        bridge_id,
+        utxo,
+        witness_request_sender,
+        mut witness_response_receiver,
+        mut user_update_receiver,
+        tx_sender,
+        retry_timeout,
+    } = handle;
+    let node = NodeId::User(user_id);
+    let mut user = User::<G>::new(user_id, bridge_id, utxo, metrics);
+    // `thread_rng` cannot move between the threads a task runs on.
+    let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+
+    'attempt: loop {
+        sleep(clock.clone(), Duration::from_millis(10)).await;
+        if !check_in(node, &control, &faults, &clock).await {
+            break;
+        }
+
+        let request = user.witness_request(&mut rng);
+        let request_id = request.request_id;
+        let deadline = retry_timeout.map(|timeout| clock.now() + timeout);
+        let timed_out = || deadline.map_or(false, |deadline| clock.now() >= deadline);
+        let mut request = faults.tamper(node, request);
+        while let Err(err) = witness_request_sender.try_send(request) {
+            if !back_off(&err, &clock, &control, &timed_out).await {
+                continue 'attempt;
+            }
+            request = err.into_inner();
+        }
+
+        let response = loop {
+            if let Some(response) = witness_response_receiver
+                .receive_async(RECEIVE_TIMEOUT)
+                .await
+            {
+                if response.request_id == request_id {
+                    break response;
+                }
+                debug!(
+                    "Discarding a response to request {}, which is not awaited.",
+                    response.request_id
+                );
+            }
+            if control.is_stopped() || !interrupt(node, &faults, &clock).await {
+                break 'attempt;
+            }
+            if timed_out() {
+                debug!("No witness received; retrying.");
+                continue 'attempt;
+            }
+        };
+        let new_trans = match user.transaction_from_response(response, &mut rng) {
+            Some(transaction) => transaction,
+            None => continue 'attempt,
+        };
+
+        let mut new_trans = Arc::new(faults.tamper(node, new_trans));
+        while let Err(err) = tx_sender.try_send(new_trans) {
+            if !back_off(&err, &clock, &control, &timed_out).await {
+                continue 'attempt;
+            }
+            new_trans = err.into_inner();
+        }
+
+        // Keep processing UTXO updates from the bridge until one confirms the transaction.
+        loop {
+            if let Some(update) = user_update_receiver.receive_async(RECEIVE_TIMEOUT).await {
+                if user.apply_update(update) {
+                    break;
+                }
+            }
+            if control.is_stopped() || !interrupt(node, &faults, &clock).await {
+                break 'attempt;
+            }
+            if timed_out() {
+                debug!("Transaction not confirmed; retrying.");
+                continue 'attempt;
+            }
+        }
+    }
+    user.final_state()
+}
+
+/// Backs off after a link pushed back with `err`, unless the run has stopped or the attempt timed
+/// out, in which case it returns false to give up.
+async fn back_off<T, F: Fn() -> bool>(
+    err: &SendError<T>,
+    clock: &Arc<dyn Clock>,
+    control: &RunControl,
+    timed_out: &F,
+) -> bool {
+    debug!("{}; backing off.", err);
+    if control.is_stopped() || timed_out() {
+        return false;
+    }
+    sleep(clock.clone(), BACKOFF).await;
+    true
+}
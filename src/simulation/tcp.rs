@@ -0,0 +1,535 @@
+use super::auth::{Keyring, Signer};
+use super::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::handshake::{self, HandshakeError, Hello};
+use super::metrics::Metrics;
+use super::noise::{self, NoiseKeys, Stream};
+use super::state::{Block, NodeId, Transaction, Utxo};
+use super::transport::{BlockTopic, Receiver, SendError, Sender, TxTopic, WitnessChannel};
+use super::wire::{self, WireError, WireKind, WireMessage};
+use accumulator::group::UnknownOrderGroup;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::{self, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Frames longer than this are taken to be garbage, and the connection they arrive on dropped.
+const MAX_FRAME_BYTES: usize = 64 << 20;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// A peer that takes longer than this to accept a frame is dropped, so a stuck peer does not hold
+/// up the sender for long.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a sender leaves a peer that turned it away before trying it again, in case it has
+/// been upgraded since.
+const REJECTED_RETRY: Duration = Duration::from_secs(10);
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// How the body of a frame is encoded: as is, or compressed with zstd.
+const CODEC_PLAIN: u8 = 0;
+const CODEC_ZSTD: u8 = 1;
+/// How hard to try compressing blocks; zstd's default, which is quick and does well enough on
+/// big numbers.
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// `message` as a frame: its length as a big-endian `u32`, then the codec of its body, then its
+/// wire encoding.
+pub fn encode_frame<T: WireMessage>(message: &T) -> io::Result<Vec<u8>> {
+    frame(CODEC_PLAIN, &wire::encode(message).map_err(invalid_data)?)
+}
+
+fn frame(codec: u8, body: &[u8]) -> io::Result<Vec<u8>> {
+    if body.len() > MAX_FRAME_BYTES {
+        return Err(invalid_data(format!(
+            "A {}-byte message is too large to send",
+            body.len()
+        )));
+    }
+    let mut frame = Vec::with_capacity(5 + body.len());
+    frame.extend_from_slice(&(body.len() as u32 + 1).to_be_bytes());
+    frame.push(codec);
+    frame.extend_from_slice(body);
+    Ok(frame)
+}
+
+/// `body` compressed, if that makes it any smaller.
+#[cfg(feature = "compression")]
+fn compress(body: &[u8]) -> Option<Vec<u8>> {
+    zstd::bulk::compress(body, ZSTD_LEVEL)
+        .ok()
+        .filter(|compressed| compressed.len() < body.len())
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_body: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(feature = "compression")]
+fn decompress(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    zstd::stream::read::Decoder::new(body)?
+        .take(MAX_FRAME_BYTES as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > MAX_FRAME_BYTES {
+        return Err(invalid_data("A compressed frame is too large"));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_body: &[u8]) -> io::Result<Vec<u8>> {
+    Err(invalid_data(
+        "Compressed frames need the compression feature",
+    ))
+}
+
+/// Reads a single frame written by `encode_frame` and decodes the message in it.
+pub fn read_frame<T: WireMessage, R: Read>(reader: &mut R) -> io::Result<T> {
+    wire::decode(&read_body(reader)?).map_err(invalid_data)
+}
+
+/// Reads a single frame and returns its body, decompressed.
+fn read_body<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    read_raw_body(reader).and_then(decode_body)
+}
+
+/// Reads a single frame and returns its body as sent: the codec, then the encoded message.
+fn read_raw_body<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_FRAME_BYTES + 1 {
+        return Err(invalid_data(format!("A {}-byte frame is malformed", len)));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// The encoded message in a frame body read by `read_raw_body`, decompressed.
+fn decode_body(mut body: Vec<u8>) -> io::Result<Vec<u8>> {
+    match body[0] {
+        CODEC_PLAIN => {
+            body.remove(0);
+            Ok(body)
+        }
+        CODEC_ZSTD => decompress(&body[1..]),
+        codec => Err(invalid_data(format!("Unknown frame codec {}", codec))),
+    }
+}
+
+/// A connection to a peer, and whether the two agreed on compact blocks.
+struct Connection {
+    stream: Box<dyn Stream>,
+    compact_blocks: bool,
+}
+
+/// What a sender knows of its link to a peer.
+enum Link {
+    Down,
+    Up(Connection),
+    /// The peer turned the sender away in the handshake, at the given time.
+    Rejected(Instant),
+}
+
+/// Connects to `peer`, encrypting the connection if given keys, and shakes hands on it carrying
+/// `kind` messages. A peer that cannot be reached leaves the link down, while an incompatible one
+/// is left alone for a while.
+fn connect(peer: &SocketAddr, kind: WireKind, keys: Option<&NoiseKeys>) -> Link {
+    let stream = match TcpStream::connect_timeout(peer, CONNECT_TIMEOUT) {
+        Ok(stream) => stream,
+        Err(err) => {
+            debug!("Failed to connect to {}: {}", peer, err);
+            return Link::Down;
+        }
+    };
+    // Messages are small and latency matters more than throughput.
+    let _ = stream.set_nodelay(true);
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+    let mut stream = match noise::initiate(stream, keys) {
+        Ok(stream) => stream,
+        Err(err) => {
+            debug!("Failed to encrypt the connection to {}: {}", peer, err);
+            return Link::Down;
+        }
+    };
+    match handshake::initiate(&mut stream, &Hello::new(kind)) {
+        Ok(agreement) => Link::Up(Connection {
+            stream,
+            compact_blocks: agreement.features.compact_blocks,
+        }),
+        Err(err @ HandshakeError::Rejected(_)) | Err(err @ HandshakeError::Incompatible(_)) => {
+            warn!("Not sending to {} for {:?}: {}", peer, REJECTED_RETRY, err);
+            Link::Rejected(Instant::now())
+        }
+        Err(err) => {
+            debug!("Failed to shake hands with {}: {}", peer, err);
+            Link::Down
+        }
+    }
+}
+
+/// Sends every message to each of a fixed set of peers over TCP. Connections are made on first
+/// use and remade after they fail; a message a peer cannot be reached for is dropped, like one
+/// lost on a lossy link, as are messages to a peer that turned the sender away in the handshake.
+pub struct TcpSender<T> {
+    /// The component sending, whose traffic the frames written count towards.
+    node: NodeId,
+    peers: Vec<SocketAddr>,
+    links: Mutex<Vec<Link>>,
+    metrics: Arc<Metrics>,
+    signer: Option<Signer>,
+    compress_blocks: bool,
+    noise: Option<NoiseKeys>,
+    message: PhantomData<fn(T)>,
+}
+
+impl<T: WireMessage> TcpSender<T> {
+    pub fn new(node: NodeId, peers: Vec<SocketAddr>, metrics: Arc<Metrics>) -> Self {
+        let links = peers.iter().map(|_| Link::Down).collect();
+        Self {
+            node,
+            peers,
+            links: Mutex::new(links),
+            metrics,
+            signer: None,
+            compress_blocks: false,
+            noise: None,
+            message: PhantomData,
+        }
+    }
+
+    /// Signs every message sent from now on with `signer`.
+    pub fn signed(mut self, signer: Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Encrypts connections made from now on with `keys`. Peers must accept encrypted connections
+    /// with the same pre-shared key.
+    pub fn encrypted(mut self, keys: NoiseKeys) -> Self {
+        self.noise = Some(keys);
+        self
+    }
+
+    /// Sends blocks compressed from now on to the peers that can decompress them. Other messages
+    /// are small, and always go as they are.
+    pub fn compressing_blocks(mut self) -> Self {
+        self.compress_blocks = true;
+        self
+    }
+
+    pub fn send(&self, message: T) {
+        let encoded = match &self.signer {
+            Some(signer) => wire::encode_signed(&message, signer),
+            None => wire::encode(&message),
+        };
+        let frames = encoded.map_err(invalid_data).and_then(|body| {
+            let compact = if self.compress_blocks && T::KIND == WireKind::Block {
+                compress(&body)
+                    .map(|compressed| frame(CODEC_ZSTD, &compressed))
+                    .transpose()?
+            } else {
+                None
+            };
+            Ok((frame(CODEC_PLAIN, &body)?, compact))
+        });
+        let (plain, compact) = match frames {
+            Ok(frames) => frames,
+            Err(err) => {
+                warn!("Failed to encode a message: {}", err);
+                self.metrics.message_dropped();
+                return;
+            }
+        };
+        let mut links = self.links.lock().unwrap();
+        for (peer, link) in self.peers.iter().zip(links.iter_mut()) {
+            let retry = match link {
+                Link::Down => true,
+                Link::Up(_) => false,
+                Link::Rejected(at) => at.elapsed() >= REJECTED_RETRY,
+            };
+            if retry {
+                *link = connect(peer, T::KIND, self.noise.as_ref());
+            }
+            let sent = match link {
+                Link::Up(connection) => match &compact {
+                    Some(compact) if connection.compact_blocks => {
+                        let sent = connection.stream.write_all(compact).ok();
+                        if sent.is_some() {
+                            self.metrics.block_compressed(plain.len(), compact.len());
+                        }
+                        sent.map(|_| compact.len())
+                    }
+                    _ => connection
+                        .stream
+                        .write_all(&plain)
+                        .ok()
+                        .map(|_| plain.len()),
+                },
+                Link::Down | Link::Rejected(_) => None,
+            };
+            if let Some(bytes) = sent {
+                self.metrics.bytes_sent(self.node, T::KIND.traffic(), bytes);
+            } else {
+                debug!("Dropping a message to {}.", peer);
+                if let Link::Up(_) = link {
+                    *link = Link::Down;
+                }
+                self.metrics.message_dropped();
+            }
+        }
+    }
+}
+
+/// Receives messages from any peer that connects to the address it listens on. A thread per
+/// connection decodes frames as they arrive and queues them up to be received.
+pub struct TcpReceiver<T> {
+    local_addr: SocketAddr,
+    messages: mpsc::Receiver<T>,
+}
+
+#[derive(Clone)]
+/// Checks the signatures on the messages a receiver gets, counting those it drops as forged.
+pub struct Verifier {
+    keyring: Arc<Keyring>,
+    metrics: Arc<Metrics>,
+}
+
+impl Verifier {
+    pub fn new(keyring: Arc<Keyring>, metrics: Arc<Metrics>) -> Self {
+        Self { keyring, metrics }
+    }
+
+    /// Decodes the message in `body`, from `peer`, or `None` if it is unsigned or forged.
+    pub fn decode<T: WireMessage>(&self, body: &[u8], peer: &str) -> Result<Option<T>, WireError> {
+        match wire::decode_verified(body, &self.keyring) {
+            Ok((_, message)) => Ok(Some(message)),
+            Err(err @ WireError::Unsigned(_)) | Err(err @ WireError::Forged(_)) => {
+                warn!("Dropping a message from {}: {}", peer, err);
+                self.metrics.message_forged();
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[derive(Clone)]
+/// Counts what a receiver reads as traffic received by a component.
+pub struct Meter {
+    node: NodeId,
+    metrics: Arc<Metrics>,
+}
+
+impl Meter {
+    pub fn new(node: NodeId, metrics: Arc<Metrics>) -> Self {
+        Self { node, metrics }
+    }
+
+    /// Counts `bytes` read for a message of type `T`.
+    pub fn received<T: WireMessage>(&self, bytes: usize) {
+        self.metrics
+            .bytes_received(self.node, T::KIND.traffic(), bytes);
+    }
+}
+
+impl<T: WireMessage + Send + 'static> TcpReceiver<T> {
+    /// Listens on `addr`. Port 0 picks any free port; see `local_addr`.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::bind_with(addr, None, None, None)
+    }
+
+    /// Like `bind`, but drops messages whose signatures do not check out against `keyring`, and
+    /// blocks, transactions and witness responses that are not signed at all.
+    pub fn bind_verified<A: ToSocketAddrs>(
+        addr: A,
+        keyring: Arc<Keyring>,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Self> {
+        Self::bind_with(addr, Some(Verifier::new(keyring, metrics)), None, None)
+    }
+
+    /// Like `bind`, but checking signatures with `verifier` if given, only accepting connections
+    /// encrypted with the pre-shared key in `keys` if given, and counting the frames read with
+    /// `meter` if given.
+    pub fn bind_with<A: ToSocketAddrs>(
+        addr: A,
+        verifier: Option<Verifier>,
+        keys: Option<NoiseKeys>,
+        meter: Option<Meter>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        let verifier = verifier.clone();
+                        let keys = keys.clone();
+                        let meter = meter.clone();
+                        thread::spawn(move || read_frames(stream, sender, verifier, keys, meter));
+                    }
+                    Err(err) => warn!("Failed to accept a connection: {}", err),
+                }
+            }
+        });
+        Ok(Self {
+            local_addr,
+            messages,
+        })
+    }
+
+    /// The address peers should send to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+/// Decrypts the connection on `stream` if given keys and shakes hands with the peer, turning it
+/// away if it is incompatible, then queues up the messages arriving on it until the peer hangs up
+/// or sends garbage. Forged messages are dropped, but the connection is kept.
+fn read_frames<T: WireMessage>(
+    stream: TcpStream,
+    messages: mpsc::Sender<T>,
+    verifier: Option<Verifier>,
+    keys: Option<NoiseKeys>,
+    meter: Option<Meter>,
+) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "a peer".to_string(), |addr| addr.to_string());
+    let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+    // A handle to lift the timeout by once the connection is set up.
+    let tcp_stream = match stream.try_clone() {
+        Ok(tcp_stream) => tcp_stream,
+        Err(err) => {
+            debug!("Failed to set up the connection from {}: {}", peer, err);
+            return;
+        }
+    };
+    let mut stream = match noise::respond(stream, keys.as_ref()) {
+        Ok(stream) => stream,
+        Err(err) => {
+            warn!(
+                "Turned {} away: failed to encrypt the connection: {}",
+                peer, err
+            );
+            return;
+        }
+    };
+    match handshake::respond(&mut stream, &Hello::new(T::KIND)) {
+        Ok(agreement) => debug!(
+            "{} connected on protocol version {}.",
+            peer, agreement.version
+        ),
+        Err(err @ HandshakeError::Incompatible(_)) => {
+            warn!("Turned {} away: {}", peer, err);
+            return;
+        }
+        Err(err) => {
+            debug!("Failed to shake hands with {}: {}", peer, err);
+            return;
+        }
+    }
+    let _ = tcp_stream.set_read_timeout(None);
+    let mut reader = BufReader::new(stream);
+    loop {
+        let body = read_raw_body(&mut reader).and_then(|body| {
+            if let Some(meter) = &meter {
+                // The body and its length prefix.
+                meter.received::<T>(4 + body.len());
+            }
+            decode_body(body)
+        });
+        let message = body.and_then(|body| {
+            let decoded = match &verifier {
+                Some(verifier) => verifier.decode(&body, &peer),
+                None => wire::decode(&body).map(Some),
+            };
+            decoded.map_err(invalid_data)
+        });
+        match message {
+            Ok(None) => {}
+            Ok(Some(message)) => {
+                if messages.send(message).is_err() {
+                    // Nobody is receiving any more.
+                    break;
+                }
+            }
+            Err(err) => {
+                if err.kind() != io::ErrorKind::UnexpectedEof {
+                    warn!("Dropping the connection from {}: {}", peer, err);
+                }
+                break;
+            }
+        }
+    }
+}
+
+impl<T: WireMessage + 'static> Sender<T> for TcpSender<T> {
+    /// TCP pushes back by itself: each write waits until the peer takes it, or times out and
+    /// drops the message, so the sender never gets one back.
+    fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        TcpSender::send(self, message);
+        Ok(())
+    }
+
+    fn send(&self, message: T) {
+        TcpSender::send(self, message);
+    }
+}
+
+impl<T: WireMessage + Send + 'static> Receiver<T> for TcpReceiver<T> {
+    fn try_receive(&self) -> Option<T> {
+        self.messages.try_recv().ok()
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        self.messages.recv_timeout(timeout).ok()
+    }
+}
+
+/// Length-prefixed wire-format frames over TCP, so components can run in separate processes, on one
+/// machine or several.
+pub struct Tcp;
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> BlockTopic<G, T> for Tcp
+where
+    Block<G, T>: WireMessage + Send + Sync + 'static,
+{
+    type BlockSender = TcpSender<Arc<Block<G, T>>>;
+    type BlockReceiver = TcpReceiver<Arc<Block<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> TxTopic<G, T> for Tcp
+where
+    Transaction<G, T>: WireMessage + Send + Sync + 'static,
+{
+    type TxSender = TcpSender<Arc<Transaction<G, T>>>;
+    type TxReceiver = TcpReceiver<Arc<Transaction<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup> WitnessChannel<G> for Tcp
+where
+    WitnessResponse<G, Utxo>: WireMessage + Send + 'static,
+    UserUpdate<G>: WireMessage + Send + 'static,
+{
+    type RequestSender = TcpSender<WitnessRequest>;
+    type RequestReceiver = TcpReceiver<WitnessRequest>;
+    type ResponseSender = TcpSender<WitnessResponse<G, Utxo>>;
+    type ResponseReceiver = TcpReceiver<WitnessResponse<G, Utxo>>;
+    type UpdateSender = TcpSender<UserUpdate<G>>;
+    type UpdateReceiver = TcpReceiver<UserUpdate<G>>;
+}
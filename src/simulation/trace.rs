@@ -0,0 +1,143 @@
+use super::network::MessageKind;
+use super::state::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+/// A message as it crossed the simulated network. Times are virtual, in microseconds since the
+/// start of the run; a lost message has no delivery times, a duplicated one has two.
+pub struct TraceRecord {
+    pub sent_us: u64,
+    pub from: NodeId,
+    pub to: NodeId,
+    pub kind: MessageKind,
+    /// What the message carried, e.g. `block 12 (40 transactions)`.
+    pub message: String,
+    pub delivered_us: Vec<u64>,
+}
+
+impl TraceRecord {
+    pub fn new(
+        sent: Duration,
+        from: NodeId,
+        to: NodeId,
+        kind: MessageKind,
+        message: String,
+        deliveries: &[Duration],
+    ) -> Self {
+        Self {
+            sent_us: sent.as_micros() as u64,
+            from,
+            to,
+            kind,
+            message,
+            delivered_us: deliveries.iter().map(|at| at.as_micros() as u64).collect(),
+        }
+    }
+
+    pub fn deliveries(&self) -> Vec<Duration> {
+        self.delivered_us
+            .iter()
+            .map(|us| Duration::from_micros(*us))
+            .collect()
+    }
+
+    /// Whether `other` is the same message sent at the same time, whenever it arrived.
+    fn is_same_message(&self, other: &Self) -> bool {
+        self.sent_us == other.sent_us
+            && self.from == other.from
+            && self.to == other.to
+            && self.kind == other.kind
+            && self.message == other.message
+    }
+}
+
+/// Writes every message of a discrete-event run to a trace file, one JSON record per line.
+pub struct TraceRecorder {
+    out: BufWriter<File>,
+}
+
+impl TraceRecorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(&mut self, record: &TraceRecord) {
+        let result = serde_json::to_writer(&mut self.out, record)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(self.out));
+        if let Err(err) = result {
+            warn!("Failed to write to the message trace: {}", err);
+        }
+    }
+}
+
+/// Feeds a recorded trace back into a discrete-event run: each message is delivered exactly when
+/// (and as often as) it was in the recording, whatever the network would decide now. As long as
+/// the run sends the same messages as the recorded one, it replays it exactly. The first message
+/// that differs is logged, and from then on the network decides again.
+pub struct TraceReplayer {
+    records: VecDeque<TraceRecord>,
+    replayed: usize,
+    diverged: bool,
+}
+
+impl TraceReplayer {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let records = fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+            .collect::<io::Result<VecDeque<TraceRecord>>>()?;
+        info!("Replaying a trace of {} messages.", records.len());
+        Ok(Self {
+            records,
+            replayed: 0,
+            diverged: false,
+        })
+    }
+
+    /// The recording of `sent`, the next message of the run, if it matches the next message of
+    /// the trace.
+    pub fn replay(&mut self, sent: &TraceRecord) -> Option<TraceRecord> {
+        if self.diverged {
+            return None;
+        }
+        match self.records.pop_front() {
+            Some(recorded) if recorded.is_same_message(sent) => {
+                self.replayed += 1;
+                Some(recorded)
+            }
+            recorded => {
+                warn!(
+                    "Run diverged from the trace after {} messages: sent {:?}, recorded {:?}.",
+                    self.replayed, sent, recorded
+                );
+                self.diverged = true;
+                None
+            }
+        }
+    }
+
+    /// Logs how much of the trace the run replayed.
+    pub fn report(&self) {
+        if self.diverged {
+            warn!("Replayed {} messages before diverging.", self.replayed);
+        } else if !self.records.is_empty() {
+            warn!(
+                "Replayed {} messages; the run ended before the other {}.",
+                self.replayed,
+                self.records.len()
+            );
+        } else {
+            info!("Replayed all {} messages of the trace.", self.replayed);
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use super::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::network::{LossySender, MessageKind, Payload};
+use super::state::{Block, Transaction, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use multiqueue::{broadcast_queue, BroadcastReceiver, BroadcastSender};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+use std::sync::mpsc::TrySendError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a component waits for a message before looking up from its link, e.g. to see whether
+/// the run has been paused or stopped. Waiting takes no CPU, so this only bounds how long a
+/// component takes to notice.
+pub const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// The queues between the components of a real-time run, whose capacities are set, and whose
+/// traffic is counted, channel by channel.
+pub enum Channel {
+    /// Blocks miners broadcast.
+    Blocks,
+    /// Transactions users broadcast.
+    Transactions,
+    /// Each bridge's witness requests from its users.
+    WitnessRequests,
+    /// Each user's witness responses from its bridge.
+    WitnessResponses,
+    /// Each user's UTXO updates from its bridge.
+    UserUpdates,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Channel::Blocks,
+        Channel::Transactions,
+        Channel::WitnessRequests,
+        Channel::WitnessResponses,
+        Channel::UserUpdates,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Blocks => "blocks",
+            Channel::Transactions => "transactions",
+            Channel::WitnessRequests => "witness_requests",
+            Channel::WitnessResponses => "witness_responses",
+            Channel::UserUpdates => "user_updates",
+        }
+    }
+
+    /// The kind of traffic the channel carries, whose delivery the network config sets.
+    pub fn kind(self) -> MessageKind {
+        match self {
+            Channel::Blocks => MessageKind::Block,
+            Channel::Transactions => MessageKind::Transaction,
+            Channel::WitnessRequests | Channel::WitnessResponses | Channel::UserUpdates => {
+                MessageKind::Witness
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Why a link would not take a message, which it hands back.
+pub enum SendError<T> {
+    /// The link's queue stayed full: whoever reads it is not keeping up.
+    Full(T),
+    /// Nobody reads the link any more.
+    Closed(T),
+}
+
+impl<T> SendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Full(message) | SendError::Closed(message) => message,
+        }
+    }
+}
+
+impl<T> Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::Full(_) => write!(f, "The link's queue is full"),
+            SendError::Closed(_) => write!(f, "The link is closed"),
+        }
+    }
+}
+
+/// The sending end of a link between components. Links are bounded: a sender waits a little for
+/// room in a full queue, then gives up, so an overloaded component slows its senders down rather
+/// than running out of memory.
+pub trait Sender<T>: Send + 'static {
+    /// Hands `message` to the link, or back to the caller if the link cannot take it, so the
+    /// caller can back off and try again. A message the link takes may still be lost on the way,
+    /// as on a lossy network.
+    fn try_send(&self, message: T) -> Result<(), SendError<T>>;
+
+    /// Hands `message` to the link, dropping it if the link cannot take it. Dropped messages are
+    /// counted in the run's metrics.
+    fn send(&self, message: T);
+}
+
+/// The receiving end of a link between components.
+pub trait Receiver<T>: Send + 'static {
+    /// The next message that has arrived, if any, without waiting for one.
+    fn try_receive(&self) -> Option<T>;
+
+    /// The next message, waiting up to `timeout` of real time for one to arrive. The thread
+    /// sleeps until a message arrives or the time is up, rather than checking the link again and
+    /// again, so an idle component takes no CPU.
+    fn receive_timeout(&self, timeout: Duration) -> Option<T>;
+}
+
+/// How miners broadcast blocks to every miner and bridge. Blocks are shared rather than copied
+/// for each recipient, so a broadcast queue hands every reader the same block.
+pub trait BlockTopic<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    type BlockSender: Sender<Arc<Block<G, T>>>;
+    type BlockReceiver: Receiver<Arc<Block<G, T>>>;
+}
+
+/// How users broadcast transactions to every miner, shared like blocks.
+pub trait TxTopic<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    type TxSender: Sender<Arc<Transaction<G, T>>>;
+    type TxReceiver: Receiver<Arc<Transaction<G, T>>>;
+}
+
+/// How a user and its bridge talk: the user asks for witnesses, and the bridge answers and keeps
+/// the user's UTXO set up to date.
+pub trait WitnessChannel<G: UnknownOrderGroup> {
+    type RequestSender: Sender<WitnessRequest>;
+    type RequestReceiver: Receiver<WitnessRequest>;
+    type ResponseSender: Sender<WitnessResponse<G, Utxo>>;
+    type ResponseReceiver: Receiver<WitnessResponse<G, Utxo>>;
+    type UpdateSender: Sender<UserUpdate<G>>;
+    type UpdateReceiver: Receiver<UserUpdate<G>>;
+}
+
+/// In-process multiqueue broadcast queues, on which real-time runs simulate the network.
+pub struct Multiqueue;
+
+#[derive(Default)]
+/// Rung whenever a message is queued, so readers of a multiqueue, which can only be polled, can
+/// sleep until there is something to read.
+struct Doorbell {
+    rings: Mutex<u64>,
+    rung: Condvar,
+    /// Wakes readers waiting in tasks rather than on threads.
+    #[cfg(feature = "async-runtime")]
+    rung_async: tokio::sync::Notify,
+}
+
+impl Doorbell {
+    fn ring(&self) {
+        *self.rings.lock().unwrap() += 1;
+        self.rung.notify_all();
+        #[cfg(feature = "async-runtime")]
+        self.rung_async.notify_waiters();
+    }
+
+    fn rings(&self) -> u64 {
+        *self.rings.lock().unwrap()
+    }
+
+    /// Waits until the doorbell has rung more than `seen` times, or `timeout` has passed.
+    fn wait(&self, seen: u64, timeout: Duration) {
+        let rings = self.rings.lock().unwrap();
+        let _ = self
+            .rung
+            .wait_timeout_while(rings, timeout, |rings| *rings == seen)
+            .unwrap();
+    }
+}
+
+#[derive(Default)]
+struct QueueDepthState {
+    /// Messages queued since the queue was made.
+    sent: u64,
+    /// How many of those messages each stream has read, by stream.
+    streams: HashMap<u64, u64>,
+    next_stream: u64,
+    high_watermark: u64,
+}
+
+#[derive(Default)]
+/// How many messages a queue holds: those sent that its slowest stream has yet to read, which is
+/// what fills the queue up. Kept by counting, as multiqueue does not say.
+pub struct QueueDepth {
+    state: Mutex<QueueDepthState>,
+}
+
+impl QueueDepth {
+    /// The messages the queue holds now.
+    pub fn depth(&self) -> u64 {
+        Self::depth_of(&self.state.lock().unwrap())
+    }
+
+    /// The most messages the queue has held at once.
+    pub fn high_watermark(&self) -> u64 {
+        self.state.lock().unwrap().high_watermark
+    }
+
+    fn depth_of(state: &QueueDepthState) -> u64 {
+        let slowest = state.streams.values().cloned().min().unwrap_or(state.sent);
+        state.sent.saturating_sub(slowest)
+    }
+
+    fn sent(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.sent += 1;
+        state.high_watermark = state.high_watermark.max(Self::depth_of(&state));
+    }
+
+    /// A new stream, which has read `read` of the messages sent.
+    fn add_stream(self: &Arc<Self>, read: u64) -> StreamCursor {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_stream;
+        state.next_stream += 1;
+        state.streams.insert(id, read);
+        StreamCursor {
+            depth: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Where a stream of a queue is up to, for the queue's depth. The stream stops counting once
+/// dropped or unsubscribed.
+struct StreamCursor {
+    depth: Arc<QueueDepth>,
+    id: u64,
+}
+
+impl StreamCursor {
+    /// A cursor for a stream added from this one, which starts where this one is.
+    fn fork(&self) -> Self {
+        let read = self.depth.state.lock().unwrap().streams[&self.id];
+        self.depth.add_stream(read)
+    }
+
+    fn read(&self) {
+        if let Some(read) = self.depth.state.lock().unwrap().streams.get_mut(&self.id) {
+            *read += 1;
+        }
+    }
+}
+
+impl Drop for StreamCursor {
+    fn drop(&mut self) {
+        self.depth.state.lock().unwrap().streams.remove(&self.id);
+    }
+}
+
+/// A multiqueue broadcast queue holding up to `capacity` messages, whose readers can wait for
+/// messages without polling.
+pub fn queue<T: Clone>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (sender, receiver) = broadcast_queue(capacity);
+    let doorbell = Arc::new(Doorbell::default());
+    let depth = Arc::new(QueueDepth::default());
+    let cursor = depth.add_stream(0);
+    (
+        QueueSender {
+            inner: sender,
+            doorbell: doorbell.clone(),
+            depth,
+        },
+        QueueReceiver {
+            inner: receiver,
+            doorbell,
+            cursor,
+        },
+    )
+}
+
+/// The sending end of a `queue`.
+pub struct QueueSender<T: Clone> {
+    inner: BroadcastSender<T>,
+    doorbell: Arc<Doorbell>,
+    depth: Arc<QueueDepth>,
+}
+
+impl<T: Clone> QueueSender<T> {
+    /// Queues up `message` if there is room, waking readers waiting for one.
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_send(message)?;
+        self.depth.sent();
+        self.doorbell.ring();
+        Ok(())
+    }
+
+    /// How many messages the queue holds, as its readers keep up or fall behind.
+    pub fn depth(&self) -> Arc<QueueDepth> {
+        self.depth.clone()
+    }
+}
+
+impl<T: Clone> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            doorbell: self.doorbell.clone(),
+            depth: self.depth.clone(),
+        }
+    }
+}
+
+/// A stream of what is sent on a `queue`.
+pub struct QueueReceiver<T: Clone> {
+    inner: BroadcastReceiver<T>,
+    doorbell: Arc<Doorbell>,
+    cursor: StreamCursor,
+}
+
+impl<T: Clone> QueueReceiver<T> {
+    /// Another stream of everything sent on the queue from now on. It must be read, or the queue
+    /// fills up.
+    pub fn add_stream(&self) -> Self {
+        Self {
+            inner: self.inner.add_stream(),
+            doorbell: self.doorbell.clone(),
+            cursor: self.cursor.fork(),
+        }
+    }
+
+    /// Stops reading this stream, so it no longer holds up the queue.
+    pub fn unsubscribe(self) {
+        self.inner.unsubscribe();
+    }
+
+    /// The next message, waiting up to `timeout` of real time for one to arrive, for a reader
+    /// running as a task: the task yields its thread while it waits. Dropping the future before it
+    /// is done loses no message.
+    #[cfg(feature = "async-runtime")]
+    pub async fn receive_async(&mut self, timeout: Duration) -> Option<T> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Register before the check below, so a message queued after it still wakes us.
+            let rung = self.doorbell.rung_async.notified();
+            if let Ok(message) = self.inner.try_recv() {
+                self.cursor.read();
+                return Some(message);
+            }
+            if tokio::time::timeout_at(deadline, rung).await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+impl<T: Clone + Payload + Send + 'static> Sender<T> for LossySender<T> {
+    fn try_send(&self, message: T) -> Result<(), SendError<T>> {
+        LossySender::try_send(self, message)
+    }
+
+    fn send(&self, message: T) {
+        LossySender::send(self, message);
+    }
+}
+
+impl<T: Clone + Send + 'static> Receiver<T> for QueueReceiver<T> {
+    fn try_receive(&self) -> Option<T> {
+        let message = self.inner.try_recv().ok()?;
+        self.cursor.read();
+        Some(message)
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Read the rings first, so a message queued after the check below still wakes us.
+            let seen = self.doorbell.rings();
+            if let Ok(message) = self.inner.try_recv() {
+                self.cursor.read();
+                return Some(message);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            self.doorbell.wait(seen, deadline - now);
+        }
+    }
+}
+
+impl<G: UnknownOrderGroup, T: 'static + Clone + Hash + Debug + Send + Sync> BlockTopic<G, T>
+    for Multiqueue
+{
+    type BlockSender = LossySender<Arc<Block<G, T>>>;
+    type BlockReceiver = QueueReceiver<Arc<Block<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup, T: 'static + Clone + Hash + Debug + Send + Sync> TxTopic<G, T>
+    for Multiqueue
+{
+    type TxSender = LossySender<Arc<Transaction<G, T>>>;
+    type TxReceiver = QueueReceiver<Arc<Transaction<G, T>>>;
+}
+
+impl<G: UnknownOrderGroup> WitnessChannel<G> for Multiqueue {
+    type RequestSender = LossySender<WitnessRequest>;
+    type RequestReceiver = QueueReceiver<WitnessRequest>;
+    type ResponseSender = LossySender<WitnessResponse<G, Utxo>>;
+    type ResponseReceiver = QueueReceiver<WitnessResponse<G, Utxo>>;
+    type UpdateSender = LossySender<UserUpdate<G>>;
+    type UpdateReceiver = QueueReceiver<UserUpdate<G>>;
+}
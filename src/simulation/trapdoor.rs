@@ -0,0 +1,136 @@
+use super::arith;
+use super::modulus;
+use accumulator::group::{ElemFrom, Group, TypeRep, UnknownOrderGroup};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rug::Integer;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Bits of the trapdoor modulus: as many as `Rsa2048`'s, so elements, proofs and messages are the
+/// size they would be in a secure group.
+const TRAPDOOR_BITS: u32 = 2048;
+
+/// The modulus `TrapdoorRsa` works under and the group's exponent, installed once per process.
+static TRAPDOOR: OnceLock<Trapdoor> = OnceLock::new();
+
+/// An RSA modulus together with its Carmichael function λ(N) = lcm(p - 1, q - 1), the exponent of
+/// the group, which anyone who knows the factors can work out.
+pub struct Trapdoor {
+    pub n: Integer,
+    half: Integer,
+    lambda: Integer,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+/// An RSA group whose factors the simulation knows, for testing logic at populations too large for
+/// a secure group. Every power is taken with its exponent reduced modulo λ(N), so adding thousands
+/// of elements costs a single exponentiation by at most 2048 bits rather than one by thousands of
+/// primes. Knowing the order, anyone can forge witnesses and proofs, so this group is INSECURE and
+/// only for simulation. Install it with `install` before using it.
+pub enum TrapdoorRsa {}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// An element of `TrapdoorRsa`, kept like `modulus::CustomRsaElem` as whichever of it and its
+/// negation is at most N/2.
+pub struct TrapdoorRsaElem(Integer);
+
+impl TypeRep for TrapdoorRsa {
+    type Rep = Trapdoor;
+
+    fn rep() -> &'static Trapdoor {
+        TRAPDOOR
+            .get()
+            .expect("No trapdoor modulus installed: call trapdoor::install first")
+    }
+}
+
+impl Group for TrapdoorRsa {
+    type Elem = TrapdoorRsaElem;
+
+    fn op_(trapdoor: &Trapdoor, a: &TrapdoorRsaElem, b: &TrapdoorRsaElem) -> TrapdoorRsaElem {
+        reduce(trapdoor, arith::mul_mod(&a.0, &b.0, &trapdoor.n))
+    }
+
+    fn id_(_: &Trapdoor) -> TrapdoorRsaElem {
+        TrapdoorRsaElem(Integer::from(1))
+    }
+
+    fn inv_(trapdoor: &Trapdoor, x: &TrapdoorRsaElem) -> TrapdoorRsaElem {
+        let inverse = Integer::from(x.0.invert_ref(&trapdoor.n).expect("Element is invertible"));
+        reduce(trapdoor, inverse)
+    }
+
+    /// `x` to the power `n` modulo λ(N), which is the same element since x^λ(N) = 1 for every
+    /// element. Negative powers come out non-negative.
+    fn exp_(trapdoor: &Trapdoor, x: &TrapdoorRsaElem, n: &Integer) -> TrapdoorRsaElem {
+        let n = Integer::from(n.rem_euc_ref(&trapdoor.lambda));
+        let power = arith::pow_mod(&x.0, &n, &trapdoor.n).expect("Exponent is non-negative");
+        reduce(trapdoor, power)
+    }
+}
+
+impl<T> ElemFrom<T> for TrapdoorRsa
+where
+    Integer: From<T>,
+{
+    fn elem(t: T) -> TrapdoorRsaElem {
+        reduce(Self::rep(), Integer::from(t))
+    }
+}
+
+impl UnknownOrderGroup for TrapdoorRsa {
+    fn unknown_order_elem_(_: &Trapdoor) -> TrapdoorRsaElem {
+        TrapdoorRsaElem(Integer::from(2))
+    }
+}
+
+/// `value` modulo N, as the element it stands for.
+fn reduce(trapdoor: &Trapdoor, value: Integer) -> TrapdoorRsaElem {
+    let value = value.rem_euc(&trapdoor.n);
+    if value > trapdoor.half {
+        TrapdoorRsaElem(Integer::from(&trapdoor.n - value))
+    } else {
+        TrapdoorRsaElem(value)
+    }
+}
+
+impl Serialize for TrapdoorRsaElem {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string_radix(16))
+    }
+}
+
+impl<'de> Deserialize<'de> for TrapdoorRsaElem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let value = Integer::from_str_radix(&hex, 16).map_err(de::Error::custom)?;
+        Ok(TrapdoorRsa::elem(value))
+    }
+}
+
+/// Sets up the modulus `TrapdoorRsa` works under, from primes drawn with `seed`, so every process
+/// of a cluster deployment started with the same config derives the same group without a file.
+/// Later calls keep the first modulus.
+pub fn install(seed: u64) {
+    if TRAPDOOR.get().is_some() {
+        return;
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let p = modulus::random_prime(TRAPDOOR_BITS / 2, &mut rng);
+    let mut q = modulus::random_prime(TRAPDOOR_BITS - TRAPDOOR_BITS / 2, &mut rng);
+    while q == p {
+        q = modulus::random_prime(TRAPDOOR_BITS - TRAPDOOR_BITS / 2, &mut rng);
+    }
+    let n = Integer::from(&p * &q);
+    let lambda = (p - 1u32).lcm(&(q - 1u32));
+    warn!(
+        "Using the trapdoor group: its {}-bit modulus has known factors, so the accumulator is \
+         INSECURE. Use it only to test the simulation's logic.",
+        n.significant_bits()
+    );
+    let half = Integer::from(&n >> 1);
+    let _ = TRAPDOOR.set(Trapdoor { n, half, lambda });
+}
@@ -1,126 +1,513 @@
-use super::state::Transaction;
-use super::state::Utxo;
-use crate::simulation::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::clock::Clock;
+use super::control::RunControl;
+use super::injection::FaultInjector;
+use super::instrument::Instrumented;
+use super::logging;
+use super::metrics::{Metrics, ResourceUsage};
+use super::proofs::ProofOp;
+use super::rpc::Requester;
+use super::shutdown::FinalState;
+use super::state::{self, Transaction};
+use super::state::{NodeId, Utxo};
+use super::transport::{Receiver, Sender, TxTopic, WitnessChannel, RECEIVE_TIMEOUT};
+use super::util;
+use super::witness_update::WitnessUpdate;
+use crate::simulation::bridge::{SpentReceipt, UserUpdate, WitnessRequest, WitnessResponse};
 use accumulator::group::UnknownOrderGroup;
-use multiqueue::{BroadcastReceiver, BroadcastSender};
+use accumulator::Witness;
 use rand::Rng;
-use std::collections::HashSet;
-use std::thread::sleep;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
 use uuid::Uuid;
 
+/// How long a user waits before trying again to send a message a link would not take.
+const BACKOFF: Duration = Duration::from_millis(50);
+
+/// Sends `message`, backing off while the link pushes back. Gives up, returning false, once the
+/// run stops or the attempt times out.
+fn send_or_back_off<T, S: Sender<T>>(
+    sender: &S,
+    message: T,
+    clock: &dyn Clock,
+    control: &RunControl,
+    timed_out: &dyn Fn() -> bool,
+) -> bool {
+    let mut message = message;
+    loop {
+        match sender.try_send(message) {
+            Ok(()) => return true,
+            Err(err) => {
+                debug!("{}; backing off.", err);
+                if control.is_stopped() || timed_out() {
+                    return false;
+                }
+                message = err.into_inner();
+                clock.sleep(BACKOFF);
+            }
+        }
+    }
+}
+
 /// A end-user or light-client in our system.
-pub struct User {
+pub struct User<G: UnknownOrderGroup> {
     id: usize, // For bridges to know who to send witness responses to.
+    bridge_id: usize,
     utxo_set: HashSet<Utxo>,
+    pending_request_id: Option<Uuid>,
+    /// Counts attempts to spend a UTXO, including retries.
+    attempt: u64,
+    /// Whether this user is waiting for a transaction (or its first UTXO) to be confirmed.
+    waiting: bool,
+    /// Proofs that UTXOs this user spent are no longer in the accumulator, kept to be presented
+    /// in a dispute.
+    spent_receipts: Vec<SpentReceipt<G>>,
+    /// Witnesses to this user's UTXOs as of `witness_height`, if it keeps its own from published
+    /// witness updates rather than asking its bridge. UTXOs without one are spent with a witness
+    /// from the bridge.
+    witnesses: Option<HashMap<Utxo, Witness<G, Utxo>>>,
+    witness_height: u64,
+    metrics: Arc<Metrics>,
 }
 
-impl User {
-    /// Runs a user's simulation loop.
+impl<G: UnknownOrderGroup> User<G> {
+    pub fn new(id: usize, bridge_id: usize, init_utxo: Utxo, metrics: Arc<Metrics>) -> Self {
+        let mut user = Self::unfunded(id, bridge_id, metrics);
+        user.utxo_set.insert(init_utxo);
+        user.waiting = false;
+        user
+    }
+
+    /// A user without UTXOs, e.g. one joining mid-run who must wait for its first UTXO to be minted
+    /// before it can transact.
+    pub fn unfunded(id: usize, bridge_id: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            id,
+            bridge_id,
+            utxo_set: HashSet::new(),
+            pending_request_id: None,
+            attempt: 0,
+            waiting: true,
+            spent_receipts: Vec::new(),
+            witnesses: None,
+            witness_height: 0,
+            metrics,
+        }
+    }
+
+    /// A user resuming from a checkpoint with `utxos`, and `waiting` for a transaction to be
+    /// confirmed if it has one pending.
+    pub fn restored(
+        id: usize,
+        bridge_id: usize,
+        utxos: Vec<Utxo>,
+        waiting: bool,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let mut user = Self::unfunded(id, bridge_id, metrics);
+        user.utxo_set = utxos.into_iter().collect();
+        user.waiting = waiting || user.utxo_set.is_empty();
+        user
+    }
+
+    /// Has this user keep its own witnesses, starting from `witnesses` as of the block at
+    /// `height`, and learn of its UTXOs from published witness updates rather than from its
+    /// bridge.
+    pub fn keep_witnesses(&mut self, witnesses: Vec<(Utxo, Witness<G, Utxo>)>, height: u64) {
+        self.witnesses = Some(witnesses.into_iter().collect());
+        self.witness_height = height;
+    }
+
+    /// Runs a user's simulation loop until the run is stopped or the user crashes, then returns
+    /// where it got to.
     // Right now users are limited to one transaction per block (i.e. they can issue one transaction
     // based on their UTXO set as of some block), since users have to wait for their state to be
     // updated before issuing a subsequent transaction. TODO: Allow for more tx per user per block.
-    pub fn start<G: 'static + UnknownOrderGroup>(
+    pub fn start<N: TxTopic<G, Utxo> + WitnessChannel<G>>(
         id: usize,
         bridge_id: usize,
         init_utxo: Utxo,
-        witness_request_sender: &BroadcastSender<WitnessRequest>,
-        witness_response_receiver: &BroadcastReceiver<WitnessResponse<G, Utxo>>,
-        user_update_receiver: &BroadcastReceiver<UserUpdate>,
-        tx_sender: &BroadcastSender<Transaction<G, Utxo>>,
-    ) {
-        let mut utxo_set = HashSet::new();
-        utxo_set.insert(init_utxo);
-        let mut user = Self { id, utxo_set };
-
-        loop {
-            sleep(Duration::from_millis(10));
-
-            // Get a UTXO to spend.
-            let mut utxos_to_spend = Vec::new();
-            utxos_to_spend.push(user.get_input_for_transaction());
-
-            // Request a witness for the UTXO we are spending.
-            let response = {
-                let witness_request_id = Uuid::new_v4();
-                loop {
-                    witness_request_sender
-                        .try_send(WitnessRequest {
-                            user_id: user.id,
-                            request_id: witness_request_id,
-                            utxos: utxos_to_spend.clone(),
-                        })
-                        .unwrap();
-
-                    let response = loop {
-                        match witness_response_receiver.try_recv() {
-                            Ok(response) => break response,
-                            Err(_) => (),
-                        }
-                        sleep(Duration::from_millis(10));
-                    };
-                    if response.request_id == witness_request_id {
-                        break response;
-                    }
-                    // Drain any other responses so we don't loop forever.
-                    loop {
-                        if witness_response_receiver.try_recv().is_err() {
-                            break;
-                        }
-                    }
-                }
-            };
+        witness_request_sender: &N::RequestSender,
+        witness_response_receiver: &N::ResponseReceiver,
+        user_update_receiver: &N::UpdateReceiver,
+        tx_sender: &N::TxSender,
+        retry_timeout: Option<Duration>,
+        clock: &dyn Clock,
+        control: &RunControl,
+        metrics: Arc<Metrics>,
+        faults: &FaultInjector,
+    ) -> FinalState
+    where
+        G: 'static,
+    {
+        let node = NodeId::User(id);
+        let _span = logging::node_span(node).entered();
+        let mut user = Self::new(id, bridge_id, init_utxo, metrics);
+        let mut rng = rand::thread_rng();
+        let mut witnesses = Requester::new(witness_request_sender, witness_response_receiver);
+
+        'attempt: loop {
+            clock.sleep(Duration::from_millis(10));
+            if !control.wait_while_paused() || !faults.interrupt(node, clock) {
+                break;
+            }
 
-            let num = 1; //rand::thread_rng().gen_range(1, 3);
-            let mut new_utxos = vec![];
-            for _ in 0..num {
-                new_utxos.push(Utxo {
-                    id: Uuid::new_v4(),
-                    user_id: user.id,
-                });
+            // Request a witness for the UTXO we are spending. If the request, the response, the
+            // transaction or the update confirming it is lost, start over after a while.
+            let request = user.witness_request(&mut rng);
+            // Everything logged until the next attempt belongs to this spend.
+            let _trace = logging::trace_span(request.request_id).entered();
+            witnesses.await_response_to(&request);
+            let deadline = retry_timeout.map(|timeout| clock.now() + timeout);
+            let timed_out = || deadline.map_or(false, |deadline| clock.now() >= deadline);
+            let request = faults.tamper(node, request);
+            if !send_or_back_off(witnesses.requests(), request, clock, control, &timed_out) {
+                continue 'attempt;
             }
 
-            let new_trans = Transaction {
-                utxos_created: new_utxos,
-                utxos_spent_with_witnesses: response.utxos_with_witnesses,
+            let response = loop {
+                if let Some(response) = witnesses.receive_timeout(RECEIVE_TIMEOUT) {
+                    break response;
+                }
+                if control.is_stopped() || !faults.interrupt(node, clock) {
+                    break 'attempt;
+                }
+                if timed_out() {
+                    debug!("No witness received; retrying.");
+                    continue 'attempt;
+                }
+            };
+            let new_trans = match user.transaction_from_response(response, &mut rng) {
+                Some(transaction) => transaction,
+                None => continue 'attempt,
             };
 
             // Issue a transaction to miners.
-            tx_sender.try_send(new_trans).unwrap();
-            println!(
-                "User {} for bridge {} issued a transaction (1 input + {} output/s).",
-                id, bridge_id, num
-            );
+            let new_trans = Arc::new(faults.tamper(node, new_trans));
+            if !send_or_back_off(tx_sender, new_trans, clock, control, &timed_out) {
+                continue 'attempt;
+            }
 
             // Keep processing UTXO updates from the bridge until one of them is non-empty (i.e. the
             // one we care about, pertaining to the UTXO we spent).
             loop {
-                match user_update_receiver.try_recv() {
-                    Ok(update) => {
-                        if !update.is_empty() {
-                            user.update(update);
-                            break;
-                        }
+                if let Some(update) = user_update_receiver.receive_timeout(RECEIVE_TIMEOUT) {
+                    if user.apply_update(update) {
+                        break;
+                    }
+                }
+                if control.is_stopped() || !faults.interrupt(node, clock) {
+                    break 'attempt;
+                }
+                if timed_out() {
+                    debug!("Transaction not confirmed; retrying.");
+                    continue 'attempt;
+                }
+            }
+        }
+        user.final_state()
+    }
+
+    /// Builds a request for a witness to the UTXO this user will spend next, and remembers its ID
+    /// so the matching response can be recognized.
+    pub fn witness_request<R: Rng>(&mut self, rng: &mut R) -> WitnessRequest {
+        let request_id = util::new_uuid(rng);
+        self.pending_request_id = Some(request_id);
+        self.attempt += 1;
+        self.waiting = true;
+        self.metrics
+            .witness_requested(self.id, self.bridge_id, request_id);
+        WitnessRequest {
+            user_id: self.id,
+            request_id,
+            utxos: vec![self.get_input_for_transaction()],
+        }
+    }
+
+    /// Builds a transaction spending the UTXO this user will spend next with its own witness, if it
+    /// keeps one, so that its bridge need not be asked.
+    pub fn transaction_from_own_witness<R: Rng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Option<Transaction<G, Utxo>> {
+        let utxo = self.get_input_for_transaction();
+        let witness = self.witnesses.as_ref()?.get(&utxo)?.clone();
+        let request_id = util::new_uuid(rng);
+        self.pending_request_id = Some(request_id);
+        self.attempt += 1;
+        self.waiting = true;
+        self.transaction_from_response(
+            WitnessResponse {
+                request_id,
+                utxos_with_witnesses: vec![(utxo, witness)],
+            },
+            rng,
+        )
+    }
+
+    /// Replaces the witnesses this user keeps with `witnesses`, fresh from its bridge as of the
+    /// block at `height`, rather than carrying on updating its own. Ignored unless this user has
+    /// applied the witness update for that very block, since it could not bring witnesses from
+    /// another height into line with the updates it applies. Returns whether it took them.
+    pub fn refresh_witnesses(
+        &mut self,
+        witnesses: Vec<(Utxo, Witness<G, Utxo>)>,
+        height: u64,
+    ) -> bool {
+        if self.witnesses.is_none() || height != self.witness_height {
+            debug!(
+                "Ignoring witnesses for block {} at block {}.",
+                height, self.witness_height
+            );
+            return false;
+        }
+        let utxo_set = &self.utxo_set;
+        let refreshed: HashMap<Utxo, Witness<G, Utxo>> = witnesses
+            .into_iter()
+            .filter(|(utxo, _)| utxo_set.contains(utxo))
+            .collect();
+        debug!(
+            "Refreshed {} witnesses at block {}.",
+            refreshed.len(),
+            height
+        );
+        self.witnesses = Some(refreshed);
+        self.report_resources();
+        true
+    }
+
+    /// The height of the latest witness update this user applied.
+    pub fn witness_height(&self) -> u64 {
+        self.witness_height
+    }
+
+    /// Applies a published witness update: brings this user's witnesses up to the block, adds
+    /// ones to the UTXOs it created for the user, and applies the UTXO changes as if its bridge had
+    /// sent them. Returns whether it confirms the transaction this user is waiting on. Witnesses
+    /// kept across a missed update are dropped, since they cannot be brought up to date; UTXOs
+    /// without one are spent with a witness from the bridge.
+    pub fn apply_witness_update(&mut self, update: &WitnessUpdate<G>) -> bool {
+        if self.witnesses.is_none() || update.height <= self.witness_height {
+            return false;
+        }
+        let start = Instant::now();
+        let is_next = update.height == self.witness_height + 1;
+        let witnesses = self.witnesses.take().unwrap();
+        let mut updated = HashMap::new();
+        if is_next {
+            for (utxo, witness) in witnesses {
+                if update.deleted.contains(&utxo) {
+                    continue;
+                }
+                match update.update(&utxo, witness) {
+                    Ok(witness) => {
+                        updated.insert(utxo, witness);
                     }
-                    Err(_) => (),
+                    Err(err) => warn!("Failed to update a witness: {:?}", err),
                 }
-                sleep(Duration::from_millis(10));
             }
+        } else {
+            debug!(
+                "Missed witness updates {} to {}; dropping witnesses.",
+                self.witness_height + 1,
+                update.height - 1
+            );
+        }
+        let owned = |utxos: &[Utxo]| -> Vec<Utxo> {
+            utxos
+                .iter()
+                .filter(|utxo| utxo.user_id == self.id)
+                .cloned()
+                .collect()
+        };
+        let user_update = UserUpdate {
+            utxos_added: owned(&update.added),
+            utxos_deleted: owned(&update.deleted),
+            spent_receipt: None,
+        };
+        for utxo in &user_update.utxos_added {
+            if let Some(witness) = update.new_witness(utxo) {
+                updated.insert(utxo.clone(), witness);
+            }
+        }
+        self.witnesses = Some(updated);
+        self.witness_height = update.height;
+        self.metrics
+            .cpu_used(NodeId::User(self.id), start.elapsed());
+        self.apply_update(user_update)
+    }
+
+    /// Turns a witness response into a transaction spending the witnessed UTXOs. Returns `None` if
+    /// the response does not answer this user's pending request.
+    pub fn transaction_from_response<R: Rng>(
+        &mut self,
+        response: WitnessResponse<G, Utxo>,
+        rng: &mut R,
+    ) -> Option<Transaction<G, Utxo>> {
+        if self.pending_request_id != Some(response.request_id) {
+            return None;
+        }
+        if response.utxos_with_witnesses.is_empty() {
+            // The bridge holds none of them, e.g. as it is behind; a retry may find it caught up.
+            debug!("Bridge {} sent no witnesses; not spending.", self.bridge_id);
+            return None;
+        }
+        let start = Instant::now();
+        self.pending_request_id = None;
+        self.metrics.witness_received(self.id);
+
+        let num = 1; //rand::thread_rng().gen_range(1, 3);
+
+        // The outputs split the inputs' value between them, the first taking any remainder.
+        let spent = response.utxos_with_witnesses.iter().map(|(utxo, _)| utxo);
+        let value = match state::total_value(spent) {
+            Some(value) => value,
+            None => {
+                warn!("The witnessed UTXOs' total value overflows; not spending them.");
+                return None;
+            }
+        };
+        let mut new_utxos = vec![];
+        for i in 0..num {
+            new_utxos.push(Utxo {
+                id: util::new_uuid(rng),
+                user_id: self.id,
+                value: value / num + if i == 0 { value % num } else { 0 },
+            });
+        }
+
+        let transaction = Transaction {
+            utxos_created: new_utxos,
+            utxos_spent_with_witnesses: response.utxos_with_witnesses,
+        };
+        self.metrics
+            .transaction_issued(self.id, transaction.id(), response.request_id);
+        self.metrics
+            .cpu_used(NodeId::User(self.id), start.elapsed());
+        debug!(
+            "Issued a transaction to bridge {} (1 input + {} output/s).",
+            self.bridge_id, num
+        );
+        Some(transaction)
+    }
+
+    /// Applies a UTXO update from the bridge. Returns whether it confirms the transaction this user
+    /// is waiting on, i.e. whether it is non-empty and not a duplicate.
+    pub fn apply_update(&mut self, update: UserUpdate<G>) -> bool {
+        if update.is_empty() {
+            return false;
+        }
+        let start = Instant::now();
+        self.update(update);
+        self.metrics
+            .cpu_used(NodeId::User(self.id), start.elapsed());
+        if !self.waiting {
+            return false;
+        }
+        self.waiting = false;
+        self.metrics.transaction_confirmed(self.id);
+        true
+    }
+
+    /// Replaces this user's view of its UTXOs, e.g. after restarting, and abandons any spend in
+    /// progress. Returns whether the user has anything to spend.
+    pub fn resync(&mut self, utxos: Vec<Utxo>) -> bool {
+        self.utxo_set = utxos.into_iter().collect();
+        if let Some(witnesses) = &mut self.witnesses {
+            let utxo_set = &self.utxo_set;
+            witnesses.retain(|utxo, _| utxo_set.contains(utxo));
+        }
+        self.pending_request_id = None;
+        self.waiting = self.utxo_set.is_empty();
+        self.report_resources();
+        !self.waiting
+    }
+
+    /// Whether this user owns any UTXOs to spend.
+    pub fn is_funded(&self) -> bool {
+        !self.utxo_set.is_empty()
+    }
+
+    pub fn bridge_id(&self) -> usize {
+        self.bridge_id
+    }
+
+    pub fn final_state(&self) -> FinalState {
+        FinalState::User {
+            user_id: self.id,
+            utxos: self.utxo_set.len(),
+            waiting: self.waiting,
         }
     }
 
+    /// The receipts this user holds for UTXOs it spent, oldest first.
+    pub fn spent_receipts(&self) -> &[SpentReceipt<G>] {
+        &self.spent_receipts
+    }
+
+    /// The receipt proving that `utxo`, which this user spent, is no longer in the accumulator,
+    /// if it was sent one.
+    pub fn spent_receipt(&self, utxo: &Utxo) -> Option<&SpentReceipt<G>> {
+        self.spent_receipts
+            .iter()
+            .find(|receipt| receipt.utxos.contains(utxo))
+    }
+
+    /// The number of the latest attempt to spend a UTXO.
+    pub fn attempt(&self) -> u64 {
+        self.attempt
+    }
+
+    /// Whether `attempt` is still the latest attempt and has not been confirmed yet.
+    pub fn is_waiting_on(&self, attempt: u64) -> bool {
+        self.waiting && self.attempt == attempt
+    }
+
     // TODO: Maybe support more inputs than one.
     // Expects executable to call `update` to remove this UTXO when it is confirmed.
     fn get_input_for_transaction(&self) -> Utxo {
         self.utxo_set.iter().next().unwrap().clone()
     }
 
-    fn update(&mut self, update: UserUpdate) {
+    fn update(&mut self, update: UserUpdate<G>) {
         for utxo in update.utxos_deleted {
             self.utxo_set.remove(&utxo);
         }
         for utxo in update.utxos_added {
             self.utxo_set.insert(utxo.clone());
         }
+        if let Some(receipt) = update.spent_receipt {
+            self.keep_receipt(receipt);
+        }
+        self.report_resources();
+    }
+
+    /// Checks a receipt for spent UTXOs before keeping it; one that does not verify would not
+    /// settle anything.
+    fn keep_receipt(&mut self, receipt: SpentReceipt<G>) {
+        let is_valid = Instrumented::new(self.metrics.as_ref()).verify_nonmembership(
+            ProofOp::SpentUtxo,
+            &receipt.acc,
+            &receipt.utxos,
+            &receipt.proof,
+        );
+        if is_valid {
+            self.spent_receipts.push(receipt);
+        } else {
+            warn!(
+                "Discarded a receipt for block {} that does not verify.",
+                receipt.height
+            );
+        }
+    }
+
+    /// Reports the UTXOs this user owns, and the witnesses it keeps if it keeps its own.
+    fn report_resources(&self) {
+        let witnesses = self.witnesses.as_ref().map_or(0, HashMap::len);
+        self.metrics.resources_changed(
+            NodeId::User(self.id),
+            ResourceUsage::estimate(self.utxo_set.len(), witnesses, 0, 0, 0, 0),
+        );
     }
 }
@@ -1,13 +1,19 @@
 use super::state::Transaction;
 use accumulator::group::UnknownOrderGroup;
 use accumulator::Witness;
+use rand::Rng;
+use sha2::{Digest, Sha256};
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
 
 #[allow(clippy::type_complexity)]
 /// Extracts the elements added and deleted in a set of `transactions`.
 pub fn elems_from_transactions<G: UnknownOrderGroup, T: Clone + Hash + Debug>(
-    transactions: &[Transaction<G, T>],
+    transactions: &[Arc<Transaction<G, T>>],
 ) -> (Vec<T>, Vec<(T, Witness<G, T>)>) {
     let mut elems_added = Vec::new();
     let mut elems_deleted = Vec::new();
@@ -23,3 +29,87 @@ pub fn elems_from_transactions<G: UnknownOrderGroup, T: Clone + Hash + Debug>(
 
     (elems_added, elems_deleted)
 }
+
+/// Draws a UUID from `rng`, so that runs driven by a seeded generator are reproducible.
+pub fn new_uuid<R: Rng>(rng: &mut R) -> Uuid {
+    Uuid::from_bytes(rng.gen())
+}
+
+/// SHA-256 of whatever `value` feeds its hasher.
+pub fn sha256<T: Hash + ?Sized>(value: &T) -> [u8; 32] {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    value.hash(&mut hasher);
+    hasher.0.finalize().into()
+}
+
+/// Feeds the bytes a `Hash` implementation writes into SHA-256.
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    /// Unused: the digest is read from the inner hasher.
+    fn finish(&self) -> u64 {
+        0
+    }
+}
+
+/// `bytes` as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 * bytes.len());
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+/// The bytes `hex` spells out, if it is valid hex.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect()
+}
+
+/// Counts the connections a server is handling, so that it can turn away those beyond its limit
+/// rather than start a thread for each.
+pub struct ConnectionLimit {
+    open: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl ConnectionLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            open: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// A slot for one more connection, given back when it is dropped, or `None` if all `max` are
+    /// taken.
+    pub fn acquire(&self) -> Option<ConnectionSlot> {
+        if self.open.fetch_add(1, Ordering::SeqCst) >= self.max {
+            self.open.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ConnectionSlot(self.open.clone()))
+    }
+}
+
+/// A connection counted against a `ConnectionLimit`.
+pub struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,238 @@
+use super::batch::{self, BatchProof};
+use super::genesis::Mints;
+use super::instrument::Instrumented;
+use super::metrics::Metrics;
+use super::proof_cache::ProofCache;
+use super::proofs::ProofOp;
+use super::state::{Block, UpdateProof, Valued};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::Accumulator;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+/// Why a block was not applied.
+pub enum BlockError {
+    /// The block is at or below the current height, e.g. a duplicate. Not a sign of misbehavior.
+    Stale,
+    /// The block skips one or more heights.
+    UnexpectedHeight,
+    /// The block includes a transaction twice, or spends a UTXO twice.
+    DoubleSpend,
+    /// The block's proofs do not show that its accumulator follows from the current one.
+    InvalidProof,
+    /// The block does not prove that the UTXOs it creates were not already in the accumulator, or
+    /// its proof of that does not hold.
+    Reinsertion,
+    /// A transaction creates more value than the UTXOs it spends hold, or its total value
+    /// overflows. Only mints the run authorized may create value from nothing.
+    Inflation,
+    /// The block's header does not commit to its accumulator.
+    CommitmentMismatch,
+}
+
+/// Checks that `block` can extend a chain at `height` whose accumulator is `acc`. A block that
+/// proves the UTXOs it creates are fresh must prove it correctly; with `require_fresh`, a block
+/// creating UTXOs must prove it. The values of spent UTXOs are those the membership proof covers,
+/// so no transaction can claim more than it spends, and transactions spending nothing must be
+/// mints of `mints`. The proofs checked are counted in `metrics`,
+/// and with a `cache`, proofs another component has checked already are not checked again.
+///
+/// It runs the stages `check_structure`, `verify_deletions` and `verify_additions` in turn, which
+/// a pipeline can run on blocks one after another instead.
+pub fn validate_block<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug + Valued>(
+    acc: &Accumulator<G, T>,
+    height: u64,
+    block: &Block<G, T>,
+    require_fresh: bool,
+    mints: Option<&Mints<T>>,
+    metrics: &Metrics,
+    cache: Option<&ProofCache<G, T>>,
+) -> Result<(), BlockError> {
+    let (elems_added, elems_deleted) = check_structure(height, block, require_fresh, mints)?;
+    let check = || {
+        verify_deletions(acc, block, &elems_added, &elems_deleted, metrics)?;
+        verify_additions(acc, block, &elems_added, metrics)
+    };
+    match cache {
+        Some(cache) => cache.check(acc, block, metrics, check),
+        None => check(),
+    }
+}
+
+/// The cheap checks of `validate_block`, which need no accumulator: that `block` is the next
+/// block of a chain at `height`, that its transactions neither create value, but for mints of
+/// `mints`, nor spend or create a UTXO twice, that its header commits to its accumulator, and,
+/// with `require_fresh`, that it proves any UTXOs it creates are fresh. Returns the UTXOs it
+/// creates and spends.
+pub fn check_structure<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug + Valued>(
+    height: u64,
+    block: &Block<G, T>,
+    require_fresh: bool,
+    mints: Option<&Mints<T>>,
+) -> Result<(Vec<T>, Vec<T>), BlockError> {
+    if block.height <= height {
+        return Err(BlockError::Stale);
+    }
+    if block.height != height + 1 {
+        return Err(BlockError::UnexpectedHeight);
+    }
+    let (elems_added, elems_deleted) = check_transactions(&[block], mints)?;
+    if !block.header().commits_to(&block.acc_new) {
+        return Err(BlockError::CommitmentMismatch);
+    }
+    if block.proof_fresh.is_none() && require_fresh && !elems_added.is_empty() {
+        return Err(BlockError::Reinsertion);
+    }
+    Ok((elems_added, elems_deleted))
+}
+
+/// Checks that `blocks`, consecutive blocks fetched to catch up, can extend a chain at `height`
+/// whose accumulator is `acc`, by their batch proof rather than each block's own proofs. That
+/// proves only the accumulator after the last block, so the batch must also prove the UTXOs the
+/// blocks create are fresh against `acc` if a block would have to on its own: the blocks' own
+/// proofs of that are against accumulators nothing here checks. With a `cache`, a batch another
+/// component has checked already is not checked again.
+pub fn validate_batch<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug + Valued>(
+    acc: &Accumulator<G, T>,
+    height: u64,
+    blocks: &[Block<G, T>],
+    proof: &BatchProof<G, T>,
+    require_fresh: bool,
+    mints: Option<&Mints<T>>,
+    metrics: &Metrics,
+    cache: Option<&ProofCache<G, T>>,
+) -> Result<(), BlockError> {
+    let first = blocks.first().ok_or(BlockError::Stale)?;
+    if first.height <= height {
+        return Err(BlockError::Stale);
+    }
+    let is_consecutive = blocks
+        .iter()
+        .enumerate()
+        .all(|(index, block)| block.height == height + 1 + index as u64);
+    if !is_consecutive {
+        return Err(BlockError::UnexpectedHeight);
+    }
+    let blocks_ref: Vec<&Block<G, T>> = blocks.iter().collect();
+    check_transactions(&blocks_ref, mints)?;
+    if !blocks
+        .iter()
+        .all(|block| block.header().commits_to(&block.acc_new))
+    {
+        return Err(BlockError::CommitmentMismatch);
+    }
+
+    let elems_added = batch::created(blocks);
+    if proof.proof_fresh.is_none() && require_fresh && !elems_added.is_empty() {
+        return Err(BlockError::Reinsertion);
+    }
+
+    let check = || {
+        let instrumented = Instrumented::new(metrics);
+        if !instrumented.verify_batch(proof, acc, blocks) {
+            return Err(BlockError::InvalidProof);
+        }
+        if let Some(proof_fresh) = &proof.proof_fresh {
+            let is_fresh = instrumented.verify_nonmembership(
+                ProofOp::FreshUtxos,
+                acc,
+                &elems_added,
+                proof_fresh,
+            );
+            if !is_fresh {
+                return Err(BlockError::Reinsertion);
+            }
+        }
+        Ok(())
+    };
+    match cache {
+        Some(cache) => cache.check_batch(acc, blocks, proof, metrics, check),
+        None => check(),
+    }
+}
+
+/// Checks that no transaction in `blocks` but a mint of `mints` creates value, and that no UTXO
+/// is spent or created twice across them. Returns the UTXOs created and spent.
+fn check_transactions<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug + Valued>(
+    blocks: &[&Block<G, T>],
+    mints: Option<&Mints<T>>,
+) -> Result<(Vec<T>, Vec<T>), BlockError> {
+    let mut elems_added = Vec::new();
+    let mut elems_deleted = Vec::new();
+    for transaction in blocks.iter().flat_map(|block| &block.transactions) {
+        if !transaction.conserves_value(mints) {
+            return Err(BlockError::Inflation);
+        }
+        elems_added.extend(transaction.utxos_created.iter().cloned());
+        elems_deleted.extend(
+            transaction
+                .utxos_spent_with_witnesses
+                .iter()
+                .map(|(elem, _witness)| elem.clone()),
+        );
+    }
+    let mut spent = HashSet::new();
+    if !elems_deleted.iter().all(|elem| spent.insert(elem)) {
+        return Err(BlockError::DoubleSpend);
+    }
+    let mut created = HashSet::new();
+    if !elems_added.iter().all(|elem| created.insert(elem)) {
+        return Err(BlockError::DoubleSpend);
+    }
+    Ok((elems_added, elems_deleted))
+}
+
+/// The first proof stage of `validate_block`: checks that `block`'s proofs show that deleting
+/// `elems_deleted` from `acc` gives the accumulator it adds to. A block proven by a swap is
+/// checked in full here, as its one proof covers `elems_added` too. Two-step proofs must meet at
+/// the same intermediate accumulator, which is compared before either proof is checked, as it
+/// costs nothing.
+pub fn verify_deletions<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+    acc: &Accumulator<G, T>,
+    block: &Block<G, T>,
+    elems_added: &[T],
+    elems_deleted: &[T],
+    metrics: &Metrics,
+) -> Result<(), BlockError> {
+    let instrumented = Instrumented::new(metrics);
+    let is_valid = match &block.proof_update {
+        UpdateProof::Sequential { deleted, added } => {
+            deleted.witness == added.witness
+                && instrumented.verify_deletion(deleted, acc, elems_deleted)
+        }
+        UpdateProof::Swap(proof) => {
+            instrumented.verify_swap(proof, acc, &block.acc_new, elems_deleted, elems_added)
+        }
+    };
+    if !is_valid {
+        return Err(BlockError::InvalidProof);
+    }
+    Ok(())
+}
+
+/// The second proof stage of `validate_block`: checks that adding `elems_added` gives `block`'s
+/// accumulator, unless a swap proof showed it already, and that any proof the block has that they
+/// were not in `acc` holds.
+pub fn verify_additions<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug>(
+    acc: &Accumulator<G, T>,
+    block: &Block<G, T>,
+    elems_added: &[T],
+    metrics: &Metrics,
+) -> Result<(), BlockError> {
+    let instrumented = Instrumented::new(metrics);
+    if let UpdateProof::Sequential { added, .. } = &block.proof_update {
+        if !instrumented.verify_additions(&block.acc_new, elems_added, added) {
+            return Err(BlockError::InvalidProof);
+        }
+    }
+    if let Some(proof) = &block.proof_fresh {
+        let is_fresh =
+            instrumented.verify_nonmembership(ProofOp::FreshUtxos, acc, elems_added, proof);
+        if !is_fresh {
+            return Err(BlockError::Reinsertion);
+        }
+    }
+    Ok(())
+}
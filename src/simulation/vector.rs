@@ -0,0 +1,296 @@
+use super::config::Config;
+use super::proofs::{MEMBERSHIP_PROOF_BYTES, NONMEMBERSHIP_PROOF_BYTES};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{Accumulator, VCError, VectorCommitment, VectorProof, Witness};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rug::Integer;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Chance that a user flips its bit in any one block interval.
+const FLIP_PROBABILITY: f64 = 0.25;
+
+/// Bytes of a vector proof: a membership proof for the bits set to one and a non-membership proof
+/// for the bits set to zero.
+pub const VECTOR_PROOF_BYTES: usize = MEMBERSHIP_PROOF_BYTES + NONMEMBERSHIP_PROOF_BYTES;
+
+/// A block of the vector chain: the bits it commits to at a height, and a proof that the commitment
+/// after it holds them.
+pub struct VectorBlock<G: UnknownOrderGroup> {
+    pub height: u64,
+    pub bits: Vec<(bool, Integer)>,
+    pub vc: VectorCommitment<G>,
+    pub proof: VectorProof<G>,
+}
+
+impl<G: UnknownOrderGroup> VectorBlock<G> {
+    pub fn verify(&self) -> bool {
+        VectorCommitment::verify(&self.vc, &self.bits, &self.proof)
+    }
+}
+
+/// Keeps a sparse bit vector, indexed by user ID, and the commitment to it. The commitment is an
+/// accumulator of the positions set to one, so every other position is zero however long the
+/// vector.
+pub struct Committer<G: UnknownOrderGroup> {
+    height: u64,
+    vc: VectorCommitment<G>,
+    ones: BTreeSet<usize>,
+}
+
+impl<G: UnknownOrderGroup> Committer<G> {
+    pub fn new() -> Self {
+        Self {
+            height: 0,
+            vc: VectorCommitment::empty(),
+            ones: BTreeSet::new(),
+        }
+    }
+
+    /// The bit at position `index`.
+    pub fn bit(&self, index: usize) -> bool {
+        self.ones.contains(&index)
+    }
+
+    /// Flips the bits at `indices` and commits to the result in a new block. The crate's
+    /// commitment can only set bits, so a block that clears one commits to the vector afresh, and
+    /// proves every bit set as well as those cleared.
+    pub fn commit(&mut self, indices: &[usize]) -> Result<VectorBlock<G>, VCError> {
+        let mut flips = Vec::new();
+        let mut clears_bit = false;
+        for index in indices {
+            let bit = !self.bit(*index);
+            if bit {
+                self.ones.insert(*index);
+            } else {
+                self.ones.remove(index);
+                clears_bit = true;
+            }
+            flips.push((bit, Integer::from(*index)));
+        }
+        let (vc, bits) = if clears_bit {
+            let mut bits: Vec<(bool, Integer)> = self
+                .ones
+                .iter()
+                .map(|index| (true, Integer::from(*index)))
+                .collect();
+            bits.extend(flips.into_iter().filter(|(bit, _)| !bit));
+            (VectorCommitment::empty(), bits)
+        } else {
+            (self.vc.clone(), flips)
+        };
+        let (vc, proof) = VectorCommitment::update(vc, &self.ones_set(), &bits)?;
+        self.vc = vc.clone();
+        self.height += 1;
+        Ok(VectorBlock {
+            height: self.height,
+            bits,
+            vc,
+            proof,
+        })
+    }
+
+    /// A proof of the bit at `index` against the current commitment.
+    pub fn open(&self, index: usize) -> Result<VectorProof<G>, VCError> {
+        let position = Integer::from(index);
+        if !self.bit(index) {
+            return VectorCommitment::open(&self.vc, &self.ones_set(), &[position], &[]);
+        }
+        let others: Vec<Integer> = self
+            .ones
+            .iter()
+            .filter(|other| **other != index)
+            .map(|other| Integer::from(*other))
+            .collect();
+        let witness = Witness(Accumulator::empty().add(&others));
+        VectorCommitment::open(&self.vc, &self.ones_set(), &[], &[(position, witness)])
+    }
+
+    /// The positions set to one, as the elements of the accumulator behind the commitment.
+    fn ones_set(&self) -> Vec<Integer> {
+        self.ones
+            .iter()
+            .map(|index| Integer::from(*index))
+            .collect()
+    }
+}
+
+impl<G: UnknownOrderGroup> Default for Committer<G> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Totals for a finished vector-commitment run.
+pub struct VectorSummary {
+    pub blocks: u64,
+    /// Bits flipped from zero to one, and from one to zero.
+    pub bits_set: u64,
+    pub bits_cleared: u64,
+    /// Blocks that cleared a bit, and so committed to the vector afresh.
+    pub recommitments: u64,
+    /// Positions opened by their users after flipping them.
+    pub openings: u64,
+    /// Blocks and openings whose proofs did not verify.
+    pub failed_verifications: u64,
+    pub commit_time: Duration,
+    pub block_verification_time: Duration,
+    pub opening_time: Duration,
+    pub opening_verification_time: Duration,
+}
+
+impl VectorSummary {
+    /// Estimated bytes of every proof made, one per block and one per opening.
+    pub fn proof_bytes(&self) -> u64 {
+        (self.blocks + self.openings) * VECTOR_PROOF_BYTES as u64
+    }
+}
+
+impl fmt::Display for VectorSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Vector commitment summary")?;
+        writeln!(f, "  blocks:                  {}", self.blocks)?;
+        writeln!(
+            f,
+            "  bits flipped:            {} set, {} cleared",
+            self.bits_set, self.bits_cleared
+        )?;
+        writeln!(f, "  recommitments:           {}", self.recommitments)?;
+        writeln!(f, "  openings:                {}", self.openings)?;
+        writeln!(
+            f,
+            "  failed verifications:    {}",
+            self.failed_verifications
+        )?;
+        writeln!(
+            f,
+            "  commit time:             {:.1?} mean, {:.1?} to verify",
+            mean(self.commit_time, self.blocks),
+            mean(self.block_verification_time, self.blocks)
+        )?;
+        writeln!(
+            f,
+            "  opening time:            {:.1?} mean, {:.1?} to verify",
+            mean(self.opening_time, self.openings),
+            mean(self.opening_verification_time, self.openings)
+        )?;
+        write!(f, "  proof bytes:             {}", self.proof_bytes())
+    }
+}
+
+fn mean(total: Duration, count: u64) -> Duration {
+    if count == 0 {
+        return Duration::from_secs(0);
+    }
+    total / count as u32
+}
+
+/// A run in which the accumulator backs a vector commitment rather than a UTXO set. Each user owns
+/// the position of the vector at its ID. Every block interval, users flip their bits at random, the
+/// committer commits to the flips in a block that users verify, and each user that flipped its bit
+/// has the committer open its position and checks the bit it wrote. Runs are stepped on a single
+/// thread whatever the mode, and are deterministic for a given seed.
+pub struct VectorDemo<G: UnknownOrderGroup> {
+    config: Config,
+    rng: StdRng,
+    committer: Committer<G>,
+    summary: VectorSummary,
+}
+
+impl<G: UnknownOrderGroup> VectorDemo<G> {
+    pub fn new(config: Config) -> Self {
+        let rng = StdRng::seed_from_u64(config.seed);
+        Self {
+            config,
+            rng,
+            committer: Committer::new(),
+            summary: VectorSummary::default(),
+        }
+    }
+
+    /// Runs until the chain reaches the bounds in the config.
+    pub fn run(mut self) -> VectorSummary {
+        info!(
+            "Vector commitment run starting with {} users.",
+            self.config.num_users()
+        );
+        if self.config.max_blocks.is_none() && self.config.max_duration_ms.is_none() {
+            warn!("The run has no bounds, so it never ends.");
+        }
+        let block_interval = Duration::from_millis(self.config.block_interval_ms);
+        let mut now = Duration::from_secs(0);
+        while !self.config.is_finished(self.committer.height, now) {
+            self.step();
+            now += block_interval;
+        }
+        info!(
+            "Vector commitment run exiting at block {}.",
+            self.committer.height
+        );
+        self.summary
+    }
+
+    /// Flips users' bits, commits to them and verifies the block and each flipped position.
+    fn step(&mut self) {
+        let flipped: Vec<usize> = (0..self.config.num_users())
+            .filter(|_| self.rng.gen_bool(FLIP_PROBABILITY))
+            .collect();
+        let cleared = flipped
+            .iter()
+            .filter(|index| self.committer.bit(**index))
+            .count() as u64;
+        self.summary.bits_cleared += cleared;
+        self.summary.bits_set += flipped.len() as u64 - cleared;
+
+        let start = Instant::now();
+        let block = match self.committer.commit(&flipped) {
+            Ok(block) => block,
+            Err(err) => {
+                warn!("Failed to commit to {} flips: {:?}", flipped.len(), err);
+                return;
+            }
+        };
+        self.summary.commit_time += start.elapsed();
+        self.summary.blocks += 1;
+        if cleared > 0 {
+            self.summary.recommitments += 1;
+        }
+        let start = Instant::now();
+        let is_valid = block.verify();
+        self.summary.block_verification_time += start.elapsed();
+        if !is_valid {
+            warn!("Block {} does not prove its bits.", block.height);
+            self.summary.failed_verifications += 1;
+        }
+        debug!(
+            "Committed block {} with {} flips.",
+            block.height,
+            flipped.len()
+        );
+
+        for index in flipped {
+            let start = Instant::now();
+            let proof = match self.committer.open(index) {
+                Ok(proof) => proof,
+                Err(err) => {
+                    warn!("Failed to open position {}: {:?}", index, err);
+                    continue;
+                }
+            };
+            self.summary.opening_time += start.elapsed();
+            self.summary.openings += 1;
+            let bit = (self.committer.bit(index), Integer::from(index));
+            let start = Instant::now();
+            let is_valid = VectorCommitment::verify(&block.vc, &[bit], &proof);
+            self.summary.opening_verification_time += start.elapsed();
+            if !is_valid {
+                warn!("The opening of position {} does not verify.", index);
+                self.summary.failed_verifications += 1;
+            }
+        }
+    }
+}
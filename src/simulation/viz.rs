@@ -0,0 +1,234 @@
+use super::config::Config;
+use super::network::MessageKind;
+use super::state::NodeId;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{error, warn};
+
+#[derive(Clone, Debug, Serialize)]
+/// A node as a front end draws it. Users are grouped under their bridge.
+pub struct VizNode {
+    pub id: String,
+    pub kind: &'static str,
+    pub bridge: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// A pair of nodes that exchange messages of some kind.
+pub struct VizLink {
+    pub source: String,
+    pub target: String,
+    pub kind: MessageKind,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// One line of the feed. Times are simulated milliseconds since the start of the run.
+pub enum Record {
+    /// Every node at the start of the run, and who talks to whom. Always the first record.
+    Topology {
+        nodes: Vec<VizNode>,
+        links: Vec<VizLink>,
+    },
+    /// A node added during the run.
+    Joined { at_ms: u64, node: VizNode },
+    /// A node stopped or restarted.
+    Status {
+        at_ms: u64,
+        id: String,
+        online: bool,
+    },
+    /// A block forged by `miner`, if known. Byzantine miners' blocks are marked as such.
+    Block {
+        at_ms: u64,
+        miner: Option<usize>,
+        height: u64,
+        transactions: usize,
+        byzantine: bool,
+    },
+    /// A message on its way from `source` to `target`. Lost messages have no delivery time; a
+    /// duplicated message appears once per copy.
+    Message {
+        sent_ms: u64,
+        delivered_ms: Option<u64>,
+        source: String,
+        target: String,
+        kind: MessageKind,
+    },
+}
+
+/// The id under which a front end knows `node`, e.g. `miner-3`.
+pub fn node_id(node: NodeId) -> String {
+    match node {
+        NodeId::Miner(id) => format!("miner-{}", id),
+        NodeId::Bridge(id) => format!("bridge-{}", id),
+        NodeId::User(id) => format!("user-{}", id),
+    }
+}
+
+fn viz_node(node: NodeId, bridge: Option<usize>) -> VizNode {
+    let kind = match node {
+        NodeId::Miner(_) => "miner",
+        NodeId::Bridge(_) => "bridge",
+        NodeId::User(_) => "user",
+    };
+    VizNode {
+        id: node_id(node),
+        kind,
+        bridge,
+    }
+}
+
+fn millis(time: Duration) -> u64 {
+    time.as_millis() as u64
+}
+
+/// A streaming JSON-lines feed of a run for a web front end, e.g. one built on d3: the topology
+/// first, then blocks, messages and node status changes as they happen. Blocks flush the feed, so
+/// it can be followed while the run goes on.
+pub struct Feed {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl Feed {
+    /// Creates the feed at `path` and writes the topology of a run configured by `config`.
+    pub fn create<P: AsRef<Path>>(path: P, config: &Config) -> io::Result<Self> {
+        let feed = Self {
+            out: Mutex::new(BufWriter::new(File::create(path)?)),
+        };
+        let (nodes, links) = topology(config);
+        feed.write(&Record::Topology { nodes, links });
+        Ok(feed)
+    }
+
+    /// The feed `config` asks for, if any. Failing to create it is logged rather than fatal.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let path = config.viz_output.as_ref()?;
+        match Self::create(path, config) {
+            Ok(feed) => Some(feed),
+            Err(err) => {
+                error!("Failed to create {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn joined(&self, at: Duration, user_id: usize, bridge_id: usize) {
+        self.write(&Record::Joined {
+            at_ms: millis(at),
+            node: viz_node(NodeId::User(user_id), Some(bridge_id)),
+        });
+    }
+
+    pub fn status(&self, at: Duration, node: NodeId, online: bool) {
+        self.write(&Record::Status {
+            at_ms: millis(at),
+            id: node_id(node),
+            online,
+        });
+    }
+
+    pub fn block(
+        &self,
+        at: Duration,
+        miner: Option<usize>,
+        height: u64,
+        transactions: usize,
+        byzantine: bool,
+    ) {
+        self.write(&Record::Block {
+            at_ms: millis(at),
+            miner,
+            height,
+            transactions,
+            byzantine,
+        });
+        if let Err(err) = self.out.lock().unwrap().flush() {
+            warn!("Failed to flush the visualization feed: {}", err);
+        }
+    }
+
+    /// Records a message sent at `sent` that arrives at each of `deliveries`, or is lost if there
+    /// are none.
+    pub fn message(
+        &self,
+        sent: Duration,
+        deliveries: &[Duration],
+        from: NodeId,
+        to: NodeId,
+        kind: MessageKind,
+    ) {
+        let record = |delivered: Option<&Duration>| Record::Message {
+            sent_ms: millis(sent),
+            delivered_ms: delivered.cloned().map(millis),
+            source: node_id(from),
+            target: node_id(to),
+            kind,
+        };
+        if deliveries.is_empty() {
+            self.write(&record(None));
+        }
+        for delivered in deliveries {
+            self.write(&record(Some(delivered)));
+        }
+    }
+
+    fn write(&self, record: &Record) {
+        let mut out = self.out.lock().unwrap();
+        let result = serde_json::to_writer(&mut *out, record)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(out));
+        if let Err(err) = result {
+            warn!("Failed to write to the visualization feed: {}", err);
+        }
+    }
+}
+
+/// Miners send blocks to each other and to bridges, users send transactions to miners, and each
+/// user trades witnesses with its bridge. Returns the nodes of a run configured by `config`, users
+/// under their bridge, and the links between them.
+pub fn topology(config: &Config) -> (Vec<VizNode>, Vec<VizLink>) {
+    let miners: Vec<NodeId> = (0..config.num_miners).map(NodeId::Miner).collect();
+    let bridges: Vec<NodeId> = (0..config.num_bridges()).map(NodeId::Bridge).collect();
+    let mut users = Vec::new();
+    let mut first_user = 0;
+    for (bridge_id, count) in config.users_per_bridge.iter().enumerate() {
+        users.extend((first_user..first_user + count).map(|user_id| (user_id, bridge_id)));
+        first_user += count;
+    }
+
+    let mut nodes: Vec<VizNode> = miners
+        .iter()
+        .chain(&bridges)
+        .map(|node| viz_node(*node, None))
+        .collect();
+    nodes.extend(
+        users
+            .iter()
+            .map(|(user_id, bridge_id)| viz_node(NodeId::User(*user_id), Some(*bridge_id))),
+    );
+
+    let link = |source: NodeId, target: NodeId, kind| VizLink {
+        source: node_id(source),
+        target: node_id(target),
+        kind,
+    };
+    let mut links = Vec::new();
+    for miner in &miners {
+        for to in miners.iter().chain(&bridges).filter(|to| *to != miner) {
+            links.push(link(*miner, *to, MessageKind::Block));
+        }
+    }
+    for (user_id, bridge_id) in users {
+        let user = NodeId::User(user_id);
+        links.push(link(user, NodeId::Bridge(bridge_id), MessageKind::Witness));
+        for miner in &miners {
+            links.push(link(user, *miner, MessageKind::Transaction));
+        }
+    }
+    (nodes, links)
+}
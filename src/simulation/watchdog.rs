@@ -0,0 +1,261 @@
+//! Stall detection. A run whose message plumbing deadlocks rarely fails outright: blocks stop
+//! coming, users wait forever on confirmations, or a queue fills and stays full, and the run idles
+//! until someone notices. The watchdog looks at the run's metrics every so often, alerts when it
+//! sees one of these stalls, and can stop the run with a dump of where everything stood.
+use super::clock::Clock;
+use super::config::Config;
+use super::control::RunControl;
+use super::metrics::Metrics;
+use super::transport::{Channel, RECEIVE_TIMEOUT};
+use super::viz;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display, Write};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, warn};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// What the watchdog takes for a stall. Each check is off if not set.
+pub struct WatchdogConfig {
+    /// How long the chain may go without a new block.
+    pub block_timeout: Option<Duration>,
+    /// How many blocks a user's transaction may go unconfirmed.
+    pub unconfirmed_blocks: Option<u64>,
+    /// How long a channel's queue may stay full. Real-time runs only.
+    pub full_channel_timeout: Option<Duration>,
+    /// Whether to stop the run at the first stall.
+    pub abort: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// Something the watchdog found stuck.
+pub enum Stall {
+    /// The chain has stayed at `height` since `since`.
+    NoBlocks { height: u64, since: Duration },
+    /// User `user_id`'s transaction has gone unconfirmed for `blocks` blocks.
+    Unconfirmed { user_id: usize, blocks: u64 },
+    /// A queue of `channel` has been full since `since`.
+    ChannelFull { channel: Channel, since: Duration },
+}
+
+impl Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stall::NoBlocks { height, since } => {
+                write!(f, "No block since block {} at {:.1?}", height, since)
+            }
+            Stall::Unconfirmed { user_id, blocks } => write!(
+                f,
+                "User {}'s transaction unconfirmed for {} blocks",
+                user_id, blocks
+            ),
+            Stall::ChannelFull { channel, since } => {
+                write!(f, "A {} queue full since {:.1?}", channel.name(), since)
+            }
+        }
+    }
+}
+
+/// A user's outstanding transaction, as the watchdog follows it.
+struct Outstanding {
+    issued_at: Duration,
+    /// The chain's height when the watchdog first saw the transaction.
+    height: u64,
+    alerted: bool,
+}
+
+/// Looks for stalls in a run's metrics, alerting on each once, until it clears.
+pub struct Watchdog {
+    config: WatchdogConfig,
+    capacities: BTreeMap<Channel, usize>,
+    height: u64,
+    last_block_at: Duration,
+    blocks_alerted: bool,
+    outstanding: HashMap<usize, Outstanding>,
+    /// When each channel that is full now filled up.
+    full_since: BTreeMap<Channel, Duration>,
+    channels_alerted: BTreeSet<Channel>,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig, capacities: BTreeMap<Channel, usize>) -> Self {
+        Self {
+            config,
+            capacities,
+            height: 0,
+            last_block_at: Duration::default(),
+            blocks_alerted: false,
+            outstanding: HashMap::new(),
+            full_since: BTreeMap::new(),
+            channels_alerted: BTreeSet::new(),
+        }
+    }
+
+    /// The watchdog `config` asks for, if any.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let capacities = Channel::ALL
+            .iter()
+            .map(|channel| (*channel, config.channel_capacity(*channel)))
+            .collect();
+        Some(Self::new(config.watchdog()?, capacities))
+    }
+
+    /// Checks `metrics` at simulated time `now`, alerting on the stalls that have set in since
+    /// the last check, and stopping the run through `control` if the config says to. Returns the
+    /// new stalls.
+    pub fn check(&mut self, now: Duration, metrics: &Metrics, control: &RunControl) -> Vec<Stall> {
+        let stalls = self.stalls(now, metrics);
+        for stall in &stalls {
+            warn!("Watchdog: {}.", stall);
+            metrics.stall_detected();
+        }
+        if self.config.abort {
+            if let Some(stall) = stalls.first() {
+                error!(
+                    "Watchdog stopping the run: {}.\n{}",
+                    stall,
+                    diagnostics(now, metrics)
+                );
+                control.stop();
+            }
+        }
+        stalls
+    }
+
+    /// Has the time up to `now` not count towards a stall, e.g. while the run is paused.
+    pub fn rest(&mut self, now: Duration) {
+        self.last_block_at = now;
+        for since in self.full_since.values_mut() {
+            *since = now;
+        }
+    }
+
+    /// Checks on the run from a thread of its own, every `RECEIVE_TIMEOUT` of real time, until
+    /// the run is stopped. Time spent paused does not count.
+    pub fn spawn(
+        mut self,
+        clock: Arc<dyn Clock>,
+        control: Arc<RunControl>,
+        metrics: Arc<Metrics>,
+    ) -> JoinHandle<()> {
+        self.rest(clock.now());
+        thread::spawn(move || {
+            while !control.is_stopped() {
+                thread::sleep(RECEIVE_TIMEOUT);
+                if control.is_paused() {
+                    self.rest(clock.now());
+                } else {
+                    self.check(clock.now(), &metrics, &control);
+                }
+            }
+        })
+    }
+
+    fn stalls(&mut self, now: Duration, metrics: &Metrics) -> Vec<Stall> {
+        let mut stalls = Vec::new();
+        let height = metrics.chain_height();
+        if height > self.height {
+            self.height = height;
+            self.last_block_at = now;
+            self.blocks_alerted = false;
+        }
+        if let Some(timeout) = self.config.block_timeout {
+            if !self.blocks_alerted && now >= self.last_block_at + timeout {
+                self.blocks_alerted = true;
+                stalls.push(Stall::NoBlocks {
+                    height,
+                    since: self.last_block_at,
+                });
+            }
+        }
+
+        let issued = metrics.outstanding_transactions();
+        self.outstanding
+            .retain(|user_id, outstanding| issued.get(user_id) == Some(&outstanding.issued_at));
+        for (user_id, issued_at) in issued {
+            let outstanding = self.outstanding.entry(user_id).or_insert(Outstanding {
+                issued_at,
+                height,
+                alerted: false,
+            });
+            let blocks = height.saturating_sub(outstanding.height);
+            match self.config.unconfirmed_blocks {
+                Some(limit) if !outstanding.alerted && blocks >= limit => {
+                    outstanding.alerted = true;
+                    stalls.push(Stall::Unconfirmed { user_id, blocks });
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(timeout) = self.config.full_channel_timeout {
+            for (channel, depth) in metrics.queue_depths() {
+                let capacity = self.capacities.get(&channel).copied().unwrap_or(usize::MAX);
+                if depth.depth < capacity as u64 {
+                    self.full_since.remove(&channel);
+                    self.channels_alerted.remove(&channel);
+                    continue;
+                }
+                let since = *self.full_since.entry(channel).or_insert(now);
+                if now >= since + timeout && self.channels_alerted.insert(channel) {
+                    stalls.push(Stall::ChannelFull { channel, since });
+                }
+            }
+        }
+        stalls
+    }
+}
+
+/// Where the run stood at `now`: the chain's height, the depth of every channel's queues, every
+/// miner's mempool and bridge's backlog, and the transactions waiting to be confirmed.
+pub fn diagnostics(now: Duration, metrics: &Metrics) -> String {
+    let live = metrics.live();
+    let mut out = String::new();
+    writeln!(out, "Run state at {:.1?}:", now).unwrap();
+    writeln!(out, "  chain height:            {}", metrics.chain_height()).unwrap();
+    for (node, height) in &live.block_heights {
+        writeln!(
+            out,
+            "  {:<25}{}",
+            format!("{} height:", viz::node_id(*node)),
+            height
+        )
+        .unwrap();
+    }
+    for (channel, depth) in metrics.queue_depths() {
+        writeln!(
+            out,
+            "  {:<25}{} deep, {} at most",
+            format!("{} queue:", channel.name()),
+            depth.depth,
+            depth.high_watermark
+        )
+        .unwrap();
+    }
+    for (miner_id, depth) in &live.mempool_depths {
+        writeln!(
+            out,
+            "  {:<25}{} transactions",
+            format!("miner {} mempool:", miner_id),
+            depth
+        )
+        .unwrap();
+    }
+    for (bridge_id, length) in &live.bridge_queue_lengths {
+        writeln!(
+            out,
+            "  {:<25}{} requests",
+            format!("bridge {} backlog:", bridge_id),
+            length
+        )
+        .unwrap();
+    }
+    write!(
+        out,
+        "  unconfirmed transactions: {}",
+        metrics.outstanding_transactions().len()
+    )
+    .unwrap();
+    out
+}
@@ -0,0 +1,191 @@
+use super::bridge::UserUpdate;
+use super::config::Config;
+use super::state::{Block, Utxo};
+use super::util::ConnectionLimit;
+use accumulator::group::UnknownOrderGroup;
+use serde::Serialize;
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use tungstenite::Message;
+
+/// A client that has not finished its WebSocket handshake within this long is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A client that takes longer than this to accept an event is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Events queued for a client that has not caught up. A client this far behind is dropped rather
+/// than let the queue grow.
+const CLIENT_QUEUE_DEPTH: usize = 256;
+
+/// Clients fed at once. Connections beyond this are closed as they are accepted.
+const MAX_CLIENTS: usize = 64;
+
+#[derive(Clone, Debug, Serialize)]
+/// The UTXOs a transaction in a block created and spent.
+pub struct TransactionSummary<'a> {
+    pub utxos_created: &'a [Utxo],
+    pub utxos_spent: Vec<&'a Utxo>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// One event of the feed, sent as a JSON text message. Times are simulated milliseconds since the
+/// start of the run.
+pub enum Event<'a> {
+    /// A block that reached the miners.
+    Block {
+        at_ms: u64,
+        height: u64,
+        transactions: Vec<TransactionSummary<'a>>,
+    },
+    /// A change to the UTXOs a bridge tracks for `user_id`, sent as it processes a block.
+    UserUpdate {
+        at_ms: u64,
+        user_id: usize,
+        utxos_added: &'a [Utxo],
+        utxos_deleted: &'a [Utxo],
+    },
+}
+
+/// Broadcasts new blocks and user updates as JSON events to every WebSocket client connected to
+/// the address it listens on. Clients only see events from when they connect, and cannot send
+/// anything back. Each client has a thread of its own writing to it from a bounded queue, so
+/// one slow client holds up neither the others nor the run, and at most `MAX_CLIENTS` are fed at
+/// once.
+pub struct WebSocketFeed {
+    clients: Arc<Mutex<Vec<SyncSender<String>>>>,
+}
+
+impl WebSocketFeed {
+    /// Listens for clients on `addr` until the process exits.
+    pub fn serve<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Serving the WebSocket feed on {}.", listener.local_addr()?);
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        let limit = ConnectionLimit::new(MAX_CLIENTS);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept a WebSocket client: {}", err);
+                        continue;
+                    }
+                };
+                let slot = match limit.acquire() {
+                    Some(slot) => slot,
+                    None => {
+                        warn!(
+                            "Turning away a WebSocket client: {} are connected.",
+                            MAX_CLIENTS
+                        );
+                        continue;
+                    }
+                };
+                let accepted = accepted.clone();
+                thread::spawn(move || {
+                    let _slot = slot;
+                    if let Err(err) = serve_client(stream, &accepted) {
+                        warn!("Failed to accept a WebSocket client: {}", err);
+                    }
+                });
+            }
+        });
+        Ok(Self { clients })
+    }
+
+    /// The feed `config` asks for, if any. Failing to serve it is logged rather than fatal.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let addr = config.websocket_addr.as_ref()?;
+        match Self::serve(addr) {
+            Ok(feed) => Some(feed),
+            Err(err) => {
+                error!("Failed to serve the WebSocket feed on {}: {}", addr, err);
+                None
+            }
+        }
+    }
+
+    pub fn block<G: UnknownOrderGroup>(&self, at: Duration, block: &Block<G, Utxo>) {
+        let transactions = block
+            .transactions
+            .iter()
+            .map(|transaction| TransactionSummary {
+                utxos_created: &transaction.utxos_created,
+                utxos_spent: transaction
+                    .utxos_spent_with_witnesses
+                    .iter()
+                    .map(|(utxo, _)| utxo)
+                    .collect(),
+            })
+            .collect();
+        self.send(&Event::Block {
+            at_ms: at.as_millis() as u64,
+            height: block.height,
+            transactions,
+        });
+    }
+
+    pub fn user_update<G: UnknownOrderGroup>(
+        &self,
+        at: Duration,
+        user_id: usize,
+        update: &UserUpdate<G>,
+    ) {
+        self.send(&Event::UserUpdate {
+            at_ms: at.as_millis() as u64,
+            user_id,
+            utxos_added: &update.utxos_added,
+            utxos_deleted: &update.utxos_deleted,
+        });
+    }
+
+    /// Queues `event` for every client, dropping those that have gone away or fallen too far
+    /// behind.
+    fn send(&self, event: &Event) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let text = match serde_json::to_string(event) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!("Failed to encode a WebSocket event: {}", err);
+                return;
+            }
+        };
+        clients.retain(|client| match client.try_send(text.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                debug!("Dropping a WebSocket client that fell behind.");
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+/// Completes the WebSocket handshake with the client at the other end of `stream`, adds a queue
+/// for it to `clients`, and writes it each event queued until it goes away or its queue is dropped.
+fn serve_client(stream: TcpStream, clients: &Mutex<Vec<SyncSender<String>>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut client = tungstenite::accept(stream)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let (sender, events) = mpsc::sync_channel(CLIENT_QUEUE_DEPTH);
+    clients.lock().unwrap().push(sender);
+    for text in events {
+        if let Err(err) = client.send(Message::text(text)) {
+            debug!("Dropping a WebSocket client: {}", err);
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use super::auth::{Keyring, Signer};
+use super::bridge::{UserUpdate, WitnessRequest, WitnessResponse};
+use super::network::MessageKind;
+use super::state::{Block, NodeId, Transaction};
+use accumulator::group::UnknownOrderGroup;
+use ed25519_dalek::Signature;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// The version of the wire format written by this build. Bump it whenever a message type changes
+/// shape, so that peers running another build reject its messages instead of misreading them.
+pub const WIRE_VERSION: u16 = 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Which message type an envelope holds.
+pub enum WireKind {
+    Block,
+    Transaction,
+    WitnessRequest,
+    WitnessResponse,
+    UserUpdate,
+}
+
+impl WireKind {
+    /// Whether a receiver that checks signatures insists on one for this type of message, rather
+    /// than only checking those that are signed.
+    pub fn needs_signature(self) -> bool {
+        match self {
+            WireKind::Block | WireKind::Transaction | WireKind::WitnessResponse => true,
+            WireKind::WitnessRequest | WireKind::UserUpdate => false,
+        }
+    }
+
+    /// The kind of traffic this type of message is counted as.
+    pub fn traffic(self) -> MessageKind {
+        match self {
+            WireKind::Block => MessageKind::Block,
+            WireKind::Transaction => MessageKind::Transaction,
+            WireKind::WitnessRequest | WireKind::WitnessResponse | WireKind::UserUpdate => {
+                MessageKind::Witness
+            }
+        }
+    }
+
+    /// Whether `node` is the kind of component that sends this type of message: miners send
+    /// blocks, users send transactions and witness requests, and bridges send the rest.
+    pub fn may_come_from(self, node: NodeId) -> bool {
+        match self {
+            WireKind::Block => matches!(node, NodeId::Miner(_)),
+            WireKind::Transaction | WireKind::WitnessRequest => matches!(node, NodeId::User(_)),
+            WireKind::WitnessResponse | WireKind::UserUpdate => matches!(node, NodeId::Bridge(_)),
+        }
+    }
+}
+
+/// A message that can cross a link between components, in memory or over a network.
+pub trait WireMessage: Serialize + DeserializeOwned {
+    const KIND: WireKind;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> WireMessage for Block<G, T>
+where
+    Block<G, T>: Serialize + DeserializeOwned,
+{
+    const KIND: WireKind = WireKind::Block;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> WireMessage for Transaction<G, T>
+where
+    Transaction<G, T>: Serialize + DeserializeOwned,
+{
+    const KIND: WireKind = WireKind::Transaction;
+}
+
+impl WireMessage for WitnessRequest {
+    const KIND: WireKind = WireKind::WitnessRequest;
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Hash + Debug> WireMessage for WitnessResponse<G, T>
+where
+    WitnessResponse<G, T>: Serialize + DeserializeOwned,
+{
+    const KIND: WireKind = WireKind::WitnessResponse;
+}
+
+impl<G: UnknownOrderGroup> WireMessage for UserUpdate<G>
+where
+    UserUpdate<G>: Serialize + DeserializeOwned,
+{
+    const KIND: WireKind = WireKind::UserUpdate;
+}
+
+/// A shared message goes on the wire as the message itself.
+impl<M: WireMessage> WireMessage for Arc<M> {
+    const KIND: WireKind = M::KIND;
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+/// A message as it goes on the wire: the format version and message type, then the message itself
+/// in bincode, and optionally who sent it and their signature on the type and message. Transports
+/// and traces deal in envelopes, so a message written by one can be read by any other.
+pub struct Envelope {
+    pub version: u16,
+    pub kind: WireKind,
+    pub payload: Vec<u8>,
+    pub sender: Option<NodeId>,
+    pub signature: Vec<u8>,
+}
+
+impl Envelope {
+    /// What the sender signs: the message type, then the message.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + self.payload.len());
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+#[derive(Debug)]
+/// Why a message could not be encoded or decoded.
+pub enum WireError {
+    Bincode(bincode::Error),
+    /// The envelope was written in another version of the wire format.
+    Version(u16),
+    /// The envelope holds another type of message than the one expected.
+    Kind {
+        expected: WireKind,
+        found: WireKind,
+    },
+    /// The message should have been signed, but was not.
+    Unsigned(WireKind),
+    /// The signature is not that of the component the message claims to come from, or that
+    /// component does not send this type of message.
+    Forged(NodeId),
+}
+
+impl From<bincode::Error> for WireError {
+    fn from(err: bincode::Error) -> Self {
+        WireError::Bincode(err)
+    }
+}
+
+impl Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireError::Bincode(err) => write!(f, "{}", err),
+            WireError::Version(version) => write!(
+                f,
+                "Wire format version {} is not version {}",
+                version, WIRE_VERSION
+            ),
+            WireError::Kind { expected, found } => {
+                write!(f, "Expected a {:?} message, found a {:?}", expected, found)
+            }
+            WireError::Unsigned(kind) => write!(f, "A {:?} message is unsigned", kind),
+            WireError::Forged(sender) => {
+                write!(f, "A message claiming to be from {:?} is forged", sender)
+            }
+        }
+    }
+}
+
+impl Error for WireError {}
+
+/// `message` in an unsigned envelope, encoded.
+pub fn encode<M: WireMessage>(message: &M) -> Result<Vec<u8>, WireError> {
+    Ok(bincode::serialize(&seal(message)?)?)
+}
+
+/// `message` in an envelope signed by `signer`, encoded.
+pub fn encode_signed<M: WireMessage>(message: &M, signer: &Signer) -> Result<Vec<u8>, WireError> {
+    let mut envelope = seal(message)?;
+    let (sender, signature) = signer.sign(&envelope.signed_bytes());
+    envelope.sender = Some(sender);
+    envelope.signature = signature.to_bytes().to_vec();
+    Ok(bincode::serialize(&envelope)?)
+}
+
+fn seal<M: WireMessage>(message: &M) -> Result<Envelope, WireError> {
+    Ok(Envelope {
+        version: WIRE_VERSION,
+        kind: M::KIND,
+        payload: bincode::serialize(message)?,
+        sender: None,
+        signature: Vec::new(),
+    })
+}
+
+/// The message in an envelope encoded by `encode` or `encode_signed`, if it is of this wire version
+/// and type. Signatures are not checked.
+pub fn decode<M: WireMessage>(bytes: &[u8]) -> Result<M, WireError> {
+    let envelope = open::<M>(bytes)?;
+    Ok(bincode::deserialize(&envelope.payload)?)
+}
+
+/// Like `decode`, but also checks the signature on the envelope against the keys in `keyring`,
+/// and returns who signed it. Messages of a type that needs a signature must have one.
+pub fn decode_verified<M: WireMessage>(
+    bytes: &[u8],
+    keyring: &Keyring,
+) -> Result<(Option<NodeId>, M), WireError> {
+    let envelope = open::<M>(bytes)?;
+    match envelope.sender {
+        Some(sender) => {
+            let authentic = M::KIND.may_come_from(sender)
+                && Signature::from_slice(&envelope.signature).map_or(false, |signature| {
+                    keyring.verify(sender, &envelope.signed_bytes(), &signature)
+                });
+            if !authentic {
+                return Err(WireError::Forged(sender));
+            }
+        }
+        None if M::KIND.needs_signature() => return Err(WireError::Unsigned(M::KIND)),
+        None => {}
+    }
+    Ok((envelope.sender, bincode::deserialize(&envelope.payload)?))
+}
+
+fn open<M: WireMessage>(bytes: &[u8]) -> Result<Envelope, WireError> {
+    let envelope: Envelope = bincode::deserialize(bytes)?;
+    if envelope.version != WIRE_VERSION {
+        return Err(WireError::Version(envelope.version));
+    }
+    if envelope.kind != M::KIND {
+        return Err(WireError::Kind {
+            expected: M::KIND,
+            found: envelope.kind,
+        });
+    }
+    Ok(envelope)
+}
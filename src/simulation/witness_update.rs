@@ -0,0 +1,76 @@
+use super::state::{Block, Utxo};
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{AccError, Accumulator, Witness};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Hash, Debug, Deserialize, Serialize)]
+#[serde(bound(
+    serialize = "Accumulator<G, Utxo>: Serialize",
+    deserialize = "Accumulator<G, Utxo>: Deserialize<'de>"
+))]
+/// What anyone holding a witness needs from a block to bring it up to date, published by the leader
+/// with each block: the UTXOs it deleted and added, the accumulator between the two, and the one
+/// after. It leaves out the block's transaction witnesses and proofs, so a client can keep its own
+/// witnesses without a bridge or the whole block.
+pub struct WitnessUpdate<G: UnknownOrderGroup> {
+    pub height: u64,
+    pub deleted: Vec<Utxo>,
+    pub added: Vec<Utxo>,
+    /// The accumulator before the block with `deleted` deleted.
+    pub acc_deleted: Accumulator<G, Utxo>,
+    pub acc_new: Accumulator<G, Utxo>,
+}
+
+impl<G: UnknownOrderGroup> WitnessUpdate<G> {
+    /// The update for `block`, which follows `acc`. A block proven by a single swap does not
+    /// carry the accumulator between its deletions and additions, so it is worked out from `acc`
+    /// and the witnesses the block's transactions spend with.
+    pub fn from_block(
+        block: &Block<G, Utxo>,
+        acc: &Accumulator<G, Utxo>,
+    ) -> Result<Self, AccError> {
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+        for transaction in &block.transactions {
+            deleted.extend(transaction.utxos_spent_with_witnesses.iter().cloned());
+            added.extend(transaction.utxos_created.iter().cloned());
+        }
+        let acc_deleted = match block.acc_deleted() {
+            Some(acc_deleted) => acc_deleted.clone(),
+            None => acc.clone().delete(&deleted)?,
+        };
+        Ok(Self {
+            height: block.height,
+            deleted: deleted.into_iter().map(|(utxo, _witness)| utxo).collect(),
+            added,
+            acc_deleted,
+            acc_new: block.acc_new.clone(),
+        })
+    }
+
+    /// Brings `witness` to `utxo`, current as of the block before, up to this block. Fails if the
+    /// block spent `utxo`.
+    pub fn update(
+        &self,
+        utxo: &Utxo,
+        witness: Witness<G, Utxo>,
+    ) -> Result<Witness<G, Utxo>, AccError> {
+        self.acc_new
+            .update_membership_witness(witness, &[utxo.clone()], &self.added, &self.deleted)
+    }
+
+    /// A witness to `utxo`, one of the UTXOs this block added: the accumulator without the
+    /// deleted UTXOs, with the other added ones added. `None` if the block did not add it.
+    pub fn new_witness(&self, utxo: &Utxo) -> Option<Witness<G, Utxo>> {
+        if !self.added.contains(utxo) {
+            return None;
+        }
+        let others: Vec<Utxo> = self
+            .added
+            .iter()
+            .filter(|other| *other != utxo)
+            .cloned()
+            .collect();
+        Some(Witness(self.acc_deleted.clone().add(&others)))
+    }
+}
@@ -0,0 +1,105 @@
+use accumulator::group::UnknownOrderGroup;
+use accumulator::{AccError, Accumulator, Witness};
+use rayon::prelude::*;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+#[derive(Clone, Debug)]
+/// A set of elements of an accumulator and a single witness to all of them, kept current as
+/// blocks are applied. Updating the one witness against a block's additions and deletions takes a
+/// single pass however many elements there are, and witnesses to individual elements are split off
+/// it only when asked for, by RootFactor (BBF V3 Section 4.1), rather than each being recomputed.
+pub struct WitnessSet<G: UnknownOrderGroup, T: Clone + Hash + Debug> {
+    elems: Vec<T>,
+    witness: Witness<G, T>,
+}
+
+impl<G: UnknownOrderGroup, T: Clone + Eq + Hash + Debug> WitnessSet<G, T> {
+    /// The set of `elems`, with `witness` to all of them.
+    pub fn new(elems: Vec<T>, witness: Witness<G, T>) -> Self {
+        Self { elems, witness }
+    }
+
+    pub fn elems(&self) -> &[T] {
+        &self.elems
+    }
+
+    pub fn len(&self) -> usize {
+        self.elems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elems.is_empty()
+    }
+
+    pub fn contains(&self, elem: &T) -> bool {
+        self.elems.contains(elem)
+    }
+
+    /// Brings the set up to `acc_new`, the accumulator after a block that added `added` and
+    /// deleted `deleted` from the set, and added and deleted `untracked_added` and
+    /// `untracked_deleted` outside it.
+    pub fn update(
+        &mut self,
+        acc_new: &Accumulator<G, T>,
+        added: &[T],
+        deleted: &[T],
+        untracked_added: &[T],
+        untracked_deleted: &[T],
+    ) -> Result<(), AccError> {
+        self.elems.retain(|elem| !deleted.contains(elem));
+        self.elems.extend(added.iter().cloned());
+        self.witness = acc_new.update_membership_witness(
+            self.witness.clone(),
+            &self.elems,
+            untracked_added,
+            untracked_deleted,
+        )?;
+        Ok(())
+    }
+
+    /// Re-derives the witness from `others`, every element of `acc` outside the set, rather than
+    /// from the chain of updates before. Returns whether the updated witness was the same, or
+    /// `Err` if the re-derived one is no witness to the set in `acc` either, in which case the set
+    /// is left as it was.
+    pub fn refresh(&mut self, acc: &Accumulator<G, T>, others: &[T]) -> Result<bool, AccError> {
+        let witness = Witness(Accumulator::empty().add(others));
+        if witness.0.clone().add(&self.elems) != *acc {
+            return Err(AccError::BadWitness);
+        }
+        let is_unchanged = witness == self.witness;
+        self.witness = witness;
+        Ok(is_unchanged)
+    }
+
+    /// A witness to each of `elems`, which must all be in the set.
+    pub fn witnesses(&self, elems: &[T]) -> Result<Vec<(T, Witness<G, T>)>, AccError> {
+        let subset_witness = self
+            .witness
+            .clone()
+            .compute_subset_witness(&self.elems, elems)?;
+        Ok(subset_witness.compute_individual_witnesses(elems))
+    }
+
+    /// A witness to each of `elems`, as `witnesses` gives, split into `parts` worked out in
+    /// parallel on the current rayon pool. Each part derives a subset witness of its own before
+    /// splitting it, so this only pays off for more elements than there are parts.
+    pub fn witnesses_in_parallel(
+        &self,
+        elems: &[T],
+        parts: usize,
+    ) -> Result<Vec<(T, Witness<G, T>)>, AccError>
+    where
+        T: Send + Sync,
+    {
+        if elems.is_empty() {
+            return Ok(Vec::new());
+        }
+        let part_size = (elems.len() + parts.max(1) - 1) / parts.max(1);
+        let parts: Vec<Vec<(T, Witness<G, T>)>> = elems
+            .par_chunks(part_size)
+            .map(|part| self.witnesses(part))
+            .collect::<Result<_, _>>()?;
+        Ok(parts.into_iter().flatten().collect())
+    }
+}
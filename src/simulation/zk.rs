@@ -0,0 +1,360 @@
+use super::config::{Config, GroupKind};
+use super::modulus;
+use super::network::ELEM_BYTES;
+use super::util;
+use accumulator::group::UnknownOrderGroup;
+use accumulator::hash::{blake2b, hash_to_prime};
+use accumulator::{Accumulator, Witness};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rug::integer::Order;
+use rug::Integer;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Chance that a user spends a coin in any one block interval.
+const SPEND_PROBABILITY: f64 = 0.5;
+
+/// Bits of the verifier's challenge.
+const CHALLENGE_BITS: u32 = 128;
+
+/// Bits by which the prover's masks exceed what they hide, so that the responses reveal nothing of
+/// it but with negligible probability.
+const SLACK_BITS: u32 = 128;
+
+/// Bits of the primes the accumulator represents elements by.
+const PRIME_BITS: u32 = 256;
+
+/// Bits of the class group's discriminant, whose square root bounds the group's order.
+const CLASS_GROUP_DISCRIMINANT_BITS: u32 = 2048;
+
+/// Bytes of a serial number.
+const SERIAL_BYTES: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+/// What the chain sees of a coin until it is spent: SHA-256 of its serial number and a random
+/// opening. The accumulator holds these, so it does not reveal which serial number is whose.
+pub struct CoinCommitment(pub [u8; 32]);
+
+#[derive(Clone, Debug)]
+/// A coin as its owner knows it. Spending it reveals the serial number, never the opening.
+pub struct Coin {
+    pub serial: Uuid,
+    pub opening: Uuid,
+}
+
+impl Coin {
+    pub fn new<R: Rng>(rng: &mut R) -> Self {
+        Self {
+            serial: util::new_uuid(rng),
+            opening: util::new_uuid(rng),
+        }
+    }
+
+    pub fn commitment(&self) -> CoinCommitment {
+        CoinCommitment(util::sha256(&(self.serial, self.opening)))
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A zero-knowledge proof that the prover holds a witness to some element of an accumulator,
+/// without revealing the element or the witness, bound to the serial number of the coin it spends.
+///
+/// It is a Fiat-Shamir sigma protocol over the group. With g the group's unknown-order element, x
+/// the element's prime and w its witness, so that w^x = A, the prover blinds the witness as
+/// W = w · g^r for a random r, so that W^x · g^-y = A for y = r · x, and proves it knows x and y
+/// by a Schnorr-style proof of that representation: it sends T = W^kx · g^-ky for random masks,
+/// and answers the challenge c with sx = kx + c · x and sy = ky + c · y over the integers. The
+/// verifier checks that W^sx = T · A^c · g^sy, and that the answers are no bigger than honest ones.
+///
+/// This is a demonstration, not a sound spending proof, and it accepts forged spends: it does not
+/// prove that x is a prime the accumulator holds rather than any exponent the prover can take a
+/// root by, so x = 1, W = A and y = 0 spends any serial number without a coin. Nor does it prove
+/// that x commits to the serial number. A sound scheme proves both, with range proofs and proofs
+/// about the commitment (Camenisch-Lysyanskaya 2002, as in Zerocoin), at several times the cost.
+pub struct ZkMembershipProof<G: UnknownOrderGroup> {
+    pub blinded_witness: G::Elem,
+    pub t: G::Elem,
+    pub s_x: Integer,
+    pub s_y: Integer,
+}
+
+impl<G: UnknownOrderGroup> ZkMembershipProof<G> {
+    /// Proves that `coin`, to whose commitment `witness` is a witness, is in `acc`, blinding the
+    /// witness with a `blinding_bits`-bit exponent. See `blinding_bits`.
+    pub fn prove<R: Rng>(
+        acc: &Accumulator<G, CoinCommitment>,
+        coin: &Coin,
+        witness: &Witness<G, CoinCommitment>,
+        blinding_bits: u32,
+        rng: &mut R,
+    ) -> Self {
+        let g = G::unknown_order_elem();
+        let x = hash_to_prime(&coin.commitment());
+        let r = random_integer(blinding_bits, rng);
+        let blinded_witness = G::op(witness.0.value(), &G::exp(&g, &r));
+        let y = Integer::from(&r * &x);
+        let k_x = random_integer(PRIME_BITS + CHALLENGE_BITS + SLACK_BITS, rng);
+        let k_y = random_integer(
+            blinding_bits + PRIME_BITS + CHALLENGE_BITS + SLACK_BITS,
+            rng,
+        );
+        let t = G::op(&G::exp(&blinded_witness, &k_x), &G::inv(&G::exp(&g, &k_y)));
+        let c = challenge::<G>(acc.value(), coin.serial, &blinded_witness, &t);
+        Self {
+            s_x: k_x + Integer::from(&c * &x),
+            s_y: k_y + c * y,
+            blinded_witness,
+            t,
+        }
+    }
+
+    /// Whether this proves that the spender of the coin with `serial` holds a witness to some
+    /// element of `acc`, for a proof made with `blinding_bits`. Forged proofs pass too; see above.
+    pub fn verify(
+        &self,
+        acc: &Accumulator<G, CoinCommitment>,
+        serial: Uuid,
+        blinding_bits: u32,
+    ) -> bool {
+        let is_bounded = |s: &Integer, bits: u32| *s >= 0 && s.significant_bits() <= bits + 1;
+        if !is_bounded(&self.s_x, PRIME_BITS + CHALLENGE_BITS + SLACK_BITS)
+            || !is_bounded(
+                &self.s_y,
+                blinding_bits + PRIME_BITS + CHALLENGE_BITS + SLACK_BITS,
+            )
+        {
+            return false;
+        }
+        let g = G::unknown_order_elem();
+        let c = challenge::<G>(acc.value(), serial, &self.blinded_witness, &self.t);
+        let lhs = G::exp(&self.blinded_witness, &self.s_x);
+        let rhs = G::op(
+            &self.t,
+            &G::op(&G::exp(acc.value(), &c), &G::exp(&g, &self.s_y)),
+        );
+        lhs == rhs
+    }
+
+    /// Estimated bytes on the wire: two group elements and the two answers.
+    pub fn bytes(&self) -> usize {
+        2 * ELEM_BYTES + (self.s_x.significant_bits() + self.s_y.significant_bits()) as usize / 8
+    }
+}
+
+/// The challenge the verifier would have sent, hashed from the statement and the prover's
+/// commitment.
+fn challenge<G: UnknownOrderGroup>(
+    acc: &G::Elem,
+    serial: Uuid,
+    blinded_witness: &G::Elem,
+    t: &G::Elem,
+) -> Integer {
+    let mut c = blake2b(&(acc, serial, blinded_witness, t));
+    c.keep_bits_mut(CHALLENGE_BITS);
+    c
+}
+
+/// Bits of the exponent that blinds a witness in the group `config` runs in: more than the group's
+/// order has, so the blinded witness is close to uniform. The order is unknown, but at most the
+/// modulus for RSA groups, and the square root of the discriminant for the class group.
+pub fn blinding_bits(config: &Config) -> u32 {
+    let order_bits = match config.group {
+        GroupKind::Rsa100 => 100,
+        GroupKind::Rsa2048 | GroupKind::Trapdoor => 2048,
+        GroupKind::Rsa => modulus::installed_bits().expect("The RSA modulus is installed"),
+        GroupKind::ClassGroup => CLASS_GROUP_DISCRIMINANT_BITS / 2,
+    };
+    order_bits + SLACK_BITS
+}
+
+/// A uniformly random integer below 2^`bits`.
+fn random_integer<R: Rng>(bits: u32, rng: &mut R) -> Integer {
+    let mut bytes = vec![0; (bits as usize + 7) / 8];
+    rng.fill_bytes(&mut bytes);
+    let mut n = Integer::from_digits(&bytes, Order::Msf);
+    n.keep_bits_mut(bits);
+    n
+}
+
+#[derive(Clone, Debug, Default)]
+/// Totals for a finished zero-knowledge run.
+pub struct ZkSummary {
+    pub blocks: u64,
+    /// Spends the miner accepted without learning which coin they spent.
+    pub spends: u64,
+    /// Spends rejected for a serial number already spent or a proof that does not verify.
+    pub rejected: u64,
+    /// Estimated bytes of the proofs and serial numbers spends carried.
+    pub proof_bytes: u64,
+    pub proving_time: Duration,
+    pub verification_time: Duration,
+    /// Time users spent keeping their coins' witnesses current.
+    pub witness_update_time: Duration,
+}
+
+impl fmt::Display for ZkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Zero-knowledge summary")?;
+        writeln!(f, "  blocks:                  {}", self.blocks)?;
+        writeln!(f, "  spends:                  {}", self.spends)?;
+        writeln!(f, "  rejected spends:         {}", self.rejected)?;
+        writeln!(
+            f,
+            "  proof time:              {:.1?} mean, {:.1?} to verify",
+            mean(self.proving_time, self.spends + self.rejected),
+            mean(self.verification_time, self.spends + self.rejected)
+        )?;
+        writeln!(
+            f,
+            "  witness update time:     {:.1?} per block",
+            mean(self.witness_update_time, self.blocks)
+        )?;
+        write!(f, "  proof bytes:             {}", self.proof_bytes)
+    }
+}
+
+fn mean(total: Duration, count: u64) -> Duration {
+    if count == 0 {
+        return Duration::from_secs(0);
+    }
+    total / count as u32
+}
+
+/// A privacy demo: a chain of coins whose spends do not reveal which coin they spend. The
+/// accumulator holds coin commitments and only grows. To spend a coin, a user reveals its serial
+/// number and proves in zero knowledge that it holds a witness to some element of the accumulator,
+/// which the miner checks blindly, rejecting serial numbers spent before. Each spend mints a coin
+/// of the same value for a random user, whose commitment the next block adds, and users keep their
+/// own witnesses current. Runs are stepped on a single thread whatever the mode, and are
+/// deterministic for a given seed.
+///
+/// As the proof is not sound, the miner accepts forged spends, and its check of serial numbers
+/// spent before guards nothing. No payload in the config runs this until the proof is sound; it
+/// is kept to measure the cost of the flow from code.
+pub struct ZkDemo<G: UnknownOrderGroup> {
+    config: Config,
+    /// See `blinding_bits`.
+    blinding_bits: u32,
+    rng: StdRng,
+    height: u64,
+    acc: Accumulator<G, CoinCommitment>,
+    /// The serial numbers of every coin spent.
+    spent_serials: HashSet<Uuid>,
+    /// Each user's coins, with a witness to each.
+    wallets: Vec<Vec<(Coin, Witness<G, CoinCommitment>)>>,
+    summary: ZkSummary,
+}
+
+impl<G: UnknownOrderGroup> ZkDemo<G> {
+    /// Starts from a genesis where every user owns a single coin.
+    pub fn new(config: Config) -> Self {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let coins: Vec<Coin> = (0..config.num_users())
+            .map(|_| Coin::new(&mut rng))
+            .collect();
+        let commitments: Vec<CoinCommitment> = coins.iter().map(Coin::commitment).collect();
+        let witnesses = Witness(Accumulator::empty()).compute_individual_witnesses(&commitments);
+        let wallets = coins
+            .into_iter()
+            .zip(witnesses)
+            .map(|(coin, (_, witness))| vec![(coin, witness)])
+            .collect();
+        Self {
+            acc: Accumulator::empty().add(&commitments),
+            blinding_bits: blinding_bits(&config),
+            config,
+            rng,
+            height: 0,
+            spent_serials: HashSet::new(),
+            wallets,
+            summary: ZkSummary::default(),
+        }
+    }
+
+    /// Runs until the chain reaches the bounds in the config.
+    pub fn run(mut self) -> ZkSummary {
+        info!(
+            "Zero-knowledge run starting with {} users.",
+            self.config.num_users()
+        );
+        warn!("Zero-knowledge spend proofs are a demonstration: forged spends pass them.");
+        if self.config.max_blocks.is_none() && self.config.max_duration_ms.is_none() {
+            warn!("The run has no bounds, so it never ends.");
+        }
+        let block_interval = Duration::from_millis(self.config.block_interval_ms);
+        let mut now = Duration::from_secs(0);
+        while !self.config.is_finished(self.height, now) {
+            self.step();
+            now += block_interval;
+        }
+        info!("Zero-knowledge run exiting at block {}.", self.height);
+        self.summary
+    }
+
+    /// Has users spend, has the miner check each spend blindly, and adds the coins minted in a
+    /// block, updating every witness.
+    fn step(&mut self) {
+        let num_users = self.config.num_users();
+        let mut minted = Vec::new();
+        for user_id in 0..num_users {
+            if !self.rng.gen_bool(SPEND_PROBABILITY) || self.wallets[user_id].is_empty() {
+                continue;
+            }
+            let (coin, witness) = self.wallets[user_id].remove(0);
+            let start = Instant::now();
+            let proof = ZkMembershipProof::prove(
+                &self.acc,
+                &coin,
+                &witness,
+                self.blinding_bits,
+                &mut self.rng,
+            );
+            self.summary.proving_time += start.elapsed();
+            self.summary.proof_bytes += (proof.bytes() + SERIAL_BYTES) as u64;
+
+            // The miner sees only the serial number and the proof.
+            let start = Instant::now();
+            let is_valid = proof.verify(&self.acc, coin.serial, self.blinding_bits);
+            self.summary.verification_time += start.elapsed();
+            if !is_valid || !self.spent_serials.insert(coin.serial) {
+                warn!("Rejected a spend by user {}.", user_id);
+                self.summary.rejected += 1;
+                continue;
+            }
+            self.summary.spends += 1;
+            let recipient = self.rng.gen_range(0, num_users);
+            minted.push((recipient, Coin::new(&mut self.rng)));
+        }
+
+        let added: Vec<CoinCommitment> = minted.iter().map(|(_, coin)| coin.commitment()).collect();
+        let start = Instant::now();
+        for wallet in &mut self.wallets {
+            for (_, witness) in wallet.iter_mut() {
+                *witness = Witness(witness.0.clone().add(&added));
+            }
+        }
+        for (index, (recipient, coin)) in minted.into_iter().enumerate() {
+            let others: Vec<CoinCommitment> = added
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, commitment)| *commitment)
+                .collect();
+            let witness = Witness(self.acc.clone().add(&others));
+            self.wallets[recipient].push((coin, witness));
+        }
+        self.summary.witness_update_time += start.elapsed();
+        self.acc = self.acc.clone().add(&added);
+        self.height += 1;
+        self.summary.blocks += 1;
+        debug!(
+            "Forged block {} minting {} coins.",
+            self.height,
+            added.len()
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Deterministic runs: the same seed and bounds make the same run, block for block.
+use accumulator::group::Rsa100;
+use accumulator_demo::simulation::report::RunResult;
+use accumulator_demo::simulation::Simulation;
+
+const SEED: u64 = 7;
+const BLOCKS: u64 = 100;
+
+fn run(seed: u64) -> RunResult<Rsa100> {
+    Simulation::<Rsa100>::builder()
+        .deterministic()
+        .seed(seed)
+        .max_blocks(BLOCKS)
+        .build()
+        .run_here()
+}
+
+#[test]
+fn test_same_seed_same_run() {
+    let first = run(SEED);
+    let second = run(SEED);
+
+    assert_eq!(first.block_height, BLOCKS);
+    assert_eq!(second.block_height, BLOCKS);
+    let heights: Vec<u64> = first
+        .outcome()
+        .blocks
+        .iter()
+        .map(|(height, _)| *height)
+        .collect();
+    assert_eq!(heights, (1..=BLOCKS).collect::<Vec<_>>());
+
+    assert_eq!(first.summary.blocks, second.summary.blocks);
+    assert_eq!(first.summary.transactions, second.summary.transactions);
+    assert_eq!(first.summary.rejected_blocks, 0);
+    assert_eq!(second.summary.rejected_blocks, 0);
+    assert!(first.acc == second.acc);
+    assert_eq!(first.outcome(), second.outcome());
+}
@@ -0,0 +1,17 @@
+//! The invariant checker on a whole run: an honest deterministic run keeps every bridge in step
+//! with the UTXO set, or the run panics with the difference.
+use accumulator::group::Rsa100;
+use accumulator_demo::simulation::Simulation;
+
+#[test]
+fn test_deterministic_run_keeps_invariants() {
+    let result = Simulation::<Rsa100>::builder()
+        .deterministic()
+        .check_invariants()
+        .seed(7)
+        .max_blocks(20)
+        .build()
+        .run_here();
+    assert_eq!(result.block_height, 20);
+    assert_eq!(result.summary.rejected_blocks, 0);
+}